@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cache::CACHE_DIR;
+use crate::cli::{AbortArgs, Args};
+use crate::esp::split_plugin_name;
+use crate::paths::find_fallout4;
+use crate::utils::{ensure_directory_exists, remove_file_if_exists};
+
+/// Sentinel file asking every running build to stop
+const ABORT_ALL_FILE: &str = "abort";
+
+/// Path of the sentinel asking builds of `plugin` (or every build) to stop
+pub fn abort_path(fallout4: &Path, plugin: Option<&str>) -> PathBuf {
+    let file_name = match plugin {
+        Some(plugin) => {
+            let stem = split_plugin_name(plugin).map_or(plugin, |(name, _)| name);
+            format!("{}-{}", ABORT_ALL_FILE, stem.to_lowercase())
+        }
+        None => ABORT_ALL_FILE.to_string(),
+    };
+    fallout4.join(CACHE_DIR).join(file_name)
+}
+
+/// Returns true if the build of `plugin` was asked to stop
+pub fn abort_requested(fallout4: &Path, plugin: &str) -> bool {
+    abort_path(fallout4, Some(plugin)).exists() || abort_path(fallout4, None).exists()
+}
+
+/// Removes the sentinels for `plugin`, so a request only stops one build
+pub fn clear_abort(fallout4: &Path, plugin: &str) -> Result<(), String> {
+    remove_file_if_exists(abort_path(fallout4, Some(plugin)))?;
+    remove_file_if_exists(abort_path(fallout4, None))
+}
+
+/// Runs the `abort` command.
+///
+/// Asks a running build to stop once its current stage is done. The build
+/// records its progress and exits, and the next build picks up where it
+/// stopped.
+pub fn run_abort(args: &Args, abort: &AbortArgs) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+
+    let path = abort_path(&fallout4, abort.plugin.as_deref());
+    if let Some(parent) = path.parent() {
+        ensure_directory_exists(parent)?;
+    }
+    fs::write(&path, "").map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+
+    match &abort.plugin {
+        Some(plugin) => println!("The build of {} will stop after its current stage", plugin),
+        None => println!("Running builds will stop after their current stage"),
+    }
+    Ok(())
+}
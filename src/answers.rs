@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use log::info;
+
+/// Answers loaded with --answers, keyed by prompt identifier
+static ANSWERS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// Set by --assume-yes and --no-prompt
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// How long prompts wait for the user, set with --prompt-timeout
+static TIMEOUT: OnceLock<PromptTimeout> = OnceLock::new();
+
+/// Answers taken when the user doesn't answer in time
+#[derive(Debug, Clone)]
+struct PromptTimeout {
+    timeout: Duration,
+    defaults: BTreeMap<&'static str, String>,
+}
+
+/// A question the build can ask, which the answers file can answer in advance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prompt {
+    /// Whether to run the setup on first launch
+    RunSetup,
+    /// Name of the plugin to build, when none was given
+    PluginName,
+    /// Whether to create an empty plugin when neither it nor xPrevisPatch.esp exists
+    CreatePlugin,
+    /// Masters of a newly created plugin
+    Masters,
+    /// Whether to rename xPrevisPatch.esp to the plugin
+    RenameSeed,
+    /// Seed plugin to start from when several are found
+    SeedPlugin,
+    /// Whether to copy a plugin given as a path into Data
+    StagePlugin,
+    /// Stage to start from when the plugin already exists
+    StartStage,
+    /// Build mode when none was given and the plugin suits another than clean
+    BuildMode,
+    /// What to do after a stage failed: retry, open the logs, skip it or abort
+    StageFailed,
+    /// Whether to clean up leftovers of a previous failed run
+    CleanLeftovers,
+    /// Whether to re-enable the DLLs a crashed run left disabled, at startup
+    RestoreDlls,
+    /// Whether to build a light plugin adding new cells: proceed or abort
+    LightPluginCells,
+    /// Whether to use the native archiver when Archive2 is missing (--wizard)
+    UseNativeArchiver,
+    /// Whether to continue despite environment problems (--wizard)
+    IgnoreEnvironmentProblems,
+    /// Whether to start the build set up by the wizard
+    StartBuild,
+    /// Whether to go ahead with the build plan (--confirm-plan)
+    ConfirmPlan,
+    /// Whether to install the latest release (self-update)
+    SelfUpdate,
+}
+
+impl Prompt {
+    /// Whether the prompt is a yes/no question
+    pub fn is_yes_no(&self) -> bool {
+        !matches!(
+            self,
+            Prompt::PluginName | Prompt::Masters | Prompt::SeedPlugin | Prompt::StartStage | Prompt::BuildMode | Prompt::StageFailed | Prompt::LightPluginCells
+        )
+    }
+
+    /// Finds a prompt by its identifier
+    pub fn from_id(id: &str) -> Option<Prompt> {
+        ALL_PROMPTS.iter().copied().find(|prompt| prompt.id() == id)
+    }
+}
+
+/// Every prompt, in the order they are documented
+pub const ALL_PROMPTS: [Prompt; 18] = [
+    Prompt::RunSetup,
+    Prompt::PluginName,
+    Prompt::CreatePlugin,
+    Prompt::Masters,
+    Prompt::RenameSeed,
+    Prompt::SeedPlugin,
+    Prompt::StagePlugin,
+    Prompt::StartStage,
+    Prompt::BuildMode,
+    Prompt::StageFailed,
+    Prompt::CleanLeftovers,
+    Prompt::RestoreDlls,
+    Prompt::LightPluginCells,
+    Prompt::UseNativeArchiver,
+    Prompt::IgnoreEnvironmentProblems,
+    Prompt::StartBuild,
+    Prompt::ConfirmPlan,
+    Prompt::SelfUpdate,
+];
+
+impl Prompt {
+    /// Identifier of the prompt in the answers file
+    pub fn id(&self) -> &'static str {
+        match self {
+            Prompt::RunSetup => "run-setup",
+            Prompt::PluginName => "plugin-name",
+            Prompt::CreatePlugin => "create-plugin",
+            Prompt::Masters => "masters",
+            Prompt::RenameSeed => "rename-seed",
+            Prompt::SeedPlugin => "seed-plugin",
+            Prompt::StagePlugin => "stage-plugin",
+            Prompt::StartStage => "start-stage",
+            Prompt::BuildMode => "build-mode",
+            Prompt::StageFailed => "stage-failed",
+            Prompt::CleanLeftovers => "clean-leftovers",
+            Prompt::RestoreDlls => "restore-dlls",
+            Prompt::LightPluginCells => "light-plugin-cells",
+            Prompt::UseNativeArchiver => "use-native-archiver",
+            Prompt::IgnoreEnvironmentProblems => "ignore-environment-problems",
+            Prompt::StartBuild => "start-build",
+            Prompt::ConfirmPlan => "confirm-plan",
+            Prompt::SelfUpdate => "self-update",
+        }
+    }
+}
+
+/// Loads the answers file given with --answers.
+///
+/// The file is TOML with one entry per prompt identifier, e.g.
+/// `rename-seed = true`, `start-stage = 6` or `masters = ["PRP.esp"]`.
+///
+/// # Returns
+/// * `Ok(())` if the answers were loaded
+/// * `Err(String)` if the file can't be read, isn't valid TOML, names an
+///   unknown prompt or was already loaded
+pub fn load_answers(path: &Path) -> Result<(), String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Error reading answers file {}: {}", path.display(), e))?;
+    let table: toml::Table = toml::from_str(&content)
+        .map_err(|e| format!("Error parsing answers file {}: {}", path.display(), e))?;
+
+    let mut answers = BTreeMap::new();
+    for (id, value) in table {
+        if Prompt::from_id(&id).is_none() {
+            let known: Vec<&str> = ALL_PROMPTS.iter().map(Prompt::id).collect();
+            return Err(format!(
+                "ERROR - Unknown prompt '{}' in answers file {}. Known prompts: {}",
+                id,
+                path.display(),
+                known.join(", ")
+            ));
+        }
+        let answer = match value {
+            toml::Value::String(text) => text,
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string).unwrap_or_else(|| item.to_string()))
+                .collect::<Vec<_>>()
+                .join(","),
+            other => other.to_string(),
+        };
+        answers.insert(id, answer);
+    }
+
+    ANSWERS
+        .set(answers)
+        .map_err(|_| "ERROR - Answers file already loaded".to_string())
+}
+
+/// Returns the predetermined answer to a prompt, if the answers file has one
+pub fn answer_for(prompt: Prompt) -> Option<&'static str> {
+    let answer = ANSWERS.get()?.get(prompt.id())?;
+    info!("Answering {} with '{}' from the answers file", prompt.id(), answer);
+    Some(answer.as_str())
+}
+
+/// Makes prompts stop waiting after `timeout` and take a default answer.
+///
+/// # Arguments
+/// * `timeout` - How long a prompt waits for the user
+/// * `defaults` - `PROMPT=ANSWER` pairs (--timeout-answer); yes/no prompts
+///   without one answer no, other prompts without one fail the build
+///
+/// # Returns
+/// * `Ok(())` if the timeout was set
+/// * `Err(String)` if a default is malformed or names an unknown prompt
+pub fn set_prompt_timeout(timeout: Duration, defaults: &[String]) -> Result<(), String> {
+    let mut parsed = BTreeMap::new();
+    for default in defaults {
+        let (id, answer) = default
+            .split_once('=')
+            .ok_or_else(|| format!("ERROR - Expected PROMPT=ANSWER for --timeout-answer, got '{}'", default))?;
+        let prompt = Prompt::from_id(id.trim())
+            .ok_or_else(|| format!("ERROR - Unknown prompt '{}' in --timeout-answer", id.trim()))?;
+        parsed.insert(prompt.id(), answer.trim().to_string());
+    }
+
+    TIMEOUT
+        .set(PromptTimeout { timeout, defaults: parsed })
+        .map_err(|_| "ERROR - Prompt timeout already set".to_string())
+}
+
+/// How long prompts wait for the user, if they time out at all
+pub fn prompt_timeout() -> Option<Duration> {
+    TIMEOUT.get().map(|timeout| timeout.timeout)
+}
+
+/// Answer taken when a prompt times out, if it has one
+pub fn timeout_answer(prompt: Prompt) -> Option<&'static str> {
+    let timeout = TIMEOUT.get()?;
+    match timeout.defaults.get(prompt.id()) {
+        Some(answer) => Some(answer.as_str()),
+        None if prompt.is_yes_no() => Some("n"),
+        None => None,
+    }
+}
+
+/// Answers every yes/no prompt without an answer on file with yes (--assume-yes)
+pub fn set_assume_yes() {
+    ASSUME_YES.store(true, Ordering::Relaxed);
+}
+
+/// Returns true with --assume-yes
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Returns true if the answers file answers a prompt
+pub fn has_answer(prompt: Prompt) -> bool {
+    ANSWERS.get().is_some_and(|answers| answers.contains_key(prompt.id()))
+}
@@ -0,0 +1,194 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::info;
+use serde::{Deserialize, Serialize};
+use crate::cli::{Args, RestoreBackupArgs};
+use crate::esp::split_plugin_name;
+use crate::paths::find_fallout4;
+use crate::utils::{ensure_directory_exists, timestamp};
+
+/// Folder under the Fallout 4 directory holding one backup folder per build
+pub const BACKUP_DIR: &str = "PrevisbineBackups";
+
+/// Name of the manifest written into every backup folder
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// One file saved before the build modified or deleted it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// Where the file was, and where it is restored to
+    pub original: PathBuf,
+    /// File name of the copy inside the backup folder
+    pub backup: String,
+}
+
+/// List of files saved by a build, written as `manifest.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub plugin: String,
+    pub created: String,
+    pub entries: Vec<BackupEntry>,
+}
+
+/// Backup of every artifact a build overwrites or deletes.
+///
+/// The backup folder is only created once the first file is saved, so runs
+/// that don't touch existing files leave nothing behind.
+pub struct RunBackup {
+    dir: PathBuf,
+    enabled: bool,
+    manifest: RefCell<BackupManifest>,
+}
+
+impl RunBackup {
+    /// Creates the backup for a build of `plugin_name` (with extension), stored
+    /// under `<Fallout 4>/PrevisbineBackups/<plugin>-<timestamp>`
+    pub fn new(fallout4: &Path, plugin_name: &str, enabled: bool) -> Self {
+        let created = timestamp();
+        Self {
+            dir: fallout4.join(BACKUP_DIR).join(format!("{}-{}", plugin_name, created)),
+            enabled,
+            manifest: RefCell::new(BackupManifest {
+                plugin: plugin_name.to_string(),
+                created,
+                entries: Vec::new(),
+            }),
+        }
+    }
+
+    /// The folder backups are written to
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns true if at least one file was backed up
+    pub fn has_entries(&self) -> bool {
+        !self.manifest.borrow().entries.is_empty()
+    }
+
+    /// Copies a file into the backup folder before it is overwritten or deleted.
+    ///
+    /// Missing files are ignored, as are files already saved by this run (the
+    /// first copy is the original).
+    ///
+    /// # Returns
+    /// * `Ok(())` if the file was saved or didn't need saving
+    /// * `Err(String)` if the copy or the manifest couldn't be written
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if !self.enabled || !path.is_file() {
+            return Ok(());
+        }
+
+        let mut manifest = self.manifest.borrow_mut();
+        if manifest.entries.iter().any(|entry| entry.original == path) {
+            return Ok(());
+        }
+
+        ensure_directory_exists(&self.dir)?;
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "file".to_string());
+        let mut backup_name = file_name.clone();
+        let mut counter = 2;
+        while self.dir.join(&backup_name).exists() {
+            backup_name = format!("{}.{}", file_name, counter);
+            counter += 1;
+        }
+
+        fs::copy(path, self.dir.join(&backup_name))
+            .map_err(|e| format!("Error backing up {}: {}", path.display(), e))?;
+        info!("Backed up {} to {}", path.display(), self.dir.display());
+
+        manifest.entries.push(BackupEntry {
+            original: path.to_path_buf(),
+            backup: backup_name,
+        });
+        write_manifest(&self.dir, &manifest)
+    }
+}
+
+fn write_manifest(dir: &Path, manifest: &BackupManifest) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Error serializing backup manifest: {}", e))?;
+    fs::write(dir.join(MANIFEST_FILE), json)
+        .map_err(|e| format!("Error writing backup manifest in {}: {}", dir.display(), e))
+}
+
+/// Reads the manifest of a backup folder
+pub fn read_manifest(dir: &Path) -> Result<BackupManifest, String> {
+    let path = dir.join(MANIFEST_FILE);
+    let json = fs::read_to_string(&path)
+        .map_err(|e| format!("Error reading backup manifest {}: {}", path.display(), e))?;
+    serde_json::from_str(&json)
+        .map_err(|e| format!("Error parsing backup manifest {}: {}", path.display(), e))
+}
+
+/// Returns the most recent backup folder, optionally only for one plugin
+pub fn latest_backup(fallout4: &Path, plugin_name: Option<&str>) -> Option<PathBuf> {
+    let mut backups: Vec<(String, PathBuf)> = fs::read_dir(fallout4.join(BACKUP_DIR))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join(MANIFEST_FILE).exists())
+        .filter_map(|path| {
+            let manifest = read_manifest(&path).ok()?;
+            let matches = plugin_name.is_none_or(|plugin| same_plugin(&manifest.plugin, plugin));
+            matches.then_some((manifest.created, path))
+        })
+        .collect();
+
+    backups.sort();
+    backups.pop().map(|(_, path)| path)
+}
+
+/// Compares plugin names case-insensitively, with or without the extension
+pub(crate) fn same_plugin(a: &str, b: &str) -> bool {
+    let stem = |name: &str| split_plugin_name(name).map_or(name, |(stem, _)| stem).to_lowercase();
+    stem(a) == stem(b)
+}
+
+/// Copies every file of a backup back to where it was before the build.
+///
+/// # Returns
+/// * `Ok(usize)` with the number of files restored
+/// * `Err(String)` if the manifest can't be read or a file can't be restored
+pub fn restore_backup(dir: &Path) -> Result<usize, String> {
+    let manifest = read_manifest(dir)?;
+
+    for entry in &manifest.entries {
+        if let Some(parent) = entry.original.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        fs::copy(dir.join(&entry.backup), &entry.original)
+            .map_err(|e| format!("Error restoring {}: {}", entry.original.display(), e))?;
+        info!("Restored {}", entry.original.display());
+    }
+
+    Ok(manifest.entries.len())
+}
+
+/// Runs the `restore-backup` command.
+///
+/// Restores the given backup folder, or the latest backup (of the given
+/// plugin, if any) when no folder is specified.
+pub fn run_restore_backup(args: &Args, restore: &RestoreBackupArgs) -> Result<(), String> {
+    let dir = match &restore.backup {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let fallout4 = match &args.fallout4_path {
+                Some(path) => PathBuf::from(path),
+                None => find_fallout4()?,
+            };
+            latest_backup(&fallout4, restore.plugin.as_deref()).ok_or_else(|| {
+                format!("ERROR - No backups found in {}", fallout4.join(BACKUP_DIR).display())
+            })?
+        }
+    };
+
+    let restored = restore_backup(&dir)?;
+    println!("Restored {} files from {}", restored, dir.display());
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::backup::same_plugin;
+use crate::cache::{content_fingerprint, CACHE_DIR};
+use crate::cli::{Args, HistoryArgs};
+use crate::paths::find_fallout4;
+use crate::report::{BuildReport, StageTiming};
+use crate::utils::{ensure_directory_exists, format_duration, timestamp};
+
+/// Name of the build history file in the cache folder
+const BUILD_HISTORY_FILE: &str = "build-history.json";
+
+/// Number of builds kept in the history
+const MAX_BUILDS: usize = 500;
+
+/// One finished build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    /// Number of the build, increasing over the life of the history
+    pub id: u32,
+    /// When the build ended, as `YYYYMMDD-HHMMSS` UTC
+    pub finished: String,
+    pub tool_version: String,
+    pub plugin: String,
+    pub mode: String,
+    pub stages: Vec<StageTiming>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Content hash of the plugin and every archive the build produced
+    pub outputs: BTreeMap<String, String>,
+    pub run_dir: Option<PathBuf>,
+}
+
+impl BuildRecord {
+    /// Total time spent in the stages of the build
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.stages.iter().map(|timing| timing.seconds).sum())
+    }
+}
+
+/// Every build run on this installation, oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildHistory {
+    pub builds: Vec<BuildRecord>,
+}
+
+impl BuildHistory {
+    /// Path of the build history file
+    pub fn path(fallout4: &Path) -> PathBuf {
+        fallout4.join(CACHE_DIR).join(BUILD_HISTORY_FILE)
+    }
+
+    /// Loads the build history, starting empty if there is none or it can't be read
+    pub fn load(fallout4: &Path) -> Self {
+        fs::read_to_string(Self::path(fallout4))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the build history file
+    pub fn save(&self, fallout4: &Path) -> Result<(), String> {
+        let path = Self::path(fallout4);
+        if let Some(parent) = path.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing build history: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Error writing build history {}: {}", path.display(), e))
+    }
+
+    /// Adds a finished build, hashing its outputs in `data_dir`.
+    ///
+    /// # Returns
+    /// The number given to the build
+    pub fn record(&mut self, report: &BuildReport, data_dir: &Path) -> u32 {
+        let id = self.builds.last().map_or(1, |build| build.id + 1);
+        let outputs = std::iter::once(&report.plugin)
+            .chain(report.archives.iter())
+            .filter_map(|name| content_fingerprint(&data_dir.join(name)).map(|hash| (name.clone(), hash)))
+            .collect();
+
+        self.builds.push(BuildRecord {
+            id,
+            finished: timestamp(),
+            tool_version: report.tool_version.clone(),
+            plugin: report.plugin.clone(),
+            mode: report.mode.clone(),
+            stages: report.stages.clone(),
+            success: report.success,
+            error: report.error.clone(),
+            outputs,
+            run_dir: report.run_dir.clone(),
+        });
+        if self.builds.len() > MAX_BUILDS {
+            self.builds.drain(..self.builds.len() - MAX_BUILDS);
+        }
+        id
+    }
+}
+
+/// Runs the `history` command.
+///
+/// Lists the most recent builds (optionally of one plugin), or shows the
+/// details of one build when its number is given.
+pub fn run_history(args: &Args, history_args: &HistoryArgs) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+    let history = BuildHistory::load(&fallout4);
+
+    if let Some(id) = history_args.build {
+        let build = history
+            .builds
+            .iter()
+            .find(|build| build.id == id)
+            .ok_or_else(|| format!("ERROR - Build {} not found in the build history", id))?;
+        print_build(build);
+        return Ok(());
+    }
+
+    let builds: Vec<&BuildRecord> = history
+        .builds
+        .iter()
+        .rev()
+        .filter(|build| history_args.plugin.as_deref().is_none_or(|plugin| same_plugin(&build.plugin, plugin)))
+        .take(history_args.limit)
+        .collect();
+    if builds.is_empty() {
+        println!("No builds recorded yet");
+        return Ok(());
+    }
+
+    println!("{:>5}  {:<15}  {:<32}  {:<7}  {:<7}  {:>12}", "Build", "Finished", "Plugin", "Mode", "Result", "Duration");
+    for build in builds {
+        println!(
+            "{:>5}  {:<15}  {:<32}  {:<7}  {:<7}  {:>12}",
+            build.id,
+            build.finished,
+            build.plugin,
+            build.mode,
+            if build.success { "ok" } else { "failed" },
+            format_duration(build.duration())
+        );
+    }
+    Ok(())
+}
+
+/// Prints everything recorded about one build
+fn print_build(build: &BuildRecord) {
+    println!("Build {}", build.id);
+    println!("  Finished:     {}", build.finished);
+    println!("  Tool version: {}", build.tool_version);
+    println!("  Plugin:       {}", build.plugin);
+    println!("  Mode:         {}", build.mode);
+    println!("  Result:       {}", if build.success { "ok" } else { "failed" });
+    if let Some(error) = &build.error {
+        println!("  Error:        {}", error);
+    }
+    if let Some(run_dir) = &build.run_dir {
+        println!("  Run folder:   {}", run_dir.display());
+    }
+
+    println!("\n  Stages:");
+    for timing in &build.stages {
+        println!(
+            "    {:<22} {:>12}{}",
+            timing.stage,
+            format_duration(Duration::from_secs_f64(timing.seconds)),
+            if timing.success { "" } else { "  (failed)" }
+        );
+    }
+    println!("    {:<22} {:>12}", "Total", format_duration(build.duration()));
+
+    if !build.outputs.is_empty() {
+        println!("\n  Outputs:");
+        for (name, hash) in &build.outputs {
+            println!("    {:<40} {}", name, hash);
+        }
+    }
+}
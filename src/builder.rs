@@ -1,19 +1,63 @@
-use std::env;
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
-use std::thread::sleep;
-use std::time::Duration;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 use log::{info, warn};
 
-use crate::cli::{Args, BuildMode, BuildStage};
+use crate::answers::{has_answer, Prompt};
+use crate::abort::{abort_requested, clear_abort};
+use crate::backup::RunBackup;
+use crate::package::{create_release_package, write_release_notes, ReleaseFiles, ReleaseInfo};
+use crate::leftovers::{clean_leftovers, find_leftovers, Leftover};
+use crate::style;
+use crate::headless;
+use crate::title;
+use crate::console;
+use crate::logging::{self, apply_retention, prune_runs, Retention};
+use crate::events::{self, EventObserver};
+use crate::observer::{NoopObserver, ProgressObserver};
+use crate::stages::{available_in, depends_on, is_xedit_stage, resolve_prerequisites, StageContext, ALL_STAGES};
+use crate::diagnostics::{create_diagnostics_bundle, failure_excerpt};
+use crate::notify::notify;
+use crate::cache::{content_fingerprint, snapshot, BuildCache, ResumePoint, Snapshot};
+use crate::cli::{Args, ArchiveCompression, ArchiverKind, BuildMode, BuildStage, NotesFormat};
 use crate::paths::Paths;
+use crate::report::{BuildReport, OutputKind, QueueReport, StageTiming};
 use crate::tools::creation_kit::{CkpeSettings, run_creation_kit};
-use crate::tools::archive::{run_archive, extract_archive, run_bsarch, get_archive_qualifiers};
+use crate::tools::archive::{
+    PackCompression, PackRequest, is_previs_entry, verify_extraction, audit_archive, xbox_archive_name,
+    part_archive_name, existing_part_archives, split_staged_content, list_entry_names, sort_entry_names,
+    entry_path, ARCHIVE_STAGING_DIR, LOOSE_BACKUP_DIR,
+};
+use crate::tools::archiver::{create_archiver, Archiver};
+use crate::tools::ba2::{read_ba2_entries, read_ba2_records, set_ba2_version};
+use crate::tools::ck_log::{compress_log, read_ck_log};
+use crate::tools::command_line::set_tool_environment;
+use crate::game::{detect_game_build, read_exe_version, resolve_ba2_version};
+use crate::locks::{describe_io_error, wait_for_unlock};
 use crate::tools::xedit::run_xedit_script;
-use crate::ui::{prompt_for_plugin_name, prompt_for_stage, prompt_yes_no};
-use crate::validation::{verify_environment, check_plugin, directory_has_files, check_stage_prerequisites};
-use crate::utils::{remove_file_if_exists, remove_dir_all_if_exists};
+use crate::ui::{
+    prompt_after_failure, FailureAction, prompt_for_build_mode, prompt_for_masters, prompt_for_plugin_name, prompt_for_stage,
+    prompt_for_seed, prompt_light_plugin_cells, prompt_yes_no,
+};
+use crate::esp::{extension_flags, resolve_plugin_name, summarize_cells, write_plugin_header, PluginHeader};
+use crate::build_history::BuildHistory;
+use crate::history::{StageHistory, CLEANUP_STEP};
+use crate::verify_cache::DEFAULT_PROFILE;
+use crate::manifest::BuildManifest;
+use crate::validation::{
+    verify_environment_cached, check_plugin, directory_has_files, check_stage_prerequisites,
+    check_platform, check_texture_archive, check_prp, recommend_build_mode, warn_on_texture_files,
+    count_files, clear_file_counts, find_seed_plugins, light_plugin_warning, LIGHT_PLUGIN_GUIDANCE, SEED_PLUGIN,
+};
+use crate::utils::{
+    remove_file_if_exists, remove_file_with_retry, remove_dir_all_if_exists, ensure_directory_exists, move_dir_merge, copy_dir_merge,
+    format_bytes, format_duration, free_space, open_in_file_manager, path_size, timestamp,
+};
+
+/// Number of warnings in a Creation Kit stage's log above which the build
+/// warns about them
+const CK_WARNING_THRESHOLD: usize = 100;
 
 pub struct PrevisbineBuilder {
     args: Args,
@@ -22,46 +66,80 @@ pub struct PrevisbineBuilder {
     plugin_name: String,
     plugin_name_ext: String,
     plugin_archive: String,
+    /// Where the plugin was given when it's outside Data
+    plugin_source: Option<PathBuf>,
+    run_dir: PathBuf,
     logfile: PathBuf,
     unattended_logfile: PathBuf,
+    report: BuildReport,
+    ba2_version: Option<u32>,
+    archiver: Box<dyn Archiver>,
+    backup: RunBackup,
+    cache: BuildCache,
+    /// Set when an earlier build of a chain already verified the environment
+    environment_verified: bool,
+    /// Missing earlier stages to run first (--with-prerequisites)
+    prerequisite_stages: Vec<BuildStage>,
+    history: StageHistory,
+    /// Number of cells in the plugin, which stage durations scale with
+    plugin_cells: u64,
+    observer: Box<dyn ProgressObserver>,
 }
 
 impl PrevisbineBuilder {
     /// Creates a new `PrevisbineBuilder` instance
     pub fn new(args: Args) -> Result<Self, String> {
+        let archiver_kind = args.archiver_kind();
+        if args.also_xbox {
+            if args.mode == BuildMode::Xbox {
+                return Err("ERROR - --also-xbox can't be combined with xbox mode".to_string());
+            }
+            if archiver_kind != ArchiverKind::Archive2 {
+                return Err(format!("ERROR - --also-xbox requires Archive2 and can't be used with {}", archiver_kind));
+            }
+        }
+        if args.mode == BuildMode::Xbox && args.compression == ArchiveCompression::None {
+            return Err("ERROR - --compression none can't be combined with xbox mode, Xbox archives are always compressed".to_string());
+        }
+        if args.mode == BuildMode::Xbox && archiver_kind == ArchiverKind::Native {
+            return Err("ERROR - Xbox compression isn't supported by the native archiver, use Archive2".to_string());
+        }
+
         // Initialize paths
         let paths = Paths::new(
             args.fo4edit_path.clone(),
             args.fallout4_path.clone(),
-            args.use_bsarch,
+            archiver_kind == ArchiverKind::Bsarch,
             args.bsarch_path.clone(),
         )?;
 
-        // Extract plugin name
-        let (plugin_name, plugin_name_ext) = if let Some(plugin) = args.plugin.clone() {
-            let plugin_lowercase = plugin.to_lowercase();
-            if plugin_lowercase.ends_with(".esp")
-                || plugin_lowercase.ends_with(".esm")
-                || plugin_lowercase.ends_with(".esl")
-            {
-                let name = plugin.clone();
-                let name_without_ext = name
-                    .rfind('.')
-                    .map(|i| &name[0..i])
-                    .unwrap_or(&name)
-                    .to_string();
-                (name_without_ext, plugin)
-            } else {
-                (plugin.clone(), format!("{}.esp", plugin))
+        // A plugin given as a path outside Data is copied in before the build
+        let mut args = args;
+        let plugin_source = match args.plugin.as_deref().map(Path::new) {
+            Some(path) if path.is_absolute() || path.parent().is_some_and(|parent| !parent.as_os_str().is_empty()) => {
+                if !path.is_file() {
+                    return Err(format!("ERROR - Plugin {} not found", path.display()));
+                }
+                let source = path.to_path_buf();
+                args.plugin = source.file_name().map(|name| name.to_string_lossy().into_owned());
+                Some(source)
             }
-        } else {
-            (String::new(), String::new())
+            _ => None,
+        };
+        if args.copy_back && plugin_source.is_none() {
+            warn!("WARNING - --copy-back only applies when the plugin is given as a path outside Data");
+        }
+
+        // Extract plugin name
+        let (plugin_name, plugin_name_ext) = match &args.plugin {
+            Some(plugin) => resolve_plugin_name(plugin, &paths.fallout4.join("Data"), args.plugin_extension.map(|ext| ext.suffix()))?,
+            None => (String::new(), String::new()),
         };
 
-        // Setup temp and log files
-        let temp_dir = env::temp_dir();
-        let logfile = temp_dir.join(format!("{}.log", plugin_name));
-        let unattended_logfile = temp_dir.join("UnattendedScript.log");
+        // Log files live in the run folder, which is set up once the plugin is known
+        let run_dir = PathBuf::from(&args.runs_dir);
+        let logfile = run_dir.join(format!("{}.log", plugin_name));
+        let unattended_logfile = run_dir.join("UnattendedScript.log");
 
         // CKPE settings
         let ckpe_settings = CkpeSettings {
@@ -73,6 +151,13 @@ impl PrevisbineBuilder {
 
         let plugin_archive = format!("{} - Main.ba2", plugin_name);
 
+        let ba2_version = resolve_ba2_version(args.ba2_version, &paths.fallout4);
+        let backup = RunBackup::new(&paths.fallout4, &plugin_name_ext, !args.no_backup);
+        let archiver = create_archiver(archiver_kind, &paths.archive2, paths.bsarch.as_deref(), &args, ba2_version)?;
+
+        let output = if args.no_archive || args.archive_dry_run { OutputKind::Loose } else { OutputKind::Archive };
+        let report = BuildReport::new(&plugin_name_ext, &args.mode.to_string(), output);
+
         Ok(Self {
             args,
             paths,
@@ -80,93 +165,1236 @@ impl PrevisbineBuilder {
             plugin_name,
             plugin_name_ext,
             plugin_archive,
+            plugin_source,
+            run_dir,
             logfile,
             unattended_logfile,
+            report,
+            ba2_version,
+            archiver,
+            backup,
+            cache: BuildCache::default(),
+            environment_verified: false,
+            prerequisite_stages: Vec::new(),
+            history: StageHistory::default(),
+            plugin_cells: 0,
+            observer: if events::is_enabled() { Box::new(EventObserver) } else { Box::new(NoopObserver) },
         })
     }
 
+    /// Builds a base plugin and its patches (--chain) one after the other.
+    ///
+    /// Each build runs with the results of the previous ones in Data. The
+    /// environment is verified once and every build shares the same settings.
+    ///
+    /// # Returns
+    /// * `Ok(())` if every plugin was built
+    /// * `Err(String)` naming the plugin that failed; later plugins aren't built
+    pub fn run_chain(args: Args) -> Result<(), String> {
+        let plugins: Vec<String> = args.plugin.iter().chain(args.chain.iter()).cloned().collect();
+        let mut ckpe_settings = None;
+
+        for (index, plugin) in plugins.iter().enumerate() {
+            console!("\n[{}/{}] Building {}", index + 1, plugins.len(), plugin);
+
+            let mut plugin_args = args.clone();
+            plugin_args.plugin = Some(plugin.clone());
+            plugin_args.chain.clear();
+
+            let mut builder = Self::new(plugin_args)?;
+            if let Some(settings) = ckpe_settings.take() {
+                builder.ckpe_settings = settings;
+                builder.environment_verified = true;
+            }
+
+            builder
+                .run()
+                .map_err(|e| format!("{}\nChain stopped at {} ({} of {})", e, plugin, index + 1, plugins.len()))?;
+            if builder.report.aborted {
+                console!("\nChain stopped at {} ({} of {})", plugin, index + 1, plugins.len());
+                return Ok(());
+            }
+            if builder.environment_verified {
+                ckpe_settings = Some(builder.ckpe_settings.clone());
+            }
+        }
+
+        console!("\n{}", style::success(&format!("Built {} plugins: {}", plugins.len(), plugins.join(", "))));
+        Ok(())
+    }
+
+    /// Builds unrelated plugins (--queue) back to back.
+    ///
+    /// The environment is verified once. A plugin that fails doesn't stop the
+    /// queue: the working files it left in Data are cleaned up so the next
+    /// plugin starts from a clean slate, and the queue goes on. A report of
+    /// every build is written to the runs folder at the end.
+    ///
+    /// # Returns
+    /// * `Ok(())` if every plugin was built
+    /// * `Err(String)` naming the plugins that failed, or if the environment check failed
+    pub fn run_queue(args: Args) -> Result<(), String> {
+        let plugins: Vec<String> = args.plugin.iter().chain(args.queue.iter()).cloned().collect();
+        let output = if args.no_archive || args.archive_dry_run { OutputKind::Loose } else { OutputKind::Archive };
+        let mut queue_report = QueueReport::new(&args.mode.to_string());
+        let mut ckpe_settings = None;
+
+        for (index, plugin) in plugins.iter().enumerate() {
+            console!("\n[{}/{}] Building {}", index + 1, plugins.len(), plugin);
+
+            let mut plugin_args = args.clone();
+            plugin_args.plugin = Some(plugin.clone());
+            plugin_args.queue.clear();
+
+            let mut builder = match Self::new(plugin_args) {
+                Ok(builder) => builder,
+                Err(e) => {
+                    console!("\n{}", style::error(&e));
+                    let mut report = BuildReport::new(plugin, &args.mode.to_string(), output);
+                    report.error = Some(e);
+                    report.finished = true;
+                    queue_report.add(report);
+                    continue;
+                }
+            };
+            match ckpe_settings.take() {
+                Some(settings) => {
+                    builder.ckpe_settings = settings;
+                    builder.environment_verified = true;
+                }
+                None => builder.verify_environment()?,
+            }
+            ckpe_settings = Some(builder.ckpe_settings.clone());
+
+            if let Err(e) = builder.run() {
+                console!("\n{}", style::error(&e));
+                // Leave Data as the next plugin expects it
+                if let Err(e) = clean_leftovers(&find_leftovers(&builder.paths.fallout4, true), &builder.backup) {
+                    warn!("{}", e);
+                }
+            }
+            let aborted = builder.report.aborted;
+            queue_report.add(builder.report);
+            if aborted {
+                queue_report.not_built = plugins[index + 1..].to_vec();
+                break;
+            }
+        }
+
+        print_queue_summary(&queue_report);
+        let report_path = PathBuf::from(&args.runs_dir).join(format!("queue-{}-report.json", timestamp()));
+        if let Some(parent) = report_path.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        match queue_report.write(&report_path) {
+            Ok(()) => console!("Queue report written to {}", report_path.display()),
+            Err(e) => warn!("{}", e),
+        }
+
+        if queue_report.failed > 0 {
+            let failed: Vec<&str> = queue_report
+                .builds
+                .iter()
+                .filter(|report| !report.success && !report.aborted)
+                .map(|report| report.plugin.as_str())
+                .collect();
+            return Err(format!(
+                "ERROR - {} of {} plugins failed: {}",
+                failed.len(),
+                plugins.len(),
+                failed.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Verifies the environment, unless an earlier build of the same run already did
+    fn verify_environment(&mut self) -> Result<(), String> {
+        if !self.environment_verified {
+            verify_environment_cached(
+                &self.paths,
+                &mut self.ckpe_settings,
+                self.args.archiver_kind(),
+                self.args.fallout_london,
+                self.args.profile.as_deref().unwrap_or(DEFAULT_PROFILE),
+                self.args.reverify,
+            )?;
+            self.environment_verified = true;
+        }
+        Ok(())
+    }
+
+    /// Sends the build's progress events to `observer`
+    pub fn set_observer(&mut self, observer: Box<dyn ProgressObserver>) {
+        self.observer = observer;
+    }
+
     /// Main entry point to run the builder
     pub fn run(&mut self) -> Result<(), String> {
-        // Initialize log file
-        if let Ok(mut file) = File::create(&self.logfile) {
-            writeln!(
-                file,
-                "Starting Previsbine Builder for plugin {}",
-                self.plugin_name_ext
-            )
-            .map_err(|e| format!("Error writing to log file: {}", e))?;
+        title::start_build();
+        let result = self.run_build();
+
+        if !self.report.stages.is_empty() {
+            self.print_stage_timings();
+        }
+
+        if self.backup.has_entries() {
+            console!(
+                "\nFiles replaced by this build were backed up to {}\nRun `restore-backup` to undo the build.",
+                self.backup.dir().display()
+            );
+            self.report.backup_dir = Some(self.backup.dir().to_path_buf());
+        }
+
+        self.report.plugin = self.plugin_name_ext.clone();
+        self.report.success = result.is_ok() && !self.report.aborted;
+        self.report.error = result.as_ref().err().cloned();
+
+        if self.report.success && !self.args.archive_dry_run {
+            match self.write_manifest() {
+                Ok(manifest_path) => {
+                    info!("Manifest of the generated files written to {}", manifest_path.display());
+                    self.report.manifest = Some(manifest_path);
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        if self.report.success && self.args.collect_outputs {
+            if let Err(e) = self.collect_outputs() {
+                warn!("{}", e);
+            }
+        }
+
+        if self.report.success && self.args.copy_back {
+            if let Err(e) = self.copy_back_outputs() {
+                warn!("{}", e);
+            }
+        }
+
+        if self.report.success && self.args.package {
+            match self.package_release() {
+                Ok(package_path) => {
+                    console!("\nRelease package written to {}", package_path.display());
+                    if let Some(format) = self.args.release_notes {
+                        match self.release_notes(&package_path, format) {
+                            Ok(notes_path) => {
+                                console!("Upload description written to {}", notes_path.display());
+                                self.report.release_notes = Some(notes_path);
+                            }
+                            Err(e) => warn!("{}", e),
+                        }
+                    }
+                    self.report.package = Some(package_path);
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        self.report.warnings = logging::take_warnings();
+        self.print_warnings();
+
+        title::finish_build(match &result {
+            Ok(()) if self.report.aborted => "Build stopped",
+            Ok(()) => "Build complete",
+            Err(_) => "Build failed",
+        });
+        match &result {
+            Ok(()) if self.report.aborted => notify("Build stopped", &format!("{} stopped on request", self.plugin_name_ext)),
+            Ok(()) => notify("Build complete", &format!("Previsbines for {} are ready", self.plugin_name_ext)),
+            Err(e) => notify("Build failed", &format!("{}: {}", self.plugin_name_ext, e)),
         }
 
+        if !self.run_dir.exists() {
+            self.observer.build_finished(&self.report);
+            return result;
+        }
+        self.report.run_dir = Some(self.run_dir.clone());
+
+        if result.is_err() {
+            let bundle_path = self.run_dir.join(format!("{}-diagnostics.zip", self.plugin_name));
+            match create_diagnostics_bundle(
+                &bundle_path,
+                &self.run_dir,
+                &self.logfile,
+                &self.paths,
+                &self.args,
+                &self.report,
+            ) {
+                Ok(()) => {
+                    console!("\nDiagnostics saved to {}\nAttach this file when asking for help.", bundle_path.display());
+                    self.report.diagnostics = Some(bundle_path);
+                }
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        let mut builds = BuildHistory::load(&self.paths.fallout4);
+        let build_id = builds.record(&self.report, &self.paths.fallout4.join("Data"));
+        match builds.save(&self.paths.fallout4) {
+            Ok(()) => info!("Recorded as build {} in the build history", build_id),
+            Err(e) => warn!("{}", e),
+        }
+
+        self.report.finished = true;
+        let report_path = self.report_path();
+        match self.report.write(&report_path) {
+            Ok(()) => info!("Build report written to {}", report_path.display()),
+            Err(e) => warn!("{}", e),
+        }
+        self.observer.build_finished(&self.report);
+        if let Err(e) = &result {
+            logging::write_line(e);
+        }
+        logging::close_log_file();
+        if let Some(artifacts_dir) = &self.args.artifacts_dir {
+            export_artifacts(&self.run_dir, Path::new(artifacts_dir));
+        }
+
+        result
+    }
+
+    fn run_build(&mut self) -> Result<(), String> {
         // Show header
-        println!("=================================================================");
-        println!("Automatic Previsbine Builder (V2.6 Rust port Mar 2025)");
-        println!("If you use MO2 then this must be run from within MO2");
-        println!();
+        if let Some(rule) = style::rule() {
+            console!("{}", rule);
+        }
+        console!("Automatic Previsbine Builder (V2.6 Rust port Mar 2025)");
+        console!("If you use MO2 then this must be run from within MO2");
+        console!();
+
+        self.stage_plugin_source()?;
 
         // Determine starting stage
-        let start_stage = self.determine_starting_stage()?;
+        let start_stage = match self.determine_starting_stage()? {
+            ResumePoint::Stage(stage) => stage,
+            ResumePoint::UpToDate => return Ok(()),
+        };
+        self.cache = BuildCache::load(&self.paths.fallout4, &self.plugin_name_ext, &self.cache_settings());
+        let first_stage = self.prerequisite_stages.first().copied().unwrap_or(start_stage);
+        self.cache.invalidate_from(first_stage);
+
+        // Show what will run and what it touches before changing anything
+        self.history = StageHistory::load(&self.paths.fallout4);
+        self.plugin_cells = self.count_plugin_cells();
+        let stages: Vec<BuildStage> = self.prerequisite_stages.iter().copied().chain(self.planned_stages(start_stage)).collect();
+        self.print_plan(&stages);
+        check_platform(&stages, self.args.archiver_kind())?;
+        if self.args.confirm_plan && !prompt_yes_no(Prompt::ConfirmPlan, "Start the build?")? {
+            return Err("Build cancelled by user".to_string());
+        }
 
-        // Get stage as integer for comparisons
-        let start_stage_val = start_stage as i32;
+        // The plugin may only be known now if it was prompted for
+        self.backup = RunBackup::new(&self.paths.fallout4, &self.plugin_name_ext, !self.args.no_backup);
+        self.start_run_dir()?;
+        clear_abort(&self.paths.fallout4, &self.plugin_name_ext)?;
+
+        // Mirror the console into the plugin's log from here on
+        logging::open_log_file(&self.logfile)?;
+        logging::write_line(&format!("Starting Previsbine Builder for plugin {}", self.plugin_name_ext));
 
         // Verify environment
-        verify_environment(&self.paths, &mut self.ckpe_settings, &self.plugin_name, self.args.use_bsarch)?;
+        self.verify_environment()?;
         
+        // Without a plugin or seed, offer to start from an empty plugin
+        self.create_plugin_if_missing()?;
+
         // Check plugin
         check_plugin(
             &self.paths,
             &self.plugin_name_ext,
             &self.plugin_archive,
-            self.args.no_prompt,
-            |msg| prompt_yes_no(msg, self.args.no_prompt),
+            &self.args.seed_plugins,
+            &self.backup,
+            |msg| prompt_yes_no(Prompt::RenameSeed, msg),
+            |seeds| prompt_for_seed(&self.plugin_name_ext, seeds),
         )?;
 
-        // Execute stages
-        if start_stage_val <= BuildStage::GeneratePrecombines as i32 {
-            self.stage_generate_precombines()?;
+        // New cells in a light plugin make for subtly broken previs
+        if first_stage <= BuildStage::GeneratePrevis {
+            self.check_light_plugin()?;
+        }
+
+        // The merge stages rewrite the plugin in place
+        self.backup.save(&self.paths.fallout4.join("Data").join(&self.plugin_name_ext))?;
+
+        // Patches built against the Previs Repair Pack need a suitable PRP
+        if let Some(prp) = &self.args.prp {
+            check_prp(&self.paths, prp, &self.plugin_name_ext, self.args.prp_min_version.as_deref())?;
+        }
+
+        // Leftovers of an interrupted run would make the first stages fail
+        self.handle_leftovers(first_stage <= BuildStage::GeneratePrecombines)?;
+
+        // Texture archives are left alone, but flag obvious problems with them
+        check_texture_archive(&self.paths, &self.plugin_name)?;
+
+        // Execute stages, the plugin may only exist now
+        self.plugin_cells = self.count_plugin_cells();
+        self.observer.build_started(&self.plugin_name_ext, &stages);
+        for (index, stage) in stages.iter().copied().enumerate() {
+            self.observer.stage_started(stage, index, stages.len());
+            title::set_stage(stage, index, stages.len());
+            console!("\n{}", style::banner(&format!("[{}] {}", stage as i32, stage.description())));
+            self.print_estimate(&stages[index..]);
+            if self.run_stage_with_recovery(stage, &stages[index + 1..])? {
+                self.record_cache(Some(stage));
+            }
+            self.observer.progress(((index + 1) * 100 / stages.len()) as u8);
+
+            if let Some(next) = stages.get(index + 1) {
+                if abort_requested(&self.paths.fallout4, &self.plugin_name_ext) {
+                    clear_abort(&self.paths.fallout4, &self.plugin_name_ext)?;
+                    self.report.aborted = true;
+                    headless::set_aborted();
+                    console!(
+                        "\n{}",
+                        style::warning(&format!(
+                            "Build stopped after {}. Run it again to continue with {}.",
+                            stage.description(),
+                            next.description()
+                        ))
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        // Cleanup
+        let size_before = self.tracked_size();
+        self.cleanup()?;
+        self.record_disk_use(CLEANUP_STEP, size_before);
+        self.record_cache(None);
+
+        console!("\n{}", style::success("Build complete!"));
+        Ok(())
+    }
+
+    /// Prints the resolved build plan: tools, plugin, mode, stages and the
+    /// existing files the build will overwrite or delete
+    fn print_plan(&self, stages: &[BuildStage]) {
+        let archiver_kind = self.args.archiver_kind();
+        let archiver = match archiver_kind {
+            ArchiverKind::Archive2 => format!("Archive2 ({})", self.paths.archive2.display()),
+            ArchiverKind::Bsarch => format!(
+                "BSArch ({})",
+                self.paths.bsarch.as_ref().map(|path| path.display().to_string()).unwrap_or_else(|| "not found".to_string())
+            ),
+            ArchiverKind::Native => "built-in".to_string(),
+        };
+
+        console!("Build plan:");
+        console!("  Plugin:       {}", self.plugin_name_ext);
+        console!("  Mode:         {}", self.args.mode);
+        match (stages.first(), stages.last()) {
+            (Some(first), Some(last)) => console!(
+                "  Stages:       [{}] {} to [{}] {} ({} stages)",
+                *first as i32,
+                first.description(),
+                *last as i32,
+                last.description(),
+                stages.len()
+            ),
+            _ => console!("  Stages:       none"),
+        }
+        console!("  Fallout 4:    {}", self.paths.fallout4.display());
+        console!("  Creation Kit: {}", self.paths.creation_kit.display());
+        if let Some(launcher) = &self.args.ck_launcher {
+            console!("  CK launcher:  {}", launcher);
+        }
+        console!("  xEdit:        {}", self.paths.fo4edit.display());
+        console!("  Archiver:     {}", if self.args.no_archive { "none, files stay loose".to_string() } else { archiver });
+
+        let affected = self.affected_files(stages);
+        if affected.is_empty() {
+            console!("  No existing files will be overwritten or deleted");
+        } else {
+            console!(
+                "  Existing files that will be overwritten or deleted{}:",
+                if self.args.no_backup { "" } else { " (backed up first)" }
+            );
+            for file in affected {
+                console!("    Data\\{}", file);
+            }
         }
+        self.print_disk_estimate(stages);
+        console!();
+    }
 
-        if start_stage_val <= BuildStage::MergePrecombines as i32 {
-            self.stage_merge_precombines()?;
+    /// Prints how much each stage is expected to grow or shrink the build's
+    /// files, from the disk use of past builds, and warns if the drive
+    /// holding Data may run out of space
+    fn print_disk_estimate(&self, stages: &[BuildStage]) {
+        let steps: Vec<(&str, Option<i64>)> = stages
+            .iter()
+            .map(|stage| stage.description())
+            .chain(Some(CLEANUP_STEP))
+            .map(|step| (step, self.history.estimate_disk(step, self.plugin_cells)))
+            .collect();
+        if steps.iter().all(|(_, estimate)| estimate.is_none()) {
+            return;
         }
 
-        if start_stage_val <= BuildStage::ArchivePrecombines as i32 {
-            self.stage_archive_precombines()?;
+        let change = |bytes: i64| format!("{}{}", if bytes < 0 { "-" } else { "+" }, format_bytes(bytes.unsigned_abs()));
+        console!("  Disk use (estimated from past builds):");
+        let (mut total, mut peak) = (0i64, 0i64);
+        for (step, estimate) in &steps {
+            match estimate {
+                Some(bytes) => {
+                    total += bytes;
+                    peak = peak.max(total);
+                    console!("    {:<26} {}", step, change(*bytes));
+                }
+                None => console!("    {:<26} unknown", step),
+            }
         }
+        console!("    Peak {} above now, {} once the build is done", change(peak), change(total));
 
-        if self.args.mode == BuildMode::Clean {
-            if start_stage_val <= BuildStage::CompressPsg as i32 {
-                self.stage_compress_psg()?;
+        let data_dir = self.paths.fallout4.join("Data");
+        if let Some(free) = free_space(&data_dir) {
+            console!("    Free on the drive: {}", format_bytes(free));
+            if peak > 0 && peak as u64 > free {
+                warn!(
+                    "WARNING - The build may need about {} more space but only {} is free on the drive holding {}",
+                    format_bytes(peak as u64),
+                    format_bytes(free),
+                    data_dir.display()
+                );
             }
+        }
+    }
+
+    /// Number of new and overridden cells in the plugin, or 0 if it can't be read
+    fn count_plugin_cells(&self) -> u64 {
+        summarize_cells(&self.paths.fallout4.join("Data").join(&self.plugin_name_ext))
+            .map(|cells| (cells.new_cells() + cells.overridden_cells()) as u64)
+            .unwrap_or(0)
+    }
 
-            if start_stage_val <= BuildStage::BuildCdx as i32 {
-                self.stage_build_cdx()?;
+    /// Total size of the files in Data the build reads or writes
+    fn tracked_size(&self) -> u64 {
+        let data_dir = self.paths.fallout4.join("Data");
+        self.tracked_paths().iter().map(|path| path_size(&data_dir.join(path))).sum()
+    }
+
+    /// Records how much a step changed the size of the build's files
+    ///
+    /// # Arguments
+    /// * `step` - Stage description, or `CLEANUP_STEP`
+    /// * `size_before` - `tracked_size` before the step ran
+    fn record_disk_use(&mut self, step: &str, size_before: u64) {
+        if self.args.archive_dry_run {
+            return;
+        }
+        let change = self.tracked_size() as i64 - size_before as i64;
+        self.history.record_disk(step, self.plugin_cells, change);
+        if let Err(e) = self.history.save(&self.paths.fallout4) {
+            warn!("{}", e);
+        }
+    }
+
+    /// Existing files and folders in Data that running `stages` (and the
+    /// cleanup after them) will overwrite or delete
+    fn affected_files(&self, stages: &[BuildStage]) -> Vec<String> {
+        let data_dir = self.paths.fallout4.join("Data");
+        let keep = self.args.keep();
+        let mut files = Vec::new();
+        let mut add = |name: String| {
+            if data_dir.join(&name).exists() && !files.contains(&name) {
+                files.push(name);
+            }
+        };
+
+        for stage in stages {
+            match stage {
+                BuildStage::VerifyEnvironment => {}
+                BuildStage::GeneratePrecombines => {
+                    add("CombinedObjects.esp".to_string());
+                    add(format!("{} - Geometry.psg", self.plugin_name));
+                }
+                BuildStage::MergePrecombines | BuildStage::MergePrevis => add(self.plugin_name_ext.clone()),
+                BuildStage::ArchivePrecombines | BuildStage::ArchiveVis if !self.args.no_archive => {
+                    for archive in existing_part_archives(&data_dir, &self.plugin_name) {
+                        add(archive);
+                    }
+                }
+                BuildStage::ArchivePrecombines | BuildStage::ArchiveVis => {}
+                BuildStage::CompressPsg => {
+                    add(format!("{} - Geometry.csg", self.plugin_name));
+                    if !keep.psg {
+                        add(format!("{} - Geometry.psg", self.plugin_name));
+                    }
+                }
+                BuildStage::BuildCdx => add(format!("{}.cdx", self.plugin_name)),
+                BuildStage::GeneratePrevis => add("Previs.esp".to_string()),
             }
         }
 
-        if start_stage_val <= BuildStage::GeneratePrevis as i32 {
-            self.stage_generate_previs()?;
+        if !keep.working_esps {
+            add("CombinedObjects.esp".to_string());
+            add("Previs.esp".to_string());
         }
+        if !keep.vis_loose && !self.args.no_archive && !self.args.archive_dry_run {
+            add("vis".to_string());
+        }
+        files
+    }
 
-        if start_stage_val <= BuildStage::MergePrevis as i32 {
-            self.stage_merge_previs()?;
+    /// Returns the stages to run, in order, starting at `start_stage`
+    fn planned_stages(&self, start_stage: BuildStage) -> Vec<BuildStage> {
+        ALL_STAGES
+            .into_iter()
+            .filter(|stage| *stage >= start_stage)
+            .filter(|stage| self.args.only.is_none_or(|only| only == *stage as i32))
+            .filter(|stage| self.stage_enabled(*stage))
+            .collect()
+    }
+
+    /// Whether a stage runs with the current mode and options
+    fn stage_enabled(&self, stage: BuildStage) -> bool {
+        match stage {
+            BuildStage::ArchivePrecombines => !self.args.no_archive,
+            _ => available_in(stage, self.args.mode),
         }
+    }
 
-        if start_stage_val <= BuildStage::ArchiveVis as i32 {
-            self.stage_archive_vis()?;
+    /// Runs a single stage, retrying it as configured with --retry, and
+    /// records how long it took
+    fn run_stage(&mut self, stage: BuildStage) -> Result<(), String> {
+        let started = Instant::now();
+        clear_file_counts();
+        // Only retry if a failed attempt's output can be removed without
+        // touching files that were there before the stage ran
+        let output_dirs = self.stage_output_dirs(stage);
+        let retries = if output_dirs.iter().any(|(dir, ext)| directory_has_files(dir, ext)) {
+            0
+        } else {
+            self.args.retries_for(stage)
+        };
+        let size_before = self.tracked_size();
+        set_tool_environment(self.args.tool_env_for(stage));
+        let mut attempt = 0;
+        let result = loop {
+            let result = self.execute_stage(stage);
+            self.collect_stage_logs(stage);
+            match result {
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    let delay = Duration::from_secs(self.args.retry_delay.saturating_mul(1 << (attempt - 1).min(16)));
+                    warn!("{}", e);
+                    console!(
+                        "{}",
+                        style::warning(&format!(
+                            "{} failed, retrying in {} (retry {} of {})",
+                            stage.description(),
+                            format_duration(delay),
+                            attempt,
+                            retries
+                        ))
+                    );
+                    thread::sleep(delay);
+                    for (dir, _) in &output_dirs {
+                        remove_dir_all_if_exists(dir)?;
+                    }
+                }
+                result => break result,
+            }
+        };
+        set_tool_environment(Vec::new());
+        if result.is_err() {
+            self.print_failure_excerpt(stage);
         }
 
-        // Cleanup
-        self.cleanup()?;
+        self.report.stages.push(StageTiming {
+            stage: stage.description().to_string(),
+            seconds: started.elapsed().as_secs_f64(),
+            success: result.is_ok(),
+        });
+        if result.is_ok() && events::is_enabled() {
+            self.emit_stage_outputs(stage);
+        }
+        self.observer.stage_finished(stage, started.elapsed(), result.as_ref().map(|_| ()).map_err(String::as_str));
+
+        if result.is_ok() && !self.args.archive_dry_run {
+            self.history.record(stage, self.plugin_cells, started.elapsed());
+            self.record_disk_use(stage.description(), size_before);
+        }
+        self.checkpoint();
+        result
+    }
+
+    /// Saves how far the build got after a stage: the log file is synced and
+    /// the report rewritten, so a crash (e.g. the CK taking the system down)
+    /// leaves both showing the last finished stage
+    fn checkpoint(&mut self) {
+        logging::sync_log_file();
+        if !self.run_dir.exists() {
+            return;
+        }
+        self.report.plugin = self.plugin_name_ext.clone();
+        self.report.run_dir = Some(self.run_dir.clone());
+        self.report.warnings = logging::peek_warnings();
+        if let Err(e) = self.report.write(self.report_path()) {
+            warn!("{}", e);
+        }
+    }
+
+    fn report_path(&self) -> PathBuf {
+        self.run_dir.join(format!("{}-report.json", self.plugin_name))
+    }
+
+    /// Runs a stage, and when it fails in an interactive build, asks whether
+    /// to retry it, open the logs, skip it (if no later stage needs it) or
+    /// abort, cleaning up the working files.
+    ///
+    /// # Arguments
+    /// * `stage` - The stage to run
+    /// * `later` - The stages still to run after it
+    ///
+    /// # Returns
+    /// * `Ok(true)` if the stage succeeded
+    /// * `Ok(false)` if it failed and was skipped
+    /// * `Err(String)` with the stage's error if the build stops
+    fn run_stage_with_recovery(&mut self, stage: BuildStage, later: &[BuildStage]) -> Result<bool, String> {
+        let interactive = has_answer(Prompt::StageFailed) || !headless::is_non_interactive();
+        let output_dirs = self.stage_output_dirs(stage);
+        let outputs_were_empty = !output_dirs.iter().any(|(dir, ext)| directory_has_files(dir, ext));
+        let can_skip = !later.iter().any(|next| depends_on(*next, stage));
+
+        loop {
+            let error = match self.run_stage(stage) {
+                Ok(()) => return Ok(true),
+                Err(e) if interactive => e,
+                Err(e) => return Err(e),
+            };
+            console!("\n{}", style::warning(&format!("{} failed: {}", stage.description(), error)));
+
+            loop {
+                match prompt_after_failure(stage, can_skip)? {
+                    FailureAction::Retry => break,
+                    FailureAction::OpenLogs => {
+                        if let Err(e) = open_in_file_manager(&self.run_dir) {
+                            warn!("{}", e);
+                        }
+                    }
+                    FailureAction::Skip => {
+                        warn!("WARNING - {} failed and was skipped: {}", stage.description(), error);
+                        return Ok(false);
+                    }
+                    FailureAction::Abort => {
+                        clean_leftovers(&find_leftovers(&self.paths.fallout4, true), &self.backup)?;
+                        return Err(error);
+                    }
+                }
+            }
+
+            // Start the retry from the same state as the first attempt
+            if outputs_were_empty {
+                for (dir, _) in &output_dirs {
+                    remove_dir_all_if_exists(dir)?;
+                }
+            }
+        }
+    }
+
+    /// Folders (and the file extension) a stage generates into, which must be
+    /// empty when it starts
+    fn stage_output_dirs(&self, stage: BuildStage) -> Vec<(PathBuf, &'static str)> {
+        let data_dir = self.paths.fallout4.join("Data");
+        match stage {
+            BuildStage::GeneratePrecombines => vec![(data_dir.join("meshes").join("precombined"), ".nif")],
+            BuildStage::GeneratePrevis => vec![(data_dir.join("vis"), ".uvd")],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reports the files and folders a stage produced as events
+    fn emit_stage_outputs(&self, stage: BuildStage) {
+        let data_dir = self.paths.fallout4.join("Data");
+        let files = match stage {
+            BuildStage::GeneratePrecombines => {
+                vec!["CombinedObjects.esp".to_string(), format!("{} - Geometry.psg", self.plugin_name)]
+            }
+            BuildStage::MergePrecombines | BuildStage::MergePrevis => vec![self.plugin_name_ext.clone()],
+            BuildStage::ArchivePrecombines => vec![self.plugin_archive.clone()],
+            BuildStage::CompressPsg => vec![format!("{} - Geometry.csg", self.plugin_name)],
+            BuildStage::BuildCdx => vec![format!("{}.cdx", self.plugin_name)],
+            BuildStage::GeneratePrevis => vec!["Previs.esp".to_string()],
+            BuildStage::ArchiveVis => self.report.archives.clone(),
+            BuildStage::VerifyEnvironment => Vec::new(),
+        };
+        for file in files {
+            let path = data_dir.join(file);
+            if path.is_file() {
+                events::emit_file(stage, &path);
+            }
+        }
+        for (dir, extension) in self.stage_output_dirs(stage) {
+            events::emit_folder(stage, &dir, count_files(&dir, &[extension])[0]);
+        }
+    }
+
+    /// Prints how long the next stage and the rest of the build should take,
+    /// based on earlier builds
+    fn print_estimate(&self, remaining: &[BuildStage]) {
+        let Some(stage) = remaining.first() else {
+            return;
+        };
+        let Some(estimate) = self.history.estimate(*stage, self.plugin_cells) else {
+            return;
+        };
+
+        let total: Option<Duration> = remaining
+            .iter()
+            .map(|stage| self.history.estimate(*stage, self.plugin_cells))
+            .sum();
+        match total {
+            Some(total) if remaining.len() > 1 => console!(
+                "{}: about {} (about {} for the rest of the build)",
+                stage.description(),
+                format_duration(estimate),
+                format_duration(total)
+            ),
+            _ => console!("{}: about {}", stage.description(), format_duration(estimate)),
+        }
+    }
+
+    fn execute_stage(&mut self, stage: BuildStage) -> Result<(), String> {
+        match stage {
+            BuildStage::VerifyEnvironment => Ok(()),
+            BuildStage::GeneratePrecombines => self.stage_generate_precombines(),
+            BuildStage::MergePrecombines => self.stage_merge_precombines(),
+            BuildStage::ArchivePrecombines => self.stage_archive_precombines(),
+            BuildStage::CompressPsg => self.stage_compress_psg(),
+            BuildStage::BuildCdx => self.stage_build_cdx(),
+            BuildStage::GeneratePrevis => self.stage_generate_previs(),
+            BuildStage::MergePrevis => self.stage_merge_previs(),
+            BuildStage::ArchiveVis if self.args.no_archive => self.export_loose_files(),
+            BuildStage::ArchiveVis => {
+                let archives = self.stage_archive_vis()?;
+                self.report.archives.extend(archives);
+                if self.args.keep_loose {
+                    self.report.loose_backup_dir = Some(self.loose_backup_dir());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates an empty plugin when neither the plugin nor the xPrevisPatch.esp
+    /// seed exists, with the masters from --seed-master or asked for.
+    ///
+    /// The PRP plugin (--prp) is added as a master, as patches built against
+    /// PRP need it. An .esm or .esl plugin gets the master and light flags.
+    fn create_plugin_if_missing(&self) -> Result<(), String> {
+        let data_dir = self.paths.fallout4.join("Data");
+        let plugin_path = data_dir.join(&self.plugin_name_ext);
+        if plugin_path.exists() || !find_seed_plugins(&data_dir, &self.args.seed_plugins).is_empty() {
+            return Ok(());
+        }
+
+        let create = self.args.create_plugin
+            || prompt_yes_no(
+                Prompt::CreatePlugin,
+                &format!("{} and {} don't exist, create an empty plugin?", self.plugin_name_ext, SEED_PLUGIN),
+            )?;
+        if !create {
+            return Ok(());
+        }
+
+        let requested = if self.args.seed_masters.is_empty() && !self.args.create_plugin {
+            prompt_for_masters()?
+        } else {
+            self.args.seed_masters.clone()
+        };
 
-        println!("\nBuild complete!");
+        let mut masters = vec!["Fallout4.esm".to_string()];
+        for master in requested.iter().chain(self.args.prp.iter()) {
+            if !masters.iter().any(|existing| existing.eq_ignore_ascii_case(master)) {
+                masters.push(master.clone());
+            }
+        }
+        if let Some(missing) = masters.iter().find(|master| !data_dir.join(master).exists()) {
+            return Err(format!("ERROR - Master {} not found in Data", missing));
+        }
+
+        let mut header = PluginHeader::new_plugin(&masters);
+        header.flags = extension_flags(&self.plugin_name_ext);
+        write_plugin_header(&plugin_path, &header)?;
+        console!("Created {} with masters {}", self.plugin_name_ext, masters.join(", "));
+        Ok(())
+    }
+
+    /// Warns when the plugin is a light plugin adding new cells and asks
+    /// whether to go on
+    fn check_light_plugin(&self) -> Result<(), String> {
+        let plugin_path = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
+        let Some(warning) = light_plugin_warning(&plugin_path)? else {
+            return Ok(());
+        };
+        warn!("{}", warning);
+        if prompt_light_plugin_cells(LIGHT_PLUGIN_GUIDANCE)? {
+            Ok(())
+        } else {
+            Err(format!("Aborted by user. {}", LIGHT_PLUGIN_GUIDANCE))
+        }
+    }
+
+    /// Plugins xEdit loads ahead of the plugin being built: PRP first, then --master plugins
+    fn xedit_masters(&self) -> Vec<String> {
+        self.args.prp.iter().chain(self.args.masters.iter()).cloned().collect()
+    }
+
+    /// Offers to clean up what a previous failed run left behind.
+    ///
+    /// Leftovers are removed without asking with --auto-clean. Otherwise the
+    /// user is asked, and a fresh build can't start until they are gone.
+    fn handle_leftovers(&self, fresh_build: bool) -> Result<(), String> {
+        let leftovers = find_leftovers(&self.paths.fallout4, fresh_build);
+        if leftovers.is_empty() {
+            return Ok(());
+        }
+
+        console!("Found leftovers from a previous run:");
+        for leftover in &leftovers {
+            console!("  {}", leftover);
+        }
+
+        let clean = self.args.auto_clean || prompt_yes_no(Prompt::CleanLeftovers, "Clean them up now?")?;
+        if clean {
+            return clean_leftovers(&leftovers, &self.backup);
+        }
+
+        if leftovers.iter().all(|leftover| matches!(leftover, Leftover::DisabledDll { .. })) {
+            warn!("WARNING - Disabled DLLs left in place, ENB/ReShade may not load");
+            return Ok(());
+        }
+        Err("ERROR - Leftovers from a previous run must be removed first, remove them or use --auto-clean".to_string())
+    }
+
+    /// Creates this build's `<plugin>-<timestamp>` folder under the runs folder
+    /// and points the log files at it, removing the plugin's oldest run
+    /// folders beyond --keep-runs
+    fn start_run_dir(&mut self) -> Result<(), String> {
+        info!("Run folders are kept in {}", self.args.runs_dir);
+        // Leave room for the folder about to be created
+        match prune_runs(Path::new(&self.args.runs_dir), &self.plugin_name, self.args.keep_runs.saturating_sub(1)) {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} old run folders of {}", removed, self.plugin_name),
+            Err(e) => warn!("{}", e),
+        }
+        if let Some(days) = self.args.keep_days {
+            let retention = Retention { keep_days: Some(days), ..Retention::default() };
+            match apply_retention(Path::new(&self.args.runs_dir), &retention) {
+                Ok(removed) if removed.is_empty() => {}
+                Ok(removed) => info!("Removed {} run folders older than {} days", removed.len(), days),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        self.run_dir = PathBuf::from(&self.args.runs_dir)
+            .join(format!("{}-{}", self.plugin_name, timestamp()));
+        ensure_directory_exists(&self.run_dir)?;
+
+        self.logfile = self.run_dir.join(format!("{}.log", self.plugin_name));
+        self.unattended_logfile = self.run_dir.join("UnattendedScript.log");
+        info!("Logs for this build are in {}", self.run_dir.display());
+
+        if let Some(artifacts_dir) = self.args.artifacts_dir.clone() {
+            let run_dir = self.run_dir.clone();
+            headless::on_timeout(move || {
+                logging::close_log_file();
+                export_artifacts(&run_dir, Path::new(&artifacts_dir));
+            });
+        }
         Ok(())
     }
 
-    fn determine_starting_stage(&mut self) -> Result<BuildStage, String> {
-        if let Some(stage) = self.args.start_stage {
+    /// The Creation Kit or xEdit log a stage writes, if it ran a tool and the log exists
+    fn stage_tool_log(&self, stage: BuildStage) -> Option<PathBuf> {
+        let source = match stage {
+            BuildStage::GeneratePrecombines
+            | BuildStage::CompressPsg
+            | BuildStage::BuildCdx
+            | BuildStage::GeneratePrevis => self.ckpe_settings.log_file.clone(),
+            BuildStage::MergePrecombines | BuildStage::MergePrevis => Some(self.unattended_logfile.clone()),
+            _ => None,
+        };
+        source.filter(|path| path.exists())
+    }
+
+    /// Prints the part of the stage's tool log showing why it failed, so it
+    /// doesn't have to be looked up
+    fn print_failure_excerpt(&self, stage: BuildStage) {
+        let Some(log) = self.stage_tool_log(stage) else {
+            return;
+        };
+        let excerpt: Vec<String> = if is_xedit_stage(stage) {
+            let Ok(content) = fs::read(&log) else {
+                return;
+            };
+            failure_excerpt(&String::from_utf8_lossy(&content)).into_iter().map(str::to_string).collect()
+        } else {
+            let Ok(ck_log) = read_ck_log(&log) else {
+                return;
+            };
+            ck_log.failure_excerpt().to_vec()
+        };
+        if excerpt.is_empty() {
+            return;
+        }
+
+        console!("\nFrom {}:", log.display());
+        for line in excerpt {
+            console!("  {}", line);
+        }
+    }
+
+    /// Keeps a copy of the Creation Kit or xEdit log a stage produced, as
+    /// both are overwritten by the next stage using the same tool. CK logs
+    /// can run to hundreds of MB, so they are kept gzip-compressed and only
+    /// their start, end and error contexts are passed on to the observer.
+    fn collect_stage_logs(&self, stage: BuildStage) {
+        let Some(source) = self.stage_tool_log(stage) else {
+            return;
+        };
+
+        let file_name = |tool: &str| format!("{}-{}-{}", stage as i32, stage.description().replace(' ', ""), tool);
+        if is_xedit_stage(stage) {
+            let target = self.run_dir.join(format!("{}.log", file_name("xEdit")));
+            if let Err(e) = fs::copy(&source, &target) {
+                warn!("Could not copy {} to {}: {}", source.display(), target.display(), e);
+            }
+            if let Ok(content) = fs::read(&source) {
+                for line in String::from_utf8_lossy(&content).lines() {
+                    self.observer.log_line(stage, line);
+                }
+            }
+            return;
+        }
+
+        let target = self.run_dir.join(format!("{}.log.gz", file_name("CreationKit")));
+        if let Err(e) = compress_log(&source, &target) {
+            warn!("{}", e);
+        }
+        match read_ck_log(&source) {
+            Ok(ck_log) => {
+                for line in ck_log.capped_lines() {
+                    self.observer.log_line(stage, &line);
+                }
+                if ck_log.warnings > CK_WARNING_THRESHOLD {
+                    warn!(
+                        "WARNING - The Creation Kit logged {} warnings during {}, see {}",
+                        ck_log.warnings,
+                        stage.description(),
+                        target.display()
+                    );
+                }
+            }
+            Err(e) => warn!("{}", e),
+        }
+
+        // The CK deletes its log on every run, so keep one per stage next to it
+        if self.args.keep().ck_logs {
+            let stem = source.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let kept = source.with_file_name(format!("{}-{}.log", stem, stage.description().replace(' ', "")));
+            if let Err(e) = fs::copy(&source, &kept) {
+                warn!("Could not copy {} to {}: {}", source.display(), kept.display(), e);
+            }
+        }
+    }
+
+    /// Writes the manifest of the files the build produced (the plugin, its
+    /// archives and what they hold, the CSG and CDX, and any loose
+    /// precombined and vis files) to the run folder
+    fn write_manifest(&self) -> Result<PathBuf, String> {
+        let data_dir = self.paths.fallout4.join("Data");
+        let mut manifest = BuildManifest::new(&self.plugin_name_ext);
+
+        for name in [
+            self.plugin_name_ext.clone(),
+            format!("{} - Geometry.csg", self.plugin_name),
+            format!("{}.cdx", self.plugin_name),
+        ] {
+            let path = data_dir.join(&name);
+            if path.is_file() {
+                manifest.add_file(&name, &path)?;
+            }
+        }
+        // Archives of earlier builds' stages are outputs too when only some stages ran
+        let mut archives = self.report.archives.clone();
+        for archive in existing_part_archives(&data_dir, &self.plugin_name) {
+            if !archives.contains(&archive) {
+                archives.push(archive);
+            }
+        }
+        for archive in &archives {
+            manifest.add_archive(archive, &data_dir.join(archive))?;
+        }
+
+        let loose_root = self.report.loose_output_dir.clone().unwrap_or(data_dir);
+        for folder in ["meshes\\precombined", "vis"] {
+            manifest.add_folder(&loose_root, folder)?;
+        }
+
+        let manifest_path = self.run_dir.join(format!("{}-manifest.json", self.plugin_name));
+        manifest.write(&manifest_path)?;
+        Ok(manifest_path)
+    }
+
+    /// Copies the finished plugin and its archives into the run folder (--collect-outputs)
+    fn collect_outputs(&self) -> Result<(), String> {
+        let data_dir = self.paths.fallout4.join("Data");
+        let output_dir = self.run_dir.join("output");
+        ensure_directory_exists(&output_dir)?;
+
+        for file_name in std::iter::once(&self.plugin_name_ext).chain(self.report.archives.iter()) {
+            let source = data_dir.join(file_name);
+            if source.exists() {
+                fs::copy(&source, output_dir.join(file_name))
+                    .map_err(|e| format!("Error copying {} to the run folder: {}", file_name, e))?;
+            }
+        }
+
+        info!("Copied the plugin and archives to {}", output_dir.display());
+        Ok(())
+    }
+
+    /// Copies a plugin given as a path outside Data into Data, asking first
+    /// if that replaces a different plugin of the same name
+    fn stage_plugin_source(&self) -> Result<(), String> {
+        let Some(source) = &self.plugin_source else {
+            return Ok(());
+        };
+        let target = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
+        if target.exists() && content_fingerprint(&target) == content_fingerprint(source) {
+            info!("{} in Data is already the same as {}", self.plugin_name_ext, source.display());
+            return Ok(());
+        }
+
+        let message = if target.exists() {
+            format!("Copy {} into Data, replacing the {} there?", source.display(), self.plugin_name_ext)
+        } else {
+            format!("Copy {} into Data to build it?", source.display())
+        };
+        if !prompt_yes_no(Prompt::StagePlugin, &message)? {
+            return Err(format!("ERROR - {} must be in Data to be built", self.plugin_name_ext));
+        }
+
+        fs::copy(source, &target).map_err(|e| format!("Error copying {} into Data: {}", source.display(), e))?;
+        console!("Copied {} into Data", source.display());
+        Ok(())
+    }
+
+    /// Copies the finished plugin and archives back next to the plugin given
+    /// as a path outside Data (--copy-back)
+    fn copy_back_outputs(&self) -> Result<(), String> {
+        let Some(source_dir) = self.plugin_source.as_deref().and_then(Path::parent) else {
+            return Ok(());
+        };
+        let data_dir = self.paths.fallout4.join("Data");
+
+        for file_name in std::iter::once(&self.plugin_name_ext).chain(self.report.archives.iter()) {
+            let source = data_dir.join(file_name);
+            if source.exists() {
+                fs::copy(&source, source_dir.join(file_name))
+                    .map_err(|e| format!("Error copying {} back to {}: {}", file_name, source_dir.display(), e))?;
+            }
+        }
+
+        console!("Copied the plugin and archives back to {}", source_dir.display());
+        Ok(())
+    }
+
+    /// Packages the plugin and its archives into a zip in the run folder (--package)
+    fn package_release(&self) -> Result<PathBuf, String> {
+        create_release_package(
+            &self.paths.fallout4.join("Data"),
+            &self.plugin_name,
+            &self.plugin_name_ext,
+            self.args.package_version.as_deref(),
+            self.args.readme_template.as_deref().map(Path::new),
+            &self.run_dir,
+        )
+    }
+
+    /// Writes the upload description for the package (--release-notes)
+    fn release_notes(&self, package_path: &Path, format: NotesFormat) -> Result<PathBuf, String> {
+        let data_dir = self.paths.fallout4.join("Data");
+        let version_of = |path: &Path| read_exe_version(path).map(|version| version.to_string());
+        let game_version = version_of(&self.paths.fallout4.join("Fallout4.exe")).map(|version| {
+            match detect_game_build(&self.paths.fallout4) {
+                Some(build) => format!("{} ({})", version, build),
+                None => version,
+            }
+        });
+        let info = ReleaseInfo {
+            files: ReleaseFiles::collect(&data_dir, &self.plugin_name, &self.plugin_name_ext),
+            version: self.args.package_version.clone(),
+            mode: self.report.mode.clone(),
+            game_version,
+            creation_kit_version: version_of(&self.paths.creation_kit),
+            ckpe_version: version_of(&self.paths.fallout4.join("winhttp.dll")),
+            cells: summarize_cells(&data_dir.join(&self.plugin_name_ext)).ok(),
+        };
+        write_release_notes(package_path, &info, format)
+    }
+
+    /// Prints the warnings raised during the build again, so they don't
+    /// scroll away
+    fn print_warnings(&self) {
+        if self.report.warnings.is_empty() {
+            return;
+        }
+        console!("\n{}", style::warning("Things you should check:"));
+        for warning in &self.report.warnings {
+            console!("  - {}", warning);
+        }
+    }
+
+    /// Prints how long each stage took
+    fn print_stage_timings(&self) {
+        console!("\nStage timings:");
+        let mut total = 0.0;
+        for timing in &self.report.stages {
+            total += timing.seconds;
+            console!(
+                "  {:<22} {:>12}{}",
+                timing.stage,
+                format_duration(Duration::from_secs_f64(timing.seconds)),
+                if timing.success { String::new() } else { format!("  {}", style::warning("(failed)")) }
+            );
+        }
+        console!("  {:<22} {:>12}", "Total", format_duration(Duration::from_secs_f64(total)));
+    }
+
+    fn determine_starting_stage(&mut self) -> Result<ResumePoint, String> {
+        if let Some(stage) = self.args.start_stage.or(self.args.only) {
             match BuildStage::from_i32(stage) {
+                Some(stage) if self.args.with_prerequisites => {
+                    self.check_build_mode()?;
+
+                    // Run whatever earlier stages are missing first
+                    let context = StageContext {
+                        data_dir: self.paths.fallout4.join("Data"),
+                        plugin_name_ext: &self.plugin_name_ext,
+                        plugin_name: &self.plugin_name,
+                        build_mode: self.args.mode,
+                        has_files: &directory_has_files,
+                    };
+                    let prerequisites: Vec<BuildStage> = resolve_prerequisites(stage, &context)?
+                        .into_iter()
+                        .filter(|prerequisite| self.stage_enabled(*prerequisite))
+                        .collect();
+                    if !prerequisites.is_empty() {
+                        let names: Vec<&str> = prerequisites.iter().map(|stage| stage.description()).collect();
+                        console!("Running missing prerequisites first: {}", names.join(", "));
+                    }
+                    self.prerequisite_stages = prerequisites;
+                    Ok(ResumePoint::Stage(stage))
+                }
                 Some(stage) => {
+                    self.check_build_mode()?;
+
                     // Check prerequisites for this stage
                     check_stage_prerequisites(
                         stage,
@@ -176,7 +1404,7 @@ impl PrevisbineBuilder {
                         &self.args.mode,
                         directory_has_files,
                     )?;
-                    Ok(stage)
+                    Ok(ResumePoint::Stage(stage))
                 }
                 None => {
                     Err(format!("ERROR - Invalid stage number: {}", stage))
@@ -184,28 +1412,182 @@ impl PrevisbineBuilder {
             }
         } else if self.plugin_name.is_empty() {
             // No plugin specified on command line
-            let (plugin_name, plugin_name_ext, plugin_archive) = prompt_for_plugin_name()?;
+            let (plugin_name, plugin_name_ext, plugin_archive) = prompt_for_plugin_name(
+                &self.paths.fallout4.join("Data"),
+                self.args.plugin_extension.map(|ext| ext.suffix()),
+            )?;
             self.plugin_name = plugin_name;
             self.plugin_name_ext = plugin_name_ext;
             self.plugin_archive = plugin_archive;
             
-            let plugin_path = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
-            if plugin_path.exists() {
-                // Plugin already exists, prompt for stage
-                prompt_for_stage(&self.args.mode)
-            } else {
-                Ok(BuildStage::VerifyEnvironment)
-            }
+            self.check_build_mode()?;
+            self.resume_or_prompt()
         } else {
             // Plugin specified but check if it already exists
-            let plugin_path = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
-            if plugin_path.exists() {
-                // Plugin already exists, prompt for stage
-                prompt_for_stage(&self.args.mode)
-            } else {
-                Ok(BuildStage::VerifyEnvironment)
+            self.check_build_mode()?;
+            self.resume_or_prompt()
+        }
+    }
+
+    /// Compares the build mode with the one recommended for the plugin's
+    /// content, and switches to it with --auto-mode. Without --mode, asks
+    /// which mode to use when another than the default suits the plugin.
+    fn check_build_mode(&mut self) -> Result<(), String> {
+        if matches!(self.args.mode, BuildMode::Xbox | BuildMode::PrevisOnly) {
+            return Ok(());
+        }
+        let plugin_path = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
+        if !plugin_path.exists() {
+            return Ok(());
+        }
+
+        let recommendation = match recommend_build_mode(&plugin_path) {
+            Ok(Some(recommendation)) => recommendation,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                warn!("WARNING - Couldn't analyze {} to recommend a build mode: {}", self.plugin_name_ext, e);
+                return Ok(());
+            }
+        };
+        let reasons = recommendation.reasons.join(", ");
+
+        if self.args.auto_mode {
+            console!("Using {} mode for {}: {}", recommendation.mode, self.plugin_name_ext, reasons);
+            self.args.mode = recommendation.mode;
+            self.report.mode = recommendation.mode.to_string();
+        } else if recommendation.mode != self.args.mode
+            && !self.args.mode_given
+            && (!headless::is_non_interactive() || has_answer(Prompt::BuildMode))
+        {
+            let mode = prompt_for_build_mode(&self.plugin_name_ext, &recommendation)?;
+            self.args.mode = mode;
+            self.report.mode = mode.to_string();
+        } else if recommendation.mode != self.args.mode {
+            warn!(
+                "WARNING - {} mode is recommended for {} as {}. Use --mode {} or --auto-mode to switch",
+                recommendation.mode, self.plugin_name_ext, reasons, recommendation.mode
+            );
+        }
+        Ok(())
+    }
+
+    /// For an existing plugin, resumes after the last stage whose results are
+    /// still in place, or asks for the stage to start from. Previs-only builds
+    /// start at Generate Previs once the plugin's precombines are found.
+    fn resume_or_prompt(&self) -> Result<ResumePoint, String> {
+        if self.args.mode == BuildMode::PrevisOnly {
+            check_stage_prerequisites(
+                BuildStage::GeneratePrevis,
+                &self.paths,
+                &self.plugin_name_ext,
+                &self.plugin_name,
+                &self.args.mode,
+                directory_has_files,
+            )?;
+        }
+        let plugin_path = self.paths.fallout4.join("Data").join(&self.plugin_name_ext);
+        if !plugin_path.exists() {
+            return Ok(ResumePoint::Stage(BuildStage::VerifyEnvironment));
+        }
+
+        if !self.args.force {
+            let cache = BuildCache::load(&self.paths.fallout4, &self.plugin_name_ext, &self.cache_settings());
+            let planned = self.planned_stages(BuildStage::VerifyEnvironment);
+            match cache.resume_point(&self.data_snapshot(), &planned) {
+                Some(ResumePoint::UpToDate) => {
+                    console!(
+                        "{} is unchanged since its last build, nothing to do (use --force to rebuild)",
+                        self.plugin_name_ext
+                    );
+                    return Ok(ResumePoint::UpToDate);
+                }
+                Some(ResumePoint::Stage(stage)) => {
+                    console!(
+                        "Earlier stages are unchanged since the last build, resuming at [{}] {} (use --force to rebuild everything)",
+                        stage as i32,
+                        stage.description()
+                    );
+                    return Ok(ResumePoint::Stage(stage));
+                }
+                None => {}
             }
         }
+
+        if self.args.mode == BuildMode::PrevisOnly {
+            return Ok(ResumePoint::Stage(BuildStage::GeneratePrevis));
+        }
+
+        // Plugin already exists, prompt for stage
+        let context = StageContext {
+            data_dir: self.paths.fallout4.join("Data"),
+            plugin_name_ext: &self.plugin_name_ext,
+            plugin_name: &self.plugin_name,
+            build_mode: self.args.mode,
+            has_files: &directory_has_files,
+        };
+        prompt_for_stage(&context).map(ResumePoint::Stage)
+    }
+
+    /// Settings that change what the stages produce; cached results are only
+    /// reused by builds with the same settings
+    fn cache_settings(&self) -> String {
+        format!(
+            "mode={:?};archiver={};compression={};ba2={:?};no_archive={};also_xbox={};reproducible={};masters={:?}",
+            self.args.mode,
+            self.args.archiver_kind(),
+            self.args.compression,
+            self.ba2_version,
+            self.args.no_archive,
+            self.args.also_xbox,
+            self.args.reproducible,
+            self.xedit_masters()
+        )
+    }
+
+    /// Files and folders in Data the stages read or write
+    fn tracked_paths(&self) -> Vec<String> {
+        let mut paths = vec![
+            self.plugin_name_ext.clone(),
+            "CombinedObjects.esp".to_string(),
+            "Previs.esp".to_string(),
+            format!("{} - Geometry.psg", self.plugin_name),
+            format!("{} - Geometry.csg", self.plugin_name),
+            format!("{}.cdx", self.plugin_name),
+            "meshes/precombined".to_string(),
+            "vis".to_string(),
+        ];
+
+        // Every main archive part, including Xbox copies
+        let archive_prefix = format!("{} - main", self.plugin_name.to_lowercase());
+        if let Ok(entries) = fs::read_dir(self.paths.fallout4.join("Data")) {
+            paths.extend(entries.flatten().filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let lowercase = name.to_lowercase();
+                (lowercase.starts_with(&archive_prefix) && lowercase.ends_with(".ba2")).then_some(name)
+            }));
+        }
+        paths
+    }
+
+    fn data_snapshot(&self) -> Snapshot {
+        snapshot(&self.paths.fallout4.join("Data"), &self.tracked_paths())
+    }
+
+    /// Records the state after a stage (or after cleanup, when `stage` is
+    /// `None`) in the build cache
+    fn record_cache(&mut self, stage: Option<BuildStage>) {
+        if self.args.archive_dry_run {
+            return;
+        }
+
+        let state = self.data_snapshot();
+        match stage {
+            Some(stage) => self.cache.record_stage(stage, state),
+            None => self.cache.complete = Some(state),
+        }
+        if let Err(e) = self.cache.save(&self.paths.fallout4) {
+            warn!("{}", e);
+        }
     }
 
     // Stage implementations would go here...
@@ -231,18 +1613,32 @@ impl PrevisbineBuilder {
             return Err("ERROR - Previs directory (Data\\vis) not empty".to_string());
         }
 
-        // Delete working files if they exist
+        // An existing archive may hold the mod's other assets, but not old previs data
         let data_dir = self.paths.fallout4.join("Data");
+        let archive_path = data_dir.join(&self.plugin_archive);
+        if archive_path.exists() {
+            let entries = read_ba2_entries(&archive_path)?;
+            if entries.iter().any(|entry| is_previs_entry(entry)) {
+                return Err(format!(
+                    "ERROR - This Plugin already has an Archive containing previs data: {}",
+                    self.plugin_archive
+                ));
+            }
+        }
+
+        // Delete working files if they exist
         let combined_objects_esp = data_dir.join("CombinedObjects.esp");
         if combined_objects_esp.exists() {
-            fs::remove_file(&combined_objects_esp)
-                .map_err(|e| format!("Error removing CombinedObjects.esp: {}", e))?;
+            self.backup.save(&combined_objects_esp)?;
+            remove_file_with_retry(&combined_objects_esp)
+                .map_err(|e| describe_io_error("removing", &combined_objects_esp, &e))?;
         }
 
         let geometry_psg_path = data_dir.join(format!("{} - Geometry.psg", self.plugin_name));
         if geometry_psg_path.exists() {
-            fs::remove_file(&geometry_psg_path)
-                .map_err(|e| format!("Error removing Geometry.psg: {}", e))?;
+            self.backup.save(&geometry_psg_path)?;
+            remove_file_with_retry(&geometry_psg_path)
+                .map_err(|e| describe_io_error("removing", &geometry_psg_path, &e))?;
         }
 
         // Generate precombined
@@ -260,36 +1656,27 @@ impl PrevisbineBuilder {
             "CombinedObjects.esp",
             args,
             &self.ckpe_settings,
-            &self.logfile,
         )?;
 
         // Check if any precombines were created
-        let new_has_precombined = directory_has_files(&precombined_dir, ".nif");
-        if !new_has_precombined {
+        let precombined = count_files(&precombined_dir, &[".nif"])[0];
+        if precombined == 0 {
             return Err(
                 "ERROR - GeneratePrecombined failed to create any Precombines".to_string(),
             );
         }
+        info!("GeneratePrecombined created {} precombined meshes", precombined);
 
         // Check for handle array error
         if let Some(log_file) = &self.ckpe_settings.log_file {
-            if log_file.exists() {
-                let log_content = fs::read_to_string(log_file)
-                    .map_err(|e| format!("Error reading CK log file: {}", e))?;
-
-                if log_content.contains("DEFAULT: OUT OF HANDLE ARRAY ENTRIES") {
-                    return Err(
-                        "ERROR - GeneratePrecombined ran out of Reference Handles".to_string()
-                    );
-                }
+            if log_file.exists() && read_ck_log(log_file)?.out_of_handles {
+                return Err("ERROR - GeneratePrecombined ran out of Reference Handles".to_string());
             }
         }
 
         // Check PSG was created in clean mode
-        if self.args.mode == BuildMode::Clean {
-            if !geometry_psg_path.exists() {
-                return Err("ERROR - GeneratePrecombined failed to create psg file".to_string());
-            }
+        if self.args.mode == BuildMode::Clean && !geometry_psg_path.exists() {
+            return Err("ERROR - GeneratePrecombined failed to create psg file".to_string());
         }
 
         Ok(())
@@ -303,7 +1690,7 @@ impl PrevisbineBuilder {
             "Batch_FO4MergeCombinedObjectsAndCheck.pas",
             &self.plugin_name_ext,
             "CombinedObjects.esp",
-            &self.logfile,
+            &self.xedit_masters(),
             &self.unattended_logfile,
         )?;
 
@@ -322,52 +1709,9 @@ impl PrevisbineBuilder {
 
     fn stage_archive_precombines(&self) -> Result<(), String> {
         info!("Stage: Archive Precombines");
-        
-        let data_dir = self.paths.fallout4.join("Data");
-        let qualifiers = get_archive_qualifiers(&self.args.mode);
-        
-        if self.args.use_bsarch {
-            // BSArch implementation
-            let format = if self.args.mode == BuildMode::Xbox { "Xbox" } else { "General" };
-            let data_dir_str = data_dir.to_string_lossy();
-            let archive_path = data_dir.join(&self.plugin_archive);
-            let archive_path_str = archive_path.to_string_lossy();
-
-            let bsarch_args = vec![
-                "pack",
-                &data_dir_str,
-                &archive_path_str,
-                format,
-                "--include",
-                "meshes\\precombined"
-            ];
-
-            if let Some(bsarch_path) = &self.paths.bsarch {
-                run_bsarch(bsarch_path, "archiving precombines", &bsarch_args)?;
-            } else {
-                return Err("BSArch path not configured".to_string());
-            }
-        } else {
-            // Archive2 implementation
-            run_archive(
-                &self.paths.archive2,
-                &data_dir,
-                &self.plugin_archive,
-                "meshes\\precombined",
-                qualifiers,
-            )?;
-        }
-
-        // Clean up precombined directory (Archive2 only)
-        if !self.args.use_bsarch {
-            let precombined_dir = data_dir.join("meshes").join("precombined");
-            if precombined_dir.exists() {
-                fs::remove_dir_all(&precombined_dir)
-                    .map_err(|e| format!("Error removing precombined directory: {}", e))?;
-            }
-        }
 
-        Ok(())
+        self.pack_into_plugin_archive(&[PathBuf::from("meshes").join("precombined")], false)
+            .map(|_| ())
     }
 
     fn stage_compress_psg(&self) -> Result<(), String> {
@@ -391,14 +1735,17 @@ impl PrevisbineBuilder {
             &csg_file,
             "",
             &self.ckpe_settings,
-            &self.logfile,
         )?;
         
         // Delete the original PSG file after successful compression
         let csg_path = data_dir.join(&csg_file);
         if csg_path.exists() {
-            fs::remove_file(&psg_path)
-                .map_err(|e| format!("Error removing PSG file: {}", e))?;
+            if self.args.keep().psg {
+                return Ok(());
+            }
+            self.backup.save(&psg_path)?;
+            remove_file_with_retry(&psg_path)
+                .map_err(|e| describe_io_error("removing", &psg_path, &e))?;
         } else {
             return Err("ERROR - CompressPSG failed to create CSG file".to_string());
         }
@@ -419,7 +1766,6 @@ impl PrevisbineBuilder {
             &cdx_file,
             "",
             &self.ckpe_settings,
-            &self.logfile,
         )?;
 
         Ok(())
@@ -439,8 +1785,9 @@ impl PrevisbineBuilder {
         // Delete Previs.esp if it exists
         let previs_esp = data_dir.join("Previs.esp");
         if previs_esp.exists() {
-            fs::remove_file(&previs_esp)
-                .map_err(|e| format!("Error removing Previs.esp: {}", e))?;
+            self.backup.save(&previs_esp)?;
+            remove_file_with_retry(&previs_esp)
+                .map_err(|e| describe_io_error("removing", &previs_esp, &e))?;
         }
         
         run_creation_kit(
@@ -451,13 +1798,14 @@ impl PrevisbineBuilder {
             "Previs.esp",
             "clean all",
             &self.ckpe_settings,
-            &self.logfile,
         )?;
         
         // Check if visibility files were created
-        if !directory_has_files(&vis_dir, ".uvd") {
+        let vis_files = count_files(&vis_dir, &[".uvd"])[0];
+        if vis_files == 0 {
             return Err("ERROR - GeneratePreVisData failed to create visibility files".to_string());
         }
+        info!("GeneratePreVisData created {} visibility files", vis_files);
         
         // Check if Previs.esp was created
         if !previs_esp.exists() {
@@ -466,13 +1814,8 @@ impl PrevisbineBuilder {
         
         // Check for specific error in logs
         if let Some(log_file) = &self.ckpe_settings.log_file {
-            if log_file.exists() {
-                let log_content = fs::read_to_string(log_file)
-                    .map_err(|e| format!("Error reading CK log file: {}", e))?;
-
-                if log_content.contains("ERROR: visibility task did not complete.") {
-                    return Err("ERROR - GeneratePreVisData visibility task did not complete".to_string());
-                }
+            if log_file.exists() && read_ck_log(log_file)?.visibility_incomplete {
+                return Err("ERROR - GeneratePreVisData visibility task did not complete".to_string());
             }
         }
 
@@ -487,7 +1830,7 @@ impl PrevisbineBuilder {
             "Batch_FO4MergePreVisAndAutoUpdateRefr.pas",
             &self.plugin_name_ext,
             "Previs.esp",
-            &self.logfile,
+            &self.xedit_masters(),
             &self.unattended_logfile,
         )?;
 
@@ -506,112 +1849,299 @@ impl PrevisbineBuilder {
         Ok(())
     }
 
-    fn stage_archive_vis(&self) -> Result<(), String> {
+    fn stage_archive_vis(&self) -> Result<Vec<String>, String> {
         info!("Stage: Archive Vis");
-        
+
         let data_dir = self.paths.fallout4.join("Data");
-        let archive_path = data_dir.join(&self.plugin_archive);
-        let vis_dir = data_dir.join("vis");
         let precombined_dir = data_dir.join("meshes").join("precombined");
-        
-        if self.args.use_bsarch {
-            // BSArch implementation - can pack multiple directories
-            let format = if self.args.mode == BuildMode::Xbox { "Xbox" } else { "General" };
-            let data_dir_str = data_dir.to_string_lossy();
-            let archive_path_str = archive_path.to_string_lossy();
-            
-            // Check if we have both precombined and vis files
-            let has_precombined = directory_has_files(&precombined_dir, ".nif");
-            let folders = if has_precombined {
-                "meshes\\precombined,vis"
-            } else {
-                "vis"
-            };
 
-            let bsarch_args = vec![
-                "pack",
-                &data_dir_str,
-                &archive_path_str,
-                format,
-                "--include",
-                folders
-            ];
+        // Loose precombines are only present when BSArch was used for stage 3
+        let mut folders = Vec::new();
+        if directory_has_files(&precombined_dir, ".nif") {
+            folders.push(PathBuf::from("meshes").join("precombined"));
+        }
+        folders.push(PathBuf::from("vis"));
+
+        self.pack_into_plugin_archive(&folders, self.args.also_xbox)
+    }
+
+    /// Handles the generated files when archiving is disabled.
+    ///
+    /// The files stay in Data unless an output folder was given, in which case
+    /// `meshes\precombined` and `vis` are moved there with their structure intact.
+    fn export_loose_files(&mut self) -> Result<(), String> {
+        let Some(output_dir) = self.args.loose_output_dir.as_ref().map(PathBuf::from) else {
+            info!("Archiving disabled, generated files left loose in Data");
+            return Ok(());
+        };
+
+        let data_dir = self.paths.fallout4.join("Data");
+        for folder in [PathBuf::from("meshes").join("precombined"), PathBuf::from("vis")] {
+            move_dir_merge(data_dir.join(&folder), output_dir.join(&folder))?;
+        }
+
+        info!("Generated files moved to {}", output_dir.display());
+        self.report.loose_output_dir = Some(output_dir);
+        Ok(())
+    }
+
+    /// Packs loose folders (relative to Data) into the plugin's archive.
+    ///
+    /// Any existing archive is unpacked into a staging folder first, so every
+    /// file it already contains (materials, scripts, other meshes...) is
+    /// re-included in the new archive alongside the generated files. Content
+    /// larger than the configured size limit is split across additional
+    /// `- Main2.ba2`, `- Main3.ba2`... archives.
+    ///
+    /// When `also_xbox` is set, the same staged content is packed a second time
+    /// into Xbox-compressed archives next to the main ones.
+    ///
+    /// # Returns
+    /// The names of the archives that were created
+    fn pack_into_plugin_archive(&self, folders: &[PathBuf], also_xbox: bool) -> Result<Vec<String>, String> {
+        if self.args.archive_dry_run {
+            self.print_archive_plan(folders)?;
+            return Ok(Vec::new());
+        }
+
+        let data_dir = self.paths.fallout4.join("Data");
+        let staging_dir = self.paths.fallout4.join(ARCHIVE_STAGING_DIR);
+
+        remove_dir_all_if_exists(&staging_dir)?;
+        ensure_directory_exists(&staging_dir)?;
+
+        // Unpack the existing archives so none of their contents are lost
+        for archive_name in existing_part_archives(&data_dir, &self.plugin_name) {
+            self.unpack_existing_archive(&data_dir, &archive_name, &staging_dir)?;
+        }
+
+        // Previs-only builds regenerate every vis file, so none of the old
+        // ones may outlive the rebuild
+        if self.args.mode == BuildMode::PrevisOnly {
+            remove_dir_all_if_exists(staging_dir.join("vis"))?;
+        }
+
+        // Move the generated loose files alongside the original contents
+        for folder in folders {
+            move_dir_merge(data_dir.join(folder), staging_dir.join(folder))?;
+        }
+        warn_on_texture_files(&staging_dir, &self.plugin_name);
 
-            if let Some(bsarch_path) = &self.paths.bsarch {
-                run_bsarch(bsarch_path, "archiving vis files", &bsarch_args)?;
-            } else {
-                return Err("BSArch path not configured".to_string());
+        let max_bytes = self.args.max_archive_bytes()?;
+        let part_dirs = split_staged_content(&staging_dir, max_bytes)?;
+
+        let mut created = Vec::new();
+        for (index, part_dir) in part_dirs.iter().enumerate() {
+            let archive_name = part_archive_name(&self.plugin_name, index + 1);
+            self.pack_archive_part(part_dir, &data_dir.join(&archive_name), &self.args.mode)?;
+            created.push(archive_name.clone());
+
+            if also_xbox {
+                let xbox_archive = xbox_archive_name(&archive_name);
+                let xbox_archive_path = data_dir.join(&xbox_archive);
+                wait_for_unlock(&xbox_archive_path, Duration::from_secs(self.args.lock_timeout))?;
+                self.backup.save(&xbox_archive_path)?;
+                remove_file_if_exists(&xbox_archive_path)?;
+
+                self.pack_archive_part(part_dir, &xbox_archive_path, &BuildMode::Xbox)?;
+                info!("Created Xbox archive {}", xbox_archive_path.display());
+                created.push(xbox_archive);
             }
-        } else {
-            // Archive2 implementation - need to extract and re-archive
-            if archive_path.exists() {
-                // Extract existing archive
-                extract_archive(&self.paths.archive2, &data_dir, &self.plugin_archive)?;
-                
-                // Small delay to ensure files are extracted
-                sleep(Duration::from_secs(5));
-                
-                // Remove the existing archive
-                fs::remove_file(&archive_path)
-                    .map_err(|e| format!("Failed to remove existing archive: {}", e))?;
+        }
+
+        if part_dirs.len() > 1 {
+            warn!(
+                "WARNING - Content exceeded {} MB and was split into {} archives. The game only loads {} \
+                 automatically; add {} to sResourceArchiveList2 in the [Archive] section of Fallout4Custom.ini.",
+                self.args.max_archive_size,
+                part_dirs.len(),
+                self.plugin_archive,
+                created[1..]
+                    .iter()
+                    .filter(|name| !name.ends_with("(Xbox).ba2"))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        let keep_vis_loose = self.args.keep().vis_loose;
+        for part_dir in &part_dirs {
+            for folder in folders {
+                if self.archiver.keeps_loose_files() || (keep_vis_loose && folder.as_path() == Path::new("vis")) {
+                    move_dir_merge(part_dir.join(folder), data_dir.join(folder))?;
+                } else if self.args.keep_loose {
+                    move_dir_merge(part_dir.join(folder), self.loose_backup_dir().join(folder))?;
+                }
             }
-            
-            let qualifiers = get_archive_qualifiers(&self.args.mode);
-            
-            // Check if we have precombined meshes extracted
-            let has_precombined = directory_has_files(&precombined_dir, ".nif");
-            
-            if has_precombined {
-                // Archive both directories
-                run_archive(
-                    &self.paths.archive2,
-                    &data_dir,
-                    &self.plugin_archive,
-                    "meshes\\precombined,vis",
-                    qualifiers,
-                )?;
-                
-                // Clean up precombined directory
-                fs::remove_dir_all(&precombined_dir)
-                    .map_err(|e| format!("Error removing precombined directory: {}", e))?;
-            } else {
-                // Archive just the vis folder
-                run_archive(
-                    &self.paths.archive2,
-                    &data_dir,
-                    &self.plugin_archive,
-                    "vis",
-                    qualifiers,
-                )?;
+            if self.args.keep_loose && !self.archiver.keeps_loose_files() {
+                info!("Loose files kept in {}", self.loose_backup_dir().display());
             }
+
+            remove_dir_all_if_exists(part_dir)?;
         }
-        
-        // Clean up vis directory (Archive2 only)
-        if !self.args.use_bsarch && vis_dir.exists() {
-            fs::remove_dir_all(&vis_dir)
-                .map_err(|e| format!("Error removing vis directory: {}", e))?;
+
+        Ok(created)
+    }
+
+    /// Prints the files an archive stage would pack (--archive-dry-run).
+    ///
+    /// Nothing is extracted, packed, moved or deleted; the loose files stay in
+    /// Data so the rest of the build behaves as if archiving were disabled.
+    fn print_archive_plan(&self, folders: &[PathBuf]) -> Result<(), String> {
+        let data_dir = self.paths.fallout4.join("Data");
+
+        console!("\nArchive dry run for {}:", self.plugin_archive);
+
+        let mut loose_files = 0usize;
+        let mut loose_bytes = 0u64;
+        for folder in folders {
+            let folder_dir = data_dir.join(folder);
+            let prefix = folder.to_string_lossy().replace('/', "\\");
+            let mut names = list_entry_names(&folder_dir);
+            sort_entry_names(&mut names);
+
+            for name in names {
+                let size = fs::metadata(entry_path(&folder_dir, &name))
+                    .map_err(|e| format!("Error reading {}\\{}: {}", prefix, name, e))?
+                    .len();
+                console!("  {}\\{} ({} KB)", prefix, name, size.div_ceil(1024));
+                loose_files += 1;
+                loose_bytes += size;
+            }
         }
 
+        let mut existing_files = 0usize;
+        let mut existing_bytes = 0u64;
+        for archive_name in existing_part_archives(&data_dir, &self.plugin_name) {
+            let records = read_ba2_records(&data_dir.join(&archive_name))?;
+            console!("  {} existing files re-included from {}", records.len(), archive_name);
+            existing_files += records.len();
+            existing_bytes += records.iter().map(|record| u64::from(record.unpacked_size)).sum::<u64>();
+        }
+
+        let total_bytes = loose_bytes + existing_bytes;
+        let max_bytes = self.args.max_archive_bytes()?;
+        let parts = total_bytes.div_ceil(max_bytes).max(1);
+
+        console!(
+            "  {} loose files ({} MB) + {} existing files ({} MB), about {} MB uncompressed in {} archive(s)",
+            loose_files,
+            loose_bytes / (1024 * 1024),
+            existing_files,
+            existing_bytes / (1024 * 1024),
+            total_bytes / (1024 * 1024),
+            parts
+        );
+        console!("  No archives were created and no files were moved");
+
         Ok(())
     }
 
+    /// Extracts an existing archive into the staging folder, verifies the
+    /// extraction and deletes the archive
+    fn unpack_existing_archive(&self, data_dir: &Path, archive_name: &str, staging_dir: &Path) -> Result<(), String> {
+        let archive_path = data_dir.join(archive_name);
+        wait_for_unlock(&archive_path, Duration::from_secs(self.args.lock_timeout))?;
+        let entries = self.archiver.list(&archive_path)?;
+        let preserved = entries.iter().filter(|entry| !is_previs_entry(entry)).count();
+        if preserved > 0 {
+            info!(
+                "Preserving {} existing non-previs files from {}",
+                preserved, archive_name
+            );
+        }
+
+        self.archiver.extract(&archive_path, staging_dir)?;
+
+        // Make sure everything landed on disk before deleting the archive
+        verify_extraction(&entries, staging_dir)?;
+
+        self.backup.save(&archive_path)?;
+        remove_file_with_retry(&archive_path)
+            .map_err(|e| format!("Failed to remove existing archive {}: {}", archive_name, e))
+    }
+
+    /// Packs everything in a staging folder into a single archive and audits the result
+    fn pack_archive_part(&self, source_dir: &Path, archive_path: &Path, mode: &BuildMode) -> Result<(), String> {
+        let request = PackRequest::new(source_dir, archive_path)
+            .with_compression(PackCompression::for_mode(mode, self.args.compression))
+            .with_reproducible(self.args.reproducible);
+
+        self.archiver.pack(&request)?;
+
+        if let Some(version) = self.ba2_version {
+            set_ba2_version(archive_path, version)?;
+        }
+
+        audit_archive(archive_path, source_dir)
+    }
+
+    /// Returns the folder loose files are moved to when --keep-loose is used
+    fn loose_backup_dir(&self) -> PathBuf {
+        self.paths.fallout4.join(LOOSE_BACKUP_DIR).join(&self.plugin_name)
+    }
+
     fn cleanup(&self) -> Result<(), String> {
         info!("Performing cleanup");
         
-        if !self.args.keep_files {
-            // Clean up temporary files
-            let data_dir = self.paths.fallout4.join("Data");
-            
+        let keep = self.args.keep();
+        let data_dir = self.paths.fallout4.join("Data");
+
+        if !keep.working_esps {
             // Remove CombinedObjects.esp
             remove_file_if_exists(data_dir.join("CombinedObjects.esp"))?;
             
             // Remove Previs.esp
             remove_file_if_exists(data_dir.join("Previs.esp"))?;
-            
-            // Remove vis directory
+        }
+
+        // Remove vis directory, unless it's the build output
+        if !keep.vis_loose && !self.args.no_archive && !self.args.archive_dry_run {
             remove_dir_all_if_exists(data_dir.join("vis"))?;
         }
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+
+/// Copies a build's run folder into the artifacts folder (--artifacts-dir, --ci)
+fn export_artifacts(run_dir: &Path, artifacts_dir: &Path) {
+    match copy_dir_merge(run_dir, artifacts_dir) {
+        Ok(()) => console!("Build artifacts copied to {}", artifacts_dir.display()),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+/// Prints how each plugin of a --queue run went
+fn print_queue_summary(queue_report: &QueueReport) {
+    console!("\nQueue summary:");
+    for report in &queue_report.builds {
+        let status = if report.success {
+            style::success("built")
+        } else if report.aborted {
+            style::warning("stopped")
+        } else {
+            style::error("failed")
+        };
+        console!(
+            "  {:<40} {:>12}  {}",
+            report.plugin,
+            format_duration(Duration::from_secs_f64(report.total_seconds())),
+            status
+        );
+        if let Some(error) = &report.error {
+            console!("      {}", error.lines().next().unwrap_or_default());
+        }
+    }
+    for plugin in &queue_report.not_built {
+        console!("  {:<40} {:>12}  {}", plugin, "", style::warning("not built"));
+    }
+    console!(
+        "{} built, {} failed, {} not built",
+        queue_report.succeeded,
+        queue_report.failed,
+        queue_report.not_built.len()
+    );
+}
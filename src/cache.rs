@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use crate::cli::BuildStage;
+use crate::esp::split_plugin_name;
+use crate::utils::{ensure_directory_exists, walk_dir};
+
+/// Folder under the Fallout 4 directory holding one cache manifest per plugin
+pub const CACHE_DIR: &str = "PrevisbineCache";
+
+/// State of every file and folder the build reads or writes, keyed by path
+/// relative to Data. Missing paths are left out.
+pub type Snapshot = BTreeMap<String, String>;
+
+/// State recorded after a stage completed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageRecord {
+    pub stage: i32,
+    pub state: Snapshot,
+}
+
+/// Record of the last build of a plugin, used to skip stages whose results
+/// are still in place
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    pub plugin: String,
+    /// Build settings the records were made with; records are only reused
+    /// by builds with the same settings
+    pub settings: String,
+    /// Completed stages, in the order they ran
+    pub stages: Vec<StageRecord>,
+    /// State after the last build finished, including cleanup
+    pub complete: Option<Snapshot>,
+}
+
+/// Where a build can pick up according to the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumePoint {
+    /// Nothing changed since the last complete build
+    UpToDate,
+    /// The results of every stage before this one are still in place
+    Stage(BuildStage),
+}
+
+impl BuildCache {
+    /// Path of the cache manifest for a plugin
+    pub fn path(fallout4: &Path, plugin_name_ext: &str) -> PathBuf {
+        fallout4.join(CACHE_DIR).join(format!("{}.json", plugin_name_ext))
+    }
+
+    /// Loads the cache of a plugin, starting over if there is none, it can't
+    /// be read or it was made with different settings
+    pub fn load(fallout4: &Path, plugin_name_ext: &str, settings: &str) -> Self {
+        let cache = fs::read_to_string(Self::path(fallout4, plugin_name_ext))
+            .ok()
+            .and_then(|json| serde_json::from_str::<BuildCache>(&json).ok())
+            .filter(|cache| cache.settings == settings);
+
+        cache.unwrap_or_else(|| Self {
+            plugin: plugin_name_ext.to_string(),
+            settings: settings.to_string(),
+            ..Self::default()
+        })
+    }
+
+    /// Writes the cache manifest
+    pub fn save(&self, fallout4: &Path) -> Result<(), String> {
+        let path = Self::path(fallout4, &self.plugin);
+        if let Some(parent) = path.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing build cache: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Error writing build cache {}: {}", path.display(), e))
+    }
+
+    /// Finds where a build can resume given the current state.
+    ///
+    /// # Arguments
+    /// * `current` - Snapshot of the Data directory now
+    /// * `planned` - The stages a full build would run, in order
+    ///
+    /// # Returns
+    /// * `Some(ResumePoint)` if the state matches a recorded one
+    /// * `None` if the cache doesn't apply and the build can't be shortened
+    pub fn resume_point(&self, current: &Snapshot, planned: &[BuildStage]) -> Option<ResumePoint> {
+        if self.complete.as_ref() == Some(current) {
+            return Some(ResumePoint::UpToDate);
+        }
+
+        let record = self.stages.iter().rev().find(|record| &record.state == current)?;
+        planned
+            .iter()
+            .find(|stage| **stage as i32 > record.stage)
+            .map(|stage| ResumePoint::Stage(*stage))
+    }
+
+    /// Forgets the records of `stage` and every later stage, before they run again
+    pub fn invalidate_from(&mut self, stage: BuildStage) {
+        self.stages.retain(|record| record.stage < stage as i32);
+        self.complete = None;
+    }
+
+    /// Records the state after a stage completed
+    pub fn record_stage(&mut self, stage: BuildStage, state: Snapshot) {
+        self.invalidate_from(stage);
+        self.stages.push(StageRecord {
+            stage: stage as i32,
+            state,
+        });
+    }
+}
+
+/// Takes a snapshot of the given paths (relative to `data_dir`).
+///
+/// Plugins are hashed by content since the merge stages rewrite them in
+/// place; other files and folders use their sizes and modification times,
+/// which is much cheaper for large archives and generated meshes.
+pub fn snapshot(data_dir: &Path, paths: &[String]) -> Snapshot {
+    let mut state = Snapshot::new();
+    for relative in paths {
+        let path = data_dir.join(relative);
+        let fingerprint = if path.is_dir() {
+            Some(directory_fingerprint(&path))
+        } else if is_plugin(relative) {
+            content_fingerprint(&path)
+        } else {
+            file_fingerprint(&path)
+        };
+        if let Some(fingerprint) = fingerprint {
+            state.insert(relative.replace('\\', "/").to_lowercase(), fingerprint);
+        }
+    }
+    state
+}
+
+fn is_plugin(name: &str) -> bool {
+    split_plugin_name(name).is_some()
+}
+
+/// 64-bit FNV-1a hash
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Content hash of a file, or `None` if it can't be read
+pub(crate) fn content_fingerprint(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hash = FNV_OFFSET;
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buffer[..read]);
+    }
+    Some(format!("{:016x}", hash))
+}
+
+pub(crate) fn file_fingerprint(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    Some(format!("{}:{}", metadata.len(), modified))
+}
+
+fn directory_fingerprint(dir: &Path) -> String {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files);
+    files.sort();
+
+    let hash = files.iter().fold(FNV_OFFSET, |hash, (relative, fingerprint)| {
+        fnv1a(fnv1a(hash, relative.as_bytes()), fingerprint.as_bytes())
+    });
+    format!("{} files:{:016x}", files.len(), hash)
+}
+
+fn collect_files(root: &Path, files: &mut Vec<(String, String)>) {
+    walk_dir(root, &mut |path, is_dir| {
+        if is_dir {
+            return;
+        }
+        if let Some(fingerprint) = file_fingerprint(path) {
+            let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_lowercase();
+            files.push((relative, fingerprint));
+        }
+    });
+}
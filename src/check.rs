@@ -0,0 +1,205 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cli::{Args, CheckArgs};
+use crate::esp::{read_cell_previsbines, resolve_plugin_name};
+use crate::paths::find_fallout4;
+use crate::tools::ba2::read_ba2_entries;
+
+/// Problems listed per kind before the rest are only counted
+const MAX_LISTED_PROBLEMS: usize = 50;
+
+/// Form IDs in file names only keep the part within the plugin, as the
+/// load order index depends on the game's load order
+const FORM_ID_MASK: u32 = 0x00FF_FFFF;
+
+/// What `check` found in a finished plugin and its archive
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    /// Cells in the plugin
+    pub cells: usize,
+    /// Cells with precombined meshes
+    pub precombined_cells: usize,
+    /// Precombined meshes the plugin references
+    pub referenced_meshes: usize,
+    /// Referenced precombined meshes found in neither the archive nor Data
+    pub missing_meshes: Vec<String>,
+    /// Cells with precombines but no previs, and why
+    pub missing_previs: Vec<String>,
+    /// Precombined meshes of the plugin's cells no cell references
+    pub unreferenced_meshes: usize,
+}
+
+impl CheckReport {
+    /// Number of problems that leave previs broken in game
+    pub fn problems(&self) -> usize {
+        self.missing_meshes.len() + self.missing_previs.len()
+    }
+}
+
+/// Precombined meshes and previs files a build produced
+#[derive(Debug, Default)]
+struct BuildFiles {
+    /// (cell, mesh hash) of each `meshes\precombined\<cell>_<hash>_OC.nif`
+    meshes: BTreeSet<(u32, u32)>,
+    /// Cell of each `vis\<cell>.uvd`
+    previs: BTreeSet<u32>,
+}
+
+impl BuildFiles {
+    /// Adds a file given by its path below Data, ignoring other files
+    fn add(&mut self, path: &str) {
+        let path = path.replace('/', "\\").to_lowercase();
+        let Some((folder, name)) = path.rsplit_once('\\') else {
+            return;
+        };
+        let hex = |text: &str| u32::from_str_radix(text, 16).ok();
+        match folder {
+            "meshes\\precombined" => {
+                let mut parts = name.trim_end_matches(".nif").split('_');
+                if let (Some(cell), Some(hash)) = (parts.next().and_then(hex), parts.next().and_then(hex)) {
+                    self.meshes.insert((cell & FORM_ID_MASK, hash));
+                }
+            }
+            "vis" => {
+                if let Some(cell) = name.strip_suffix(".uvd").and_then(hex) {
+                    self.previs.insert(cell & FORM_ID_MASK);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds the loose files of a Data folder subfolder
+    fn add_loose(&mut self, data_dir: &Path, folder: &str) {
+        let dir = folder.split('\\').fold(data_dir.to_path_buf(), |dir, part| dir.join(part));
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            self.add(&format!("{}\\{}", folder, entry.file_name().to_string_lossy()));
+        }
+    }
+}
+
+/// Checks that every precombined mesh the plugin's cells reference (XCRI)
+/// is in the archive or loose in Data, and that every cell with precombines
+/// has previs data (VISI and a `vis\<cell>.uvd` file, of the cell named by
+/// RVIS if it has one).
+///
+/// # Arguments
+/// * `data_dir` - The Data folder
+/// * `plugin_name_ext` - The plugin file name with extension
+/// * `archive` - The plugin's archive, if it has one
+///
+/// # Returns
+/// * `Ok(CheckReport)` with what was found
+/// * `Err(String)` if the plugin or archive can't be read
+pub fn check_previsbines(data_dir: &Path, plugin_name_ext: &str, archive: Option<&Path>) -> Result<CheckReport, String> {
+    let cells = read_cell_previsbines(&data_dir.join(plugin_name_ext))?;
+
+    let mut files = BuildFiles::default();
+    if let Some(archive) = archive {
+        for entry in read_ba2_entries(archive)? {
+            files.add(&entry);
+        }
+    }
+    files.add_loose(data_dir, "meshes\\precombined");
+    files.add_loose(data_dir, "vis");
+
+    let mut report = CheckReport { cells: cells.len(), ..CheckReport::default() };
+    let mut referenced = BTreeSet::new();
+    for cell in cells.iter().filter(|cell| !cell.combined_meshes.is_empty()) {
+        report.precombined_cells += 1;
+        let cell_id = cell.form_id & FORM_ID_MASK;
+        for hash in &cell.combined_meshes {
+            referenced.insert((cell_id, *hash));
+            if !files.meshes.contains(&(cell_id, *hash)) {
+                report
+                    .missing_meshes
+                    .push(format!("meshes\\precombined\\{:08X}_{:08X}_OC.nif (cell {:08X})", cell_id, hash, cell.form_id));
+            }
+        }
+
+        let previs_cell = cell.previs_cell.unwrap_or(cell.form_id);
+        if !cell.has_previs {
+            report.missing_previs.push(format!("cell {:08X} has precombines but no previs timestamp (VISI)", cell.form_id));
+        } else if !files.previs.contains(&(previs_cell & FORM_ID_MASK)) {
+            report.missing_previs.push(format!(
+                "cell {:08X} has no previs data: vis\\{:08X}.uvd not found",
+                cell.form_id,
+                previs_cell & FORM_ID_MASK
+            ));
+        }
+    }
+    report.referenced_meshes = referenced.len();
+
+    let plugin_cells: BTreeSet<u32> = cells.iter().map(|cell| cell.form_id & FORM_ID_MASK).collect();
+    report.unreferenced_meshes = files
+        .meshes
+        .iter()
+        .filter(|mesh| plugin_cells.contains(&mesh.0) && !referenced.contains(*mesh))
+        .count();
+    Ok(report)
+}
+
+/// Runs the `check` command: audits a finished plugin and its archive for
+/// missing precombined meshes and previs data.
+///
+/// # Returns
+/// * `Ok(())` if nothing is missing
+/// * `Err(String)` if problems were found or the files can't be read
+pub fn run_check(args: &Args, check: &CheckArgs) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+    let data_dir = fallout4.join("Data");
+    let (plugin_name, plugin_name_ext) = resolve_plugin_name(&check.plugin, &data_dir, None)?;
+    if !data_dir.join(&plugin_name_ext).exists() {
+        return Err(format!("ERROR - Plugin {} not found in Data", plugin_name_ext));
+    }
+
+    let archive = match &check.archive {
+        Some(archive) => Some(data_dir.join(archive)),
+        None => Some(data_dir.join(format!("{} - Main.ba2", plugin_name))).filter(|archive| archive.exists()),
+    };
+    match &archive {
+        Some(archive) => println!("Checking {} against {}", plugin_name_ext, archive.display()),
+        None => println!("Checking {} against the loose files in Data (no archive found)", plugin_name_ext),
+    }
+
+    let report = check_previsbines(&data_dir, &plugin_name_ext, archive.as_deref())?;
+    println!(
+        "{} cells, {} with precombines referencing {} meshes",
+        report.cells, report.precombined_cells, report.referenced_meshes
+    );
+    for (title, problems) in [("Missing precombined meshes", &report.missing_meshes), ("Missing previs", &report.missing_previs)] {
+        if problems.is_empty() {
+            continue;
+        }
+        println!("\n{} ({}):", title, problems.len());
+        for problem in problems.iter().take(MAX_LISTED_PROBLEMS) {
+            println!("  {}", problem);
+        }
+        if problems.len() > MAX_LISTED_PROBLEMS {
+            println!("  ... and {} more", problems.len() - MAX_LISTED_PROBLEMS);
+        }
+    }
+    if report.unreferenced_meshes > 0 {
+        println!(
+            "\n{} precombined meshes of the plugin's cells aren't referenced by them, left over from an earlier build",
+            report.unreferenced_meshes
+        );
+    }
+
+    if report.problems() > 0 {
+        return Err(format!(
+            "ERROR - {} problems found in {}: the game will show missing or flickering buildings in those cells. Rebuild the plugin's previsbines.",
+            report.problems(),
+            plugin_name_ext
+        ));
+    }
+    println!("\nNo problems found");
+    Ok(())
+}
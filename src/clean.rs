@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+
+use crate::backup::RunBackup;
+use crate::cli::{Args, ArchiverKind, CleanPrevisbinesArgs};
+use crate::esp::{resolve_plugin_name, strip_cell_fields, PREVISBINE_CELL_FIELDS};
+use crate::game::resolve_ba2_version;
+use crate::locks::wait_for_unlock;
+use crate::paths::{archive2_path, find_bsarch, find_fallout4};
+use crate::tools::archive::{
+    audit_archive, existing_part_archives, is_previs_entry, verify_extraction, PackCompression, PackRequest,
+};
+use crate::tools::archiver::{create_archiver, Archiver};
+use crate::tools::ba2::set_ba2_version;
+use crate::utils::{ensure_directory_exists, remove_dir_all_if_exists, remove_file_with_retry, work_dir};
+
+/// Removes everything a build generated for a plugin, so it can be rebuilt
+/// from scratch.
+///
+/// Precombined meshes and vis files are removed from the plugin's archives
+/// (archives holding nothing else are deleted), the precombine and previs
+/// fields are stripped from the plugin's cells, and the PSG/CSG/CDX files are
+/// deleted. Every changed file is backed up first.
+///
+/// # Arguments
+/// * `args` - Global command line arguments (tool paths, archiver, BA2 version)
+/// * `clean` - Arguments of the clean-previsbines command
+///
+/// # Returns
+/// * `Ok(())` if the plugin was cleaned
+/// * `Err(String)` if any step fails
+pub fn run_clean_previsbines(args: &Args, clean: &CleanPrevisbinesArgs) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+    let data_dir = fallout4.join("Data");
+
+    let (plugin_name, plugin_name_ext) = resolve_plugin_name(&clean.plugin, &data_dir, None)?;
+    let plugin_path = data_dir.join(&plugin_name_ext);
+    if !plugin_path.exists() {
+        return Err(format!("ERROR - Plugin {} not found in Data", plugin_name_ext));
+    }
+
+    let backup = RunBackup::new(&fallout4, &plugin_name_ext, !args.no_backup);
+    let lock_timeout = Duration::from_secs(args.lock_timeout);
+
+    // Generated content in the archives
+    let archives = existing_part_archives(&data_dir, &plugin_name);
+    if !archives.is_empty() {
+        let archiver_kind = args.archiver_kind();
+        let xedit_dir = args.fo4edit_path.as_deref().and_then(|path| Path::new(path).parent());
+        let bsarch = find_bsarch(archiver_kind == ArchiverKind::Bsarch, args.bsarch_path.clone(), xedit_dir);
+        let version = resolve_ba2_version(args.ba2_version, &fallout4);
+        let archiver = create_archiver(archiver_kind, &archive2_path(&fallout4), bsarch.as_deref(), args, version)?;
+
+        for archive_name in &archives {
+            let archive_path = data_dir.join(archive_name);
+            let entries = archiver.list(&archive_path)?;
+            let previs_entries = entries.iter().filter(|entry| is_previs_entry(entry)).count();
+            if previs_entries == 0 {
+                continue;
+            }
+
+            backup.save(&archive_path)?;
+            wait_for_unlock(&archive_path, lock_timeout)?;
+            if previs_entries == entries.len() {
+                remove_file_with_retry(&archive_path)
+                    .map_err(|e| format!("Error removing {}: {}", archive_name, e))?;
+                println!("Removed {}, it only held precombines and vis files", archive_name);
+            } else {
+                strip_archive(archiver.as_ref(), &archive_path, &entries, args, version)?;
+                println!("Removed {} precombine and vis files from {}", previs_entries, archive_name);
+            }
+        }
+    }
+
+    // Precombine and previs fields in the plugin
+    backup.save(&plugin_path)?;
+    wait_for_unlock(&plugin_path, lock_timeout)?;
+    let cells = strip_cell_fields(&plugin_path, &PREVISBINE_CELL_FIELDS)?;
+    println!("Removed precombine and previs data from {} cells of {}", cells, plugin_name_ext);
+
+    // Shared geometry and CDX files
+    for file_name in [
+        format!("{} - Geometry.psg", plugin_name),
+        format!("{} - Geometry.csg", plugin_name),
+        format!("{}.cdx", plugin_name),
+    ] {
+        let path = data_dir.join(&file_name);
+        if path.exists() {
+            backup.save(&path)?;
+            remove_file_with_retry(&path).map_err(|e| format!("Error removing {}: {}", file_name, e))?;
+            println!("Removed {}", file_name);
+        }
+    }
+
+    if backup.has_entries() {
+        println!(
+            "Files changed by this command were backed up to {}\nRun `restore-backup` to undo it.",
+            backup.dir().display()
+        );
+    }
+    Ok(())
+}
+
+/// Packs an archive again without its precombined meshes and vis files
+fn strip_archive(
+    archiver: &dyn Archiver,
+    archive_path: &Path,
+    entries: &[String],
+    args: &Args,
+    version: Option<u32>,
+) -> Result<(), String> {
+    let archive_name = archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let work_dir = work_dir().join(format!("previsbine-clean-{}", archive_name));
+    let extract_dir = work_dir.join("content");
+    let new_archive = work_dir.join(&archive_name);
+    remove_dir_all_if_exists(&work_dir)?;
+    ensure_directory_exists(&extract_dir)?;
+
+    info!("Removing precombines and vis files from {}", archive_path.display());
+    archiver.extract(archive_path, &extract_dir)?;
+    verify_extraction(entries, &extract_dir)?;
+    remove_dir_all_if_exists(extract_dir.join("meshes").join("precombined"))?;
+    remove_dir_all_if_exists(extract_dir.join("vis"))?;
+
+    let request = PackRequest::new(&extract_dir, &new_archive)
+        .with_compression(PackCompression::for_mode(&args.mode, args.compression))
+        .with_reproducible(args.reproducible);
+    archiver.pack(&request)?;
+    if let Some(version) = version {
+        set_ba2_version(&new_archive, version)?;
+    }
+    audit_archive(&new_archive, &extract_dir)?;
+
+    fs::copy(&new_archive, archive_path)
+        .map_err(|e| format!("Error writing {}: {}", archive_path.display(), e))?;
+    remove_dir_all_if_exists(&work_dir)
+}
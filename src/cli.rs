@@ -1,14 +1,114 @@
-use clap::{Parser, ValueEnum};
+use clap::parser::ValueSource;
+use clap::error::ErrorKind;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fmt;
+use crate::config::Config;
+use crate::headless::{DEFAULT_ARTIFACTS_DIR, DEFAULT_CI_TIMEOUT_MINUTES};
+use crate::logging::{default_runs_dir, DEFAULT_KEEP_RUNS};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Largest --max-archive-size in MB; BA2 archives can't exceed 4 GB
+pub const MAX_ARCHIVE_SIZE_MB: u64 = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum BuildMode {
     Clean,
     Filtered,
     Xbox,
+    PrevisOnly,
+}
+
+/// Tool used to create and extract BA2 archives
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiverKind {
+    /// Archive2.exe from the Creation Kit
+    Archive2,
+    /// BSArch.exe from xEdit
+    Bsarch,
+    /// Built-in BA2 writer, no external tool required
+    Native,
+}
+
+impl fmt::Display for ArchiverKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiverKind::Archive2 => write!(f, "Archive2"),
+            ArchiverKind::Bsarch => write!(f, "BSArch"),
+            ArchiverKind::Native => write!(f, "native"),
+        }
+    }
+}
+
+/// Compression applied to general (non-Xbox) archives
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveCompression {
+    /// Compress archive contents (Archive2 `-compression=Default`, BSArch `-z`)
+    Default,
+    /// Store archive contents uncompressed (Archive2 `-compression=None`)
+    None,
+}
+
+impl fmt::Display for ArchiveCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveCompression::Default => write!(f, "default"),
+            ArchiveCompression::None => write!(f, "none"),
+        }
+    }
+}
+
+/// BA2 archive version to write
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ba2Version {
+    /// Match the installed game (v1 for old-gen, v8 for next-gen)
+    Auto,
+    /// Original format, loadable by every game version
+    V1,
+    /// Next-gen format
+    V7,
+    /// Next-gen format
+    V8,
+}
+
+impl Ba2Version {
+    /// Returns the version number written to the archive header, if fixed
+    pub fn number(&self) -> Option<u32> {
+        match self {
+            Ba2Version::Auto => None,
+            Ba2Version::V1 => Some(1),
+            Ba2Version::V7 => Some(7),
+            Ba2Version::V8 => Some(8),
+        }
+    }
+}
+
+/// Extension of a plugin created for the build
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PluginExtension {
+    Esp,
+    Esm,
+    Esl,
+}
+
+impl PluginExtension {
+    /// The extension with its leading dot
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            PluginExtension::Esp => ".esp",
+            PluginExtension::Esm => ".esm",
+            PluginExtension::Esl => ".esl",
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum BuildStage {
     VerifyEnvironment = 0,
     GeneratePrecombines = 1,
@@ -39,9 +139,11 @@ impl BuildStage {
 
     pub fn display_stages(build_mode: &BuildMode) -> String {
         let mut result = String::new();
-        result.push_str("[1] Generate Precombines Via CK\n");
-        result.push_str("[2] Merge PrecombineObjects.esp Via FO4Edit\n");
-        result.push_str("[3] Create BA2 Archive from Precombines\n");
+        if *build_mode != BuildMode::PrevisOnly {
+            result.push_str("[1] Generate Precombines Via CK\n");
+            result.push_str("[2] Merge PrecombineObjects.esp Via FO4Edit\n");
+            result.push_str("[3] Create BA2 Archive from Precombines\n");
+        }
 
         if *build_mode == BuildMode::Clean {
             result.push_str("[4] Compress PSG Via CK\n");
@@ -76,51 +178,776 @@ impl fmt::Display for BuildMode {
             BuildMode::Clean => write!(f, "clean"),
             BuildMode::Filtered => write!(f, "filtered"),
             BuildMode::Xbox => write!(f, "xbox"),
+            BuildMode::PrevisOnly => write!(f, "previs-only"),
         }
     }
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
 #[command(
     name = "generate_previsbines",
     about = "Automatic Previsbine Builder for Fallout 4\nBased on the original batch script by PJM",
     version = "2.6"
 )]
 pub struct Args {
-    /// Build mode: clean, filtered, or xbox
+    /// Build mode: clean, filtered, xbox, or previs-only to rebuild only the
+    /// previs of a plugin whose precombines are already built
     #[arg(value_enum)]
     #[arg(short, long, default_value = "clean")]
     pub mode: BuildMode,
 
-    /// The plugin to generate previsbines for
+    /// Choose clean or filtered mode from the plugin's content instead of --mode
+    #[arg(long, conflicts_with = "mode")]
+    pub auto_mode: bool,
+
+    /// Whether the build mode was chosen (with --mode, the wizard or the
+    /// window) rather than left at its default
+    #[arg(skip)]
+    #[serde(skip)]
+    pub mode_given: bool,
+
+    /// Open the builder window instead of building from the command line
+    #[cfg(feature = "gui")]
+    #[arg(long, conflicts_with_all = ["wizard", "chain", "queue"])]
+    pub gui: bool,
+
+    /// Set up the build step by step: plugin, build mode and environment checks
+    #[arg(long, conflicts_with_all = ["no_prompt", "non_interactive", "chain", "queue"])]
+    pub wizard: bool,
+
+    /// The plugin to generate previsbines for, by name or as a path outside Data to copy in
     #[arg(value_name = "PLUGIN")]
     pub plugin: Option<String>,
 
+    /// Patches to build after PLUGIN, in order, each with the previous results in place
+    #[arg(long, value_name = "PLUGIN", num_args = 1.., requires = "plugin", conflicts_with = "start_stage")]
+    pub chain: Vec<String>,
+
+    /// Unrelated plugins to build after PLUGIN, one after the other, continuing past failures
+    #[arg(long, value_name = "PLUGIN", num_args = 1.., conflicts_with = "chain")]
+    pub queue: Vec<String>,
+
+    /// Build against the Previs Repair Pack: load this PRP plugin in xEdit and check its version
+    #[arg(long, value_name = "PRP.esp")]
+    pub prp: Option<String>,
+
+    /// Oldest PRP version accepted with --prp (e.g. 0.73)
+    #[arg(long, value_name = "VERSION", requires = "prp")]
+    pub prp_min_version: Option<String>,
+
+    /// Extra plugins loaded in xEdit ahead of the plugin being built
+    #[arg(long = "master", value_name = "PLUGIN")]
+    #[serde(rename = "master")]
+    pub masters: Vec<String>,
+
+    /// Create an empty plugin when neither the plugin nor xPrevisPatch.esp exists, without asking
+    #[arg(long)]
+    pub create_plugin: bool,
+
+    /// Extension of PLUGIN when it's given without one and created from xPrevisPatch.esp or from scratch; esm and esl also set the master and light flags
+    #[arg(long, value_enum, value_name = "EXT")]
+    pub plugin_extension: Option<PluginExtension>,
+
+    /// Master of a plugin created from scratch, in load order (Fallout4.esm is always included)
+    #[arg(long = "seed-master", value_name = "PLUGIN")]
+    #[serde(rename = "seed-master")]
+    pub seed_masters: Vec<String>,
+
+    /// Template plugin in Data offered as the seed when xPrevisPatch.esp is missing; it is copied, not renamed (repeatable, or seed-plugins in the settings)
+    #[arg(long = "seed-plugin", value_name = "PLUGIN")]
+    #[serde(rename = "seed-plugin")]
+    pub seed_plugins: Vec<String>,
+
     /// Path to FO4Edit/xEdit executable
     #[arg(long)]
     pub fo4edit_path: Option<String>,
 
+    /// Start the Creation Kit through this loader command, which receives
+    /// CreationKit.exe and its arguments (quote parts containing spaces)
+    #[arg(long, value_name = "COMMAND")]
+    pub ck_launcher: Option<String>,
+
     /// Path to Fallout 4 installation directory
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub fallout4_path: Option<String>,
 
     #[arg(long)]
     /// Stage of the process to start from
     pub start_stage: Option<i32>,
 
-    /// Don't prompt for confirmation, just execute
+    /// Run only this stage
+    #[arg(long, value_name = "STAGE", conflicts_with_all = ["start_stage", "chain"])]
+    pub only: Option<i32>,
+
+    /// With --start-stage or --only, run the earlier stages whose results are missing instead of failing
+    #[arg(long)]
+    pub with_prerequisites: bool,
+
+    /// Retry a failing stage up to N times, e.g. `--retry generate-previs=2` (repeatable,
+    /// takes precedence over the `retries` setting)
+    #[arg(long = "retry", value_name = "STAGE=N", value_parser = parse_stage_retry)]
+    #[serde(rename = "retry")]
+    pub retries: Vec<StageRetry>,
+
+    /// Set an environment variable for the tools a stage runs, e.g.
+    /// `--tool-env generate-previs:__COMPAT_LAYER=RunAsInvoker`, or for the
+    /// tools of every stage without `STAGE:` (repeatable)
+    #[arg(long = "tool-env", value_name = "[STAGE:]NAME=VALUE", value_parser = parse_tool_env)]
+    #[serde(rename = "tool-env")]
+    pub tool_env: Vec<ToolEnv>,
+
+    /// Seconds to wait before retrying a stage; the wait doubles with every further retry
+    #[arg(long, default_value_t = 30)]
+    pub retry_delay: u64,
+
+    /// Run every stage even if the build cache shows its results are still in place
+    #[arg(long, conflicts_with = "start_stage")]
+    pub force: bool,
+
+    /// Check the game, Creation Kit, CKPE and xEdit scripts again even if none of them changed since they last passed
+    #[arg(long)]
+    pub reverify: bool,
+
+    /// Talk to a frontend: JSON events on stdout, prompt answers on stdin, console output on stderr
+    #[arg(long, global = true)]
+    pub frontend_mode: bool,
+
+    /// Console output format: `jsonl` prints one JSON object per event (stage start and end, warning, tool run, file produced) on stdout, moving everything else to stderr
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text, global = true)]
+    pub output: OutputFormat,
+
+    /// Write the JSON Lines events to this file instead, keeping the console output as it is
+    #[arg(long, value_name = "PATH", global = true)]
+    pub output_file: Option<String>,
+
+    /// Run unattended: implies --non-interactive, turns colors, progress lines and notifications off, and limits the build time
+    #[arg(long, global = true)]
+    pub headless: bool,
+
+    /// Preset for modlist tools such as Wabbajack: --headless with reproducible archives and JSON Lines events on stdout (or --output-file), no update check or notifications
+    #[arg(long, global = true)]
+    pub wabbajack: bool,
+
+    /// Preset for CI runners: --headless with --collect-outputs, a 6 hour build timeout unless --build-timeout is given, and the run folder copied to --artifacts-dir
+    #[arg(long, global = true)]
+    pub ci: bool,
+
+    /// Preset for Fallout London and other installs downgraded to 1.10.163: archives are written as BA2 version 1 unless --ba2-version is given and the build checks Fallout4.exe, F4SE, the Creation Kit and CKPE match that version
+    #[arg(long, visible_alias = "downgraded", global = true)]
+    pub fallout_london: bool,
+
+    /// Copy the run folder (logs, report, manifest, diagnostics, collected outputs) here when the build ends or times out (`artifacts` with --ci)
+    #[arg(long, value_name = "PATH")]
+    pub artifacts_dir: Option<String>,
+
+    /// Stop with exit code 4 if the build takes longer than this (12 hours by default with --headless)
+    #[arg(long, value_name = "MINUTES")]
+    pub build_timeout: Option<u64>,
+
+    /// Plain line-by-line output without colors, redrawn progress lines or decorations, for screen readers and captured output
+    #[arg(long, global = true)]
+    pub plain: bool,
+
+    /// Force the log file and build report out to disk after every stage and error, so they survive a system crash
+    #[arg(long, global = true)]
+    pub sync_logs: bool,
+
+    /// Use the paths, archiver and mode of a named profile from the settings file (`[profiles.NAME]`)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Print every Creation Kit, xEdit, Archive2 and BSArch command line before running it
+    #[arg(long, global = true)]
+    pub show_commands: bool,
+
+    /// Print the settings the tool would use (command line, settings file and defaults merged) and the resolved paths, then exit
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "toml", global = true)]
+    #[serde(skip)]
+    pub print_config: Option<ConfigFormat>,
+
+    /// Check for a newer release before starting (or set `check-updates = true` in the settings file)
+    #[arg(long, global = true)]
+    pub check_updates: bool,
+
+    /// Don't launch the Creation Kit, xEdit, Archive2 or BSArch; write placeholder outputs in Data instead, to try the whole pipeline in seconds
+    #[arg(long, global = true)]
+    pub simulate: bool,
+
+    /// Don't color console output (colors are also off when NO_COLOR is set or output isn't a terminal)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Show a desktop notification (and ring the terminal bell) when the build ends or needs input
+    #[arg(long, global = true)]
+    pub notify: bool,
+
+    /// Remove leftovers of a previous failed run without asking
+    #[arg(long)]
+    pub auto_clean: bool,
+
+    /// TOML file answering prompts in advance by identifier, e.g. `rename-seed = true` or `start-stage = 6`
+    #[arg(long, value_name = "FILE")]
+    pub answers: Option<String>,
+
+    /// Stop waiting for an answer to a prompt after this many seconds and take its default
+    #[arg(long, value_name = "SECONDS")]
+    pub prompt_timeout: Option<u64>,
+
+    /// Answer taken when a prompt times out, e.g. `start-stage=1` (yes/no prompts default to no)
+    #[arg(long, value_name = "PROMPT=ANSWER", requires = "prompt_timeout")]
+    pub timeout_answer: Vec<String>,
+
+    /// Ask for confirmation after showing the build plan
+    #[arg(long, conflicts_with = "no_prompt")]
+    pub confirm_plan: bool,
+
+    /// Same as --assume-yes --non-interactive: answer yes/no prompts with yes and fail on any other prompt
     #[arg(short, long)]
     pub no_prompt: bool,
 
-    /// Specify files to keep after completion
+    /// Answer every yes/no prompt that the answers file doesn't answer with yes
+    #[arg(short = 'y', long, global = true)]
+    pub assume_yes: bool,
+
+    /// Never wait for input: prompts the flags and answers file don't answer fail with exit code 3
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    /// Keep every intermediate file after completion (all of the --keep-* options below)
     #[arg(short, long)]
     pub keep_files: bool,
 
+    /// Keep Geometry.psg after it is compressed to Geometry.csg
+    #[arg(long)]
+    pub keep_psg: bool,
+
+    /// Keep CombinedObjects.esp and Previs.esp after they are merged
+    #[arg(long)]
+    pub keep_working_esps: bool,
+
+    /// Leave the vis files loose in Data after they are archived
+    #[arg(long)]
+    pub keep_vis_loose: bool,
+
+    /// Keep a copy of each Creation Kit stage's log next to the CKPE log file
+    #[arg(long)]
+    pub keep_ck_logs: bool,
+
     /// Use BSArch instead of Archive2
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub use_bsarch: bool,
-    
-    /// BSArch Path (Requires --use-bsarch)
-    #[arg(long)]
+
+    /// Archiver to use: archive2, bsarch, or native (--use-bsarch is the same as --archiver bsarch)
+    #[arg(long, value_enum, default_value = "archive2", global = true)]
+    pub archiver: ArchiverKind,
+
+    /// BSArch Path (Requires --use-bsarch, defaults to the copy bundled with xEdit)
+    #[arg(long, global = true)]
     pub bsarch_path: Option<String>,
-}
\ No newline at end of file
+
+    /// Folder for transient files (xEdit's plugin list, Archive2 file lists, repack and clean extractions) instead of the system temp folder
+    #[arg(long, value_name = "PATH", global = true)]
+    pub work_dir: Option<String>,
+
+    /// Disable BSArch multithreaded packing (-mt)
+    #[arg(long, global = true)]
+    pub bsarch_no_mt: bool,
+
+    /// Disable BSArch archive compression (-z)
+    #[arg(long, global = true)]
+    pub bsarch_no_compress: bool,
+
+    /// Store duplicate files only once when packing with BSArch (-share)
+    #[arg(long, global = true)]
+    pub bsarch_share: bool,
+
+    /// Also create an Xbox-compressed copy of the final archive (Archive2 only)
+    #[arg(long, conflicts_with = "no_archive")]
+    pub also_xbox: bool,
+
+    /// Compression for non-Xbox archives: default or none (uncompressed archives load faster)
+    #[arg(long, value_enum, default_value = "default", global = true)]
+    pub compression: ArchiveCompression,
+
+    /// BA2 version to write: auto-detect from the game, v1 (old-gen) or v7/v8 (next-gen)
+    #[arg(long, value_enum, default_value = "auto", global = true)]
+    pub ba2_version: Ba2Version,
+
+    /// Seconds to wait for an archive locked by the game or MO2 to be released
+    #[arg(long, default_value_t = 30, global = true)]
+    pub lock_timeout: u64,
+
+    /// Write archives in a fixed file order (single-threaded BSArch) so identical inputs give identical BA2s
+    #[arg(long, global = true)]
+    pub reproducible: bool,
+
+    /// Maximum size in MB of a single archive before content is split into additional archives (1-4096)
+    #[arg(long, default_value_t = 4000, value_parser = clap::value_parser!(u64).range(1..=MAX_ARCHIVE_SIZE_MB))]
+    pub max_archive_size: u64,
+
+    /// Move loose precombines and vis files to a backup folder after archiving instead of deleting them
+    #[arg(long, conflicts_with = "no_archive")]
+    pub keep_loose: bool,
+
+    /// List the files each archive stage would pack without creating archives or moving files
+    #[arg(long, conflicts_with = "no_archive")]
+    pub archive_dry_run: bool,
+
+    /// Folder receiving one `<plugin>-<timestamp>` folder per build with its logs and report (`previsbine-runs` next to the executable by default)
+    #[arg(long, default_value_t = default_runs_dir(), hide_default_value = true)]
+    pub runs_dir: String,
+
+    /// Number of run folders kept per plugin; older ones are removed when a build starts
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_KEEP_RUNS)]
+    pub keep_runs: usize,
+
+    /// Also remove run folders started more than this many days ago when a build starts
+    #[arg(long, value_name = "DAYS")]
+    pub keep_days: Option<u64>,
+
+    /// Also copy the finished plugin and archives into the run folder
+    #[arg(long)]
+    pub collect_outputs: bool,
+
+    /// When PLUGIN is a path outside Data, copy the finished plugin and archives back next to it
+    #[arg(long)]
+    pub copy_back: bool,
+
+    /// Package the plugin and its archives into a zip in the run folder after a successful build
+    #[arg(long, conflicts_with = "archive_dry_run")]
+    pub package: bool,
+
+    /// Version appended to the package name (`<plugin>-<version>.zip`)
+    #[arg(long, requires = "package")]
+    pub package_version: Option<String>,
+
+    /// Readme template added to the package; {plugin}, {version} and {files} are filled in
+    #[arg(long, requires = "package")]
+    pub readme_template: Option<String>,
+
+    /// Also write an upload description next to the package, filled in with the files, build mode, game, CK and CKPE versions and cell counts, and a changelog section to complete
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "package")]
+    pub release_notes: Option<NotesFormat>,
+
+    /// Don't back up the files the build overwrites or deletes
+    #[arg(long)]
+    pub no_backup: bool,
+
+    /// Skip the archive stages and leave precombines and vis files loose
+    #[arg(long)]
+    pub no_archive: bool,
+
+    /// Move the loose precombines and vis files to this folder (Requires --no-archive)
+    #[arg(long, requires = "no_archive")]
+    pub loose_output_dir: Option<String>,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+}
+
+/// How often a stage is retried when it fails (--retry)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StageRetry {
+    pub stage: BuildStage,
+    pub retries: u32,
+}
+
+/// Parses `STAGE=N`, where STAGE is a stage name (`generate-previs` or
+/// `generate_previs`) or number
+fn parse_stage_retry(value: &str) -> Result<StageRetry, String> {
+    let (name, retries) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected STAGE=N, got '{}'", value))?;
+    let name = name.trim();
+    let stage = parse_stage_name(name).ok_or_else(|| format!("unknown stage '{}'", name))?;
+    let retries = retries
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid number of retries '{}'", retries.trim()))?;
+    Ok(StageRetry { stage, retries })
+}
+
+/// An environment variable set for the tools of one stage, or of every
+/// stage (--tool-env)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolEnv {
+    /// The stage whose tools get the variable, or None for every stage
+    pub stage: Option<BuildStage>,
+    pub name: String,
+    pub value: String,
+}
+
+/// Parses a stage name (`generate-previs` or `generate_previs`) or number
+pub fn parse_stage_name(name: &str) -> Option<BuildStage> {
+    match name.parse::<i32>() {
+        Ok(number) => BuildStage::from_i32(number),
+        Err(_) => BuildStage::from_str(&name.replace('_', "-"), true).ok(),
+    }
+}
+
+/// Parses `[STAGE:]NAME=VALUE`, where STAGE is a stage name or number
+fn parse_tool_env(value: &str) -> Result<ToolEnv, String> {
+    let (target, variable_value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected [STAGE:]NAME=VALUE, got '{}'", value))?;
+    let (stage, name) = match target.split_once(':') {
+        Some((stage, name)) => {
+            let stage = parse_stage_name(stage.trim()).ok_or_else(|| format!("unknown stage '{}'", stage.trim()))?;
+            (Some(stage), name)
+        }
+        None => (None, target),
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("missing variable name in '{}'", value));
+    }
+    Ok(ToolEnv { stage, name: name.to_string(), value: variable_value.to_string() })
+}
+
+/// Intermediate files kept instead of being deleted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeepFiles {
+    pub psg: bool,
+    pub working_esps: bool,
+    pub vis_loose: bool,
+    pub ck_logs: bool,
+}
+
+impl Args {
+    /// Parses the command line, noting whether --mode was given, and takes
+    /// the settings it doesn't give from the configuration file
+    pub fn parse_command_line(config: Option<&Config>) -> Self {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        args.mode_given = matches.value_source("mode") == Some(ValueSource::CommandLine);
+        if let Some(config) = config {
+            if let Err(e) = config.apply(&mut args, &matches) {
+                Args::command().error(ErrorKind::InvalidValue, e).exit();
+            }
+        }
+        args.apply_presets(&matches);
+        args
+    }
+
+    /// Turns on the options of the --wabbajack, --ci and --fallout-london
+    /// presets, keeping the ones given on the command line
+    pub fn apply_presets(&mut self, matches: &ArgMatches) {
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if self.wabbajack {
+            self.apply_wabbajack_preset(given("output"));
+        }
+        if self.ci {
+            self.apply_ci_preset();
+        }
+        if self.fallout_london && !given("ba2_version") {
+            self.apply_fallout_london_preset();
+        }
+    }
+
+    /// Turns on the options --ci stands for, keeping a --build-timeout or
+    /// --artifacts-dir given with it
+    fn apply_ci_preset(&mut self) {
+        self.headless = true;
+        self.collect_outputs = true;
+        self.check_updates = false;
+        self.notify = false;
+        self.build_timeout.get_or_insert(DEFAULT_CI_TIMEOUT_MINUTES);
+        self.artifacts_dir.get_or_insert_with(|| DEFAULT_ARTIFACTS_DIR.to_string());
+    }
+
+    /// Turns on the options --fallout-london stands for: the downgraded
+    /// game only loads version 1 archives, unless --ba2-version was given
+    fn apply_fallout_london_preset(&mut self) {
+        self.ba2_version = Ba2Version::V1;
+    }
+
+    /// Turns on the options --wabbajack stands for: headless mode with
+    /// reproducible archives and machine-readable progress
+    ///
+    /// # Arguments
+    /// * `output_given` - Whether --output was given, which is then kept
+    fn apply_wabbajack_preset(&mut self, output_given: bool) {
+        self.headless = true;
+        self.reproducible = true;
+        self.check_updates = false;
+        self.notify = false;
+        if !output_given && self.output_file.is_none() {
+            self.output = OutputFormat::Jsonl;
+        }
+    }
+
+    /// Returns the intermediate files to keep, from --keep-files and the --keep-* options
+    pub fn keep(&self) -> KeepFiles {
+        KeepFiles {
+            psg: self.keep_files || self.keep_psg,
+            working_esps: self.keep_files || self.keep_working_esps,
+            vis_loose: self.keep_files || self.keep_vis_loose,
+            ck_logs: self.keep_files || self.keep_ck_logs,
+        }
+    }
+
+    /// Returns how often a failing stage is retried (the last --retry for it wins)
+    pub fn retries_for(&self, stage: BuildStage) -> u32 {
+        self.retries
+            .iter()
+            .rev()
+            .find(|retry| retry.stage == stage)
+            .map_or(0, |retry| retry.retries)
+    }
+
+    /// Returns the environment variables for the tools of a stage: the ones
+    /// for every stage, then the stage's own, the last --tool-env for a
+    /// variable winning
+    pub fn tool_env_for(&self, stage: BuildStage) -> Vec<(String, String)> {
+        let mut variables = BTreeMap::new();
+        let every_stage = self.tool_env.iter().filter(|env| env.stage.is_none());
+        let this_stage = self.tool_env.iter().filter(|env| env.stage == Some(stage));
+        for env in every_stage.chain(this_stage) {
+            variables.insert(env.name.clone(), env.value.clone());
+        }
+        variables.into_iter().collect()
+    }
+
+    /// Returns --max-archive-size in bytes
+    pub fn max_archive_bytes(&self) -> Result<u64, String> {
+        self.max_archive_size
+            .checked_mul(1024 * 1024)
+            .ok_or_else(|| format!("ERROR - --max-archive-size {} MB is too large", self.max_archive_size))
+    }
+
+    /// Returns the archiver selected by --archiver or --use-bsarch
+    pub fn archiver_kind(&self) -> ArchiverKind {
+        if self.use_bsarch {
+            ArchiverKind::Bsarch
+        } else {
+            self.archiver
+        }
+    }
+}
+
+/// Maintenance commands that run instead of a build
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Re-create an existing archive with a different BA2 version or compression
+    Repack(RepackArgs),
+    /// Restore the files backed up by a previous build
+    RestoreBackup(RestoreBackupArgs),
+    /// Remove a plugin's generated precombine and previs data so it can be rebuilt
+    CleanPrevisbines(CleanPrevisbinesArgs),
+    /// List previous builds, or show the details of one
+    History(HistoryArgs),
+    /// Ask a running build to stop after its current stage
+    Abort(AbortArgs),
+    /// Set up the game and tool paths and the default archiver and build mode
+    Setup,
+    /// Create a fake Fallout 4 installation for testing or reproducing path problems
+    Fixture(FixtureArgs),
+    /// Download and install the latest release
+    SelfUpdate(SelfUpdateArgs),
+    /// Compare the files of two builds: their manifests, run folders, build numbers or archives
+    DiffBuilds(DiffBuildsArgs),
+    /// Remove old run folders, applying --keep-runs and --keep-days unless other limits are given
+    CleanLogs(CleanLogsArgs),
+    /// Check a finished plugin and its archive for missing precombined meshes and previs data
+    Check(CheckArgs),
+    /// Re-enable the ENB/ReShade DLLs a crashed build left disabled (`*-PJMdisabled`)
+    RepairDlls,
+    /// Work with the settings file
+    Config(ConfigArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub action: ConfigAction,
+}
+
+/// What to do with the settings file
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigAction {
+    /// Check the settings file for unknown keys, conflicting or deprecated settings and paths that don't exist
+    Lint(ConfigLintArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConfigLintArgs {
+    /// Settings file to check (defaults to the one next to the executable)
+    #[arg(value_name = "FILE")]
+    pub file: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RepackArgs {
+    /// Archive to repack (absolute, or relative to the Data folder)
+    #[arg(value_name = "ARCHIVE")]
+    pub archive: String,
+
+    /// Use Xbox compression for the new archive (Archive2 only)
+    #[arg(long)]
+    pub xbox: bool,
+
+    /// Write the new archive here instead of replacing the original
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RestoreBackupArgs {
+    /// Backup folder to restore (defaults to the most recent backup)
+    #[arg(value_name = "BACKUP_DIR")]
+    pub backup: Option<String>,
+
+    /// Restore the most recent backup of this plugin
+    #[arg(long, conflicts_with = "backup")]
+    pub plugin: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CleanPrevisbinesArgs {
+    /// Plugin whose precombine and previs data is removed
+    #[arg(value_name = "PLUGIN")]
+    pub plugin: String,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct HistoryArgs {
+    /// Number of the build to show in detail
+    #[arg(value_name = "BUILD")]
+    pub build: Option<u32>,
+
+    /// Only list builds of this plugin
+    #[arg(long, conflicts_with = "build")]
+    pub plugin: Option<String>,
+
+    /// Number of builds to list
+    #[arg(long, default_value_t = 20, conflicts_with = "build")]
+    pub limit: usize,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CheckArgs {
+    /// Plugin to check
+    #[arg(value_name = "PLUGIN")]
+    pub plugin: String,
+
+    /// Archive holding the plugin's precombined meshes and previs (relative to Data; defaults to `<plugin> - Main.ba2`)
+    #[arg(long, value_name = "ARCHIVE")]
+    pub archive: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CleanLogsArgs {
+    /// Keep this many of the newest run folders of each plugin
+    #[arg(long, value_name = "N")]
+    pub keep: Option<usize>,
+
+    /// Remove run folders started more than this many days ago
+    #[arg(long, value_name = "DAYS")]
+    pub older_than: Option<u64>,
+
+    /// Only remove run folders of this plugin (name without extension)
+    #[arg(long)]
+    pub plugin: Option<String>,
+
+    /// List the run folders that would be removed without removing them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiffBuildsArgs {
+    /// The earlier build: a manifest, a run folder, a build number from `history`, or a BA2 archive
+    #[arg(value_name = "OLD")]
+    pub old: String,
+
+    /// The later build, given the same ways
+    #[arg(value_name = "NEW")]
+    pub new: String,
+
+    /// Also list the files that didn't change
+    #[arg(long)]
+    pub unchanged: bool,
+}
+
+/// Output format of --output
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Console messages for people
+    Text,
+    /// One JSON object per line for each build event
+    Jsonl,
+}
+
+/// Markup of the upload description written with --release-notes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotesFormat {
+    /// Markdown, for GitHub releases and most mod sites
+    Markdown,
+    /// BBCode, for the Nexus Mods description editor
+    Bbcode,
+}
+
+/// Output format of --print-config
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+/// CKPE configuration file written by the `fixture` command
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum CkpeFormat {
+    /// CreationKitPlatformExtended.ini (CKPE 0.3+)
+    Ini,
+    /// CreationKitPlatformExtended.toml
+    Toml,
+    /// fallout4_test.ini (older CKPE)
+    Legacy,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FixtureArgs {
+    /// Folder to create the installation in (must not exist or be empty)
+    #[arg(value_name = "DIR")]
+    pub dir: String,
+
+    /// Plugin to create in Data
+    #[arg(long, default_value = "Fixture.esp")]
+    pub plugin: String,
+
+    /// CKPE configuration file to write
+    #[arg(long, value_enum, default_value_t = CkpeFormat::Ini)]
+    pub ckpe: CkpeFormat,
+
+    /// Leave the increased reference handles off in the CKPE configuration
+    #[arg(long)]
+    pub no_handles: bool,
+
+    /// BatchVersion written into the xEdit scripts
+    #[arg(long, default_value_t = crate::fixture::FIXTURE_SCRIPT_VERSION)]
+    pub script_version: u32,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelfUpdateArgs {
+    /// Only report whether a newer release exists
+    #[arg(long)]
+    pub check: bool,
+
+    /// Reinstall the latest release even if it isn't newer
+    #[arg(long, conflicts_with = "check")]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AbortArgs {
+    /// Only stop the build of this plugin (defaults to every running build)
+    #[arg(value_name = "PLUGIN")]
+    pub plugin: Option<String>,
+}
@@ -0,0 +1,615 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::cli::{parse_stage_name, ArchiverKind, Args, BuildMode, ConfigFormat, ConfigLintArgs, StageRetry, ToolEnv};
+use crate::game::resolve_ba2_version;
+use crate::paths::Paths;
+use crate::style;
+use crate::tools::split_command_line;
+use crate::utils::exe_dir;
+
+/// Name of the configuration file, kept next to the executable
+pub const CONFIG_FILE: &str = "generate_previsbines.toml";
+
+/// Environment variables that change what the tool does
+const ENVIRONMENT_VARIABLES: [&str; 2] = ["RUST_LOG", "NO_COLOR"];
+
+/// Keys of the top-level settings, as written in the file
+const CONFIG_KEYS: [&str; 18] = [
+    "fallout4-path",
+    "fo4edit-path",
+    "bsarch-path",
+    "bsarch-multithreaded",
+    "bsarch-compress",
+    "bsarch-share",
+    "ck-launcher",
+    "work-dir",
+    "archiver",
+    "mode",
+    "check-updates",
+    "keep-runs",
+    "keep-days",
+    "seed-plugins",
+    "stage-environment",
+    "retries",
+    "default-profile",
+    "profiles",
+];
+
+/// Keys of a `[profiles.<name>]` table
+const PROFILE_KEYS: [&str; 8] = ["fallout4-path", "fo4edit-path", "bsarch-path", "ck-launcher", "work-dir", "archiver", "mode", "retries"];
+
+/// Settings that are no longer read, with what replaces them
+const DEPRECATED_KEYS: [(&str, &str); 1] = [("use-bsarch", "archiver = \"bsarch\"")];
+
+/// Settings written by `setup`, used when the command line doesn't give them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub fallout4_path: Option<String>,
+    pub fo4edit_path: Option<String>,
+    pub bsarch_path: Option<String>,
+    /// Pack with multiple BSArch threads (on unless set to false)
+    pub bsarch_multithreaded: Option<bool>,
+    /// Compress BSArch archives (on unless set to false)
+    pub bsarch_compress: Option<bool>,
+    /// Store duplicate files only once in BSArch archives (off unless set)
+    pub bsarch_share: Option<bool>,
+    /// Loader command the Creation Kit is started through
+    pub ck_launcher: Option<String>,
+    /// Folder for transient files instead of the system temp folder
+    pub work_dir: Option<String>,
+    /// archive2, bsarch or native
+    pub archiver: Option<String>,
+    /// clean, filtered, xbox or previs-only
+    pub mode: Option<String>,
+    /// Check for a newer release at startup (off unless set)
+    pub check_updates: Option<bool>,
+    /// Run folders kept per plugin
+    pub keep_runs: Option<usize>,
+    /// Run folders older than this many days are removed
+    pub keep_days: Option<u64>,
+    /// Template plugins offered as the seed when xPrevisPatch.esp is missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seed_plugins: Vec<String>,
+    /// Environment variables for the tools of a stage, by stage name, or of
+    /// every stage under `all`. --tool-env adds to and overrides them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stage_environment: BTreeMap<String, BTreeMap<String, String>>,
+    /// Retries of a failing stage, by stage name, e.g. `generate_previs = 2`.
+    /// --retry for the same stage takes precedence.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub retries: BTreeMap<String, u32>,
+    /// Profile used when --profile isn't given
+    pub default_profile: Option<String>,
+    /// Named sets of settings, e.g. one per game install or modlist, chosen
+    /// with --profile. A profile's settings replace the ones above.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Settings of one `[profiles.<name>]` table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    pub fallout4_path: Option<String>,
+    pub fo4edit_path: Option<String>,
+    pub bsarch_path: Option<String>,
+    pub ck_launcher: Option<String>,
+    pub work_dir: Option<String>,
+    /// archive2, bsarch or native
+    pub archiver: Option<String>,
+    /// clean, filtered, xbox or previs-only
+    pub mode: Option<String>,
+    /// Retries by stage name, added to and overriding the top-level ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub retries: BTreeMap<String, u32>,
+}
+
+impl Config {
+    /// Path of the configuration file, next to the executable (or in the
+    /// current folder if the executable's location is unknown)
+    pub fn path() -> PathBuf {
+        exe_dir().join(CONFIG_FILE)
+    }
+
+    /// Loads the configuration file.
+    ///
+    /// # Returns
+    /// * `Ok(Some(Config))` with the settings
+    /// * `Ok(None)` if there is no configuration file yet
+    /// * `Err(String)` if the file can't be read or has invalid settings
+    pub fn load() -> Result<Option<Self>, String> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Error reading configuration {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("Error parsing configuration {}: {}", path.display(), e))?;
+        config.archiver_kind()?;
+        config.build_mode()?;
+        config.tool_env()?;
+        config.stage_retries()?;
+        for name in config.profiles.keys() {
+            let profile = config.with_profile(name)?;
+            profile.archiver_kind()?;
+            profile.build_mode()?;
+            profile.stage_retries()?;
+        }
+        if let Some(name) = &config.default_profile {
+            config.with_profile(name)?;
+        }
+        Ok(Some(config))
+    }
+
+    /// The settings with a profile's settings replacing the top-level ones
+    ///
+    /// # Arguments
+    /// * `name` - Name of the profile, ignoring case
+    ///
+    /// # Returns
+    /// * `Ok(Config)` with the profile applied
+    /// * `Err(String)` if there is no such profile
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|(profile_name, _)| profile_name.eq_ignore_ascii_case(name))
+            .map(|(_, profile)| profile)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                if names.is_empty() {
+                    format!("ERROR - Profile '{}' not found, {} has no profiles", name, CONFIG_FILE)
+                } else {
+                    format!("ERROR - Profile '{}' not found in {}, use one of: {}", name, CONFIG_FILE, names.join(", "))
+                }
+            })?;
+
+        let mut config = self.clone();
+        let replace = |setting: &mut Option<String>, value: &Option<String>| {
+            if value.is_some() {
+                setting.clone_from(value);
+            }
+        };
+        replace(&mut config.fallout4_path, &profile.fallout4_path);
+        replace(&mut config.fo4edit_path, &profile.fo4edit_path);
+        replace(&mut config.bsarch_path, &profile.bsarch_path);
+        replace(&mut config.ck_launcher, &profile.ck_launcher);
+        replace(&mut config.work_dir, &profile.work_dir);
+        replace(&mut config.archiver, &profile.archiver);
+        replace(&mut config.mode, &profile.mode);
+        config.retries.extend(profile.retries.iter().map(|(stage, retries)| (stage.clone(), *retries)));
+        Ok(config)
+    }
+
+    /// Writes the configuration file
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path();
+        let settings = toml::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing configuration: {}", e))?;
+        let content = format!(
+            "# Written by `generate_previsbines setup`. Options given on the command line take precedence.\n{}",
+            settings
+        );
+        fs::write(&path, content).map_err(|e| format!("Error writing configuration {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Fills in the settings the command line didn't give
+    ///
+    /// # Arguments
+    /// * `args` - The parsed arguments
+    /// * `matches` - The matches they were parsed from, telling given options from defaults
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if let Some(name) = args.profile.clone().or_else(|| self.default_profile.clone()) {
+            let profile = self.with_profile(&name)?;
+            args.profile = Some(name);
+            return profile.apply_settings(args, &given);
+        }
+        self.apply_settings(args, &given)
+    }
+
+    fn apply_settings(&self, args: &mut Args, given: &dyn Fn(&str) -> bool) -> Result<(), String> {
+        if args.fallout4_path.is_none() {
+            args.fallout4_path = self.fallout4_path.clone();
+        }
+        if args.fo4edit_path.is_none() {
+            args.fo4edit_path = self.fo4edit_path.clone();
+        }
+        if args.bsarch_path.is_none() {
+            args.bsarch_path = self.bsarch_path.clone();
+        }
+        if let Some(multithreaded) = self.bsarch_multithreaded.filter(|_| !given("bsarch_no_mt")) {
+            args.bsarch_no_mt = !multithreaded;
+        }
+        if let Some(compress) = self.bsarch_compress.filter(|_| !given("bsarch_no_compress")) {
+            args.bsarch_no_compress = !compress;
+        }
+        if let Some(share) = self.bsarch_share.filter(|_| !given("bsarch_share")) {
+            args.bsarch_share = share;
+        }
+        if args.ck_launcher.is_none() {
+            args.ck_launcher = self.ck_launcher.clone();
+        }
+        if args.work_dir.is_none() {
+            args.work_dir = self.work_dir.clone();
+        }
+        if let Ok(Some(archiver)) = self.archiver_kind() {
+            if !given("archiver") && !args.use_bsarch {
+                args.archiver = archiver;
+            }
+        }
+        if self.check_updates == Some(true) {
+            args.check_updates = true;
+        }
+        if let Some(keep_runs) = self.keep_runs.filter(|_| !given("keep_runs")) {
+            args.keep_runs = keep_runs;
+        }
+        if args.keep_days.is_none() {
+            args.keep_days = self.keep_days;
+        }
+        if args.seed_plugins.is_empty() {
+            args.seed_plugins = self.seed_plugins.clone();
+        }
+        if let Ok(tool_env) = self.tool_env() {
+            args.tool_env = tool_env.into_iter().chain(args.tool_env.drain(..)).collect();
+        }
+        // The last retry count for a stage wins, so --retry goes after these
+        if let Ok(retries) = self.stage_retries() {
+            args.retries = retries.into_iter().chain(args.retries.drain(..)).collect();
+        }
+        if let Ok(Some(mode)) = self.build_mode() {
+            if !args.mode_given && !args.auto_mode {
+                args.mode = mode;
+                args.mode_given = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the settings this configuration sets, as written in the file
+    pub fn set_keys(&self) -> Vec<&'static str> {
+        let set = [
+            self.fallout4_path.is_some(),
+            self.fo4edit_path.is_some(),
+            self.bsarch_path.is_some(),
+            self.bsarch_multithreaded.is_some(),
+            self.bsarch_compress.is_some(),
+            self.bsarch_share.is_some(),
+            self.ck_launcher.is_some(),
+            self.work_dir.is_some(),
+            self.archiver.is_some(),
+            self.mode.is_some(),
+            self.check_updates.is_some(),
+            self.keep_runs.is_some(),
+            self.keep_days.is_some(),
+            !self.seed_plugins.is_empty(),
+            !self.stage_environment.is_empty(),
+            !self.retries.is_empty(),
+            self.default_profile.is_some(),
+            !self.profiles.is_empty(),
+        ];
+        CONFIG_KEYS.into_iter().zip(set).filter(|(_, set)| *set).map(|(key, _)| key).collect()
+    }
+
+    fn archiver_kind(&self) -> Result<Option<ArchiverKind>, String> {
+        self.archiver
+            .as_deref()
+            .map(|archiver| {
+                ArchiverKind::from_str(archiver, true)
+                    .map_err(|_| format!("ERROR - Unknown archiver '{}' in {}, use archive2, bsarch or native", archiver, CONFIG_FILE))
+            })
+            .transpose()
+    }
+
+    /// The `stage-environment` variables, as if given with --tool-env
+    fn tool_env(&self) -> Result<Vec<ToolEnv>, String> {
+        let mut tool_env = Vec::new();
+        for (stage_name, variables) in &self.stage_environment {
+            let stage = match stage_name.as_str() {
+                "all" => None,
+                name => Some(parse_stage_name(name).ok_or_else(|| {
+                    format!(
+                        "ERROR - Unknown stage '{}' in stage-environment of {}, use a stage name like generate-previs or all",
+                        name, CONFIG_FILE
+                    )
+                })?),
+            };
+            tool_env.extend(variables.iter().map(|(name, value)| ToolEnv {
+                stage,
+                name: name.clone(),
+                value: value.clone(),
+            }));
+        }
+        Ok(tool_env)
+    }
+
+    /// The `retries` table, as if given with --retry
+    fn stage_retries(&self) -> Result<Vec<StageRetry>, String> {
+        self.retries
+            .iter()
+            .map(|(name, retries)| {
+                let stage = parse_stage_name(name).ok_or_else(|| {
+                    format!(
+                        "ERROR - Unknown stage '{}' in retries of {}, use a stage name like generate_previs",
+                        name, CONFIG_FILE
+                    )
+                })?;
+                Ok(StageRetry { stage, retries: *retries })
+            })
+            .collect()
+    }
+
+    fn build_mode(&self) -> Result<Option<BuildMode>, String> {
+        self.mode
+            .as_deref()
+            .map(|mode| {
+                BuildMode::from_str(mode, true)
+                    .map_err(|_| format!("ERROR - Unknown build mode '{}' in {}, use clean, filtered, xbox or previs-only", mode, CONFIG_FILE))
+            })
+            .transpose()
+    }
+}
+
+/// Everything --print-config shows
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct EffectiveConfig<'a> {
+    settings_file: PathBuf,
+    settings_file_found: bool,
+    paths: ResolvedPaths,
+    environment: BTreeMap<&'static str, String>,
+    options: &'a Args,
+}
+
+/// Tool locations as the build would resolve them
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResolvedPaths {
+    fallout4: Option<PathBuf>,
+    fo4edit: Option<PathBuf>,
+    creation_kit: Option<PathBuf>,
+    archive2: Option<PathBuf>,
+    bsarch: Option<PathBuf>,
+    /// BA2 version the native writer would write
+    ba2_version: Option<u32>,
+    /// Why the paths couldn't be resolved
+    error: Option<String>,
+}
+
+/// Prints the settings a build would use, merged from the command line,
+/// the settings file and the defaults, with the paths resolved (--print-config).
+///
+/// # Arguments
+/// * `args` - The arguments with the settings file applied
+/// * `config` - The settings file, if one was loaded
+/// * `format` - TOML or JSON
+///
+/// # Returns
+/// * `Ok(())` once printed
+/// * `Err(String)` if the settings can't be serialized
+pub fn print_effective_config(args: &Args, config: Option<&Config>, format: ConfigFormat) -> Result<(), String> {
+    let paths = match Paths::new(
+        args.fo4edit_path.clone(),
+        args.fallout4_path.clone(),
+        args.archiver_kind() == ArchiverKind::Bsarch,
+        args.bsarch_path.clone(),
+    ) {
+        Ok(paths) => ResolvedPaths {
+            ba2_version: resolve_ba2_version(args.ba2_version, &paths.fallout4),
+            fallout4: Some(paths.fallout4),
+            fo4edit: Some(paths.fo4edit),
+            creation_kit: Some(paths.creation_kit),
+            archive2: Some(paths.archive2),
+            bsarch: paths.bsarch,
+            error: None,
+        },
+        Err(e) => ResolvedPaths {
+            fallout4: None,
+            fo4edit: None,
+            creation_kit: None,
+            archive2: None,
+            bsarch: None,
+            ba2_version: None,
+            error: Some(e),
+        },
+    };
+
+    let effective = EffectiveConfig {
+        settings_file: Config::path(),
+        settings_file_found: config.is_some(),
+        paths,
+        environment: ENVIRONMENT_VARIABLES
+            .iter()
+            .filter_map(|name| env::var(name).ok().map(|value| (*name, value)))
+            .collect(),
+        options: args,
+    };
+    let text = match format {
+        ConfigFormat::Toml => toml::to_string_pretty(&effective).map_err(|e| format!("Error serializing settings: {}", e))?,
+        ConfigFormat::Json => serde_json::to_string_pretty(&effective).map_err(|e| format!("Error serializing settings: {}", e))?,
+    };
+    println!("{}", text);
+    Ok(())
+}
+
+/// Checks a settings file for keys that aren't settings, deprecated or
+/// conflicting settings, invalid values and paths that don't exist.
+///
+/// # Arguments
+/// * `path` - The settings file
+///
+/// # Returns
+/// * `Ok(Vec<String>)` with a message per problem, starting with `ERROR - ` or `WARNING - `
+/// * `Err(String)` if the file can't be read
+pub fn lint_config(path: &Path) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Error reading configuration {}: {}", path.display(), e))?;
+    let table: toml::Table = match content.parse() {
+        Ok(table) => table,
+        Err(e) => return Ok(vec![format!("ERROR - {} isn't valid TOML: {}", path.display(), e)]),
+    };
+
+    let mut problems = Vec::new();
+    lint_keys(&table, &CONFIG_KEYS, "", &mut problems);
+    if let Some(toml::Value::Table(profiles)) = table.get("profiles") {
+        for (name, profile) in profiles {
+            match profile {
+                toml::Value::Table(profile) => lint_keys(profile, &PROFILE_KEYS, &format!("profiles.{}.", name), &mut problems),
+                _ => problems.push(format!("ERROR - profiles.{} must be a table of settings", name)),
+            }
+        }
+    }
+
+    let config: Config = match toml::from_str(&content) {
+        Ok(config) => config,
+        Err(e) => {
+            problems.push(format!("ERROR - {}", e.message()));
+            return Ok(problems);
+        }
+    };
+    problems.extend(config.archiver_kind().err());
+    problems.extend(config.build_mode().err());
+    problems.extend(config.tool_env().err());
+    problems.extend(config.stage_retries().err());
+    if let Some(name) = &config.default_profile {
+        problems.extend(config.with_profile(name).err());
+    }
+    lint_paths(&config, "", &mut problems);
+    lint_bsarch_path(&config, "", &mut problems);
+    if let Some(fallout4) = &config.fallout4_path {
+        let data_dir = Path::new(fallout4).join("Data");
+        for seed in config.seed_plugins.iter().filter(|seed| !data_dir.join(seed).exists()) {
+            problems.push(format!("WARNING - seed-plugins: {} not found in {}", seed, data_dir.display()));
+        }
+    }
+
+    for (name, profile) in &config.profiles {
+        let prefix = format!("profiles.{}.", name);
+        let own = Config {
+            fallout4_path: profile.fallout4_path.clone(),
+            fo4edit_path: profile.fo4edit_path.clone(),
+            bsarch_path: profile.bsarch_path.clone(),
+            ck_launcher: profile.ck_launcher.clone(),
+            work_dir: profile.work_dir.clone(),
+            archiver: profile.archiver.clone(),
+            mode: profile.mode.clone(),
+            retries: profile.retries.clone(),
+            ..Config::default()
+        };
+        problems.extend(own.archiver_kind().err().map(|e| format!("{} (profile {})", e, name)));
+        problems.extend(own.build_mode().err().map(|e| format!("{} (profile {})", e, name)));
+        problems.extend(own.stage_retries().err().map(|e| format!("{} (profile {})", e, name)));
+        lint_paths(&own, &prefix, &mut problems);
+        // The profile's archiver and BSArch path may each come from the top level
+        if profile.bsarch_path.is_some() || profile.archiver.is_some() {
+            lint_bsarch_path(&config.with_profile(name)?, &prefix, &mut problems);
+        }
+    }
+    Ok(problems)
+}
+
+/// Reports keys of a settings table that aren't settings or are deprecated
+fn lint_keys(table: &toml::Table, known: &[&str], prefix: &str, problems: &mut Vec<String>) {
+    for key in table.keys().filter(|key| !known.contains(&key.as_str())) {
+        if let Some((_, replacement)) = DEPRECATED_KEYS.iter().find(|(deprecated, _)| deprecated == key) {
+            problems.push(format!("WARNING - {}{} is deprecated and ignored, use {} instead", prefix, key, replacement));
+            continue;
+        }
+        let normalized = key.replace('_', "-").to_lowercase();
+        match known.iter().find(|known| **known == normalized) {
+            Some(known) => problems.push(format!("WARNING - Unknown setting {}{} is ignored, did you mean {}{}?", prefix, key, prefix, known)),
+            None => problems.push(format!("WARNING - Unknown setting {}{} is ignored", prefix, key)),
+        }
+    }
+}
+
+/// Reports configured paths that don't exist
+fn lint_paths(config: &Config, prefix: &str, problems: &mut Vec<String>) {
+    if let Some(fallout4) = &config.fallout4_path {
+        let fallout4 = Path::new(fallout4);
+        if !fallout4.is_dir() {
+            problems.push(format!("ERROR - {}fallout4-path: folder {} doesn't exist", prefix, fallout4.display()));
+        } else if !fallout4.join("Fallout4.exe").exists() {
+            problems.push(format!(
+                "WARNING - {}fallout4-path: {} has no Fallout4.exe, is it the game folder?",
+                prefix,
+                fallout4.display()
+            ));
+        }
+    }
+    for (key, path) in [("fo4edit-path", &config.fo4edit_path), ("bsarch-path", &config.bsarch_path)] {
+        match path.as_deref().map(Path::new) {
+            Some(path) if path.is_dir() => {
+                problems.push(format!("ERROR - {}{}: {} is a folder, give the path of the executable", prefix, key, path.display()))
+            }
+            Some(path) if !path.exists() => {
+                problems.push(format!("ERROR - {}{}: {} doesn't exist", prefix, key, path.display()))
+            }
+            _ => {}
+        }
+    }
+    if let Some(launcher) = &config.ck_launcher {
+        match split_command_line(launcher).first() {
+            None => problems.push(format!("ERROR - {}ck-launcher is empty", prefix)),
+            // Programs given by name alone are looked up on PATH
+            Some(program) if Path::new(program).components().count() > 1 && !Path::new(program).exists() => {
+                problems.push(format!("ERROR - {}ck-launcher: {} doesn't exist", prefix, program))
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(work_dir) = config.work_dir.as_deref().filter(|dir| !Path::new(dir).is_dir()) {
+        problems.push(format!("WARNING - {}work-dir: folder {} doesn't exist and will be created", prefix, work_dir));
+    }
+}
+
+/// Reports a BSArch path that isn't used because BSArch isn't the archiver
+fn lint_bsarch_path(config: &Config, prefix: &str, problems: &mut Vec<String>) {
+    if config.bsarch_path.is_some() && !matches!(config.archiver_kind(), Ok(Some(ArchiverKind::Bsarch))) {
+        problems.push(format!(
+            "WARNING - {}bsarch-path is set but only used with archiver = \"bsarch\", set that too or remove bsarch-path",
+            prefix
+        ));
+    }
+}
+
+/// Runs `config lint`: prints the problems found in the settings file.
+///
+/// # Returns
+/// * `Ok(())` if there are no errors, warnings aside
+/// * `Err(String)` if the file has errors or can't be read
+pub fn run_config_lint(lint: &ConfigLintArgs) -> Result<(), String> {
+    let path = lint.file.as_ref().map(PathBuf::from).unwrap_or_else(Config::path);
+    if !path.exists() {
+        println!("No settings file at {}, run `generate_previsbines setup` to create one", path.display());
+        return Ok(());
+    }
+
+    let problems = lint_config(&path)?;
+    for problem in &problems {
+        if problem.starts_with("ERROR") {
+            println!("{}", style::error(problem));
+        } else {
+            println!("{}", style::warning(problem));
+        }
+    }
+
+    let errors = problems.iter().filter(|problem| problem.starts_with("ERROR")).count();
+    if errors > 0 {
+        return Err(format!("ERROR - {} errors in {}", errors, path.display()));
+    }
+    match problems.len() {
+        0 => println!("No problems found in {}", path.display()),
+        warnings => println!("{} warnings in {}", warnings, path.display()),
+    }
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::fmt::Write as _;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::cli::Args;
+use crate::game::{detect_game_build, read_exe_version};
+use crate::paths::Paths;
+use crate::report::BuildReport;
+
+/// Number of lines kept from the end of each tool log
+const LOG_EXCERPT_LINES: usize = 500;
+
+/// Number of lines shown from the end of a tool log when a stage fails
+/// without a recognizable error line
+pub(crate) const FAILURE_EXCERPT_LINES: usize = 20;
+
+/// Lines shown before and after the last error line of a tool log
+pub(crate) const ERROR_CONTEXT_LINES: usize = 5;
+
+/// Markers of lines reporting errors in Creation Kit and xEdit logs (lowercase)
+pub(crate) const ERROR_MARKERS: [&str; 5] = ["error", "fatal", "failed", "exception", "out of handle"];
+
+/// Packs what is needed to diagnose a failed build into one zip.
+///
+/// The bundle holds the build log, the last lines of every Creation Kit and
+/// xEdit log in the run folder (compressed ones included), a description of the environment, the
+/// effective configuration and the build report.
+///
+/// # Arguments
+/// * `bundle_path` - Path of the zip to create
+/// * `run_dir` - The run folder holding the logs
+/// * `build_log` - The build log, included in full
+/// * `paths` - Paths to the game and tools
+/// * `args` - The effective command line arguments
+/// * `report` - The build report
+///
+/// # Returns
+/// * `Ok(())` if the bundle was written
+/// * `Err(String)` if it couldn't be written
+pub fn create_diagnostics_bundle(
+    bundle_path: &Path,
+    run_dir: &Path,
+    build_log: &Path,
+    paths: &Paths,
+    args: &Args,
+    report: &BuildReport,
+) -> Result<(), String> {
+    let file = File::create(bundle_path)
+        .map_err(|e| format!("Error creating diagnostics bundle {}: {}", bundle_path.display(), e))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let write_error = |e: String| format!("Error writing diagnostics bundle {}: {}", bundle_path.display(), e);
+
+    let mut add = |name: &str, content: &[u8]| -> Result<(), String> {
+        zip.start_file(name, options).map_err(|e| write_error(e.to_string()))?;
+        zip.write_all(content).map_err(|e| write_error(e.to_string()))
+    };
+
+    if let Ok(log) = fs::read(build_log) {
+        add("build.log", &log)?;
+    }
+
+    let mut tool_logs: Vec<PathBuf> = fs::read_dir(run_dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    tool_logs.retain(|path| is_tool_log(path) && path != build_log);
+    tool_logs.sort();
+    for log in tool_logs {
+        let Ok(file) = File::open(&log) else {
+            continue;
+        };
+        let name = log.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let excerpt = match name.strip_suffix(".gz") {
+            Some(name) => log_excerpt(BufReader::new(GzDecoder::new(file))).map(|excerpt| (name.to_string(), excerpt)),
+            None => log_excerpt(BufReader::new(file)).map(|excerpt| (name, excerpt)),
+        };
+        if let Ok((name, excerpt)) = excerpt {
+            add(&format!("logs/{}", name), excerpt.as_bytes())?;
+        }
+    }
+
+    add("environment.txt", describe_environment(paths, args).as_bytes())?;
+    add("config.txt", format!("{:#?}\n", args).as_bytes())?;
+    let report_json = serde_json::to_string_pretty(report)
+        .map_err(|e| format!("Error serializing build report: {}", e))?;
+    add("report.json", report_json.as_bytes())?;
+
+    zip.finish().map_err(|e| write_error(e.to_string()))?;
+    Ok(())
+}
+
+/// Whether a run folder file is a tool log, plain or gzip-compressed
+fn is_tool_log(path: &Path) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    name.ends_with(".log") || name.ends_with(".log.gz")
+}
+
+/// Returns the last lines of a log, noting how many were left out, reading
+/// it line by line so large logs aren't loaded whole
+fn log_excerpt(mut reader: impl BufRead) -> io::Result<String> {
+    let mut lines: VecDeque<String> = VecDeque::with_capacity(LOG_EXCERPT_LINES);
+    let mut total = 0;
+    let mut buffer = Vec::new();
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        if lines.len() == LOG_EXCERPT_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(String::from_utf8_lossy(&buffer).trim_end_matches(['\r', '\n']).to_string());
+        total += 1;
+        buffer.clear();
+    }
+
+    let mut excerpt = String::new();
+    if total > LOG_EXCERPT_LINES {
+        let _ = writeln!(excerpt, "[{} earlier lines omitted]", total - LOG_EXCERPT_LINES);
+    }
+    for line in lines {
+        let _ = writeln!(excerpt, "{}", line);
+    }
+    Ok(excerpt)
+}
+
+/// Picks the part of a tool log worth showing when a stage fails: the last
+/// line reporting an error with the lines around it, or else the end of the log
+///
+/// # Returns
+/// The lines to show, in order
+pub fn failure_excerpt(log: &str) -> Vec<&str> {
+    let lines: Vec<&str> = log.lines().collect();
+    let last_error = lines.iter().rposition(|line| {
+        let line = line.to_lowercase();
+        ERROR_MARKERS.iter().any(|marker| line.contains(marker))
+    });
+
+    match last_error {
+        Some(index) => {
+            let start = index.saturating_sub(ERROR_CONTEXT_LINES);
+            let end = (index + ERROR_CONTEXT_LINES + 1).min(lines.len());
+            lines[start..end].to_vec()
+        }
+        None => lines[lines.len().saturating_sub(FAILURE_EXCERPT_LINES)..].to_vec(),
+    }
+}
+
+/// Describes the tool, system, game and tool paths
+fn describe_environment(paths: &Paths, args: &Args) -> String {
+    let version_of = |path: &Path| {
+        read_exe_version(path)
+            .map(|version| version.to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+
+    let mut environment = String::new();
+    let _ = writeln!(environment, "Tool version: {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(environment, "System: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let _ = writeln!(environment, "Fallout 4: {}", paths.fallout4.display());
+    let _ = writeln!(environment, "Fallout4.exe version: {}", version_of(&paths.fallout4.join("Fallout4.exe")));
+    let _ = writeln!(
+        environment,
+        "Game build: {}",
+        detect_game_build(&paths.fallout4).map(|build| build.to_string()).unwrap_or_else(|| "unknown".to_string())
+    );
+    let _ = writeln!(environment, "Creation Kit: {} ({})", paths.creation_kit.display(), version_of(&paths.creation_kit));
+    let _ = writeln!(environment, "xEdit: {} ({})", paths.fo4edit.display(), version_of(&paths.fo4edit));
+    let _ = writeln!(environment, "Archive2: {}", paths.archive2.display());
+    let _ = writeln!(
+        environment,
+        "BSArch: {}",
+        paths.bsarch.as_ref().map(|path| path.display().to_string()).unwrap_or_else(|| "not found".to_string())
+    );
+    let _ = writeln!(environment, "Archiver: {}", args.archiver_kind());
+    let _ = writeln!(
+        environment,
+        "CKPE: {}",
+        if paths.fallout4.join("winhttp.dll").exists() { "installed" } else { "not found" }
+    );
+    environment
+}
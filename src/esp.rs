@@ -0,0 +1,641 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// Size of a record header (type, data size, flags, form ID, version control, version)
+const RECORD_HEADER_SIZE: usize = 24;
+
+/// Record version written for Fallout 4 records
+const FO4_RECORD_VERSION: u16 = 131;
+
+/// HEDR version of Fallout 4 plugins
+const FO4_HEADER_VERSION: f32 = 1.0;
+
+/// First object ID available to a new plugin
+const FIRST_OBJECT_ID: u32 = 0x800;
+
+/// Record flag marking a master file
+pub const FLAG_MASTER: u32 = 0x1;
+
+/// Record flag marking a light (ESL-flagged) plugin
+pub const FLAG_LIGHT: u32 = 0x200;
+
+/// File extensions of Fallout 4 plugins
+pub const PLUGIN_EXTENSIONS: [&str; 3] = [".esp", ".esm", ".esl"];
+
+/// Record flag marking zlib-compressed record data
+const FLAG_COMPRESSED: u32 = 0x40000;
+
+/// CELL fields written by the precombine and previs stages: combined
+/// references, physics references, precombine and previs timestamps, and
+/// the previs reference cell
+pub const PREVISBINE_CELL_FIELDS: [&[u8; 4]; 5] = [b"XCRI", b"XPRI", b"PCMB", b"VISI", b"RVIS"];
+
+/// The TES4 header record of a plugin
+#[derive(Debug, Clone, Default)]
+pub struct PluginHeader {
+    /// Record flags (master, light...)
+    pub flags: u32,
+    /// Plugin format version from HEDR (1.0 for Fallout 4)
+    pub version: f32,
+    /// Number of records and groups from HEDR
+    pub record_count: u32,
+    /// Next available object ID from HEDR
+    pub next_object_id: u32,
+    /// Author (CNAM)
+    pub author: Option<String>,
+    /// Description (SNAM)
+    pub description: Option<String>,
+    /// Master files, in load order (MAST)
+    pub masters: Vec<String>,
+}
+
+impl PluginHeader {
+    /// Creates the header of an empty Fallout 4 plugin with the given masters
+    pub fn new_plugin(masters: &[String]) -> Self {
+        Self {
+            flags: 0,
+            version: FO4_HEADER_VERSION,
+            record_count: 0,
+            next_object_id: FIRST_OBJECT_ID,
+            author: Some("GeneratePrevisbines".to_string()),
+            description: None,
+            masters: masters.to_vec(),
+        }
+    }
+
+    /// Returns true if `plugin` is one of the masters, ignoring case
+    pub fn has_master(&self, plugin: &str) -> bool {
+        self.masters.iter().any(|master| master.eq_ignore_ascii_case(plugin))
+    }
+}
+
+/// Splits a plugin file name into its name and extension.
+///
+/// # Returns
+/// * `Some((name, extension))` if it ends with a plugin extension (in any case)
+/// * `None` otherwise
+pub fn split_plugin_name(file_name: &str) -> Option<(&str, &str)> {
+    let split = file_name.len().checked_sub(4).filter(|split| file_name.is_char_boundary(*split))?;
+    let extension = &file_name[split..];
+    PLUGIN_EXTENSIONS
+        .iter()
+        .any(|known| extension.eq_ignore_ascii_case(known))
+        .then(|| file_name.split_at(split))
+}
+
+/// Resolves the name and file name of a plugin given with or without its
+/// extension. Without one, the extension of the plugin of that name in Data
+/// is used, or else `forced` (--plugin-extension) or `.esp`.
+///
+/// # Arguments
+/// * `plugin` - The plugin as given
+/// * `data_dir` - The Data folder to look for the plugin in
+/// * `forced` - Extension the plugin must have, e.g. `.esm`
+///
+/// # Returns
+/// * `Ok((name, file_name))` - the plugin name without and with its extension
+/// * `Err(String)` if the plugin's extension differs from `forced`
+pub fn resolve_plugin_name(plugin: &str, data_dir: &Path, forced: Option<&str>) -> Result<(String, String), String> {
+    if let Some((name, extension)) = split_plugin_name(plugin) {
+        if let Some(forced) = forced.filter(|forced| !extension.eq_ignore_ascii_case(forced)) {
+            return Err(format!(
+                "ERROR - {} is a {} plugin but --plugin-extension {} was given",
+                plugin,
+                extension,
+                forced.trim_start_matches('.')
+            ));
+        }
+        return Ok((name.to_string(), plugin.to_string()));
+    }
+
+    let extension = match forced {
+        Some(forced) => forced,
+        None => PLUGIN_EXTENSIONS
+            .iter()
+            .copied()
+            .find(|extension| data_dir.join(format!("{}{}", plugin, extension)).exists())
+            .unwrap_or(".esp"),
+    };
+    Ok((plugin.to_string(), format!("{}{}", plugin, extension)))
+}
+
+/// Record flags a plugin's extension calls for: .esm files are masters and
+/// .esl files light masters
+pub fn extension_flags(file_name: &str) -> u32 {
+    match split_plugin_name(file_name).map(|(_, extension)| extension.to_lowercase()) {
+        Some(extension) if extension == ".esm" => FLAG_MASTER,
+        Some(extension) if extension == ".esl" => FLAG_MASTER | FLAG_LIGHT,
+        _ => 0,
+    }
+}
+
+/// Sets record flags in a plugin's TES4 header, leaving the rest of the
+/// file as it is.
+///
+/// # Arguments
+/// * `path` - Path to the plugin
+/// * `flags` - Flags to set in addition to those already set
+pub fn set_plugin_flags(path: &Path, flags: u32) -> Result<(), String> {
+    let update = || -> std::io::Result<bool> {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let mut record_header = [0u8; 12];
+        file.read_exact(&mut record_header)?;
+        if &record_header[0..4] != b"TES4" {
+            return Ok(false);
+        }
+        let current = u32::from_le_bytes(record_header[8..12].try_into().unwrap());
+        file.seek(SeekFrom::Start(8))?;
+        file.write_all(&(current | flags).to_le_bytes())?;
+        Ok(true)
+    };
+    match update() {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("ERROR - {} is not a Fallout 4 plugin", path.display())),
+        Err(e) => Err(format!("Error updating the header of {}: {}", path.display(), e)),
+    }
+}
+
+/// Reads the TES4 header of a plugin.
+///
+/// # Arguments
+/// * `path` - Path to the .esp/.esm/.esl file
+///
+/// # Returns
+/// * `Ok(PluginHeader)` with the header fields
+/// * `Err(String)` if the file can't be read or doesn't start with a TES4 record
+pub fn read_plugin_header(path: &Path) -> Result<PluginHeader, String> {
+    let mut file = File::open(path).map_err(|e| format!("Error opening plugin {}: {}", path.display(), e))?;
+
+    let mut record_header = [0u8; RECORD_HEADER_SIZE];
+    file.read_exact(&mut record_header)
+        .map_err(|e| format!("Error reading plugin {}: {}", path.display(), e))?;
+    if &record_header[0..4] != b"TES4" {
+        return Err(format!("ERROR - {} is not a Fallout 4 plugin", path.display()));
+    }
+
+    let data_size = u32::from_le_bytes(record_header[4..8].try_into().unwrap()) as usize;
+    let mut data = vec![0u8; data_size];
+    file.read_exact(&mut data)
+        .map_err(|e| format!("Error reading the header of {}: {}", path.display(), e))?;
+
+    let mut header = PluginHeader {
+        flags: u32::from_le_bytes(record_header[8..12].try_into().unwrap()),
+        ..PluginHeader::default()
+    };
+
+    let mut offset = 0;
+    while offset + 6 <= data.len() {
+        let kind = &data[offset..offset + 4];
+        let size = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
+        let start = offset + 6;
+        let end = start + size;
+        if end > data.len() {
+            return Err(format!("ERROR - The header of {} is truncated", path.display()));
+        }
+        let field = &data[start..end];
+
+        match kind {
+            b"HEDR" if size >= 12 => {
+                header.version = f32::from_le_bytes(field[0..4].try_into().unwrap());
+                header.record_count = u32::from_le_bytes(field[4..8].try_into().unwrap());
+                header.next_object_id = u32::from_le_bytes(field[8..12].try_into().unwrap());
+            }
+            b"CNAM" => header.author = Some(zstring(field)),
+            b"SNAM" => header.description = Some(zstring(field)),
+            b"MAST" => header.masters.push(zstring(field)),
+            _ => {}
+        }
+        offset = end;
+    }
+
+    Ok(header)
+}
+
+/// Cells and worldspaces a plugin adds or overrides
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CellSummary {
+    pub new_interior_cells: usize,
+    pub overridden_interior_cells: usize,
+    pub new_exterior_cells: usize,
+    pub overridden_exterior_cells: usize,
+    pub new_worldspaces: usize,
+    pub overridden_worldspaces: usize,
+}
+
+impl CellSummary {
+    /// Number of cells added by the plugin
+    pub fn new_cells(&self) -> usize {
+        self.new_interior_cells + self.new_exterior_cells
+    }
+
+    /// Number of cells from masters the plugin overrides
+    pub fn overridden_cells(&self) -> usize {
+        self.overridden_interior_cells + self.overridden_exterior_cells
+    }
+}
+
+/// Counts the cells and worldspaces in a plugin.
+///
+/// Only record headers are read, so compressed records don't need to be
+/// decompressed. A record is new if its form ID doesn't belong to a master.
+///
+/// # Returns
+/// * `Ok(CellSummary)` with the counts
+/// * `Err(String)` if the plugin can't be read or is malformed
+pub fn summarize_cells(path: &Path) -> Result<CellSummary, String> {
+    let header = read_plugin_header(path)?;
+    let file = File::open(path).map_err(|e| format!("Error opening plugin {}: {}", path.display(), e))?;
+    let length = file
+        .metadata()
+        .map_err(|e| format!("Error reading plugin {}: {}", path.display(), e))?
+        .len();
+    let mut reader = BufReader::new(file);
+    let read_error = |e: std::io::Error| format!("Error reading plugin {}: {}", path.display(), e);
+
+    let mut summary = CellSummary::default();
+    // Open groups as (end offset, group type)
+    let mut groups: Vec<(u64, i32)> = Vec::new();
+    let mut offset = 0u64;
+    let mut record_header = [0u8; RECORD_HEADER_SIZE];
+
+    while offset + RECORD_HEADER_SIZE as u64 <= length {
+        while groups.last().is_some_and(|(end, _)| offset >= *end) {
+            groups.pop();
+        }
+
+        reader.read_exact(&mut record_header).map_err(read_error)?;
+        let kind = &record_header[0..4];
+        let size = u32::from_le_bytes(record_header[4..8].try_into().unwrap()) as u64;
+
+        if kind == b"GRUP" {
+            // Group sizes include their header, and their content follows directly
+            let group_type = i32::from_le_bytes(record_header[12..16].try_into().unwrap());
+            groups.push((offset + size, group_type));
+            offset += RECORD_HEADER_SIZE as u64;
+            continue;
+        }
+
+        let form_id = u32::from_le_bytes(record_header[12..16].try_into().unwrap());
+        let is_new = (form_id >> 24) as usize >= header.masters.len();
+        match kind {
+            // Interior cells sit in interior block (2) and sub-block (3) groups
+            b"CELL" if matches!(groups.last(), Some((_, 2 | 3))) => {
+                if is_new {
+                    summary.new_interior_cells += 1;
+                } else {
+                    summary.overridden_interior_cells += 1;
+                }
+            }
+            b"CELL" => {
+                if is_new {
+                    summary.new_exterior_cells += 1;
+                } else {
+                    summary.overridden_exterior_cells += 1;
+                }
+            }
+            b"WRLD" => {
+                if is_new {
+                    summary.new_worldspaces += 1;
+                } else {
+                    summary.overridden_worldspaces += 1;
+                }
+            }
+            _ => {}
+        }
+
+        reader.seek_relative(size as i64).map_err(read_error)?;
+        offset += RECORD_HEADER_SIZE as u64 + size;
+    }
+
+    Ok(summary)
+}
+
+/// Precombine and previs data of a CELL record
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CellPrevisbines {
+    /// Form ID of the cell, as stored in the plugin
+    pub form_id: u32,
+    /// Hashes of the precombined meshes (XCRI), each stored as
+    /// `meshes\precombined\<cell>_<hash>_OC.nif`
+    pub combined_meshes: Vec<u32>,
+    /// Number of references combined into the meshes
+    pub combined_references: usize,
+    /// Whether the cell has a previs timestamp (VISI)
+    pub has_previs: bool,
+    /// The cell whose `vis\<cell>.uvd` file holds this cell's previs (RVIS)
+    pub previs_cell: Option<u32>,
+}
+
+/// Reads the precombine and previs fields of every CELL record of a plugin.
+///
+/// # Returns
+/// * `Ok(Vec<CellPrevisbines>)` with one entry per cell, in file order
+/// * `Err(String)` if the plugin can't be read or is malformed
+pub fn read_cell_previsbines(path: &Path) -> Result<Vec<CellPrevisbines>, String> {
+    let data = fs::read(path).map_err(|e| format!("Error reading plugin {}: {}", path.display(), e))?;
+    let mut cells = Vec::new();
+    collect_cells(&data, &mut cells).map_err(|e| format!("ERROR - Can't read {}: {}", path.display(), e))?;
+    Ok(cells)
+}
+
+/// Reads the CELL records of a sequence of records and groups
+fn collect_cells(data: &[u8], cells: &mut Vec<CellPrevisbines>) -> Result<(), String> {
+    let mut offset = 0;
+    while offset < data.len() {
+        if offset + RECORD_HEADER_SIZE > data.len() {
+            return Err(format!("truncated record at offset {}", offset));
+        }
+        let kind = &data[offset..offset + 4];
+        let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+
+        if kind == b"GRUP" {
+            let end = offset + size;
+            if size < RECORD_HEADER_SIZE || end > data.len() {
+                return Err(format!("invalid group size at offset {}", offset));
+            }
+            collect_cells(&data[offset + RECORD_HEADER_SIZE..end], cells)?;
+            offset = end;
+            continue;
+        }
+
+        let end = offset + RECORD_HEADER_SIZE + size;
+        if end > data.len() {
+            return Err(format!("truncated record at offset {}", offset));
+        }
+        if kind == b"CELL" {
+            let record = &data[offset..end];
+            let mut cell = CellPrevisbines {
+                form_id: u32::from_le_bytes(record[12..16].try_into().unwrap()),
+                ..CellPrevisbines::default()
+            };
+            let content = record_content(record)?;
+            for field in split_fields(&content)? {
+                match field.kind {
+                    b"XCRI" => {
+                        let value = |index: usize| {
+                            field
+                                .data
+                                .get(index * 4..index * 4 + 4)
+                                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                                .ok_or_else(|| format!("truncated XCRI field in cell {:08X}", cell.form_id))
+                        };
+                        let mesh_count = value(0)? as usize;
+                        cell.combined_references = value(1)? as usize;
+                        cell.combined_meshes = (0..mesh_count).map(|index| value(2 + index)).collect::<Result<_, _>>()?;
+                    }
+                    b"VISI" => cell.has_previs = true,
+                    b"RVIS" if field.data.len() >= 4 => {
+                        cell.previs_cell = Some(u32::from_le_bytes(field.data[0..4].try_into().unwrap()));
+                    }
+                    _ => {}
+                }
+            }
+            cells.push(cell);
+        }
+        offset = end;
+    }
+    Ok(())
+}
+
+/// Removes fields from every CELL record of a plugin, rewriting it in place.
+///
+/// Compressed records are decompressed and compressed again, and the sizes
+/// of the groups containing changed records are updated.
+///
+/// # Arguments
+/// * `path` - Path to the plugin
+/// * `fields` - Field types to remove (e.g. [`PREVISBINE_CELL_FIELDS`])
+///
+/// # Returns
+/// * `Ok(usize)` with the number of cells that changed; the file is only
+///   written if at least one did
+/// * `Err(String)` if the plugin can't be read, parsed or written
+pub fn strip_cell_fields(path: &Path, fields: &[&[u8; 4]]) -> Result<usize, String> {
+    let data = fs::read(path).map_err(|e| format!("Error reading plugin {}: {}", path.display(), e))?;
+
+    let mut changed = 0;
+    let output = rewrite_records(&data, fields, &mut changed)
+        .map_err(|e| format!("ERROR - Can't edit {}: {}", path.display(), e))?;
+    if changed > 0 {
+        fs::write(path, output).map_err(|e| format!("Error writing plugin {}: {}", path.display(), e))?;
+    }
+    Ok(changed)
+}
+
+/// Copies a sequence of records and groups, stripping fields from CELL records
+fn rewrite_records(data: &[u8], fields: &[&[u8; 4]], changed: &mut usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut offset = 0;
+
+    while offset < data.len() {
+        if offset + RECORD_HEADER_SIZE > data.len() {
+            return Err(format!("truncated record at offset {}", offset));
+        }
+        let kind = &data[offset..offset + 4];
+        let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+
+        if kind == b"GRUP" {
+            let end = offset + size;
+            if size < RECORD_HEADER_SIZE || end > data.len() {
+                return Err(format!("invalid group size at offset {}", offset));
+            }
+            let content = rewrite_records(&data[offset + RECORD_HEADER_SIZE..end], fields, changed)?;
+            output.extend_from_slice(b"GRUP");
+            output.extend_from_slice(&((content.len() + RECORD_HEADER_SIZE) as u32).to_le_bytes());
+            output.extend_from_slice(&data[offset + 8..offset + RECORD_HEADER_SIZE]);
+            output.extend_from_slice(&content);
+            offset = end;
+            continue;
+        }
+
+        let end = offset + RECORD_HEADER_SIZE + size;
+        if end > data.len() {
+            return Err(format!("truncated record at offset {}", offset));
+        }
+        let record = &data[offset..end];
+        match kind {
+            b"CELL" => match strip_record_fields(record, fields)? {
+                Some(stripped) => {
+                    output.extend_from_slice(&stripped);
+                    *changed += 1;
+                }
+                None => output.extend_from_slice(record),
+            },
+            _ => output.extend_from_slice(record),
+        }
+        offset = end;
+    }
+
+    Ok(output)
+}
+
+/// A field of a record
+struct Field<'a> {
+    kind: &'a [u8],
+    /// The whole field with its header (and the XXXX field before a large one)
+    raw: &'a [u8],
+    data: &'a [u8],
+}
+
+/// The fields of a record, decompressed if the record is compressed
+fn record_content(record: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    let flags = u32::from_le_bytes(record[8..12].try_into().unwrap());
+    let body = &record[RECORD_HEADER_SIZE..];
+    if flags & FLAG_COMPRESSED == 0 {
+        return Ok(Cow::Borrowed(body));
+    }
+
+    if body.len() < 4 {
+        return Err("truncated compressed record".to_string());
+    }
+    let mut decompressed = Vec::with_capacity(u32::from_le_bytes(body[0..4].try_into().unwrap()) as usize);
+    ZlibDecoder::new(&body[4..])
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("can't decompress record: {}", e))?;
+    Ok(Cow::Owned(decompressed))
+}
+
+/// Splits the content of a record into its fields
+fn split_fields(content: &[u8]) -> Result<Vec<Field<'_>>, String> {
+    let mut fields = Vec::new();
+    let mut offset = 0;
+    while offset + 6 <= content.len() {
+        let start = offset;
+        let mut kind = &content[offset..offset + 4];
+        let mut size = u16::from_le_bytes([content[offset + 4], content[offset + 5]]) as usize;
+        offset += 6;
+
+        // XXXX gives the size of a following field too large for its 16-bit size
+        if kind == b"XXXX" {
+            if offset + 4 + 6 > content.len() {
+                return Err("truncated XXXX field".to_string());
+            }
+            let large_size = u32::from_le_bytes(content[offset..offset + 4].try_into().unwrap()) as usize;
+            kind = &content[offset + 4..offset + 8];
+            size = large_size;
+            offset += 4 + 6;
+        }
+
+        let end = offset + size;
+        if end > content.len() {
+            return Err("truncated field".to_string());
+        }
+        fields.push(Field { kind, raw: &content[start..end], data: &content[offset..end] });
+        offset = end;
+    }
+    Ok(fields)
+}
+
+/// Removes fields from one record, returning `None` if it had none of them
+fn strip_record_fields(record: &[u8], fields: &[&[u8; 4]]) -> Result<Option<Vec<u8>>, String> {
+    let flags = u32::from_le_bytes(record[8..12].try_into().unwrap());
+    let compressed = flags & FLAG_COMPRESSED != 0;
+    let content = record_content(record)?;
+
+    let mut kept = Vec::with_capacity(content.len());
+    let mut removed = false;
+    for field in split_fields(&content)? {
+        if fields.iter().any(|stripped| stripped.as_slice() == field.kind) {
+            removed = true;
+        } else {
+            kept.extend_from_slice(field.raw);
+        }
+    }
+
+    if !removed {
+        return Ok(None);
+    }
+
+    let body = if compressed {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&kept)
+            .map_err(|e| format!("can't compress record: {}", e))?;
+        let compressed_fields = encoder.finish().map_err(|e| format!("can't compress record: {}", e))?;
+        let mut body = (kept.len() as u32).to_le_bytes().to_vec();
+        body.extend_from_slice(&compressed_fields);
+        body
+    } else {
+        kept
+    };
+
+    let mut stripped = Vec::with_capacity(RECORD_HEADER_SIZE + body.len());
+    stripped.extend_from_slice(&record[0..4]);
+    stripped.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    stripped.extend_from_slice(&record[8..RECORD_HEADER_SIZE]);
+    stripped.extend_from_slice(&body);
+    Ok(Some(stripped))
+}
+
+/// Decodes a null-terminated string field
+fn zstring(field: &[u8]) -> String {
+    let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Writes a plugin consisting only of a TES4 header.
+///
+/// # Arguments
+/// * `path` - Path of the plugin to create
+/// * `header` - The header to write
+///
+/// # Returns
+/// * `Ok(())` if the plugin was written
+/// * `Err(String)` if a field is too long or the file can't be written
+pub fn write_plugin_header(path: &Path, header: &PluginHeader) -> Result<(), String> {
+    let mut data = Vec::new();
+
+    let mut hedr = Vec::with_capacity(12);
+    hedr.extend_from_slice(&header.version.to_le_bytes());
+    hedr.extend_from_slice(&header.record_count.to_le_bytes());
+    hedr.extend_from_slice(&header.next_object_id.to_le_bytes());
+    push_field(&mut data, b"HEDR", &hedr)?;
+
+    if let Some(author) = &header.author {
+        push_field(&mut data, b"CNAM", &to_zstring(author))?;
+    }
+    if let Some(description) = &header.description {
+        push_field(&mut data, b"SNAM", &to_zstring(description))?;
+    }
+    for master in &header.masters {
+        push_field(&mut data, b"MAST", &to_zstring(master))?;
+        // Master file size, unused by the game
+        push_field(&mut data, b"DATA", &0u64.to_le_bytes())?;
+    }
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+    record.extend_from_slice(b"TES4");
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&header.flags.to_le_bytes());
+    record.extend_from_slice(&0u32.to_le_bytes()); // Form ID
+    record.extend_from_slice(&0u32.to_le_bytes()); // Version control info
+    record.extend_from_slice(&FO4_RECORD_VERSION.to_le_bytes());
+    record.extend_from_slice(&0u16.to_le_bytes());
+    record.extend_from_slice(&data);
+
+    let mut file = File::create(path).map_err(|e| format!("Error creating plugin {}: {}", path.display(), e))?;
+    file.write_all(&record)
+        .map_err(|e| format!("Error writing plugin {}: {}", path.display(), e))
+}
+
+fn push_field(data: &mut Vec<u8>, kind: &[u8; 4], field: &[u8]) -> Result<(), String> {
+    let size = u16::try_from(field.len()).map_err(|_| {
+        format!("ERROR - {} field is too long for a plugin header", String::from_utf8_lossy(kind))
+    })?;
+    data.extend_from_slice(kind);
+    data.extend_from_slice(&size.to_le_bytes());
+    data.extend_from_slice(field);
+    Ok(())
+}
+
+/// Encodes a null-terminated string field
+fn to_zstring(value: &str) -> Vec<u8> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::cli::BuildStage;
+use crate::observer::ProgressObserver;
+use crate::report::BuildReport;
+
+/// Where JSON Lines events are written: the original stdout or a file
+static EVENTS: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Writes the events to stdout (--output jsonl, --frontend-mode).
+///
+/// Stdout is reserved for the events; everything else the process (and the
+/// tools it runs) prints goes to stderr instead.
+///
+/// # Returns
+/// * `Ok(())` if stdout was redirected
+/// * `Err(String)` if the standard handles couldn't be changed
+pub fn enable_stdout() -> Result<(), String> {
+    let _ = io::stdout().flush();
+    let events = redirect_stdout()?;
+    set_sink(events)
+}
+
+/// Writes the events to a file (--output-file), leaving the console as it is.
+///
+/// # Returns
+/// * `Ok(())` if the file was created
+/// * `Err(String)` if it can't be created
+pub fn enable_file(path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Error creating event file {}: {}", path.display(), e))?;
+    set_sink(file)
+}
+
+fn set_sink(file: File) -> Result<(), String> {
+    EVENTS
+        .set(Mutex::new(file))
+        .map_err(|_| "ERROR - Event output already enabled".to_string())
+}
+
+/// Returns true when events are written
+pub fn is_enabled() -> bool {
+    EVENTS.get().is_some()
+}
+
+/// Writes one event line, if events are enabled
+pub fn emit(event: Value) {
+    let Some(events) = EVENTS.get() else {
+        return;
+    };
+    if let Ok(mut events) = events.lock() {
+        let _ = writeln!(events, "{}", event);
+        let _ = events.flush();
+    }
+}
+
+/// Reports a warning logged by the build
+pub fn emit_warning(message: &str) {
+    emit(json!({ "event": "warning", "message": message }));
+}
+
+/// Reports an external tool about to run
+pub fn emit_tool(command: &Command) {
+    if !is_enabled() {
+        return;
+    }
+    emit(json!({
+        "event": "tool",
+        "program": command.get_program().to_string_lossy(),
+        "args": command.get_args().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>(),
+        "cwd": command.get_current_dir().map(|dir| dir.display().to_string()),
+    }));
+}
+
+/// Reports a file a stage produced
+pub fn emit_file(stage: BuildStage, path: &Path) {
+    emit(json!({
+        "event": "file_produced",
+        "stage": stage as i32,
+        "path": path.display().to_string(),
+        "bytes": path.metadata().map(|metadata| metadata.len()).ok(),
+    }));
+}
+
+/// Reports a folder of generated files (precombined meshes, vis files) a stage produced
+pub fn emit_folder(stage: BuildStage, path: &Path, files: usize) {
+    emit(json!({
+        "event": "folder_produced",
+        "stage": stage as i32,
+        "path": path.display().to_string(),
+        "files": files,
+    }));
+}
+
+/// Sends build progress as events
+pub struct EventObserver;
+
+impl ProgressObserver for EventObserver {
+    fn build_started(&self, plugin: &str, stages: &[BuildStage]) {
+        let stages: Vec<Value> = stages
+            .iter()
+            .map(|stage| json!({ "number": *stage as i32, "name": stage.description() }))
+            .collect();
+        emit(json!({ "event": "build_started", "plugin": plugin, "stages": stages }));
+    }
+
+    fn stage_started(&self, stage: BuildStage, index: usize, total: usize) {
+        emit(json!({
+            "event": "stage_started",
+            "stage": stage as i32,
+            "name": stage.description(),
+            "index": index,
+            "total": total,
+        }));
+    }
+
+    fn stage_finished(&self, stage: BuildStage, duration: Duration, result: Result<(), &str>) {
+        emit(json!({
+            "event": "stage_finished",
+            "stage": stage as i32,
+            "name": stage.description(),
+            "seconds": duration.as_secs_f64(),
+            "success": result.is_ok(),
+            "error": result.err(),
+        }));
+    }
+
+    fn progress(&self, percent: u8) {
+        emit(json!({ "event": "progress", "percent": percent }));
+    }
+
+    fn log_line(&self, stage: BuildStage, line: &str) {
+        emit(json!({ "event": "log", "stage": stage as i32, "line": line }));
+    }
+
+    fn build_finished(&self, report: &BuildReport) {
+        emit(json!({ "event": "build_finished", "report": report }));
+    }
+}
+
+/// Points stdout at stderr and returns a handle to the original stdout
+#[cfg(unix)]
+fn redirect_stdout() -> Result<File, String> {
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: dup and dup2 only duplicate the process's own standard
+    // descriptors, and the duplicate is owned by the returned File
+    unsafe {
+        let original = libc::dup(libc::STDOUT_FILENO);
+        if original < 0 {
+            return Err(format!("Error duplicating stdout: {}", io::Error::last_os_error()));
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(format!("Error redirecting stdout: {}", io::Error::last_os_error()));
+        }
+        Ok(File::from_raw_fd(original))
+    }
+}
+
+/// Points stdout at stderr and returns a handle to the original stdout
+#[cfg(windows)]
+fn redirect_stdout() -> Result<File, String> {
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::System::Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+
+    // SAFETY: only the process's standard handles are read and replaced; the
+    // original stdout handle is no longer used by the standard library and
+    // is owned by the returned File
+    unsafe {
+        let original = GetStdHandle(STD_OUTPUT_HANDLE);
+        if original.is_null() || SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+            return Err(format!("Error redirecting stdout: {}", io::Error::last_os_error()));
+        }
+        Ok(File::from_raw_handle(original as _))
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cli::{CkpeFormat, FixtureArgs};
+use crate::esp::{write_plugin_header, PluginHeader, FLAG_MASTER};
+use crate::paths::archive2_path;
+use crate::utils::ensure_directory_exists;
+
+/// Version written into the stub xEdit scripts, the oldest the builder accepts
+pub const FIXTURE_SCRIPT_VERSION: u32 = 10;
+
+/// Name of the CK log the fixture's CKPE settings redirect to
+pub const FIXTURE_CK_LOG: &str = "CreationKit.log";
+
+/// Contents of every stub executable and DLL
+const STUB_EXECUTABLE: &[u8] = b"MZ GeneratePrevisbines fixture stub\n";
+
+/// What to put in a fake installation
+#[derive(Debug, Clone)]
+pub struct FixtureOptions {
+    /// Plugin created in Data, with Fallout4.esm as its master
+    pub plugin: String,
+    /// Which CKPE configuration file to write
+    pub ckpe: CkpeFormat,
+    /// Whether CKPE has the increased reference handles enabled
+    pub handles_enabled: bool,
+    /// BatchVersion written into the xEdit scripts
+    pub script_version: u32,
+    /// Whether to put BSArch.exe next to xEdit
+    pub bsarch: bool,
+}
+
+impl Default for FixtureOptions {
+    fn default() -> Self {
+        Self {
+            plugin: "Fixture.esp".to_string(),
+            ckpe: CkpeFormat::Ini,
+            handles_enabled: true,
+            script_version: FIXTURE_SCRIPT_VERSION,
+            bsarch: true,
+        }
+    }
+}
+
+/// Locations in a fake installation created by `create_fixture`
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// The game folder (Fallout4.exe, CreationKit.exe, Data...)
+    pub fallout4: PathBuf,
+    /// FO4Edit.exe
+    pub fo4edit: PathBuf,
+    /// Where the CK log is redirected to
+    pub ck_log: PathBuf,
+    /// The plugin in Data
+    pub plugin: PathBuf,
+}
+
+/// Creates a fake Fallout 4 installation under `root`.
+///
+/// The game folder gets stub Fallout4.exe, CreationKit.exe, CKPE and
+/// Archive2 files, a CKPE configuration with the CK log redirected, and a
+/// Data folder with Fallout4.esm and the plugin. An xEdit folder next to it
+/// gets a stub FO4Edit.exe (and BSArch.exe) and the two batch scripts with
+/// their version headers. Together with --simulate this lets a build run
+/// from start to finish without the game or tools installed.
+///
+/// # Arguments
+/// * `root` - Folder to create the installation in
+/// * `options` - What to put in it
+///
+/// # Returns
+/// * `Ok(Fixture)` with the paths of the installation
+/// * `Err(String)` if a file can't be written
+pub fn create_fixture(root: &Path, options: &FixtureOptions) -> Result<Fixture, String> {
+    let fallout4 = root.join("Fallout 4");
+    let data_dir = fallout4.join("Data");
+    let xedit_dir = root.join("FO4Edit");
+    ensure_directory_exists(&data_dir)?;
+    ensure_directory_exists(xedit_dir.join("Edit Scripts"))?;
+
+    for stub in ["Fallout4.exe", "CreationKit.exe", "winhttp.dll"] {
+        write_file(&fallout4.join(stub), STUB_EXECUTABLE)?;
+    }
+    write_file(&archive2_path(&fallout4), STUB_EXECUTABLE)?;
+
+    let handles = options.handles_enabled;
+    let (ckpe_file, ckpe_config) = match options.ckpe {
+        CkpeFormat::Ini => (
+            "CreationKitPlatformExtended.ini",
+            format!("[CreationKit]\nbBSPointerHandleExtremly={}\n\n[Log]\nsOutputFile={}\n", handles, FIXTURE_CK_LOG),
+        ),
+        CkpeFormat::Toml => (
+            "CreationKitPlatformExtended.toml",
+            format!("[CreationKit]\nbBSPointerHandleExtremly = {}\n\n[Log]\nsOutputFile = \"{}\"\n", handles, FIXTURE_CK_LOG),
+        ),
+        CkpeFormat::Legacy => (
+            "fallout4_test.ini",
+            format!("[CreationKit]\nBSHandleRefObjectPatch={}\n\n[Log]\nOutputFile={}\n", u8::from(handles), FIXTURE_CK_LOG),
+        ),
+    };
+    write_file(&fallout4.join(ckpe_file), ckpe_config.as_bytes())?;
+
+    let mut master = PluginHeader::new_plugin(&[]);
+    master.flags = FLAG_MASTER;
+    write_plugin_header(&data_dir.join("Fallout4.esm"), &master)?;
+    let plugin = data_dir.join(&options.plugin);
+    write_plugin_header(&plugin, &PluginHeader::new_plugin(&["Fallout4.esm".to_string()]))?;
+
+    let fo4edit = xedit_dir.join("FO4Edit.exe");
+    write_file(&fo4edit, STUB_EXECUTABLE)?;
+    if options.bsarch {
+        write_file(&xedit_dir.join("BSArch.exe"), STUB_EXECUTABLE)?;
+    }
+    for script in [
+        "Batch_FO4MergeCombinedObjectsAndCheck.pas",
+        "Batch_FO4MergePreVisAndAutoUpdateRefr.pas",
+    ] {
+        let source = format!(
+            "unit {};\n\nconst\n  BatchVersion = {};\n\nbegin\nend.\n",
+            script.trim_end_matches(".pas"),
+            options.script_version
+        );
+        write_file(&xedit_dir.join("Edit Scripts").join(script), source.as_bytes())?;
+    }
+
+    Ok(Fixture {
+        ck_log: fallout4.join(FIXTURE_CK_LOG),
+        fallout4,
+        fo4edit,
+        plugin,
+    })
+}
+
+/// Runs the `fixture` command: creates a fake installation and prints the
+/// command line that builds its plugin with --simulate
+pub fn run_fixture(fixture_args: &FixtureArgs) -> Result<(), String> {
+    let root = PathBuf::from(&fixture_args.dir);
+    if root.exists() && fs::read_dir(&root).map_or(true, |mut entries| entries.next().is_some()) {
+        return Err(format!("ERROR - {} already exists and isn't empty", root.display()));
+    }
+
+    let options = FixtureOptions {
+        plugin: fixture_args.plugin.clone(),
+        ckpe: fixture_args.ckpe,
+        handles_enabled: !fixture_args.no_handles,
+        script_version: fixture_args.script_version,
+        bsarch: true,
+    };
+    let fixture = create_fixture(&root, &options)?;
+
+    println!("Fake installation created in {}", root.display());
+    println!("  Game folder: {}", fixture.fallout4.display());
+    println!("  xEdit:       {}", fixture.fo4edit.display());
+    println!("  Plugin:      {}", fixture.plugin.display());
+    println!("\nBuild its plugin without the game or tools with:");
+    println!(
+        "  generate_previsbines --simulate --start-stage 1 --fallout4-path \"{}\" --fo4edit-path \"{}\" {}",
+        fixture.fallout4.display(),
+        fixture.fo4edit.display(),
+        options.plugin
+    );
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        ensure_directory_exists(parent)?;
+    }
+    fs::write(path, contents).map_err(|e| format!("Error writing {}: {}", path.display(), e))
+}
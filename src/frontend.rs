@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde_json::{json, Value};
+use crate::answers::Prompt;
+use crate::events;
+
+/// Set by --frontend-mode
+static FRONTEND: AtomicBool = AtomicBool::new(false);
+
+/// Switches to frontend mode (--frontend-mode).
+///
+/// Stdout is reserved for newline-delimited JSON events; everything else the
+/// process (and the tools it runs) prints goes to stderr instead. Prompt
+/// answers are read from stdin, either as a JSON object with an `answer`
+/// field, a JSON string, or a plain line.
+///
+/// # Returns
+/// * `Ok(())` if stdout was redirected
+/// * `Err(String)` if the standard handles couldn't be changed
+pub fn enable() -> Result<(), String> {
+    events::enable_stdout()?;
+    FRONTEND.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns true in frontend mode
+pub fn is_enabled() -> bool {
+    FRONTEND.load(Ordering::Relaxed)
+}
+
+/// Announces that the build is waiting for an answer
+pub fn emit_prompt(prompt: Option<Prompt>, message: &str) {
+    events::emit(json!({
+        "event": "prompt",
+        "id": prompt.map(|prompt| prompt.id()),
+        "yes_no": prompt.is_some_and(|prompt| prompt.is_yes_no()),
+        "message": message,
+    }));
+}
+
+/// Extracts the answer from a line sent by the frontend
+pub fn parse_answer(line: &str) -> String {
+    match serde_json::from_str::<Value>(line.trim()) {
+        Ok(Value::Object(object)) => match object.get("answer") {
+            Some(Value::String(answer)) => answer.clone(),
+            Some(Value::Bool(answer)) => if *answer { "y" } else { "n" }.to_string(),
+            Some(answer) => answer.to_string(),
+            None => String::new(),
+        },
+        Ok(Value::String(answer)) => answer,
+        _ => line.to_string(),
+    }
+}
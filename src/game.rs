@@ -0,0 +1,180 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use log::{info, warn};
+use crate::cli::Ba2Version;
+
+/// Signature of the VS_FIXEDFILEINFO structure in a PE version resource
+const FIXED_FILE_INFO_SIGNATURE: [u8; 4] = [0xBD, 0x04, 0xEF, 0xFE];
+
+/// Bytes from the VS_FIXEDFILEINFO signature to the end of the file version
+const FIXED_FILE_VERSION_END: usize = 16;
+
+/// Bytes of an executable read at a time while looking for its version resource
+const VERSION_SCAN_CHUNK: usize = 64 * 1024;
+
+/// First Fallout4.exe build number of the next-gen update
+const NEXT_GEN_FIRST_BUILD: u16 = 980;
+
+/// First CreationKit.exe build number of the next-gen update
+const NEXT_GEN_CK_FIRST_BUILD: u16 = 943;
+
+/// Fallout4.exe version a downgraded install (e.g. for Fallout London) runs
+pub const DOWNGRADED_GAME_VERSION: ExeVersion = ExeVersion { major: 1, minor: 10, build: 163, revision: 0 };
+
+/// F4SE library loaded by the downgraded Fallout4.exe
+pub const DOWNGRADED_F4SE_DLL: &str = "f4se_1_10_163.dll";
+
+/// F4SE's launcher, in the game folder when F4SE is installed
+pub const F4SE_LOADER: &str = "f4se_loader.exe";
+
+/// Master file in Data that marks a Fallout London install
+pub const FALLOUT_LONDON_MARKER: &str = "LondonWorldSpace.esm";
+
+/// A four-part executable file version (e.g. 1.10.163.0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExeVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub build: u16,
+    pub revision: u16,
+}
+
+impl fmt::Display for ExeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.build, self.revision)
+    }
+}
+
+/// Which generation of the game an installation is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameBuild {
+    /// Pre next-gen update (1.10.163 and earlier)
+    OldGen,
+    /// Next-gen update (1.10.980 and later)
+    NextGen,
+}
+
+impl fmt::Display for GameBuild {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameBuild::OldGen => write!(f, "old-gen"),
+            GameBuild::NextGen => write!(f, "next-gen"),
+        }
+    }
+}
+
+/// Reads the file version from an executable's version resource.
+///
+/// # Arguments
+/// * `exe_path` - Path to the executable
+///
+/// # Returns
+/// * `Some(ExeVersion)` if a version resource was found
+/// * `None` if the file can't be read or has no version resource
+pub fn read_exe_version(exe_path: &Path) -> Option<ExeVersion> {
+    // Executables like Fallout4.exe are too large to read whole, so they are
+    // searched a chunk at a time
+    let mut file = File::open(exe_path).ok()?;
+    let mut chunk = vec![0u8; VERSION_SCAN_CHUNK];
+    let mut data = Vec::with_capacity(2 * VERSION_SCAN_CHUNK + FIXED_FILE_VERSION_END);
+    let offset = loop {
+        let read = match file.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        };
+        data.extend_from_slice(&chunk[..read]);
+
+        let found = data
+            .windows(FIXED_FILE_INFO_SIGNATURE.len())
+            .position(|window| window == FIXED_FILE_INFO_SIGNATURE);
+        match found {
+            // The file version may continue in the next chunk
+            Some(offset) if read == 0 || data.len() >= offset + FIXED_FILE_VERSION_END => break offset,
+            Some(_) => {}
+            None if read == 0 => return None,
+            // Keep the end of the chunk, which may hold part of the signature
+            None => {
+                let keep = FIXED_FILE_INFO_SIGNATURE.len() - 1;
+                data.drain(..data.len().saturating_sub(keep));
+            }
+        }
+    };
+
+    // Signature, struct version, then the file version as two DWORDs
+    let ms = read_u32(&data, offset + 8)?;
+    let ls = read_u32(&data, offset + 12)?;
+
+    Some(ExeVersion {
+        major: (ms >> 16) as u16,
+        minor: (ms & 0xFFFF) as u16,
+        build: (ls >> 16) as u16,
+        revision: (ls & 0xFFFF) as u16,
+    })
+}
+
+/// Detects whether the game installation is old-gen or next-gen from Fallout4.exe.
+///
+/// # Returns
+/// * `Some(GameBuild)` if the executable version could be read
+/// * `None` otherwise
+pub fn detect_game_build(fallout4_path: &Path) -> Option<GameBuild> {
+    let version = read_exe_version(&fallout4_path.join("Fallout4.exe"))?;
+    if version.build >= NEXT_GEN_FIRST_BUILD {
+        Some(GameBuild::NextGen)
+    } else {
+        Some(GameBuild::OldGen)
+    }
+}
+
+/// Resolves the BA2 version archives should be written with.
+///
+/// With `Ba2Version::Auto` the version is chosen from the installed game's
+/// Fallout4.exe; if that can't be read the archiver's own version is kept.
+///
+/// # Returns
+/// * `Some(version)` to rewrite archives with that version
+/// * `None` to keep whatever version the archiver writes
+pub fn resolve_ba2_version(requested: Ba2Version, fallout4_path: &Path) -> Option<u32> {
+    if let Some(version) = requested.number() {
+        return Some(version);
+    }
+
+    match detect_game_build(fallout4_path) {
+        Some(GameBuild::OldGen) => {
+            info!("Detected old-gen Fallout 4, archives will be written as BA2 version 1");
+            Some(1)
+        }
+        Some(GameBuild::NextGen) => {
+            info!("Detected next-gen Fallout 4, archives will be written as BA2 version 8");
+            Some(8)
+        }
+        None => {
+            warn!("Could not detect the Fallout 4 version, keeping the archiver's BA2 version");
+            None
+        }
+    }
+}
+
+/// Whether the Creation Kit is the next-gen build, which can't build for a
+/// downgraded game.
+///
+/// # Returns
+/// * `Some(bool)` if the CreationKit.exe version could be read
+/// * `None` otherwise
+pub fn is_next_gen_creation_kit(creation_kit_path: &Path) -> Option<bool> {
+    read_exe_version(creation_kit_path).map(|version| version.build >= NEXT_GEN_CK_FIRST_BUILD)
+}
+
+/// Whether the game folder holds Fallout London, which needs the game
+/// downgraded to 1.10.163
+pub fn is_fallout_london(fallout4_path: &Path) -> bool {
+    fallout4_path.join("Data").join(FALLOUT_LONDON_MARKER).exists()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use eframe::egui;
+use crate::answers::set_assume_yes;
+use crate::builder::PrevisbineBuilder;
+use crate::headless::set_non_interactive;
+use crate::cli::{Args, BuildMode, BuildStage};
+use crate::observer::ProgressObserver;
+use crate::paths::find_fallout4;
+use crate::report::BuildReport;
+use crate::stages::{available_in, ALL_STAGES};
+use crate::wizard::list_buildable_plugins;
+
+/// How often the window refreshes while a build runs
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Number of log lines kept in the log view
+const MAX_LOG_LINES: usize = 5000;
+
+/// Something that happened in the build thread
+enum GuiEvent {
+    Log(String),
+    Progress(u8),
+    Finished(Result<(), String>),
+}
+
+/// Forwards the build's progress to the window
+struct ChannelObserver {
+    sender: Sender<GuiEvent>,
+}
+
+impl ProgressObserver for ChannelObserver {
+    fn stage_started(&self, stage: BuildStage, index: usize, total: usize) {
+        let _ = self.sender.send(GuiEvent::Log(format!(
+            "=== [{}/{}] {} ===",
+            index + 1,
+            total,
+            stage.description()
+        )));
+    }
+
+    fn stage_finished(&self, stage: BuildStage, duration: Duration, result: Result<(), &str>) {
+        let line = match result {
+            Ok(()) => format!("{} finished in {:.0}s", stage.description(), duration.as_secs_f64()),
+            Err(e) => format!("{} failed: {}", stage.description(), e),
+        };
+        let _ = self.sender.send(GuiEvent::Log(line));
+    }
+
+    fn progress(&self, percent: u8) {
+        let _ = self.sender.send(GuiEvent::Progress(percent));
+    }
+
+    fn log_line(&self, _stage: BuildStage, line: &str) {
+        let _ = self.sender.send(GuiEvent::Log(line.to_string()));
+    }
+
+    fn build_finished(&self, report: &BuildReport) {
+        if let Some(run_dir) = &report.run_dir {
+            let _ = self.sender.send(GuiEvent::Log(format!("Logs and report saved to {}", run_dir.display())));
+        }
+    }
+}
+
+/// Window state
+struct GuiApp {
+    args: Args,
+    fallout4: Option<PathBuf>,
+    plugins: Vec<String>,
+    plugin: String,
+    mode: BuildMode,
+    /// Stage each checkbox stands for, and whether it is checked
+    stages: Vec<(BuildStage, bool)>,
+    log: Vec<String>,
+    progress: u8,
+    events: Option<Receiver<GuiEvent>>,
+    status: String,
+}
+
+impl GuiApp {
+    fn new(args: Args) -> Self {
+        let fallout4 = args.fallout4_path.as_ref().map(PathBuf::from).or_else(|| find_fallout4().ok());
+        let (plugins, status) = match &fallout4 {
+            Some(fallout4) => match list_buildable_plugins(&fallout4.join("Data")) {
+                Ok(plugins) => (plugins, String::new()),
+                Err(e) => (Vec::new(), e),
+            },
+            None => (Vec::new(), "Fallout 4 not found, start with --fallout4-path".to_string()),
+        };
+
+        let plugin = args.plugin.clone().or_else(|| plugins.first().cloned()).unwrap_or_default();
+        let mode = args.mode;
+        Self {
+            args,
+            fallout4,
+            plugins,
+            plugin,
+            mode,
+            stages: ALL_STAGES.iter().map(|stage| (*stage, true)).collect(),
+            log: Vec::new(),
+            progress: 0,
+            events: None,
+            status,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Stages checked and available in the selected mode
+    fn checked_stages(&self) -> Vec<BuildStage> {
+        self.stages
+            .iter()
+            .filter(|(stage, checked)| *checked && available_in(*stage, self.mode))
+            .map(|(stage, _)| *stage)
+            .collect()
+    }
+
+    /// Starts the build on a worker thread
+    fn start_build(&mut self) {
+        let checked = self.checked_stages();
+        let Some(first) = checked.first().copied() else {
+            self.status = "Check at least one stage".to_string();
+            return;
+        };
+
+        let mut args = self.args.clone();
+        args.plugin = Some(self.plugin.clone());
+        args.fallout4_path = self.fallout4.as_ref().map(|path| path.display().to_string());
+        args.mode = self.mode;
+        args.auto_mode = false;
+        args.mode_given = true;
+        args.no_prompt = true;
+        args.wizard = false;
+        if checked.len() == 1 {
+            args.only = Some(first as i32);
+            args.start_stage = None;
+        } else {
+            args.start_stage = Some(first as i32);
+            args.only = None;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        self.events = Some(receiver);
+        self.log.clear();
+        self.progress = 0;
+        self.status = format!("Building {}...", self.plugin);
+
+        thread::spawn(move || {
+            let result = PrevisbineBuilder::new(args).and_then(|mut builder| {
+                builder.set_observer(Box::new(ChannelObserver { sender: sender.clone() }));
+                builder.run()
+            });
+            let _ = sender.send(GuiEvent::Finished(result));
+        });
+    }
+
+    /// Takes the events sent by the build thread since the last frame
+    fn poll_events(&mut self) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        let mut finished = None;
+        for event in events.try_iter() {
+            match event {
+                GuiEvent::Log(line) => self.log.push(line),
+                GuiEvent::Progress(percent) => self.progress = percent,
+                GuiEvent::Finished(result) => finished = Some(result),
+            }
+        }
+        if self.log.len() > MAX_LOG_LINES {
+            self.log.drain(..self.log.len() - MAX_LOG_LINES);
+        }
+
+        if let Some(result) = finished {
+            self.events = None;
+            self.status = match result {
+                Ok(()) => {
+                    self.progress = 100;
+                    format!("Build of {} complete", self.plugin)
+                }
+                Err(e) => e,
+            };
+        }
+    }
+}
+
+impl eframe::App for GuiApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_events();
+        if self.is_running() {
+            ctx.request_repaint_after(REFRESH_INTERVAL);
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("Previsbine Builder");
+            ui.add_enabled_ui(!self.is_running(), |ui| {
+                egui::ComboBox::from_label("Plugin")
+                    .selected_text(self.plugin.as_str())
+                    .width(320.0)
+                    .show_ui(ui, |ui| {
+                        for plugin in &self.plugins {
+                            ui.selectable_value(&mut self.plugin, plugin.clone(), plugin.as_str());
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    ui.label("Mode:");
+                    ui.radio_value(&mut self.mode, BuildMode::Clean, "Clean")
+                        .on_hover_text("Full precombines and previs, for plugins adding content");
+                    ui.radio_value(&mut self.mode, BuildMode::Filtered, "Filtered")
+                        .on_hover_text("Only the cells the plugin changes, for patches");
+                    ui.radio_value(&mut self.mode, BuildMode::Xbox, "Xbox")
+                        .on_hover_text("Like clean, with Xbox-compressed archives");
+                    ui.radio_value(&mut self.mode, BuildMode::PrevisOnly, "Previs only")
+                        .on_hover_text("Only previs, reusing the precombines of an earlier build");
+                });
+
+                ui.label("Stages (later stages run after the first one checked):");
+                let mode = self.mode;
+                let mut first_checked = None;
+                for (index, (stage, checked)) in self.stages.iter_mut().enumerate() {
+                    if !available_in(*stage, mode) {
+                        continue;
+                    }
+                    if ui.checkbox(checked, format!("[{}] {}", *stage as i32, stage.description())).changed() && *checked {
+                        first_checked = Some(index);
+                    }
+                }
+                // A build runs every stage from its starting stage on
+                if let Some(first) = first_checked {
+                    for (_, checked) in self.stages.iter_mut().skip(first) {
+                        *checked = true;
+                    }
+                }
+
+                let can_build = !self.plugin.is_empty() && self.fallout4.is_some();
+                if ui.add_enabled(can_build, egui::Button::new("Build")).clicked() {
+                    self.start_build();
+                }
+            });
+
+            ui.add(egui::ProgressBar::new(f32::from(self.progress) / 100.0).show_percentage());
+            if !self.status.is_empty() {
+                ui.label(self.status.as_str());
+            }
+
+            ui.separator();
+            egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+                for line in &self.log {
+                    ui.monospace(line.as_str());
+                }
+            });
+        });
+    }
+}
+
+/// Opens the builder window (--gui) and returns once it is closed.
+///
+/// # Returns
+/// * `Ok(())` when the window is closed
+/// * `Err(String)` if the window can't be created
+pub fn run_gui(args: Args) -> Result<(), String> {
+    // Nobody is at the console to answer prompts
+    set_assume_yes();
+    set_non_interactive();
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([760.0, 620.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Previsbine Builder",
+        options,
+        Box::new(|_context| Ok(Box::new(GuiApp::new(args)))),
+    )
+    .map_err(|e| format!("Error opening the window: {}", e))
+}
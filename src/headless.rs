@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use serde_json::json;
+use crate::answers::Prompt;
+use crate::events;
+use crate::utils::format_duration;
+
+/// Exit code of a build or command that succeeded
+pub const EXIT_SUCCESS: i32 = 0;
+
+/// Exit code of a build that failed
+pub const EXIT_FAILED: i32 = 1;
+
+/// Exit code for invalid command line arguments (set by clap)
+pub const EXIT_USAGE: i32 = 2;
+
+/// Exit code when a non-interactive build reached a prompt the flags and answers file don't answer
+pub const EXIT_INPUT_REQUIRED: i32 = 3;
+
+/// Exit code when the build ran longer than --build-timeout
+pub const EXIT_TIMED_OUT: i32 = 4;
+
+/// Exit code when the build stopped early on request (`abort` command)
+pub const EXIT_ABORTED: i32 = 5;
+
+/// How long a headless build may run when --build-timeout isn't given
+pub const DEFAULT_HEADLESS_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// --build-timeout used by --ci when none is given
+pub const DEFAULT_CI_TIMEOUT_MINUTES: u64 = 6 * 60;
+
+/// --artifacts-dir used by --ci when none is given
+pub const DEFAULT_ARTIFACTS_DIR: &str = "artifacts";
+
+/// Set by --headless
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+
+/// Set by --non-interactive, --no-prompt and --headless
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Exit code used if the process fails
+static EXIT_CODE: AtomicI32 = AtomicI32::new(EXIT_FAILED);
+
+/// Exit code used if the process doesn't fail
+static SUCCESS_CODE: AtomicI32 = AtomicI32::new(EXIT_SUCCESS);
+
+/// Run by the watchdog before it ends a build that timed out
+static TIMEOUT_HOOK: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+
+/// Turns on headless mode, which is also non-interactive
+pub fn enable() {
+    HEADLESS.store(true, Ordering::Relaxed);
+    set_non_interactive();
+}
+
+/// Makes prompts fail instead of waiting for input
+pub fn set_non_interactive() {
+    NON_INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Returns true when prompts must not wait for input
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Returns true in headless mode
+pub fn is_enabled() -> bool {
+    HEADLESS.load(Ordering::Relaxed)
+}
+
+/// Exit code to use for the error the process is failing with
+pub fn exit_code() -> i32 {
+    EXIT_CODE.load(Ordering::Relaxed)
+}
+
+/// Exit code to use when the process ends without an error
+pub fn success_exit_code() -> i32 {
+    SUCCESS_CODE.load(Ordering::Relaxed)
+}
+
+/// Notes that a build stopped early on request, so the process exits with
+/// `EXIT_ABORTED` rather than reporting success
+pub fn set_aborted() {
+    SUCCESS_CODE.store(EXIT_ABORTED, Ordering::Relaxed);
+}
+
+/// Fails a prompt in a non-interactive build.
+///
+/// # Returns
+/// The error to return from the prompt; the process exit code is set to
+/// `EXIT_INPUT_REQUIRED`
+pub fn input_required(prompt: Option<Prompt>, message: &str) -> String {
+    EXIT_CODE.store(EXIT_INPUT_REQUIRED, Ordering::Relaxed);
+    match prompt {
+        Some(prompt) => format!(
+            "ERROR - Input required but running non-interactively: {} ({}). Answer it with a flag or in the --answers file as `{}`",
+            message.trim(),
+            prompt.id(),
+            prompt.id()
+        ),
+        None => format!("ERROR - Input required but running non-interactively: {}", message.trim()),
+    }
+}
+
+/// Sets what to save before the watchdog ends a build that timed out,
+/// replacing what was set before
+pub fn on_timeout(hook: impl FnOnce() + Send + 'static) {
+    *TIMEOUT_HOOK.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(hook));
+}
+
+/// Ends the process with `EXIT_TIMED_OUT` if it is still running after `timeout`.
+///
+/// Tools started by the build are not stopped; the leftovers check of the
+/// next build finds what they leave behind.
+pub fn start_watchdog(timeout: Duration) {
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        let message = format!("ERROR - Build timed out after {}", format_duration(timeout));
+        log::error!("{}", message);
+        eprintln!("{}", message);
+        events::emit(json!({ "event": "error", "message": message }));
+        if let Some(hook) = TIMEOUT_HOOK.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            hook();
+        }
+        std::process::exit(EXIT_TIMED_OUT);
+    });
+}
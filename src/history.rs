@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::cache::CACHE_DIR;
+use crate::cli::BuildStage;
+use crate::utils::ensure_directory_exists;
+
+/// Name of the stage duration history file in the cache folder
+const HISTORY_FILE: &str = "stage-history.json";
+
+/// Number of samples kept per stage
+const MAX_SAMPLES: usize = 20;
+
+/// Name the cleanup after the last stage is recorded under in the disk use history
+pub const CLEANUP_STEP: &str = "Cleanup";
+
+/// How long a stage took for a plugin of a given size
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StageSample {
+    /// Cells in the plugin, the size metric durations scale with
+    pub cells: u64,
+    pub seconds: f64,
+}
+
+/// How much the build's files in Data grew (or shrank) during a stage
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DiskSample {
+    /// Cells in the plugin
+    pub cells: u64,
+    pub bytes: i64,
+}
+
+/// Durations and disk use of past stages across builds, used to estimate
+/// how long a build will take and how much space it needs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StageHistory {
+    /// Most recent samples per stage, oldest first
+    pub stages: BTreeMap<String, Vec<StageSample>>,
+    /// Most recent disk use samples per stage (and cleanup), oldest first
+    #[serde(default)]
+    pub disk: BTreeMap<String, Vec<DiskSample>>,
+}
+
+impl StageHistory {
+    /// Path of the history file
+    pub fn path(fallout4: &Path) -> PathBuf {
+        fallout4.join(CACHE_DIR).join(HISTORY_FILE)
+    }
+
+    /// Loads the history, starting empty if there is none or it can't be read
+    pub fn load(fallout4: &Path) -> Self {
+        fs::read_to_string(Self::path(fallout4))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the history file
+    pub fn save(&self, fallout4: &Path) -> Result<(), String> {
+        let path = Self::path(fallout4);
+        if let Some(parent) = path.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing stage history: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Error writing stage history {}: {}", path.display(), e))
+    }
+
+    /// Adds the duration of a completed stage
+    pub fn record(&mut self, stage: BuildStage, cells: u64, duration: Duration) {
+        let samples = self.stages.entry(stage.description().to_string()).or_default();
+        samples.push(StageSample {
+            cells,
+            seconds: duration.as_secs_f64(),
+        });
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(..samples.len() - MAX_SAMPLES);
+        }
+    }
+
+    /// Adds how much a completed step changed the size of the build's files
+    ///
+    /// # Arguments
+    /// * `step` - Stage description, or `Cleanup`
+    /// * `cells` - Cells in the plugin
+    /// * `bytes` - Size change, negative if files were removed
+    pub fn record_disk(&mut self, step: &str, cells: u64, bytes: i64) {
+        let samples = self.disk.entry(step.to_string()).or_default();
+        samples.push(DiskSample { cells, bytes });
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(..samples.len() - MAX_SAMPLES);
+        }
+    }
+
+    /// Estimates how much a step will change the size of the build's files
+    /// for a plugin with `cells` cells, from the median change per cell of
+    /// the past samples.
+    ///
+    /// # Returns
+    /// * `Some(bytes)` with the estimate, negative if files will be removed
+    /// * `None` if the step never ran before
+    pub fn estimate_disk(&self, step: &str, cells: u64) -> Option<i64> {
+        let samples = self.disk.get(step)?;
+        let mut per_cell: Vec<f64> = samples
+            .iter()
+            .map(|sample| sample.bytes as f64 / sample.cells.max(1) as f64)
+            .collect();
+        if per_cell.is_empty() {
+            return None;
+        }
+        per_cell.sort_by(f64::total_cmp);
+
+        let median = per_cell[per_cell.len() / 2];
+        Some((median * cells.max(1) as f64) as i64)
+    }
+
+    /// Estimates how long a stage will take for a plugin with `cells` cells.
+    ///
+    /// Durations are assumed to grow linearly with the number of cells, so
+    /// the estimate is the median time per cell of the past samples.
+    ///
+    /// # Returns
+    /// * `Some(Duration)` with the estimate
+    /// * `None` if the stage never ran before
+    pub fn estimate(&self, stage: BuildStage, cells: u64) -> Option<Duration> {
+        let samples = self.stages.get(stage.description())?;
+        let mut per_cell: Vec<f64> = samples
+            .iter()
+            .map(|sample| sample.seconds / sample.cells.max(1) as f64)
+            .collect();
+        if per_cell.is_empty() {
+            return None;
+        }
+        per_cell.sort_by(f64::total_cmp);
+
+        let median = per_cell[per_cell.len() / 2];
+        Some(Duration::from_secs_f64(median * cells.max(1) as f64))
+    }
+}
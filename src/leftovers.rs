@@ -0,0 +1,245 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use crate::answers::{assume_yes, has_answer, Prompt};
+use crate::backup::RunBackup;
+use crate::cli::Args;
+use crate::headless;
+use crate::locks::{describe_io_error, locking_processes};
+use crate::paths::find_fallout4;
+use crate::tools::archive::ARCHIVE_STAGING_DIR;
+use crate::tools::creation_kit::{CK_CONFLICTING_DLLS, DISABLED_DLL_SUFFIX};
+use crate::ui::prompt_yes_no;
+use crate::utils::{remove_dir_all_with_retry, remove_file_with_retry};
+use crate::validation::directory_has_files;
+
+/// Something a previous, interrupted run left behind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leftover {
+    /// Intermediate plugin (CombinedObjects.esp, Previs.esp)
+    File(PathBuf),
+    /// Generated or staging folder that should be empty before a fresh build
+    Directory(PathBuf),
+    /// ENB/ReShade DLL the Creation Kit stage disabled and never re-enabled
+    DisabledDll { disabled: PathBuf, original: PathBuf },
+}
+
+impl Leftover {
+    /// The leftover file or folder
+    pub fn path(&self) -> &Path {
+        match self {
+            Leftover::File(path) | Leftover::Directory(path) => path,
+            Leftover::DisabledDll { disabled, .. } => disabled,
+        }
+    }
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leftover::File(path) => write!(f, "{} (will be deleted)", path.display()),
+            Leftover::Directory(path) => write!(f, "{} (will be deleted)", path.display()),
+            Leftover::DisabledDll { disabled, original } if original.exists() => {
+                write!(f, "{} (will be deleted, {} was reinstalled)", disabled.display(), original.display())
+            }
+            Leftover::DisabledDll { disabled, .. } => write!(f, "{} (will be re-enabled)", disabled.display()),
+        }
+    }
+}
+
+/// Looks for files and folders left behind by a previous run that failed or
+/// was interrupted.
+///
+/// # Arguments
+/// * `fallout4` - The Fallout 4 installation directory
+/// * `fresh_build` - Whether the build starts from scratch; intermediate files
+///   are only leftovers then, as resumed builds still need them
+///
+/// # Returns
+/// The leftovers found, DLLs first
+pub fn find_leftovers(fallout4: &Path, fresh_build: bool) -> Vec<Leftover> {
+    let mut leftovers = find_disabled_dlls(fallout4);
+
+    if !fresh_build {
+        return leftovers;
+    }
+
+    let data_dir = fallout4.join("Data");
+    for plugin in ["CombinedObjects.esp", "Previs.esp"] {
+        let path = data_dir.join(plugin);
+        if path.exists() {
+            leftovers.push(Leftover::File(path));
+        }
+    }
+
+    let precombined_dir = data_dir.join("meshes").join("precombined");
+    if directory_has_files(&precombined_dir, ".nif") {
+        leftovers.push(Leftover::Directory(precombined_dir));
+    }
+    let vis_dir = data_dir.join("vis");
+    if directory_has_files(&vis_dir, ".uvd") {
+        leftovers.push(Leftover::Directory(vis_dir));
+    }
+
+    // Staging folders of an archive stage that didn't finish (ArchiveStaging, ArchiveStaging2...)
+    if let Ok(entries) = fs::read_dir(fallout4) {
+        let mut staging_dirs: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(ARCHIVE_STAGING_DIR))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        staging_dirs.sort();
+        leftovers.extend(staging_dirs.into_iter().map(Leftover::Directory));
+    }
+
+    leftovers
+}
+
+/// Finds the ENB/ReShade DLLs a Creation Kit run disabled (renamed to
+/// `*-PJMdisabled`) and never re-enabled, which keeps them from loading in game
+pub fn find_disabled_dlls(fallout4: &Path) -> Vec<Leftover> {
+    CK_CONFLICTING_DLLS
+        .iter()
+        .map(|dll| (fallout4.join(format!("{}{}", dll, DISABLED_DLL_SUFFIX)), fallout4.join(dll)))
+        .filter(|(disabled, _)| disabled.exists())
+        .map(|(disabled, original)| Leftover::DisabledDll { disabled, original })
+        .collect()
+}
+
+/// Whether the Creation Kit is running, in which case disabled DLLs belong to
+/// a build still in progress rather than one that crashed
+fn creation_kit_running(fallout4: &Path) -> bool {
+    !locking_processes(&fallout4.join("CreationKit.exe")).is_empty()
+}
+
+/// Renames disabled DLLs back. A disabled copy whose DLL was reinstalled since
+/// is left alone, as only the user knows which of the two they want.
+///
+/// # Returns
+/// * `Ok(Vec<PathBuf>)` with the DLLs re-enabled
+/// * `Err(String)` if a DLL couldn't be renamed
+pub fn restore_disabled_dlls(dlls: &[Leftover]) -> Result<Vec<PathBuf>, String> {
+    let mut restored = Vec::new();
+    for dll in dlls {
+        let Leftover::DisabledDll { disabled, original } = dll else {
+            continue;
+        };
+        if original.exists() {
+            warn!(
+                "WARNING - {} was reinstalled since it was disabled, delete {} if it is no longer needed",
+                original.display(),
+                disabled.display()
+            );
+            continue;
+        }
+        fs::rename(disabled, original).map_err(|e| describe_io_error("re-enabling", original, &e))?;
+        info!("Re-enabled {}", original.display());
+        restored.push(original.clone());
+    }
+    Ok(restored)
+}
+
+/// Offers to re-enable the DLLs a crashed run left disabled, before anything
+/// else runs. Without someone to answer, only warns about them.
+///
+/// # Returns
+/// * `Ok(())` once the DLLs were restored, or left as they are
+/// * `Err(String)` if a DLL couldn't be renamed or the answer couldn't be read
+pub fn offer_dll_repair(fallout4: &Path) -> Result<(), String> {
+    let dlls: Vec<Leftover> = find_disabled_dlls(fallout4)
+        .into_iter()
+        .filter(|dll| matches!(dll, Leftover::DisabledDll { original, .. } if !original.exists()))
+        .collect();
+    if dlls.is_empty() || creation_kit_running(fallout4) {
+        return Ok(());
+    }
+
+    let names: Vec<String> = dlls
+        .iter()
+        .map(|dll| dll.path().file_name().unwrap_or_default().to_string_lossy().to_string())
+        .collect();
+    if headless::is_non_interactive() && !has_answer(Prompt::RestoreDlls) && !assume_yes() {
+        warn!(
+            "WARNING - {} left disabled by an earlier run that didn't finish, ENB/ReShade won't load in game. Run `generate_previsbines repair-dlls` to restore them.",
+            names.join(", ")
+        );
+        return Ok(());
+    }
+
+    let message = format!(
+        "{} left disabled by an earlier run that didn't finish, so ENB/ReShade won't load in game. Restore them now?",
+        names.join(", ")
+    );
+    if prompt_yes_no(Prompt::RestoreDlls, &message)? {
+        for original in restore_disabled_dlls(&dlls)? {
+            println!("Restored {}", original.display());
+        }
+    }
+    Ok(())
+}
+
+/// Runs the `repair-dlls` command: re-enables the ENB/ReShade DLLs a crashed
+/// run left disabled.
+///
+/// # Returns
+/// * `Ok(())` if there was nothing to restore or every DLL was restored
+/// * `Err(String)` if the Creation Kit is still running or a DLL couldn't be renamed
+pub fn run_repair_dlls(args: &Args) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+
+    let dlls = find_disabled_dlls(&fallout4);
+    if dlls.is_empty() {
+        println!("No disabled DLLs found in {}", fallout4.display());
+        return Ok(());
+    }
+    if creation_kit_running(&fallout4) {
+        return Err(
+            "ERROR - The Creation Kit is running, its DLLs are re-enabled once it is done. Close it first if the build crashed."
+                .to_string(),
+        );
+    }
+
+    for original in restore_disabled_dlls(&dlls)? {
+        println!("Restored {}", original.display());
+    }
+    Ok(())
+}
+
+/// Removes leftovers found by [`find_leftovers`].
+///
+/// Intermediate plugins are backed up before being deleted. A disabled DLL
+/// is renamed back unless a DLL of the same name was installed since, in
+/// which case the disabled copy is deleted.
+///
+/// # Returns
+/// * `Ok(())` if everything was cleaned up
+/// * `Err(String)` if a file or folder couldn't be removed
+pub fn clean_leftovers(leftovers: &[Leftover], backup: &RunBackup) -> Result<(), String> {
+    for leftover in leftovers {
+        match leftover {
+            Leftover::File(path) => {
+                backup.save(path)?;
+                remove_file_with_retry(path).map_err(|e| describe_io_error("removing", path, &e))?;
+            }
+            Leftover::Directory(path) => {
+                remove_dir_all_with_retry(path).map_err(|e| describe_io_error("removing", path, &e))?;
+            }
+            Leftover::DisabledDll { disabled, original } if original.exists() => {
+                backup.save(disabled)?;
+                remove_file_with_retry(disabled).map_err(|e| describe_io_error("removing", disabled, &e))?;
+            }
+            Leftover::DisabledDll { disabled, original } => {
+                fs::rename(disabled, original)
+                    .map_err(|e| format!("Error re-enabling {}: {}", original.display(), e))?;
+            }
+        }
+        info!("Cleaned up {}", leftover.path().display());
+    }
+
+    Ok(())
+}
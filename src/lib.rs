@@ -5,7 +5,42 @@ pub mod builder;
 pub mod ui;
 pub mod validation;
 pub mod utils;
+pub mod report;
+pub mod manifest;
+pub mod game;
+pub mod repack;
+pub mod locks;
+pub mod backup;
+pub mod package;
+pub mod cache;
+pub mod leftovers;
+pub mod esp;
+pub mod clean;
+pub mod check;
+pub mod stages;
+pub mod diagnostics;
+pub mod notify;
+pub mod history;
+pub mod verify_cache;
+pub mod build_history;
+pub mod abort;
+pub mod wizard;
+pub mod answers;
+pub mod style;
+pub mod observer;
+pub mod events;
+pub mod frontend;
+pub mod headless;
+pub mod logging;
+pub mod config;
+pub mod setup;
+pub mod title;
+pub mod fixture;
+pub mod update;
+#[cfg(feature = "gui")]
+pub mod gui;
 
-pub use cli::{Args, BuildMode, BuildStage};
+pub use cli::{Args, KeepFiles, ArchiveCompression, ArchiverKind, Ba2Version, BuildMode, BuildStage, Command};
 pub use paths::Paths;
-pub use builder::PrevisbineBuilder;
\ No newline at end of file
+pub use builder::PrevisbineBuilder;
+pub use observer::{NoopObserver, ProgressObserver};
\ No newline at end of file
@@ -0,0 +1,222 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+/// How often a locked file is checked again while waiting for it to be released
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns true if the error is a Windows sharing or lock violation, i.e. the
+/// file is open in another process (the game, MO2, an archive viewer...)
+pub fn is_sharing_violation(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    cfg!(windows) && matches!(error.raw_os_error(), Some(32) | Some(33))
+}
+
+/// Returns true if the error is an access denied error, which antivirus
+/// software and Defender's Controlled Folder Access cause when they block a
+/// program from writing or deleting a file
+pub fn is_access_denied(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::PermissionDenied
+}
+
+/// Whether Defender's Controlled Folder Access is turned on (in block or
+/// audit mode), read from the registry
+///
+/// # Returns
+/// * `Some(bool)` if the setting could be read
+/// * `None` if it can't, e.g. off Windows
+#[cfg(windows)]
+pub fn controlled_folder_access_enabled() -> Option<bool> {
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+    use winreg::RegKey;
+
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey("SOFTWARE\\Microsoft\\Windows Defender\\Windows Defender Exploit Guard\\Controlled Folder Access")
+        .and_then(|key| key.get_value::<u32, _>("EnableControlledFolderAccess"))
+        .ok()
+        .map(|value| value != 0)
+}
+
+/// Whether Defender's Controlled Folder Access is turned on (only known on Windows)
+#[cfg(not(windows))]
+pub fn controlled_folder_access_enabled() -> Option<bool> {
+    None
+}
+
+/// Explains an access denied error: what usually causes it during a build
+/// and the Defender exclusions that stop it.
+///
+/// # Arguments
+/// * `path` - The file or folder that couldn't be written or deleted
+/// * `programs` - Programs that were writing to it (this tool is always included)
+pub fn explain_access_denied(path: &Path, programs: &[&Path]) -> String {
+    let cause = match controlled_folder_access_enabled() {
+        Some(true) => "Windows Defender's Controlled Folder Access is turned on and is the likely cause",
+        _ => "This is usually antivirus software or Windows Defender's Controlled Folder Access blocking the build",
+    };
+    let folder = if path.is_dir() { path } else { path.parent().unwrap_or(path) };
+
+    let mut explanation = format!(
+        "Access to {} was denied. {}. Add these exclusions in an administrator PowerShell and retry:\n  Add-MpPreference -ExclusionPath \"{}\"",
+        path.display(),
+        cause,
+        folder.display()
+    );
+    let current = std::env::current_exe().ok();
+    for program in programs.iter().copied().chain(current.as_deref()) {
+        explanation.push_str(&format!(
+            "\n  Add-MpPreference -ControlledFolderAccessAllowedApplications \"{}\"",
+            program.display()
+        ));
+    }
+    explanation.push_str("\nWith other antivirus software, exclude the same folder and programs.");
+    explanation
+}
+
+/// Formats a file operation's error, explaining it when access was denied
+///
+/// # Arguments
+/// * `action` - What was being done, e.g. `removing file`
+/// * `path` - The file or folder it was done to
+/// * `error` - The error
+pub fn describe_io_error(action: &str, path: &Path, error: &io::Error) -> String {
+    let message = format!("Error {} {}: {}", action, path.display(), error);
+    if is_access_denied(error) {
+        format!("{}\n{}", message, explain_access_denied(path, &[]))
+    } else {
+        message
+    }
+}
+
+/// Waits until no other process holds the file open, so it can be extracted,
+/// replaced or deleted.
+///
+/// The processes holding the file are reported once, then the file is checked
+/// again every second until it is released or the timeout expires. Missing
+/// files and other errors are left for the caller to handle.
+///
+/// # Arguments
+/// * `path` - The file that is about to be modified
+/// * `timeout` - How long to wait for the file to be released
+///
+/// # Returns
+/// * `Ok(())` if the file isn't locked (or doesn't exist)
+/// * `Err(String)` naming the processes holding it if it is still locked after the timeout
+pub fn wait_for_unlock(path: &Path, timeout: Duration) -> Result<(), String> {
+    let start = Instant::now();
+    let mut reported = false;
+
+    loop {
+        let error = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(_) => {
+                if reported {
+                    info!("{} was released, continuing", path.display());
+                }
+                return Ok(());
+            }
+            Err(e) if is_sharing_violation(&e) => e,
+            Err(_) => return Ok(()),
+        };
+
+        let holders = locking_processes(path);
+        let holders = if holders.is_empty() {
+            "another process".to_string()
+        } else {
+            holders.join(", ")
+        };
+
+        if start.elapsed() >= timeout {
+            return Err(format!(
+                "ERROR - {} is locked by {} ({}). Close it and retry.",
+                path.display(),
+                holders,
+                error
+            ));
+        }
+
+        if !reported {
+            warn!(
+                "{} is in use by {}, waiting up to {} seconds for it to be released",
+                path.display(),
+                holders,
+                timeout.as_secs()
+            );
+            reported = true;
+        }
+        sleep(LOCK_POLL_INTERVAL);
+    }
+}
+
+/// Lists the processes that have the file open, using the Windows Restart Manager.
+///
+/// # Returns
+/// Process names with their ids, or an empty list if they can't be determined
+#[cfg(windows)]
+pub fn locking_processes(path: &Path) -> Vec<String> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{ERROR_MORE_DATA, ERROR_SUCCESS};
+    use windows_sys::Win32::System::RestartManager::{
+        RmEndSession, RmGetList, RmRegisterResources, RmStartSession, CCH_RM_SESSION_KEY,
+        RM_PROCESS_INFO,
+    };
+
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut session = 0u32;
+    let mut session_key = [0u16; CCH_RM_SESSION_KEY as usize + 1];
+
+    // SAFETY: every pointer passed below refers to a live, correctly sized
+    // buffer, and the session is always ended before returning.
+    unsafe {
+        if RmStartSession(&mut session, 0, session_key.as_mut_ptr()) != ERROR_SUCCESS {
+            return Vec::new();
+        }
+
+        let files = [wide_path.as_ptr()];
+        let mut processes = Vec::new();
+        if RmRegisterResources(session, 1, files.as_ptr(), 0, std::ptr::null(), 0, std::ptr::null())
+            == ERROR_SUCCESS
+        {
+            let mut needed = 0u32;
+            let mut count = 0u32;
+            let mut reasons = 0u32;
+            let mut result = RmGetList(session, &mut needed, &mut count, std::ptr::null_mut(), &mut reasons);
+
+            if result == ERROR_MORE_DATA {
+                let mut info: Vec<RM_PROCESS_INFO> = (0..needed).map(|_| RM_PROCESS_INFO::default()).collect();
+                count = needed;
+                result = RmGetList(session, &mut needed, &mut count, info.as_mut_ptr(), &mut reasons);
+
+                if result == ERROR_SUCCESS {
+                    info.truncate(count as usize);
+                    processes = info
+                        .iter()
+                        .map(|process| {
+                            let name_len = process
+                                .strAppName
+                                .iter()
+                                .position(|&c| c == 0)
+                                .unwrap_or(process.strAppName.len());
+                            format!(
+                                "{} (PID {})",
+                                String::from_utf16_lossy(&process.strAppName[..name_len]),
+                                process.Process.dwProcessId
+                            )
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        RmEndSession(session);
+        processes
+    }
+}
+
+/// Lists the processes that have the file open (only supported on Windows).
+#[cfg(not(windows))]
+pub fn locking_processes(_path: &Path) -> Vec<String> {
+    Vec::new()
+}
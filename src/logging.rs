@@ -0,0 +1,314 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use crate::events;
+use crate::cli::{Args, CleanLogsArgs};
+use crate::utils::{exe_dir, parse_timestamp, remove_dir_all_with_retry, timestamp};
+
+/// Number of run folders kept per plugin when --keep-runs isn't given
+pub const DEFAULT_KEEP_RUNS: usize = 10;
+
+/// Name of the runs folder when --runs-dir isn't given
+const DEFAULT_RUNS_DIR: &str = "previsbine-runs";
+
+/// Runs folder used when --runs-dir isn't given: next to the executable, so
+/// the logs of every build end up in one place whatever folder it's started from
+pub fn default_runs_dir() -> String {
+    exe_dir().join(DEFAULT_RUNS_DIR).display().to_string()
+}
+
+/// Per-plugin log of the build in progress, if one has been started
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Set by --sync-logs
+static SYNC: AtomicBool = AtomicBool::new(false);
+
+/// Warnings logged since they were last taken
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Prints a line like `println!` and mirrors it into the build's log file
+#[macro_export]
+macro_rules! console {
+    () => {
+        $crate::logging::console("")
+    };
+    ($($arg:tt)*) => {
+        $crate::logging::console(&format!($($arg)*))
+    };
+}
+
+/// Sends log records to the console logger and, from Info up, to the build's
+/// log file whatever the console filter is. Warnings are also kept for the
+/// summary at the end of the build.
+struct TeeLogger {
+    console: env_logger::Logger,
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata) || metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.console.matches(record) {
+            self.console.log(record);
+        }
+        if record.level() <= Level::Info {
+            write_line(&format!("{:<5} {}", record.level(), record.args()));
+        }
+        if record.level() == Level::Error {
+            sync_log_file();
+        }
+        if record.level() == Level::Warn {
+            let warning = record.args().to_string();
+            let warning = warning.strip_prefix("WARNING - ").unwrap_or(&warning).to_string();
+            events::emit_warning(&warning);
+            let mut warnings = WARNINGS.lock().unwrap_or_else(|e| e.into_inner());
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+/// Installs the logger: `console` prints to the terminal as configured, and
+/// every record from Info up is also written to the build's log file.
+///
+/// # Arguments
+/// * `console` - The configured console logger
+pub fn init(console: env_logger::Logger) -> Result<(), SetLoggerError> {
+    let max_level = console.filter().max(LevelFilter::Info);
+    log::set_boxed_logger(Box::new(TeeLogger { console }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Starts mirroring the console into `path`, appending if it exists
+pub fn open_log_file(path: &Path) -> Result<(), String> {
+    let file = File::options()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| format!("Error opening log file {}: {}", path.display(), e))?;
+    *LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+/// Stops mirroring the console into the log file
+pub fn close_log_file() {
+    sync_log_file();
+    *LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Writes the log file and build report through to disk at every checkpoint
+/// (--sync-logs), so they survive a system crash, at some cost in speed
+pub fn enable_sync() {
+    SYNC.store(true, Ordering::Relaxed);
+}
+
+/// Whether --sync-logs is on
+pub fn is_syncing() -> bool {
+    SYNC.load(Ordering::Relaxed)
+}
+
+/// Checkpoint after a stage or an error: lines are written to the log file
+/// as they come, and with --sync-logs they are also forced out to disk
+pub fn sync_log_file() {
+    let mut log_file = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = log_file.as_mut() {
+        let _ = file.flush();
+        if is_syncing() {
+            let _ = file.sync_data();
+        }
+    }
+}
+
+/// Returns the warnings logged since the last call, without repeats
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Returns the warnings logged so far without taking them
+pub fn peek_warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Prints a line and writes it to the log file with a timestamp
+pub fn console(line: &str) {
+    println!("{}", line);
+    write_line(line);
+}
+
+/// Writes one timestamped line to the log file, if one is open
+pub fn write_line(line: &str) {
+    let line = strip_ansi(line);
+    let stamp = timestamp();
+    append(
+        &line
+            .lines()
+            .map(|line| format!("[{}] {}", stamp, line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
+}
+
+/// Writes text to the log file as it is, e.g. a tool's own log.
+/// Does nothing if no log file is open.
+pub fn append(text: &str) {
+    let mut log_file = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = log_file.as_mut() {
+        // A failed write can't be reported anywhere better than the console,
+        // which already shows the line
+        let _ = writeln!(file, "{}", text);
+    }
+}
+
+/// Removes the escape sequences colored output adds
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the final letter of the sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Which run folders to keep, for each plugin
+#[derive(Debug, Clone, Default)]
+pub struct Retention {
+    /// Number of newest run folders kept per plugin
+    pub keep_runs: Option<usize>,
+    /// Run folders started longer ago than this many days are removed
+    pub keep_days: Option<u64>,
+    /// Only look at the run folders of this plugin (name without extension)
+    pub plugin: Option<String>,
+}
+
+/// A `<plugin>-<timestamp>` run folder
+struct RunFolder {
+    path: PathBuf,
+    /// Plugin name, lowercase
+    plugin: String,
+    /// When the run started, in seconds since the Unix epoch
+    started: u64,
+}
+
+/// Lists the run folders under `runs_dir`, oldest first
+fn list_runs(runs_dir: &Path) -> Vec<RunFolder> {
+    let Ok(entries) = fs::read_dir(runs_dir) else {
+        return Vec::new();
+    };
+
+    let mut runs: Vec<RunFolder> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?.to_lowercase();
+            let split = name.len().checked_sub(16).filter(|split| name.is_char_boundary(*split))?;
+            let (plugin, stamp) = name.split_at(split);
+            let started = parse_timestamp(stamp.strip_prefix('-')?)?;
+            Some(RunFolder { plugin: plugin.to_string(), started, path })
+        })
+        .collect();
+    runs.sort_by(|a, b| (a.started, &a.path).cmp(&(b.started, &b.path)));
+    runs
+}
+
+/// Finds the run folders a retention policy removes.
+///
+/// # Arguments
+/// * `runs_dir` - Folder holding the run folders
+/// * `retention` - What to keep
+///
+/// # Returns
+/// The run folders to remove, oldest first
+pub fn expired_runs(runs_dir: &Path, retention: &Retention) -> Vec<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let cutoff = retention.keep_days.map(|days| now.saturating_sub(days.saturating_mul(86_400)));
+    let plugin = retention.plugin.as_ref().map(|plugin| plugin.to_lowercase());
+
+    let mut runs = list_runs(runs_dir);
+    runs.retain(|run| plugin.as_ref().is_none_or(|plugin| &run.plugin == plugin));
+    runs.iter()
+        .filter(|run| {
+            let newer = runs.iter().filter(|other| other.plugin == run.plugin && other.started > run.started).count();
+            retention.keep_runs.is_some_and(|keep| newer >= keep)
+                || cutoff.is_some_and(|cutoff| run.started < cutoff)
+        })
+        .map(|run| run.path.clone())
+        .collect()
+}
+
+/// Removes the run folders a retention policy doesn't keep.
+///
+/// # Returns
+/// The run folders removed
+pub fn apply_retention(runs_dir: &Path, retention: &Retention) -> Result<Vec<PathBuf>, String> {
+    let expired = expired_runs(runs_dir, retention);
+    for run in &expired {
+        remove_dir_all_with_retry(run).map_err(|e| format!("Error removing old run folder {}: {}", run.display(), e))?;
+    }
+    Ok(expired)
+}
+
+/// Removes the oldest `<plugin>-<timestamp>` run folders of a plugin so at
+/// most `keep` remain.
+///
+/// # Arguments
+/// * `runs_dir` - Folder holding the run folders
+/// * `plugin_name` - Plugin name without extension
+/// * `keep` - Number of run folders to keep
+///
+/// # Returns
+/// The number of run folders removed
+pub fn prune_runs(runs_dir: &Path, plugin_name: &str, keep: usize) -> Result<usize, String> {
+    let retention = Retention { keep_runs: Some(keep), keep_days: None, plugin: Some(plugin_name.to_string()) };
+    apply_retention(runs_dir, &retention).map(|removed| removed.len())
+}
+
+/// Runs the `clean-logs` command.
+///
+/// Removes old run folders (logs, reports, diagnostics) from the runs
+/// folder. Without --keep or --older-than the configured --keep-runs and
+/// --keep-days are applied.
+pub fn run_clean_logs(args: &Args, clean: &CleanLogsArgs) -> Result<(), String> {
+    let runs_dir = Path::new(&args.runs_dir);
+    let retention = if clean.keep.is_none() && clean.older_than.is_none() {
+        Retention { keep_runs: Some(args.keep_runs), keep_days: args.keep_days, plugin: clean.plugin.clone() }
+    } else {
+        Retention { keep_runs: clean.keep, keep_days: clean.older_than, plugin: clean.plugin.clone() }
+    };
+
+    let runs = if clean.dry_run { expired_runs(runs_dir, &retention) } else { apply_retention(runs_dir, &retention)? };
+    if runs.is_empty() {
+        println!("No run folders to remove in {}", runs_dir.display());
+        return Ok(());
+    }
+    for run in &runs {
+        println!("{} {}", if clean.dry_run { "Would remove" } else { "Removed" }, run.display());
+    }
+    println!(
+        "{} {} run folders",
+        if clean.dry_run { "Would remove" } else { "Removed" },
+        runs.len()
+    );
+    Ok(())
+}
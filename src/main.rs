@@ -1,29 +1,258 @@
-use clap::Parser;
-use env_logger;
-use log::error;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use env_logger::WriteStyle;
+use log::{error, warn};
+use serde_json::json;
 
-use generate_previsbines::{Args, PrevisbineBuilder};
+use generate_previsbines::{Args, Command, PrevisbineBuilder};
+use generate_previsbines::abort::run_abort;
+use generate_previsbines::answers::{load_answers, set_assume_yes, set_prompt_timeout, Prompt};
+use generate_previsbines::backup::run_restore_backup;
+use generate_previsbines::build_history::run_history;
+use generate_previsbines::check::run_check;
+use generate_previsbines::clean::run_clean_previsbines;
+use generate_previsbines::cli::{ConfigAction, OutputFormat};
+use generate_previsbines::config::{print_effective_config, run_config_lint, Config};
+use generate_previsbines::events;
+use generate_previsbines::fixture::run_fixture;
+use generate_previsbines::frontend;
+use generate_previsbines::headless;
+use generate_previsbines::leftovers::{offer_dll_repair, run_repair_dlls};
+use generate_previsbines::logging::{self, run_clean_logs};
+use generate_previsbines::manifest::run_diff_builds;
+use generate_previsbines::notify;
+use generate_previsbines::paths::find_fallout4;
+use generate_previsbines::style;
+use generate_previsbines::tools::{enable_show_commands, enable_simulation, set_ck_launcher};
+use generate_previsbines::repack::run_repack;
+use generate_previsbines::setup::run_setup;
+use generate_previsbines::ui::prompt_yes_no;
+use generate_previsbines::update::{print_update_notice, remove_replaced_executable, run_self_update};
+use generate_previsbines::utils::set_work_dir;
+use generate_previsbines::wizard::run_wizard;
 
 fn main() {
+    // Parse command line arguments, with the saved settings as defaults
+    let config = match Config::load() {
+        Ok(config) => config,
+        // `config lint` explains what is wrong with the file
+        Err(_) if matches!(Args::parse_command_line(None).command, Some(Command::Config(_))) => None,
+        Err(e) => exit_with_error(&e),
+    };
+    let mut args = Args::parse_command_line(config.as_ref());
+
     // Initialize logger
-    env_logger::init();
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.plain {
+        style::enable_plain();
+    }
+    if args.no_color || args.headless || args.plain {
+        style::disable_colors();
+        logger.write_style(WriteStyle::Never);
+    }
+    if let Err(e) = logging::init(logger.build()) {
+        eprintln!("Error initializing logging: {}", e);
+    }
+    remove_replaced_executable();
+    if args.notify && !args.headless {
+        notify::enable();
+    }
+    if args.headless {
+        headless::enable();
+    }
+    if args.non_interactive || args.no_prompt {
+        headless::set_non_interactive();
+    }
+    if args.assume_yes || args.no_prompt {
+        set_assume_yes();
+    }
+    if args.show_commands {
+        enable_show_commands();
+    }
+    if args.sync_logs {
+        logging::enable_sync();
+    }
+    if let Some(work_dir) = &args.work_dir {
+        if let Err(e) = set_work_dir(Path::new(work_dir)) {
+            exit_with_error(&e);
+        }
+    }
+    if args.simulate {
+        enable_simulation();
+        warn!("WARNING - Simulating: no tools will run and the files written to Data are placeholders");
+    }
+    let build_timeout = args
+        .build_timeout
+        .map(|minutes| Duration::from_secs(minutes * 60))
+        .or(args.headless.then_some(headless::DEFAULT_HEADLESS_TIMEOUT));
+    if let Some(timeout) = build_timeout {
+        headless::start_watchdog(timeout);
+    }
+    let events = if args.frontend_mode {
+        frontend::enable()
+    } else if let Some(output_file) = &args.output_file {
+        events::enable_file(Path::new(output_file))
+    } else if args.output == OutputFormat::Jsonl {
+        events::enable_stdout()
+    } else {
+        Ok(())
+    };
+    if let Err(e) = events {
+        exit_with_error(&e);
+    }
+    if let Some(answers) = &args.answers {
+        if let Err(e) = load_answers(Path::new(answers)) {
+            exit_with_error(&e);
+        }
+    }
+    if let Some(seconds) = args.prompt_timeout {
+        if let Err(e) = set_prompt_timeout(Duration::from_secs(seconds), &args.timeout_answer) {
+            exit_with_error(&e);
+        }
+    }
 
-    // Parse command line arguments
-    let args = Args::parse();
+    if let Some(format) = args.print_config {
+        if let Err(e) = print_effective_config(&args, config.as_ref(), format) {
+            exit_with_error(&e);
+        }
+        return;
+    }
+
+    // Opt-in check for a newer release; frontends read JSON on stdout
+    if args.check_updates && !args.frontend_mode && args.output == OutputFormat::Text && !matches!(args.command, Some(Command::SelfUpdate(_))) {
+        print_update_notice(config.as_ref());
+    }
+
+    // A crashed build leaves the game without its ENB/ReShade DLLs
+    if !matches!(args.command, Some(Command::RepairDlls | Command::Fixture(_) | Command::Config(_))) {
+        if let Some(fallout4) = args.fallout4_path.clone().map(PathBuf::from).or_else(|| find_fallout4().ok()) {
+            if let Err(e) = offer_dll_repair(&fallout4) {
+                exit_with_error(&e);
+            }
+        }
+    }
+
+    // Run a maintenance command instead of a build if one was given
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::Repack(repack) => run_repack(&args, repack),
+            Command::RestoreBackup(restore) => run_restore_backup(&args, restore),
+            Command::CleanPrevisbines(clean) => run_clean_previsbines(&args, clean),
+            Command::History(history) => run_history(&args, history),
+            Command::Abort(abort) => run_abort(&args, abort),
+            Command::Setup => run_setup(config.as_ref()).map(|_| ()),
+            Command::Fixture(fixture) => run_fixture(fixture),
+            Command::SelfUpdate(update) => run_self_update(update),
+            Command::DiffBuilds(diff) => run_diff_builds(&args, diff),
+            Command::CleanLogs(clean) => run_clean_logs(&args, clean),
+            Command::Check(check) => run_check(&args, check),
+            Command::RepairDlls => run_repair_dlls(&args),
+            Command::Config(config) => match &config.action {
+                ConfigAction::Lint(lint) => run_config_lint(lint),
+            },
+        };
+        if let Err(e) = result {
+            exit_with_error(&e);
+        }
+        return;
+    }
+
+    if let Some(launcher) = &args.ck_launcher {
+        if let Err(e) = set_ck_launcher(launcher) {
+            exit_with_error(&e);
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    if args.gui {
+        if let Err(e) = generate_previsbines::gui::run_gui(args) {
+            exit_with_error(&e);
+        }
+        return;
+    }
+
+    // Offer the setup on first launch; declining saves empty settings so it isn't offered again
+    if config.is_none() && offers_setup(&args) {
+        match prompt_yes_no(Prompt::RunSetup, "No settings saved yet. Set up the game and tool paths now?") {
+            Ok(true) => match run_setup(None) {
+                Ok(config) => args = Args::parse_command_line(Some(&config)),
+                Err(e) => exit_with_error(&e),
+            },
+            Ok(false) => {
+                if let Err(e) = Config::default().save() {
+                    exit_with_error(&e);
+                }
+                println!("Run `generate_previsbines setup` to set them up later.");
+            }
+            Err(e) => exit_with_error(&e),
+        }
+    }
+
+    // Let the wizard fill in the build settings
+    let args = if args.wizard {
+        match run_wizard(args) {
+            Ok(Some(args)) => args,
+            Ok(None) => return,
+            Err(e) => exit_with_error(&e),
+        }
+    } else {
+        args
+    };
+
+    // Build unrelated plugins back to back
+    if !args.queue.is_empty() {
+        if let Err(e) = PrevisbineBuilder::run_queue(args) {
+            exit_with_error(&e);
+        }
+        exit_if_stopped();
+        return;
+    }
+
+    // Build a base plugin and its patches in order
+    if !args.chain.is_empty() {
+        if let Err(e) = PrevisbineBuilder::run_chain(args) {
+            exit_with_error(&e);
+        }
+        exit_if_stopped();
+        return;
+    }
 
     // Create and run the builder
     match PrevisbineBuilder::new(args) {
         Ok(mut builder) => {
             if let Err(e) = builder.run() {
-                error!("{}", e);
-                eprintln!("{}", e);
-                std::process::exit(1);
+                exit_with_error(&e);
             }
         }
-        Err(e) => {
-            error!("{}", e);
-            eprintln!("{}", e);
-            std::process::exit(1);
-        }
+        Err(e) => exit_with_error(&e),
+    }
+    exit_if_stopped();
+}
+
+/// Exits with `EXIT_ABORTED` if the build stopped early on request
+fn exit_if_stopped() {
+    let code = headless::success_exit_code();
+    if code != headless::EXIT_SUCCESS {
+        std::process::exit(code);
     }
+}
+
+/// Whether the first-run setup can be offered: only when someone is at the
+/// console to answer and the paths aren't given on the command line
+fn offers_setup(args: &Args) -> bool {
+    io::stdin().is_terminal()
+        && !headless::is_non_interactive()
+        && !args.frontend_mode
+        && !args.wizard
+        && args.fallout4_path.is_none()
+        && args.fo4edit_path.is_none()
+}
+
+/// Reports an error and exits with a failure code
+fn exit_with_error(e: &str) -> ! {
+    error!("{}", e);
+    eprintln!("{}", style::error(e));
+    events::emit(json!({ "event": "error", "message": e }));
+    std::process::exit(headless::exit_code());
 }
\ No newline at end of file
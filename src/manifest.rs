@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::build_history::BuildHistory;
+use crate::cli::{Args, DiffBuildsArgs};
+use crate::paths::find_fallout4;
+use crate::tools::archive::{list_entry_names, sort_entry_names, entry_path};
+use crate::tools::ba2::for_each_ba2_file;
+use crate::utils::timestamp;
+
+/// Format version written into every manifest
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Every file a build produced, with sizes and SHA-256 hashes, written to the
+/// run folder as `<plugin>-manifest.json` after a successful build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildManifest {
+    pub version: u32,
+    pub tool_version: String,
+    pub plugin: String,
+    /// When the manifest was written
+    pub created: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+/// One produced file: in Data, in a loose output folder, or inside an archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to Data (or the archive), backslash separated
+    pub path: String,
+    /// The archive holding the file, for files packed into one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// Size in bytes (uncompressed for archived files)
+    pub size: u64,
+    /// SHA-256 of the contents, lowercase hex
+    pub sha256: String,
+}
+
+impl ManifestEntry {
+    /// Identifies the file across manifests: its archive and path, ignoring case
+    pub fn key(&self) -> String {
+        match &self.archive {
+            Some(archive) => format!("{}\\{}", archive, self.path).to_lowercase(),
+            None => self.path.to_lowercase(),
+        }
+    }
+}
+
+impl BuildManifest {
+    /// Creates an empty manifest for a plugin
+    pub fn new(plugin: &str) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            plugin: plugin.to_string(),
+            created: timestamp(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Adds a file, named by its path relative to Data
+    pub fn add_file(&mut self, name: &str, path: &Path) -> Result<(), String> {
+        let size = fs::metadata(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?
+            .len();
+        self.files.push(ManifestEntry {
+            path: name.replace('/', "\\"),
+            archive: None,
+            size,
+            sha256: hash_file(path)?,
+        });
+        Ok(())
+    }
+
+    /// Adds an archive and every file packed in it
+    pub fn add_archive(&mut self, name: &str, path: &Path) -> Result<(), String> {
+        self.add_file(name, path)?;
+        let mut entries = Vec::new();
+        for_each_ba2_file(path, |record, data| {
+            entries.push(ManifestEntry {
+                path: record.name.clone(),
+                archive: Some(name.to_string()),
+                size: data.len() as u64,
+                sha256: format!("{:x}", Sha256::digest(&data)),
+            });
+            Ok(())
+        })?;
+        entries.sort_by_cached_key(ManifestEntry::key);
+        self.files.extend(entries);
+        Ok(())
+    }
+
+    /// Adds every file under `root\folder`, named relative to `root`
+    pub fn add_folder(&mut self, root: &Path, folder: &str) -> Result<(), String> {
+        let folder_dir = entry_path(root, folder);
+        let mut names = list_entry_names(&folder_dir);
+        sort_entry_names(&mut names);
+        for name in names {
+            self.add_file(&format!("{}\\{}", folder, name), &entry_path(&folder_dir, &name))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the manifest to a JSON file
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing build manifest: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Error writing build manifest {}: {}", path.display(), e))
+    }
+
+    /// Reads a manifest written by `write`
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading build manifest {}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("Error reading build manifest {}: {}", path.display(), e))
+    }
+}
+
+/// Files that differ between two builds
+#[derive(Debug, Clone, Default)]
+pub struct ManifestDiff {
+    /// Files only in the later build
+    pub added: Vec<ManifestEntry>,
+    /// Files only in the earlier build
+    pub removed: Vec<ManifestEntry>,
+    /// Files in both builds with different contents, as (earlier, later)
+    pub changed: Vec<(ManifestEntry, ManifestEntry)>,
+    /// Files in both builds with the same contents
+    pub unchanged: Vec<ManifestEntry>,
+}
+
+impl ManifestDiff {
+    /// Whether the builds produced the same files
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Change in total size from the earlier build to the later one, in bytes
+    pub fn size_delta(&self) -> i64 {
+        let added: i64 = self.added.iter().map(|file| file.size as i64).sum();
+        let removed: i64 = self.removed.iter().map(|file| file.size as i64).sum();
+        let changed: i64 = self.changed.iter().map(|(old, new)| new.size as i64 - old.size as i64).sum();
+        added - removed + changed
+    }
+
+    /// Form IDs of the cells whose precombined meshes were added, removed or
+    /// changed, taken from the mesh names (`<cell>_<hash>_OC.nif`)
+    pub fn precombined_cells(&self) -> BTreeSet<String> {
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .chain(self.changed.iter().map(|(_, new)| new))
+            .filter_map(|file| {
+                let (folder, name) = file.path.rsplit_once('\\')?;
+                if !folder.to_lowercase().starts_with("meshes\\precombined") {
+                    return None;
+                }
+                name.split_once('_').map(|(cell, _)| cell.to_uppercase())
+            })
+            .collect()
+    }
+}
+
+/// Compares the files of two builds by path (and archive), ignoring case
+///
+/// # Arguments
+/// * `old` - Files of the earlier build
+/// * `new` - Files of the later build
+///
+/// # Returns
+/// The added, removed, changed and unchanged files, each sorted by path
+pub fn diff_manifests(old: &[ManifestEntry], new: &[ManifestEntry]) -> ManifestDiff {
+    let old: BTreeMap<String, &ManifestEntry> = old.iter().map(|file| (file.key(), file)).collect();
+    let new: BTreeMap<String, &ManifestEntry> = new.iter().map(|file| (file.key(), file)).collect();
+
+    let mut diff = ManifestDiff::default();
+    for (key, old_file) in &old {
+        match new.get(key) {
+            None => diff.removed.push((*old_file).clone()),
+            Some(new_file) if new_file.sha256 != old_file.sha256 => {
+                diff.changed.push(((*old_file).clone(), (*new_file).clone()))
+            }
+            Some(_) => diff.unchanged.push((*old_file).clone()),
+        }
+    }
+    diff.added = new
+        .iter()
+        .filter(|(key, _)| !old.contains_key(*key))
+        .map(|(_, file)| (*file).clone())
+        .collect();
+    diff
+}
+
+/// Runs the `diff-builds` command: reports the files added, removed and
+/// changed between two builds, with their size differences.
+///
+/// Each build is given as a manifest, a run folder holding one, a build
+/// number from the build history, or a BA2 archive read with the native
+/// reader. Two archives are compared by their entries alone, so differently
+/// named copies of an archive can be compared.
+pub fn run_diff_builds(args: &Args, diff_args: &DiffBuildsArgs) -> Result<(), String> {
+    let both_archives = is_archive(&diff_args.old) && is_archive(&diff_args.new);
+    let old = load_build_files(args, &diff_args.old, both_archives)?;
+    let new = load_build_files(args, &diff_args.new, both_archives)?;
+    let diff = diff_manifests(&old, &new);
+
+    println!("Comparing {} with {}", diff_args.old, diff_args.new);
+    for file in &diff.added {
+        println!("  + {:<60} {:>12}", display_path(file), format_size(file.size as i64, false));
+    }
+    for file in &diff.removed {
+        println!("  - {:<60} {:>12}", display_path(file), format_size(-(file.size as i64), true));
+    }
+    for (old, new) in &diff.changed {
+        println!(
+            "  ~ {:<60} {:>12}  ({} -> {})",
+            display_path(new),
+            format_size(new.size as i64 - old.size as i64, true),
+            format_size(old.size as i64, false),
+            format_size(new.size as i64, false)
+        );
+    }
+    if diff_args.unchanged {
+        for file in &diff.unchanged {
+            println!("    {:<60} {:>12}", display_path(file), format_size(file.size as i64, false));
+        }
+    }
+
+    if diff.is_empty() {
+        println!("\nThe builds produced identical files ({} files)", diff.unchanged.len());
+        return Ok(());
+    }
+    println!(
+        "\n{} added, {} removed, {} changed, {} unchanged; total size {}",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged.len(),
+        format_size(diff.size_delta(), true)
+    );
+    let cells = diff.precombined_cells();
+    if !cells.is_empty() {
+        println!(
+            "Precombined meshes differ in {} cells: {}",
+            cells.len(),
+            cells.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn is_archive(spec: &str) -> bool {
+    spec.to_lowercase().ends_with(".ba2")
+}
+
+/// Loads the files of a build given as a manifest, run folder, build number or archive
+fn load_build_files(args: &Args, spec: &str, entries_only: bool) -> Result<Vec<ManifestEntry>, String> {
+    let path = PathBuf::from(spec);
+    if is_archive(spec) {
+        let mut manifest = BuildManifest::new("");
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        manifest.add_archive(&name, &path)?;
+        if entries_only {
+            manifest.files.retain_mut(|file| file.archive.take().is_some());
+        }
+        return Ok(manifest.files);
+    }
+    if path.is_file() {
+        return Ok(BuildManifest::read(&path)?.files);
+    }
+    if path.is_dir() {
+        return Ok(BuildManifest::read(&find_manifest(&path)?)?.files);
+    }
+
+    let id: u32 = spec
+        .parse()
+        .map_err(|_| format!("ERROR - {} is not a manifest, run folder, build number or archive", spec))?;
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+    let history = BuildHistory::load(&fallout4);
+    let build = history
+        .builds
+        .iter()
+        .find(|build| build.id == id)
+        .ok_or_else(|| format!("ERROR - Build {} not found in the build history", id))?;
+    let run_dir = build
+        .run_dir
+        .as_ref()
+        .ok_or_else(|| format!("ERROR - Build {} has no run folder", id))?;
+    Ok(BuildManifest::read(&find_manifest(run_dir)?)?.files)
+}
+
+/// Finds the `<plugin>-manifest.json` in a run folder
+fn find_manifest(run_dir: &Path) -> Result<PathBuf, String> {
+    fs::read_dir(run_dir)
+        .map_err(|e| format!("Error reading {}: {}", run_dir.display(), e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.to_string_lossy().ends_with("-manifest.json"))
+        .ok_or_else(|| {
+            format!(
+                "ERROR - {} holds no build manifest; manifests are written by successful builds",
+                run_dir.display()
+            )
+        })
+}
+
+fn display_path(file: &ManifestEntry) -> String {
+    match &file.archive {
+        Some(archive) => format!("{}: {}", archive, file.path),
+        None => file.path.clone(),
+    }
+}
+
+/// Formats a size in bytes or KB, with a sign for differences
+fn format_size(bytes: i64, signed: bool) -> String {
+    let sign = match (signed, bytes.signum()) {
+        (true, 1) => "+",
+        (_, -1) => "-",
+        _ => "",
+    };
+    let bytes = bytes.unsigned_abs();
+    if bytes < 1024 {
+        format!("{}{} B", sign, bytes)
+    } else {
+        format!("{}{} KB", sign, bytes.div_ceil(1024))
+    }
+}
+
+/// SHA-256 of a file's contents, lowercase hex, read in chunks
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    File::open(path)
+        .and_then(|mut file| io::copy(&mut file, &mut hasher))
+        .map_err(|e| format!("Error hashing {}: {}", path.display(), e))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by --notify; notifications are off by default
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns notifications on for the rest of the process
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Lets the user know the build needs attention, if notifications are enabled.
+///
+/// Shows a toast notification on Windows and rings the terminal bell
+/// everywhere, so there is still a signal when the toast can't be shown.
+///
+/// # Arguments
+/// * `title` - Short title, e.g. "Build complete"
+/// * `message` - One line of detail
+pub fn notify(title: &str, message: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    #[cfg(windows)]
+    show_toast(title, message);
+    #[cfg(not(windows))]
+    let _ = (title, message);
+
+    print!("\x07");
+    let _ = io::stdout().flush();
+}
+
+/// Shows a Windows toast through PowerShell, without waiting for it
+#[cfg(windows)]
+fn show_toast(title: &str, message: &str) {
+    use std::process::{Command, Stdio};
+
+    let quote = |text: &str| text.replace('\'', "''");
+    let script = format!(
+        "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null; \
+         $template = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+         $text = $template.GetElementsByTagName('text'); \
+         $text.Item(0).AppendChild($template.CreateTextNode('{}')) | Out-Null; \
+         $text.Item(1).AppendChild($template.CreateTextNode('{}')) | Out-Null; \
+         $toast = [Windows.UI.Notifications.ToastNotification]::new($template); \
+         [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('{{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}}\\WindowsPowerShell\\v1.0\\powershell.exe').Show($toast)",
+        quote(title),
+        quote(message)
+    );
+
+    let _ = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-WindowStyle", "Hidden", "-Command", &script])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
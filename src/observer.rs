@@ -0,0 +1,33 @@
+use std::time::Duration;
+use crate::cli::BuildStage;
+use crate::report::BuildReport;
+
+/// Receives progress events from a build, so frontends can show progress
+/// without parsing the console output.
+///
+/// Every method has an empty default, so observers only implement the
+/// events they care about. Events are sent from the thread running the build.
+pub trait ProgressObserver {
+    /// The build is about to run `stages`, in order
+    fn build_started(&self, _plugin: &str, _stages: &[BuildStage]) {}
+
+    /// A stage started; `index` counts from 0 up to `total`
+    fn stage_started(&self, _stage: BuildStage, _index: usize, _total: usize) {}
+
+    /// A stage ended, after any retries
+    fn stage_finished(&self, _stage: BuildStage, _duration: Duration, _result: Result<(), &str>) {}
+
+    /// Overall progress of the build, from 0 to 100
+    fn progress(&self, _percent: u8) {}
+
+    /// A line of the log written by the Creation Kit or xEdit during a stage
+    fn log_line(&self, _stage: BuildStage, _line: &str) {}
+
+    /// The build ended; the report says how
+    fn build_finished(&self, _report: &BuildReport) {}
+}
+
+/// Observer ignoring every event, used when no frontend is attached
+pub struct NoopObserver;
+
+impl ProgressObserver for NoopObserver {}
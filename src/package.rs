@@ -0,0 +1,226 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use log::info;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+use crate::cli::NotesFormat;
+use crate::esp::CellSummary;
+use crate::tools::archive::existing_part_archives;
+
+/// Files making up a release of a plugin, as laid out in the package
+#[derive(Debug, Clone)]
+pub struct ReleaseFiles {
+    /// Plugin file name with extension
+    pub plugin: String,
+    /// Archive file names (main parts and the texture archive, if any)
+    pub archives: Vec<String>,
+}
+
+impl ReleaseFiles {
+    /// Collects the plugin and every archive belonging to it from `data_dir`
+    pub fn collect(data_dir: &Path, plugin_name: &str, plugin_name_ext: &str) -> Self {
+        let mut archives = existing_part_archives(data_dir, plugin_name);
+        let texture_archive = format!("{} - Textures.ba2", plugin_name);
+        if data_dir.join(&texture_archive).exists() {
+            archives.push(texture_archive);
+        }
+
+        Self {
+            plugin: plugin_name_ext.to_string(),
+            archives,
+        }
+    }
+}
+
+/// Returns the package file name for a plugin, e.g. `MyMod-1.2.zip`
+pub fn package_name(plugin_name: &str, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("{}-{}.zip", plugin_name, version),
+        None => format!("{}.zip", plugin_name),
+    }
+}
+
+/// Fills in the placeholders of a readme template.
+///
+/// Supported placeholders are `{plugin}`, `{version}` and `{files}` (one
+/// packaged file name per line).
+pub fn render_readme(template: &str, files: &ReleaseFiles, version: Option<&str>) -> String {
+    let file_list = std::iter::once(&files.plugin)
+        .chain(files.archives.iter())
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    template
+        .replace("{plugin}", &files.plugin)
+        .replace("{version}", version.unwrap_or(""))
+        .replace("{files}", &file_list)
+}
+
+/// What an upload description says about a build
+#[derive(Debug, Clone)]
+pub struct ReleaseInfo {
+    pub files: ReleaseFiles,
+    pub version: Option<String>,
+    /// Build mode (clean, filtered or xbox)
+    pub mode: String,
+    /// Fallout4.exe version and game build, e.g. `1.10.163.0 (old-gen)`
+    pub game_version: Option<String>,
+    pub creation_kit_version: Option<String>,
+    /// Version of CKPE's winhttp.dll, if it has one
+    pub ckpe_version: Option<String>,
+    /// Cells in the finished plugin, if it could be read
+    pub cells: Option<CellSummary>,
+}
+
+/// Fills in an upload description for a release: what it contains, how it
+/// was built and an empty changelog entry for the author to complete.
+///
+/// # Arguments
+/// * `info` - The release and how it was built
+/// * `format` - Markdown or BBCode
+pub fn render_release_notes(info: &ReleaseInfo, format: NotesFormat) -> String {
+    let heading = |text: &str| match format {
+        NotesFormat::Markdown => format!("## {}\n\n", text),
+        NotesFormat::Bbcode => format!("[size=4][b]{}[/b][/size]\n", text),
+    };
+    let list = |items: &[String]| match format {
+        NotesFormat::Markdown => items.iter().map(|item| format!("- {}\n", item)).collect::<String>() + "\n",
+        NotesFormat::Bbcode => format!(
+            "[list]\n{}[/list]\n\n",
+            items.iter().map(|item| format!("[*]{}\n", item)).collect::<String>()
+        ),
+    };
+    let unknown = |version: &Option<String>| version.clone().unwrap_or_else(|| "unknown".to_string());
+
+    let title = match &info.version {
+        Some(version) => format!("{} {}", info.files.plugin, version),
+        None => info.files.plugin.clone(),
+    };
+    let mut notes = match format {
+        NotesFormat::Markdown => format!("# {}\n\n", title),
+        NotesFormat::Bbcode => format!("[size=5][b]{}[/b][/size]\n\n", title),
+    };
+    notes.push_str(&format!(
+        "Precombined meshes and previs data for {}, generated with the Creation Kit.\n\n",
+        info.files.plugin
+    ));
+
+    notes.push_str(&heading("Files"));
+    let files: Vec<String> = std::iter::once(&info.files.plugin).chain(info.files.archives.iter()).cloned().collect();
+    notes.push_str(&list(&files));
+
+    notes.push_str(&heading("Build details"));
+    let mut details = vec![
+        format!("Built with GeneratePrevisbines {}", env!("CARGO_PKG_VERSION")),
+        format!("Build mode: {}", info.mode),
+        format!("Fallout 4: {}", unknown(&info.game_version)),
+        format!("Creation Kit: {}", unknown(&info.creation_kit_version)),
+        format!("CKPE: {}", unknown(&info.ckpe_version)),
+    ];
+    if let Some(cells) = &info.cells {
+        details.push(format!(
+            "Cells: {} new and {} overridden ({} interior, {} exterior)",
+            cells.new_cells(),
+            cells.overridden_cells(),
+            cells.new_interior_cells + cells.overridden_interior_cells,
+            cells.new_exterior_cells + cells.overridden_exterior_cells
+        ));
+    }
+    notes.push_str(&list(&details));
+
+    notes.push_str(&heading("Changelog"));
+    notes.push_str(&match format {
+        NotesFormat::Markdown => format!("### {}\n\n", info.version.as_deref().unwrap_or("Initial release")),
+        NotesFormat::Bbcode => format!("[b]{}[/b]\n", info.version.as_deref().unwrap_or("Initial release")),
+    });
+    notes.push_str(&list(&["Regenerated precombines and previs".to_string()]));
+    notes
+}
+
+/// Writes the upload description next to a package (`<package>-notes.md` or `.bbcode.txt`)
+///
+/// # Returns
+/// * `Ok(PathBuf)` with the path of the description
+/// * `Err(String)` if it can't be written
+pub fn write_release_notes(package_path: &Path, info: &ReleaseInfo, format: NotesFormat) -> Result<PathBuf, String> {
+    let stem = package_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = match format {
+        NotesFormat::Markdown => "md",
+        NotesFormat::Bbcode => "bbcode.txt",
+    };
+    let path = package_path.with_file_name(format!("{}-notes.{}", stem, extension));
+    fs::write(&path, render_release_notes(info, format))
+        .map_err(|e| format!("Error writing release notes {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// Packages a finished plugin and its archives into a zip ready for upload.
+///
+/// Files are placed at the root of the zip, which mod managers install into
+/// Data. Archives are stored without recompression as their content is
+/// already compressed.
+///
+/// # Arguments
+/// * `data_dir` - The Fallout 4 Data directory
+/// * `plugin_name` - Plugin name without extension
+/// * `plugin_name_ext` - Plugin name with extension
+/// * `version` - Release version appended to the package name
+/// * `readme_template` - Optional readme template, added as `<plugin> - Readme.txt`
+/// * `output_dir` - Directory the package is written to
+///
+/// # Returns
+/// * `Ok(PathBuf)` with the path of the created package
+/// * `Err(String)` if a file is missing or the package can't be written
+pub fn create_release_package(
+    data_dir: &Path,
+    plugin_name: &str,
+    plugin_name_ext: &str,
+    version: Option<&str>,
+    readme_template: Option<&Path>,
+    output_dir: &Path,
+) -> Result<PathBuf, String> {
+    let files = ReleaseFiles::collect(data_dir, plugin_name, plugin_name_ext);
+    if !data_dir.join(&files.plugin).exists() {
+        return Err(format!("ERROR - Can't package {}, the plugin was not found in Data", files.plugin));
+    }
+
+    let readme = match readme_template {
+        Some(path) => {
+            let template = fs::read_to_string(path)
+                .map_err(|e| format!("Error reading readme template {}: {}", path.display(), e))?;
+            Some(render_readme(&template, &files, version))
+        }
+        None => None,
+    };
+
+    let package_path = output_dir.join(package_name(plugin_name, version));
+    let package_file = File::create(&package_path)
+        .map_err(|e| format!("Error creating package {}: {}", package_path.display(), e))?;
+    let mut zip = ZipWriter::new(package_file);
+    let zip_error = |e: zip::result::ZipError| format!("Error writing package {}: {}", package_path.display(), e);
+    let io_error = |e: io::Error| format!("Error writing package {}: {}", package_path.display(), e);
+
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let entries = std::iter::once((&files.plugin, deflated))
+        .chain(files.archives.iter().map(|archive| (archive, stored)));
+    for (file_name, options) in entries {
+        let mut source = File::open(data_dir.join(file_name))
+            .map_err(|e| format!("Error opening {} for packaging: {}", file_name, e))?;
+        zip.start_file(file_name.as_str(), options).map_err(zip_error)?;
+        io::copy(&mut source, &mut zip).map_err(io_error)?;
+        info!("Packaged {}", file_name);
+    }
+
+    if let Some(readme) = readme {
+        zip.start_file(format!("{} - Readme.txt", plugin_name), deflated)
+            .map_err(zip_error)?;
+        io::Write::write_all(&mut zip, readme.as_bytes()).map_err(io_error)?;
+    }
+
+    zip.finish().map_err(zip_error)?;
+    Ok(package_path)
+}
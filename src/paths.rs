@@ -1,6 +1,6 @@
 use std::env;
-use std::path::PathBuf;
-use log::warn;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
 
 #[cfg(windows)]
 use winreg::enums::*;
@@ -38,36 +38,10 @@ impl Paths {
 
         // Prepare other paths
         let creation_kit = fallout4.join("CreationKit.exe");
-        let archive2 = fallout4
-            .join("tools")
-            .join("archive2")
-            .join("archive2.exe");
+        let archive2 = archive2_path(&fallout4);
 
         // Handle BSArch path
-        let bsarch = if use_bsarch {
-            if let Some(path) = bsarch_path {
-                Some(PathBuf::from(path))
-            } else {
-                // Try to find BSArch in common locations
-                let possible_paths = [
-                    PathBuf::from("tools").join("BSArch").join("bsarch.exe"),
-                    PathBuf::from("BSArch").join("bsarch.exe"),
-                    PathBuf::from("bsarch.exe"),
-                ];
-
-                let found_path = possible_paths.iter()
-                    .find(|p| p.exists())
-                    .cloned();
-
-                if found_path.is_none() {
-                    warn!("BSArch enabled but path not specified and not found in common locations. Will check during environment verification.");
-                }
-
-                found_path
-            }
-        } else {
-            None
-        };
+        let bsarch = find_bsarch(use_bsarch, bsarch_path, fo4edit.parent());
 
         Ok(Paths {
             fo4edit,
@@ -79,6 +53,51 @@ impl Paths {
     }
 }
 
+/// Returns the location of Archive2.exe within a Fallout 4 installation
+pub fn archive2_path(fallout4: &Path) -> PathBuf {
+    fallout4
+        .join("tools")
+        .join("archive2")
+        .join("archive2.exe")
+}
+
+/// Resolves the BSArch executable when BSArch is enabled, using the given
+/// path or searching the xEdit folder and common locations
+///
+/// BSArch ships with xEdit, so the folder of the resolved FO4Edit executable
+/// and its tool subfolders are checked before the working directory.
+pub fn find_bsarch(use_bsarch: bool, bsarch_path: Option<String>, xedit_dir: Option<&Path>) -> Option<PathBuf> {
+    if !use_bsarch {
+        return None;
+    }
+
+    if let Some(path) = bsarch_path {
+        return Some(PathBuf::from(path));
+    }
+
+    // Try to find BSArch next to xEdit, then in common locations
+    let mut possible_paths = Vec::new();
+    if let Some(dir) = xedit_dir {
+        possible_paths.push(dir.join("bsarch.exe"));
+        possible_paths.push(dir.join("BSArch").join("bsarch.exe"));
+        possible_paths.push(dir.join("Tools").join("bsarch.exe"));
+        possible_paths.push(dir.join("Tools").join("BSArch").join("bsarch.exe"));
+        possible_paths.push(dir.join("Hotkeys").join("bsarch.exe"));
+    }
+    possible_paths.push(PathBuf::from("tools").join("BSArch").join("bsarch.exe"));
+    possible_paths.push(PathBuf::from("BSArch").join("bsarch.exe"));
+    possible_paths.push(PathBuf::from("bsarch.exe"));
+
+    let found_path = possible_paths.into_iter().find(|p| p.exists());
+
+    match &found_path {
+        Some(path) => info!("Found BSArch at {}", path.display()),
+        None => warn!("BSArch enabled but path not specified and not found next to xEdit or in common locations. Will check during environment verification."),
+    }
+
+    found_path
+}
+
 fn find_fo4edit() -> Result<PathBuf, String> {
     // First check current directory
     let current_dir =
@@ -141,7 +160,7 @@ fn find_fo4edit() -> Result<PathBuf, String> {
 /// specify the path manually using a parameter such as `--fallout4_path`.
 ///
 /// ```
-fn find_fallout4() -> Result<PathBuf, String> {
+pub fn find_fallout4() -> Result<PathBuf, String> {
     // Try registry on Windows
     #[cfg(windows)]
     {
@@ -154,8 +173,19 @@ fn find_fallout4() -> Result<PathBuf, String> {
         }
     }
 
+    // Steam on Linux and macOS installs the game in its library for Proton
+    #[cfg(not(windows))]
+    if let Some(home) = env::var_os("HOME") {
+        for library in [".steam/steam", ".local/share/Steam", "Library/Application Support/Steam"] {
+            let path = PathBuf::from(&home).join(library).join("steamapps/common/Fallout 4");
+            if path.join("Fallout4.exe").exists() {
+                return Ok(path);
+            }
+        }
+    }
+
     Err(
-        "Fallout 4 installation not found. Please specify path with --fallout4_path"
+        "Fallout 4 installation not found. Please specify path with --fallout4-path"
             .to_string(),
     )
 }
\ No newline at end of file
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::info;
+
+use crate::cli::{Args, ArchiveCompression, ArchiverKind, BuildMode, RepackArgs};
+use crate::game::resolve_ba2_version;
+use crate::paths::{archive2_path, find_bsarch, find_fallout4};
+use crate::tools::archive::{audit_archive, verify_extraction, PackCompression, PackRequest};
+use crate::tools::archiver::create_archiver;
+use crate::tools::ba2::set_ba2_version;
+use crate::locks::wait_for_unlock;
+use crate::utils::{ensure_directory_exists, remove_dir_all_if_exists, work_dir};
+
+/// Re-creates an existing archive with a different BA2 version or compression.
+///
+/// The archive is extracted to a temporary folder and packed again with the
+/// requested settings, without regenerating any of its content.
+///
+/// # Arguments
+/// * `args` - Global command line arguments (tool paths, archiver, BA2 version)
+/// * `repack` - Arguments of the repack command
+///
+/// # Returns
+/// * `Ok(())` if the archive was repacked and audited successfully
+/// * `Err(String)` if any step fails; the original archive is left untouched
+pub fn run_repack(args: &Args, repack: &RepackArgs) -> Result<(), String> {
+    let fallout4 = match &args.fallout4_path {
+        Some(path) => PathBuf::from(path),
+        None => find_fallout4()?,
+    };
+    let data_dir = fallout4.join("Data");
+
+    let archive_path = if Path::new(&repack.archive).is_absolute() {
+        PathBuf::from(&repack.archive)
+    } else {
+        data_dir.join(&repack.archive)
+    };
+    if !archive_path.exists() {
+        return Err(format!("ERROR - Archive {} not found", archive_path.display()));
+    }
+
+    let output_path = repack.output.as_ref().map(PathBuf::from).unwrap_or_else(|| archive_path.clone());
+    let archive_name = archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let archiver_kind = args.archiver_kind();
+    if repack.xbox && args.compression == ArchiveCompression::None {
+        return Err("ERROR - --compression none can't be combined with --xbox".to_string());
+    }
+    if repack.xbox && archiver_kind != ArchiverKind::Archive2 {
+        return Err(format!("ERROR - Xbox compression requires Archive2 and can't be used with {}", archiver_kind));
+    }
+
+    let xedit_dir = args.fo4edit_path.as_deref().and_then(|path| Path::new(path).parent());
+    let bsarch = find_bsarch(archiver_kind == ArchiverKind::Bsarch, args.bsarch_path.clone(), xedit_dir);
+    let version = resolve_ba2_version(args.ba2_version, &fallout4);
+    let archiver = create_archiver(archiver_kind, &archive2_path(&fallout4), bsarch.as_deref(), args, version)?;
+
+    let work_dir = work_dir().join(format!("previsbine-repack-{}", archive_name));
+    let extract_dir = work_dir.join("content");
+    let new_archive = work_dir.join(&archive_name);
+    remove_dir_all_if_exists(&work_dir)?;
+    ensure_directory_exists(&extract_dir)?;
+
+    info!("Repacking {}", archive_path.display());
+
+    // Extract the original archive
+    let entries = archiver.list(&archive_path)?;
+    archiver.extract(&archive_path, &extract_dir)?;
+    verify_extraction(&entries, &extract_dir)?;
+
+    // Pack it again with the requested settings
+    let mode = if repack.xbox { BuildMode::Xbox } else { BuildMode::Clean };
+    let request = PackRequest::new(&extract_dir, &new_archive)
+        .with_compression(PackCompression::for_mode(&mode, args.compression))
+        .with_reproducible(args.reproducible);
+    archiver.pack(&request)?;
+
+    if let Some(version) = version {
+        set_ba2_version(&new_archive, version)?;
+    }
+    audit_archive(&new_archive, &extract_dir)?;
+
+    // Only replace the original once the new archive is known to be complete
+    wait_for_unlock(&output_path, Duration::from_secs(args.lock_timeout))?;
+    fs::copy(&new_archive, &output_path).map_err(|e| {
+        format!("Error writing repacked archive {}: {}", output_path.display(), e)
+    })?;
+    remove_dir_all_if_exists(&work_dir)?;
+
+    println!("Repacked {} -> {}", archive_path.display(), output_path.display());
+    Ok(())
+}
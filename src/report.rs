@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::logging;
+
+/// How the generated precombine/previs files were delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputKind {
+    /// Packed into the plugin's BA2 archive
+    Archive,
+    /// Left as loose files
+    Loose,
+}
+
+/// Wall-clock duration of one executed build stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+    pub success: bool,
+}
+
+/// Machine-readable summary of a build, written as JSON when the build ends
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildReport {
+    pub tool_version: String,
+    pub plugin: String,
+    pub mode: String,
+    pub output: OutputKind,
+    /// Where loose files were moved to, if not left in Data
+    pub loose_output_dir: Option<PathBuf>,
+    /// Where loose files were kept after archiving (--keep-loose)
+    pub loose_backup_dir: Option<PathBuf>,
+    /// Archives created by the build
+    pub archives: Vec<String>,
+    /// Folder holding this build's logs, report and collected outputs
+    pub run_dir: Option<PathBuf>,
+    /// Release package created with --package
+    pub package: Option<PathBuf>,
+    /// Upload description written with --release-notes
+    pub release_notes: Option<PathBuf>,
+    /// Manifest of the files the build produced, with their hashes
+    pub manifest: Option<PathBuf>,
+    /// Diagnostics bundle written because the build failed
+    pub diagnostics: Option<PathBuf>,
+    /// Where files overwritten or deleted by the build were backed up
+    pub backup_dir: Option<PathBuf>,
+    /// Duration of each stage that ran, in order
+    pub stages: Vec<StageTiming>,
+    pub success: bool,
+    /// The build was stopped with the `abort` command
+    pub aborted: bool,
+    /// Whether the build ended; false in the copies written after each stage
+    pub finished: bool,
+    pub error: Option<String>,
+    /// Warnings raised during the build, in the order they were raised
+    pub warnings: Vec<String>,
+}
+
+impl BuildReport {
+    /// Creates an empty report for the given plugin and build mode
+    pub fn new(plugin: &str, mode: &str, output: OutputKind) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            plugin: plugin.to_string(),
+            mode: mode.to_string(),
+            output,
+            loose_output_dir: None,
+            loose_backup_dir: None,
+            archives: Vec::new(),
+            run_dir: None,
+            package: None,
+            release_notes: None,
+            manifest: None,
+            diagnostics: None,
+            backup_dir: None,
+            stages: Vec::new(),
+            success: false,
+            aborted: false,
+            finished: false,
+            error: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Writes the report to a JSON file.
+    ///
+    /// The report is written next to the file and then renamed over it, so a
+    /// crash mid-write leaves the previous checkpoint rather than a truncated
+    /// file. With --sync-logs it is forced out to disk first.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        write_json(self, path.as_ref(), "build report")
+    }
+
+    /// Seconds the stages that ran took altogether
+    pub fn total_seconds(&self) -> f64 {
+        self.stages.iter().map(|timing| timing.seconds).sum()
+    }
+}
+
+/// Summary of a --queue run over independent plugins, written as JSON once
+/// the last one is built
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueReport {
+    pub tool_version: String,
+    pub mode: String,
+    /// Report of each plugin that was built, in queue order
+    pub builds: Vec<BuildReport>,
+    /// Plugins not built because the queue was stopped with `abort`
+    pub not_built: Vec<String>,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl QueueReport {
+    /// Creates an empty report for a queue built in the given mode
+    pub fn new(mode: &str) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            mode: mode.to_string(),
+            builds: Vec::new(),
+            not_built: Vec::new(),
+            succeeded: 0,
+            failed: 0,
+        }
+    }
+
+    /// Adds the report of a plugin's build
+    pub fn add(&mut self, report: BuildReport) {
+        if report.success {
+            self.succeeded += 1;
+        } else if !report.aborted {
+            self.failed += 1;
+        }
+        self.builds.push(report);
+    }
+
+    /// Writes the report to a JSON file, like [`BuildReport::write`]
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        write_json(self, path.as_ref(), "queue report")
+    }
+}
+
+/// Writes a report next to `path` and renames it over it, syncing it to disk first with --sync-logs
+fn write_json<T: Serialize>(report: &T, path: &Path, what: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| format!("Error serializing {}: {}", what, e))?;
+
+    let partial = path.with_extension("json.partial");
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&partial)?;
+        file.write_all(json.as_bytes())?;
+        if logging::is_syncing() {
+            file.sync_all()?;
+        }
+        fs::rename(&partial, path)
+    };
+    write().map_err(|e| format!("Error writing {} {}: {}", what, path.display(), e))
+}
@@ -0,0 +1,142 @@
+use std::path::PathBuf;
+use crate::cli::{ArchiverKind, BuildMode};
+use crate::config::Config;
+use crate::paths::{archive2_path, find_bsarch, find_fallout4, Paths};
+use crate::style;
+use crate::ui::{prompt_for_line, print_build_modes};
+use crate::wizard::prompt_for_existing_path;
+
+/// Runs the `setup` command: finds or asks for the game and tools, checks
+/// them, picks the default archiver and build mode and writes the
+/// configuration file, so later builds only need the plugin name.
+///
+/// # Arguments
+/// * `current` - The existing configuration, whose settings are offered again
+///
+/// # Returns
+/// * `Ok(Config)` with the settings written
+/// * `Err(String)` if input couldn't be read or the file couldn't be written
+pub fn run_setup(current: Option<&Config>) -> Result<Config, String> {
+    let current = current.cloned().unwrap_or_default();
+    if let Some(rule) = style::rule() {
+        println!("{}", rule);
+    }
+    println!("Previsbine builder setup");
+    println!("The settings are saved to {} and used by every build.", Config::path().display());
+    println!();
+
+    // Game
+    let fallout4 = match current.fallout4_path.as_ref().map(PathBuf::from).filter(|path| path.join("Data").is_dir()) {
+        Some(path) => path,
+        None => match find_fallout4() {
+            Ok(path) => path,
+            Err(_) => prompt_for_existing_path("Fallout 4 folder (the one holding Fallout4.exe)", |path| {
+                path.join("Data").is_dir()
+            })?,
+        },
+    };
+    println!("Fallout 4:    {}", fallout4.display());
+    let fallout4_path = Some(fallout4.display().to_string());
+
+    // xEdit
+    let paths = match Paths::new(current.fo4edit_path.clone(), fallout4_path.clone(), false, None) {
+        Ok(paths) if paths.fo4edit.is_file() => paths,
+        _ => {
+            let xedit = prompt_for_existing_path("Path of FO4Edit64.exe or xEdit64.exe", |path| path.is_file())?;
+            Paths::new(Some(xedit.display().to_string()), fallout4_path.clone(), false, None)?
+        }
+    };
+    println!("xEdit:        {}", paths.fo4edit.display());
+    println!(
+        "Creation Kit: {}{}",
+        paths.creation_kit.display(),
+        if paths.creation_kit.exists() { "" } else { " (not installed yet)" }
+    );
+
+    // Archiver
+    let archive2_found = archive2_path(&fallout4).exists();
+    let default_archiver = match current.archiver.as_deref() {
+        Some(archiver) => archiver.to_string(),
+        None if archive2_found => "archive2".to_string(),
+        None => "native".to_string(),
+    };
+    println!();
+    println!("Archivers: archive2 (comes with the Creation Kit{}), bsarch, native (built in)", if archive2_found { "" } else { ", not found" });
+    let archiver = loop {
+        let input = prompt_for_line(&format!("Archiver (Enter for {}): ", default_archiver))?;
+        let input = if input.is_empty() { default_archiver.clone() } else { input };
+        match input.to_lowercase().as_str() {
+            "archive2" => break ArchiverKind::Archive2,
+            "bsarch" => break ArchiverKind::Bsarch,
+            "native" => break ArchiverKind::Native,
+            _ => println!("Enter archive2, bsarch or native"),
+        }
+    };
+
+    let bsarch_path = if archiver == ArchiverKind::Bsarch {
+        match find_bsarch(true, current.bsarch_path.clone(), paths.fo4edit.parent()).filter(|path| path.is_file()) {
+            Some(path) => {
+                println!("BSArch:       {}", path.display());
+                current.bsarch_path.clone()
+            }
+            None => Some(prompt_for_existing_path("Path of BSArch.exe", |path| path.is_file())?.display().to_string()),
+        }
+    } else {
+        current.bsarch_path.clone()
+    };
+
+    // Build mode
+    let default_mode = current.mode.clone().unwrap_or_else(|| "clean".to_string());
+    println!();
+    println!("Default build mode:");
+    print_build_modes();
+    let mode = loop {
+        let input = prompt_for_line(&format!("Mode (Enter for {}): ", default_mode))?;
+        let input = if input.is_empty() { default_mode.clone() } else { input };
+        match input.to_lowercase().as_str() {
+            "1" | "clean" => break BuildMode::Clean,
+            "2" | "filtered" => break BuildMode::Filtered,
+            "3" | "xbox" => break BuildMode::Xbox,
+            "4" | "previs-only" => break BuildMode::PrevisOnly,
+            _ => println!("Enter 1, 2, 3 or 4"),
+        }
+    };
+
+    // Update check
+    let default_check = if current.check_updates == Some(true) { "y" } else { "n" };
+    println!();
+    let check_updates = loop {
+        let input = prompt_for_line(&format!("Check for a newer release at startup? [Y/N] (Enter for {}): ", default_check))?;
+        let input = if input.is_empty() { default_check.to_string() } else { input };
+        match input.to_lowercase().as_str() {
+            "y" | "yes" => break true,
+            "n" | "no" => break false,
+            _ => println!("Enter Y or N"),
+        }
+    };
+
+    let config = Config {
+        fallout4_path,
+        fo4edit_path: Some(paths.fo4edit.display().to_string()),
+        bsarch_path,
+        bsarch_multithreaded: current.bsarch_multithreaded,
+        bsarch_compress: current.bsarch_compress,
+        bsarch_share: current.bsarch_share,
+        ck_launcher: current.ck_launcher.clone(),
+        work_dir: current.work_dir.clone(),
+        archiver: Some(archiver.to_string().to_lowercase()),
+        mode: Some(mode.to_string()),
+        check_updates: Some(check_updates),
+        keep_runs: current.keep_runs,
+        keep_days: current.keep_days,
+        seed_plugins: current.seed_plugins.clone(),
+        stage_environment: current.stage_environment.clone(),
+        retries: current.retries.clone(),
+        default_profile: current.default_profile.clone(),
+        profiles: current.profiles.clone(),
+    };
+    let path = config.save()?;
+    println!();
+    println!("{}", style::success(&format!("Settings saved to {}", path.display())));
+    Ok(config)
+}
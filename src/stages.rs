@@ -0,0 +1,213 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use crate::cli::{BuildMode, BuildStage};
+
+/// Every build stage, in the order a full build runs them
+pub const ALL_STAGES: [BuildStage; 8] = [
+    BuildStage::GeneratePrecombines,
+    BuildStage::MergePrecombines,
+    BuildStage::ArchivePrecombines,
+    BuildStage::CompressPsg,
+    BuildStage::BuildCdx,
+    BuildStage::GeneratePrevis,
+    BuildStage::MergePrevis,
+    BuildStage::ArchiveVis,
+];
+
+/// Something a stage needs to find in Data before it can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requirement {
+    Plugin,
+    PrecombinedMeshes,
+    CombinedObjects,
+    GeometryPsg,
+    VisFiles,
+    PrevisPlugin,
+    /// Precombines of an earlier build, loose or in the plugin's archive
+    ExistingPrecombines,
+}
+
+impl Requirement {
+    /// The stage that produces this requirement, if any
+    pub fn producer(&self) -> Option<BuildStage> {
+        match self {
+            Requirement::Plugin | Requirement::ExistingPrecombines => None,
+            Requirement::PrecombinedMeshes | Requirement::CombinedObjects | Requirement::GeometryPsg => {
+                Some(BuildStage::GeneratePrecombines)
+            }
+            Requirement::VisFiles | Requirement::PrevisPlugin => Some(BuildStage::GeneratePrevis),
+        }
+    }
+
+    fn is_met(&self, context: &StageContext) -> bool {
+        let data_dir = &context.data_dir;
+        match self {
+            Requirement::Plugin => data_dir.join(context.plugin_name_ext).exists(),
+            Requirement::PrecombinedMeshes => {
+                (context.has_files)(&data_dir.join("meshes").join("precombined"), ".nif")
+            }
+            Requirement::CombinedObjects => data_dir.join("CombinedObjects.esp").exists(),
+            Requirement::GeometryPsg => data_dir.join(format!("{} - Geometry.psg", context.plugin_name)).exists(),
+            Requirement::VisFiles => (context.has_files)(&data_dir.join("vis"), ".uvd"),
+            Requirement::PrevisPlugin => data_dir.join("Previs.esp").exists(),
+            Requirement::ExistingPrecombines => {
+                Requirement::PrecombinedMeshes.is_met(context)
+                    || data_dir.join(format!("{} - Main.ba2", context.plugin_name)).exists()
+            }
+        }
+    }
+
+    fn missing_message(&self, context: &StageContext) -> String {
+        let what = match self {
+            Requirement::Plugin => return format!("ERROR - Plugin {} does not exist", context.plugin_name_ext),
+            Requirement::ExistingPrecombines => {
+                return format!(
+                    "ERROR - No precombined meshes found loose or in {} - Main.ba2. Previs-only builds reuse the precombines of an earlier build, run a clean or filtered build first.",
+                    context.plugin_name
+                )
+            }
+            Requirement::PrecombinedMeshes => "No precombined meshes found",
+            Requirement::CombinedObjects => "CombinedObjects.esp not found",
+            Requirement::GeometryPsg => "No Geometry.psg file found",
+            Requirement::VisFiles => "No visibility files found",
+            Requirement::PrevisPlugin => "Previs.esp not found",
+        };
+        match self.producer() {
+            Some(producer) => format!("ERROR - {}. Run {} first.", what, producer.description()),
+            None => format!("ERROR - {}", what),
+        }
+    }
+}
+
+/// Where the stage requirements are looked for
+pub struct StageContext<'a> {
+    pub data_dir: PathBuf,
+    pub plugin_name_ext: &'a str,
+    pub plugin_name: &'a str,
+    pub build_mode: BuildMode,
+    /// Checks whether a folder holds files with an extension
+    pub has_files: &'a dyn Fn(&Path, &str) -> bool,
+}
+
+/// Stages whose results a stage builds on
+pub fn dependencies(stage: BuildStage) -> &'static [BuildStage] {
+    match stage {
+        BuildStage::VerifyEnvironment | BuildStage::GeneratePrecombines => &[],
+        BuildStage::MergePrecombines | BuildStage::ArchivePrecombines | BuildStage::CompressPsg => {
+            &[BuildStage::GeneratePrecombines]
+        }
+        BuildStage::BuildCdx | BuildStage::GeneratePrevis => &[BuildStage::MergePrecombines],
+        BuildStage::MergePrevis | BuildStage::ArchiveVis => &[BuildStage::GeneratePrevis],
+    }
+}
+
+/// Files a stage needs in Data before it runs in a build mode
+pub fn requirements(stage: BuildStage, build_mode: BuildMode) -> &'static [Requirement] {
+    match stage {
+        BuildStage::VerifyEnvironment => &[],
+        BuildStage::GeneratePrevis if build_mode == BuildMode::PrevisOnly => {
+            &[Requirement::Plugin, Requirement::ExistingPrecombines]
+        }
+        BuildStage::GeneratePrecombines | BuildStage::GeneratePrevis => &[Requirement::Plugin],
+        BuildStage::MergePrecombines => &[Requirement::PrecombinedMeshes, Requirement::CombinedObjects],
+        BuildStage::ArchivePrecombines => &[Requirement::PrecombinedMeshes],
+        BuildStage::CompressPsg => &[Requirement::GeometryPsg],
+        BuildStage::BuildCdx => &[Requirement::Plugin],
+        BuildStage::MergePrevis => &[Requirement::VisFiles, Requirement::PrevisPlugin],
+        BuildStage::ArchiveVis => &[Requirement::VisFiles],
+    }
+}
+
+/// Whether a stage exists in a build mode (PSG and CDX stages are Clean only,
+/// previs-only builds skip every precombine stage)
+pub fn available_in(stage: BuildStage, build_mode: BuildMode) -> bool {
+    match stage {
+        BuildStage::CompressPsg | BuildStage::BuildCdx => build_mode == BuildMode::Clean,
+        BuildStage::GeneratePrecombines | BuildStage::MergePrecombines | BuildStage::ArchivePrecombines => {
+            build_mode != BuildMode::PrevisOnly
+        }
+        _ => true,
+    }
+}
+
+/// Error for a stage that doesn't exist in a build mode
+pub fn unavailable_message(stage: BuildStage, build_mode: BuildMode) -> String {
+    match build_mode {
+        BuildMode::PrevisOnly => format!(
+            "ERROR - {} isn't run in previs-only mode, which reuses the existing precombines",
+            stage.description()
+        ),
+        _ => format!("ERROR - {} is only available in Clean mode, not in {} mode", stage.description(), build_mode),
+    }
+}
+
+/// Whether a stage runs an xEdit script rather than the Creation Kit or an archiver
+pub fn is_xedit_stage(stage: BuildStage) -> bool {
+    matches!(stage, BuildStage::MergePrecombines | BuildStage::MergePrevis)
+}
+
+/// Returns true if `stage` builds on `ancestor`, directly or through other stages
+pub fn depends_on(stage: BuildStage, ancestor: BuildStage) -> bool {
+    dependencies(stage)
+        .iter()
+        .any(|dependency| *dependency == ancestor || depends_on(*dependency, ancestor))
+}
+
+/// Checks that a stage can run on its own.
+///
+/// # Returns
+/// * `Ok(())` if the stage is available in the build mode and its requirements are in Data
+/// * `Err(String)` naming the first missing requirement and the stage producing it
+pub fn check_requirements(stage: BuildStage, context: &StageContext) -> Result<(), String> {
+    if !available_in(stage, context.build_mode) {
+        return Err(unavailable_message(stage, context.build_mode));
+    }
+
+    match requirements(stage, context.build_mode).iter().find(|requirement| !requirement.is_met(context)) {
+        Some(requirement) => Err(requirement.missing_message(context)),
+        None => Ok(()),
+    }
+}
+
+/// Finds the stages that must run before `stage` to produce what it is missing.
+///
+/// Producers of missing requirements are added (with whatever they are
+/// missing in turn), followed by every stage between them and `stage` that
+/// builds on their results, so e.g. regenerating previs for Archive Vis also
+/// merges it.
+///
+/// # Returns
+/// * `Ok(Vec<BuildStage>)` with the stages to run first, in order (empty if nothing is missing)
+/// * `Err(String)` if a missing requirement can't be produced by any stage
+pub fn resolve_prerequisites(stage: BuildStage, context: &StageContext) -> Result<Vec<BuildStage>, String> {
+    if !available_in(stage, context.build_mode) {
+        return Err(unavailable_message(stage, context.build_mode));
+    }
+
+    let mut needed = BTreeSet::new();
+    collect_producers(stage, context, &mut needed)?;
+
+    let follow_ups: Vec<BuildStage> = ALL_STAGES
+        .iter()
+        .copied()
+        .filter(|candidate| *candidate < stage && available_in(*candidate, context.build_mode))
+        .filter(|candidate| needed.iter().any(|producer| depends_on(*candidate, *producer)))
+        .collect();
+    needed.extend(follow_ups);
+
+    Ok(needed.into_iter().collect())
+}
+
+fn collect_producers(
+    stage: BuildStage,
+    context: &StageContext,
+    needed: &mut BTreeSet<BuildStage>,
+) -> Result<(), String> {
+    for requirement in requirements(stage, context.build_mode).iter().filter(|requirement| !requirement.is_met(context)) {
+        let producer = requirement.producer().ok_or_else(|| requirement.missing_message(context))?;
+        if needed.insert(producer) {
+            collect_producers(producer, context, needed)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use owo_colors::{OwoColorize, Stream};
+
+/// Set by --plain
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Turns colored output off (--no-color). Without it, colors are used when
+/// the terminal supports them and `NO_COLOR` isn't set.
+pub fn disable_colors() {
+    owo_colors::set_override(false);
+}
+
+/// Turns on plain output (--plain): no colors, redrawn progress lines or
+/// decorations, for screen readers and captured output
+pub fn enable_plain() {
+    PLAIN.store(true, Ordering::Relaxed);
+    disable_colors();
+}
+
+/// Returns true with --plain
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Line separating the header printed when a command starts from what
+/// follows, or nothing with --plain
+pub fn rule() -> Option<&'static str> {
+    (!is_plain()).then_some("=================================================================")
+}
+
+/// Banner printed when a stage starts
+pub fn banner(text: &str) -> String {
+    if is_plain() {
+        return text.to_string();
+    }
+    format!("=== {} ===", text)
+        .if_supports_color(Stream::Stdout, |text| text.bold().cyan().to_string())
+        .to_string()
+}
+
+/// Mark showing whether a check passed, put before the reason when it didn't
+pub fn check_mark(passed: bool) -> &'static str {
+    match (passed, is_plain()) {
+        (true, false) => "\u{2713}",
+        (false, false) => "\u{2717}",
+        (true, true) => "ok",
+        (false, true) => "not ready:",
+    }
+}
+
+/// Status line for something that went well
+pub fn success(text: &str) -> String {
+    text.if_supports_color(Stream::Stdout, |text| text.green().to_string())
+        .to_string()
+}
+
+/// Status line for something the user should look at
+pub fn warning(text: &str) -> String {
+    text.if_supports_color(Stream::Stdout, |text| text.yellow().to_string())
+        .to_string()
+}
+
+/// Error printed to stderr
+pub fn error(text: &str) -> String {
+    text.if_supports_color(Stream::Stderr, |text| text.red().to_string())
+        .to_string()
+}
@@ -0,0 +1,91 @@
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::cli::BuildStage;
+
+/// Name shown at the start of the console title
+const TITLE_PREFIX: &str = "GeneratePrevisbines";
+
+/// How often the elapsed time in the title is refreshed
+const TITLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The stage shown in the title and when the build started, while a build runs
+static STATE: Mutex<Option<TitleState>> = Mutex::new(None);
+
+/// Starts the thread refreshing the title
+static TICKER: Once = Once::new();
+
+struct TitleState {
+    stage: String,
+    started: Instant,
+}
+
+/// Shows the build in the console title, with the elapsed time kept up to date
+pub fn start_build() {
+    *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some(TitleState {
+        stage: "Starting".to_string(),
+        started: Instant::now(),
+    });
+    if !cfg!(windows) {
+        return;
+    }
+    TICKER.call_once(|| {
+        thread::spawn(|| loop {
+            if let Some(title) = current_title() {
+                set_title(&title);
+            }
+            thread::sleep(TITLE_INTERVAL);
+        });
+    });
+}
+
+/// Shows the stage that just started in the console title
+///
+/// # Arguments
+/// * `stage` - The stage
+/// * `index` - Its position among the stages of the build, from 0
+/// * `total` - Number of stages in the build
+pub fn set_stage(stage: BuildStage, index: usize, total: usize) {
+    if let Some(state) = STATE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        state.stage = format!("[{}/{}] {}", index + 1, total, stage.description());
+    }
+    if let Some(title) = current_title() {
+        set_title(&title);
+    }
+}
+
+/// Stops refreshing the title and shows how the build ended
+pub fn finish_build(outcome: &str) {
+    *STATE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    set_title(&format!("{} \u{2014} {}", TITLE_PREFIX, outcome));
+}
+
+/// Title for the build in progress, if there is one
+fn current_title() -> Option<String> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let state = state.as_ref()?;
+    let elapsed = state.started.elapsed().as_secs();
+    Some(format!(
+        "{} \u{2014} {} \u{2014} {:02}:{:02}:{:02}",
+        TITLE_PREFIX,
+        state.stage,
+        elapsed / 3600,
+        (elapsed % 3600) / 60,
+        elapsed % 60
+    ))
+}
+
+/// Sets the console window title. Only done on Windows, where it shows on the taskbar.
+#[cfg(windows)]
+fn set_title(title: &str) {
+    use windows_sys::Win32::System::Console::SetConsoleTitleW;
+
+    let wide: Vec<u16> = title.encode_utf16().chain(std::iter::once(0)).collect();
+    // SAFETY: `wide` is a NUL-terminated UTF-16 string that outlives the call
+    unsafe {
+        SetConsoleTitleW(wide.as_ptr());
+    }
+}
+
+#[cfg(not(windows))]
+fn set_title(_title: &str) {}
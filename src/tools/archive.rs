@@ -1,206 +1,655 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 use std::thread::sleep;
-use std::time::Duration;
-use log::{info, error};
-use crate::cli::BuildMode;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn, error};
+use crate::cli::{Args, ArchiveCompression, BuildMode};
+use crate::tools::ba2::read_ba2_entries;
+use crate::tools::command_line::prepare_command;
+use crate::tools::progress::{output_with_progress, ArchiveProgress};
+use crate::utils::{remove_dir_all_with_retry, walk_dir, work_dir};
 
-/// Executes Archive2.exe to create a BA2 archive with the given folders.
+/// Folder under the Fallout 4 directory used to stage archive contents while repacking
+pub const ARCHIVE_STAGING_DIR: &str = "ArchiveStaging";
+
+/// Folder under the Fallout 4 directory where loose files are kept after archiving (--keep-loose)
+pub const LOOSE_BACKUP_DIR: &str = "LooseBackup";
+
+/// Archive format to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// General archive (meshes, vis, materials...); texture archives are never written
+    General,
+}
+
+impl ArchiveFormat {
+    /// Returns the value passed to Archive2's `-format` option
+    fn archive2_name(&self) -> &'static str {
+        match self {
+            ArchiveFormat::General => "General",
+        }
+    }
+}
+
+/// Compression used when packing an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackCompression {
+    /// The archiver's default (zlib) compression
+    Default,
+    /// Uncompressed
+    None,
+    /// Xbox compression (Archive2 only)
+    Xbox,
+}
+
+impl PackCompression {
+    /// Returns the compression for an archive built in `build_mode`: Xbox mode
+    /// always uses Xbox compression, other modes use the requested compression
+    pub fn for_mode(build_mode: &BuildMode, compression: ArchiveCompression) -> Self {
+        match (build_mode, compression) {
+            (BuildMode::Xbox, _) => PackCompression::Xbox,
+            (_, ArchiveCompression::None) => PackCompression::None,
+            (_, ArchiveCompression::Default) => PackCompression::Default,
+        }
+    }
+}
+
+/// Describes an archive to create, independent of the archiver that packs it
+#[derive(Debug, Clone)]
+pub struct PackRequest {
+    /// Directory the packed folders are relative to, which also determines the
+    /// paths stored in the archive (e.g. `vis\...`)
+    pub root: PathBuf,
+    /// Full path of the archive to create
+    pub archive_path: PathBuf,
+    /// Top-level folders under `root` to pack; every non-empty folder when empty
+    pub folders: Vec<String>,
+    /// Archive format to write
+    pub format: ArchiveFormat,
+    /// Compression to use
+    pub compression: PackCompression,
+    /// Pack an explicit, canonically ordered file list with single-threaded
+    /// archivers so identical inputs produce byte-identical archives
+    pub reproducible: bool,
+}
+
+impl PackRequest {
+    /// Creates a request to pack everything under `root` into a general
+    /// archive with default compression
+    pub fn new(root: &Path, archive_path: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            archive_path: archive_path.to_path_buf(),
+            folders: Vec::new(),
+            format: ArchiveFormat::General,
+            compression: PackCompression::Default,
+            reproducible: false,
+        }
+    }
+
+    /// Restricts the request to the given top-level folders
+    pub fn with_folders<I, S>(mut self, folders: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.folders = folders.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets the compression to use
+    pub fn with_compression(mut self, compression: PackCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets whether the archive must be reproducible
+    pub fn with_reproducible(mut self, reproducible: bool) -> Self {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// Returns the folders to pack: the requested ones, or every non-empty
+    /// top-level folder under the root
+    pub fn resolved_folders(&self) -> Result<Vec<String>, String> {
+        if !self.folders.is_empty() {
+            return Ok(self.folders.clone());
+        }
+
+        let mut folders: Vec<String> = fs::read_dir(&self.root)
+            .map_err(|e| format!("Error reading {}: {}", self.root.display(), e))?
+            .flatten()
+            .filter(|entry| entry.path().is_dir() && !list_entry_names(&entry.path()).is_empty())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        sort_entry_names(&mut folders);
+        Ok(folders)
+    }
+
+    /// Returns the entry names (relative to the root) of every file to pack, in canonical order
+    pub fn entry_names(&self) -> Result<Vec<String>, String> {
+        let mut names = Vec::new();
+        for folder in self.resolved_folders()? {
+            let prefix = folder.replace('/', "\\");
+            names.extend(
+                list_entry_names(&self.root.join(&folder))
+                    .into_iter()
+                    .map(|name| format!("{}\\{}", prefix, name)),
+            );
+        }
+        sort_entry_names(&mut names);
+        Ok(names)
+    }
+}
+
+/// Executes Archive2.exe to create a BA2 archive.
 ///
 /// # Arguments
 /// * `archive2_path` - Path to Archive2.exe
-/// * `data_dir` - Fallout 4 Data directory
-/// * `plugin_archive` - Archive file name
-/// * `folders` - A comma-separated list of folders to include in the archive
-/// * `qualifiers` - Additional qualifiers to pass to archive2 (e.g., compression type)
+/// * `request` - The archive to create and what to pack into it
 ///
 /// # Returns
 /// * `Ok(())` if the archive is created successfully
 /// * `Err(String)` if the command fails
-pub fn run_archive(
-    archive2_path: &PathBuf,
-    data_dir: &PathBuf,
-    plugin_archive: &str,
-    folders: &str,
-    qualifiers: &str,
-) -> Result<(), String> {
-    let archive_path = data_dir.join(plugin_archive);
+pub fn run_archive(archive2_path: &PathBuf, request: &PackRequest) -> Result<(), String> {
+    let archive_path = request.archive_path.as_path();
+    ensure_not_texture_archive(archive_path)?;
+
+    let folders = request.resolved_folders()?;
+    let folder_list = folders.join(",");
 
-    info!("Creating archive: {} with folders: {}", plugin_archive, folders);
+    info!("Creating archive: {} with folders: {}", archive_path.display(), folder_list);
+
+    // Archive2 orders files as it finds them when given folders; a file list
+    // fixes the order so the same inputs always produce the same archive
+    let file_list = if request.reproducible {
+        Some(write_file_list(request)?)
+    } else {
+        None
+    };
 
     let mut command = Command::new(archive2_path);
-    command.current_dir(data_dir)
-        .arg(folders)
-        .arg(format!("-c={}", plugin_archive))
-        .arg(qualifiers)
-        .arg("-f=General")
+    command.current_dir(&request.root);
+    match &file_list {
+        Some(list_path) => {
+            command.arg(list_path).arg(format!("-r={}", request.root.display()));
+        }
+        None => {
+            command.arg(&folder_list);
+        }
+    }
+    command.arg(format!("-c={}", archive_path.display()));
+    match request.compression {
+        PackCompression::Default => {}
+        PackCompression::None => {
+            command.arg("-compression=None");
+        }
+        PackCompression::Xbox => {
+            command.arg("-compression=XBox");
+        }
+    }
+    command
+        .arg(format!("-f={}", request.format.archive2_name()))
         .arg("-q");
 
+    prepare_command(&mut command);
+
+    let folder_refs: Vec<&str> = folders.iter().map(String::as_str).collect();
+    let progress = ArchiveProgress::packing(archive_path, &request.root, &folder_refs);
+
     // Execute and check result
-    match command.output() {
+    let result = output_with_progress(&mut command, progress);
+    if let Some(list_path) = &file_list {
+        let _ = fs::remove_file(list_path);
+    }
+
+    match result {
         Ok(output) => {
+            log_archive2_output(&output);
             if output.status.success() {
                 if !archive_path.exists() {
-                    return Err(format!("Archive was not created: {}", plugin_archive));
+                    return Err(format!("Archive was not created: {}", archive_path.display()));
                 }
                 Ok(())
             } else {
-                let error = String::from_utf8_lossy(&output.stderr);
-                Err(format!("Archive2 failed: {}", error))
+                Err(archive2_error("Archive2 failed", &output))
             }
         }
         Err(e) => Err(format!("Failed to execute Archive2: {}", e))
     }
 }
 
-/// Extracts the plugin's archive to the Data directory.
+/// Known Archive2 failure messages (matched case-insensitively) and the
+/// explanation shown to the user for each
+const ARCHIVE2_KNOWN_ERRORS: &[(&str, &str)] = &[
+    (
+        "access is denied",
+        "Archive2 could not access a file. Close the game, Creation Kit, and any archive viewers, \
+         and check that the Data folder isn't read-only or blocked by antivirus.",
+    ),
+    (
+        "being used by another process",
+        "The archive is locked by another process. Close the game, Creation Kit, or MO2 tools using it and retry.",
+    ),
+    (
+        "no files",
+        "Archive2 found no files to pack. Check that the generated folders exist and aren't empty.",
+    ),
+    (
+        "cannot find the path",
+        "A folder passed to Archive2 does not exist. The previous stage may not have produced any output.",
+    ),
+    (
+        "not a valid archive",
+        "The existing archive is corrupt or not a BA2 file. Remove or restore it and retry.",
+    ),
+    (
+        "not enough space",
+        "The disk is full. Free up space on the drive containing the Data folder and retry.",
+    ),
+];
+
+/// Logs everything Archive2 wrote to stdout and stderr
+fn log_archive2_output(output: &Output) {
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        debug!("Archive2: {}", line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        debug!("Archive2 (stderr): {}", line);
+    }
+}
+
+/// Builds an error message from Archive2's output.
+///
+/// Archive2 writes its diagnostics to stdout, so both streams are inspected.
+/// Recognised failures are turned into an actionable explanation; otherwise
+/// the raw output and exit code are reported.
+fn archive2_error(context: &str, output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let combined = format!("{}\n{}", stdout.trim(), stderr.trim());
+    let combined = combined.trim();
+    let lowercase = combined.to_lowercase();
+
+    let exit_code = output.status.code().unwrap_or(-1);
+
+    for (pattern, explanation) in ARCHIVE2_KNOWN_ERRORS {
+        if lowercase.contains(pattern) {
+            return format!("{} (exit code {}): {}\n{}", context, exit_code, explanation, combined);
+        }
+    }
+
+    if combined.is_empty() {
+        format!("{} with exit code {} and no output", context, exit_code)
+    } else {
+        format!("{} with exit code {}: {}", context, exit_code, combined)
+    }
+}
+
+/// Extracts the plugin's archive into the given directory.
 ///
 /// # Arguments
 /// * `archive2_path` - Path to Archive2.exe
 /// * `data_dir` - Fallout 4 Data directory
 /// * `plugin_archive` - Archive file name
+/// * `output_dir` - Directory the archive contents are extracted into
 ///
 /// # Returns
 /// * `Ok(())` if the extraction is successful
 /// * `Err(String)` if the command fails
 pub fn extract_archive(
     archive2_path: &PathBuf,
-    data_dir: &PathBuf,
+    data_dir: &Path,
     plugin_archive: &str,
+    output_dir: &Path,
 ) -> Result<(), String> {
     let archive_path = data_dir.join(plugin_archive);
+    ensure_not_texture_archive(&archive_path)?;
 
     if !archive_path.exists() {
         return Err(format!("Archive does not exist: {}", plugin_archive));
     }
 
-    info!("Extracting archive: {}", plugin_archive);
+    info!("Extracting archive: {} to {}", plugin_archive, output_dir.display());
 
     let mut command = Command::new(archive2_path);
     command.current_dir(data_dir)
         .arg(plugin_archive)
-        .arg("-e=.")
+        .arg(format!("-e={}", output_dir.display()))
         .arg("-q");
+    prepare_command(&mut command);
 
     // Execute and check result
-    match command.output() {
+    let result = match read_ba2_entries(&archive_path) {
+        Ok(entries) => output_with_progress(&mut command, ArchiveProgress::extracting(output_dir, entries.len())),
+        Err(_) => command.output(),
+    };
+    match result {
         Ok(output) => {
+            log_archive2_output(&output);
             if output.status.success() {
                 Ok(())
             } else {
-                let error = String::from_utf8_lossy(&output.stderr);
-                Err(format!("Archive2 extraction failed: {}", error))
+                Err(archive2_error("Archive2 extraction failed", &output))
             }
         }
         Err(e) => Err(format!("Failed to execute Archive2 extraction: {}", e))
     }
 }
 
-/// Adds files from the specified folder to the plugin's archive.
-/// Since Archive2 doesn't support adding to existing archives, this extracts
-/// the archive first, then creates a new one with the combined content.
+/// How long to wait for extracted files to appear on disk (MO2's virtual
+/// file system can lag behind the extracting process)
+const EXTRACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Converts a BA2 entry name (backslash separated) into a path under `root`
+pub fn entry_path(root: &Path, entry: &str) -> PathBuf {
+    entry
+        .split(['\\', '/'])
+        .filter(|part| !part.is_empty())
+        .fold(root.to_path_buf(), |path, part| path.join(part))
+}
+
+/// Verifies that every entry of an archive has been extracted under `output_dir`.
+///
+/// Files are polled for until they all exist or the extraction timeout expires,
+/// so the original archive is never deleted while part of its content is
+/// still missing from disk.
 ///
 /// # Arguments
-/// * `archive2_path` - Path to Archive2.exe
-/// * `data_dir` - Fallout 4 Data directory
-/// * `plugin_archive` - Archive file name
-/// * `folder` - The folder to add to the archive (e.g., "vis")
-/// * `qualifiers` - Archive qualifiers
-/// * `has_files_fn` - Function to check if directory has files
+/// * `entries` - Entry names read from the archive before extraction
+/// * `output_dir` - Directory the archive was extracted into
 ///
 /// # Returns
-/// * `Ok(())` if successful
-/// * `Err(String)` if any command fails
-pub fn add_to_archive<F>(
-    archive2_path: &PathBuf,
-    data_dir: &PathBuf,
-    plugin_archive: &str,
-    folder: &str,
-    qualifiers: &str,
-    has_files_fn: F,
-) -> Result<(), String>
-where
-    F: Fn(&PathBuf, &str) -> bool,
-{
-    let archive_path = data_dir.join(plugin_archive);
-    let precombined_dir = data_dir.join("meshes").join("precombined");
+/// * `Ok(())` if every entry exists on disk
+/// * `Err(String)` listing the missing files otherwise
+pub fn verify_extraction(entries: &[String], output_dir: &Path) -> Result<(), String> {
+    let start = Instant::now();
 
-    if !archive_path.exists() {
-        return run_archive(archive2_path, data_dir, plugin_archive, folder, qualifiers);
+    loop {
+        let missing: Vec<&String> = entries
+            .iter()
+            .filter(|entry| !entry_path(output_dir, entry).exists())
+            .collect();
+
+        if missing.is_empty() {
+            info!("Verified {} extracted files", entries.len());
+            return Ok(());
+        }
+
+        if start.elapsed() >= EXTRACTION_TIMEOUT {
+            let examples: Vec<&str> = missing.iter().take(5).map(|entry| entry.as_str()).collect();
+            return Err(format!(
+                "ERROR - Extraction incomplete, {} of {} files missing (e.g. {})",
+                missing.len(),
+                entries.len(),
+                examples.join(", ")
+            ));
+        }
+
+        sleep(Duration::from_secs(1));
     }
+}
+
+/// Sorts archive entry names into the canonical order used for packing:
+/// case-insensitive with backslash separators, ties broken by the original name
+pub fn sort_entry_names(names: &mut [String]) {
+    names.sort_by_cached_key(|name| (name.to_lowercase().replace('/', "\\"), name.clone()));
+}
+
+/// Writes the request's files, in canonical order, to an Archive2 file list
+/// (`.achlist`, a JSON array of absolute paths) in the temp folder
+fn write_file_list(request: &PackRequest) -> Result<PathBuf, String> {
+    let files: Vec<String> = request
+        .entry_names()?
+        .iter()
+        .map(|name| entry_path(&request.root, name).to_string_lossy().into_owned())
+        .collect();
 
-    // Extract existing archive
-    extract_archive(archive2_path, data_dir, plugin_archive)?;
+    let stem = request
+        .archive_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "archive".to_string());
+    let list_path = work_dir().join(format!("{}.achlist", stem));
 
-    // Small delay to ensure files are extracted
-    sleep(Duration::from_secs(5));
+    let json = serde_json::to_string_pretty(&files)
+        .map_err(|e| format!("Error building file list: {}", e))?;
+    fs::write(&list_path, json)
+        .map_err(|e| format!("Error writing file list {}: {}", list_path.display(), e))?;
 
-    // Remove the existing archive
-    if let Err(e) = fs::remove_file(&archive_path) {
-        return Err(format!("Failed to remove existing archive: {}", e));
+    Ok(list_path)
+}
+
+/// Lists every file under `root` as an archive entry name
+/// (relative to `root`, backslash separated)
+pub fn list_entry_names(root: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    walk_dir(root, &mut |path, is_dir| {
+        if !is_dir {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+            names.push(parts.join("\\"));
+        }
+    });
+    names
+}
+
+/// Audits a newly packed archive against the loose files it was built from.
+///
+/// Every file under `source_dir` must be present in the archive (compared
+/// case-insensitively, as archivers may change the case of stored names).
+/// This must run before the loose files are deleted.
+///
+/// # Arguments
+/// * `archive_path` - The archive that was just created
+/// * `source_dir` - Directory containing the loose files that were packed
+///
+/// # Returns
+/// * `Ok(())` if every loose file is in the archive
+/// * `Err(String)` listing the missing entries otherwise
+pub fn audit_archive(archive_path: &Path, source_dir: &Path) -> Result<(), String> {
+    let packed: HashSet<String> = read_ba2_entries(archive_path)?
+        .iter()
+        .map(|entry| entry.replace('/', "\\").to_lowercase())
+        .collect();
+
+    let expected = list_entry_names(source_dir);
+    let missing: Vec<&String> = expected
+        .iter()
+        .filter(|name| !packed.contains(&name.to_lowercase()))
+        .collect();
+
+    if !missing.is_empty() {
+        let examples: Vec<&str> = missing.iter().take(5).map(|name| name.as_str()).collect();
+        return Err(format!(
+            "ERROR - Archive {} is missing {} of {} files (e.g. {}). Loose files have been kept in {}",
+            archive_path.display(),
+            missing.len(),
+            expected.len(),
+            examples.join(", "),
+            source_dir.display()
+        ));
     }
 
-    // Check if we have precombined meshes extracted
-    let has_precombined = has_files_fn(&precombined_dir, ".nif");
+    info!(
+        "Audited {}: {} files packed, {} expected",
+        archive_path.display(),
+        packed.len(),
+        expected.len()
+    );
+    Ok(())
+}
 
-    if has_precombined {
-        // Archive both directories
-        run_archive(
-            archive2_path,
-            data_dir,
-            plugin_archive,
-            &format!("meshes\\precombined,{}", folder),
-            qualifiers,
-        )?;
+/// Extracts an archive into the given directory using BSArch.
+///
+/// # Arguments
+/// * `bsarch_path` - Path to BSArch executable
+/// * `archive_path` - Full path of the archive to extract
+/// * `output_dir` - Directory the archive contents are extracted into
+///
+/// # Returns
+/// * `Ok(())` if the extraction is successful
+/// * `Err(String)` if the command fails
+pub fn extract_bsarch(
+    bsarch_path: &PathBuf,
+    archive_path: &Path,
+    output_dir: &Path,
+) -> Result<(), String> {
+    ensure_not_texture_archive(archive_path)?;
 
-        // Clean up precombined directory
-        fs::remove_dir_all(precombined_dir)
-            .map_err(|e| format!("Error removing precombined directory: {}", e))?;
-    } else {
-        // Archive the new folder
-        run_archive(archive2_path, data_dir, plugin_archive, folder, qualifiers)?;
+    if !archive_path.exists() {
+        return Err(format!("Archive does not exist: {}", archive_path.display()));
     }
 
+    let progress = read_ba2_entries(archive_path)
+        .ok()
+        .map(|entries| ArchiveProgress::extracting(output_dir, entries.len()));
+
+    let archive_path_str = archive_path.to_string_lossy();
+    let output_dir_str = output_dir.to_string_lossy();
+    run_bsarch(
+        bsarch_path,
+        "extracting archive",
+        &["unpack", &archive_path_str, &output_dir_str],
+        progress,
+    )
+}
+
+/// Returns true if the archive name refers to a texture archive (`<plugin> - Textures.ba2`).
+/// Texture archives are never extracted, deleted, or repacked by this tool.
+pub fn is_texture_archive(archive_name: &str) -> bool {
+    archive_name.to_lowercase().ends_with(" - textures.ba2")
+}
+
+/// Returns an error if the archive path refers to a texture archive
+fn ensure_not_texture_archive(archive_path: &Path) -> Result<(), String> {
+    let archive_name = archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    if is_texture_archive(&archive_name) {
+        return Err(format!(
+            "ERROR - Refusing to modify texture archive {}",
+            archive_name
+        ));
+    }
     Ok(())
 }
 
+/// Returns true if an archive entry is generated previs data
+/// (a precombined mesh or a visibility file).
+pub fn is_previs_entry(entry: &str) -> bool {
+    let entry = entry.replace('/', "\\").to_lowercase();
+    entry.starts_with("meshes\\precombined\\") || entry.starts_with("vis\\")
+}
+
+/// Packing options passed through to BSArch
+#[derive(Debug, Clone, Copy)]
+pub struct BsarchOptions {
+    /// Pack using multiple threads (`-mt`)
+    pub multithreaded: bool,
+    /// Compress archive contents (`-z`)
+    pub compress: bool,
+    /// Store identical files only once (`-share`)
+    pub share_data: bool,
+}
+
+impl Default for BsarchOptions {
+    /// Matches the flags used by the original batch script (`-mt -z`)
+    fn default() -> Self {
+        Self {
+            multithreaded: true,
+            compress: true,
+            share_data: false,
+        }
+    }
+}
+
+impl BsarchOptions {
+    /// Builds the BSArch packing options from the command line flags
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            multithreaded: !args.bsarch_no_mt,
+            compress: !args.bsarch_no_compress,
+            share_data: args.bsarch_share,
+        }
+    }
+
+    /// Returns the BSArch command-line flags for these options
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.multithreaded {
+            flags.push("-mt");
+        }
+        if self.compress {
+            flags.push("-z");
+        }
+        if self.share_data {
+            flags.push("-share");
+        }
+        flags
+    }
+}
+
 /// Runs BSArch to perform archiving operations
 ///
 /// # Arguments
 /// * `bsarch_path` - Path to BSArch executable
 /// * `action` - Description of the action being performed
 /// * `bsarch_args` - Arguments to pass to BSArch
+/// * `progress` - What to poll for a progress indicator
+///
+/// BSArch can exit with code 0 after logging errors or skipping files, so its
+/// output is captured and scanned as well as the exit status.
 ///
 /// # Returns
 /// * `Ok(())` if successful
-/// * `Err(String)` if the command fails
+/// * `Err(String)` if the command fails or BSArch reports an error
 pub fn run_bsarch(
     bsarch_path: &PathBuf,
     action: &str,
     bsarch_args: &[&str],
+    progress: Option<ArchiveProgress>,
 ) -> Result<(), String> {
     info!("Running BSArch to perform action: '{}' with args: {:?}", action, bsarch_args);
     info!("Executing: {} {}", bsarch_path.display(), bsarch_args.join(" "));
 
     let mut command = Command::new(bsarch_path);
     command.args(bsarch_args);
+    prepare_command(&mut command);
 
-    // Execute the command and capture status
-    match command.status() {
-        Ok(status) => {
-            if status.success() {
-                info!("BSArch action '{}' completed successfully.", action);
-                Ok(())
-            } else {
+    // Execute the command and capture its output
+    let result = match progress {
+        Some(progress) => output_with_progress(&mut command, progress),
+        None => command.output(),
+    };
+    match result {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            for line in stdout.lines() {
+                debug!("BSArch: {}", line);
+            }
+            for line in stderr.lines() {
+                debug!("BSArch (stderr): {}", line);
+            }
+
+            if !output.status.success() {
                 let error_msg = format!(
                     "BSArch action '{}' failed with exit code: {:?}.",
                     action,
-                    status.code().unwrap_or(-1)
+                    output.status.code().unwrap_or(-1)
                 );
                 error!("{}", error_msg);
-                Err(error_msg)
+                return Err(error_msg);
             }
+
+            check_bsarch_output(action, stdout.lines().chain(stderr.lines()))?;
+            info!("BSArch action '{}' completed successfully.", action);
+            Ok(())
         }
         Err(e) => {
             let error_msg = format!(
@@ -215,18 +664,194 @@ pub fn run_bsarch(
     }
 }
 
-/// Retrieves the appropriate archive qualifiers based on the build mode.
+/// Packs an archive with BSArch.
+///
+/// BSArch always packs the whole root folder, so requests limited to
+/// specific folders are rejected.
 ///
 /// # Arguments
-/// * `build_mode` - The current build mode
+/// * `bsarch_path` - Path to BSArch executable
+/// * `request` - The archive to create and what to pack into it
+/// * `options` - BSArch packing options
+///
+/// # Returns
+/// * `Ok(())` if the archive is created successfully
+/// * `Err(String)` if the request can't be packed by BSArch or the command fails
+pub fn run_bsarch_pack(bsarch_path: &PathBuf, request: &PackRequest, options: &BsarchOptions) -> Result<(), String> {
+    ensure_not_texture_archive(&request.archive_path)?;
+
+    if !request.folders.is_empty() {
+        return Err("BSArch can only pack a whole folder, not a selection of folders".to_string());
+    }
+
+    let mut options = match request.compression {
+        PackCompression::Default => *options,
+        PackCompression::None => BsarchOptions { compress: false, ..*options },
+        PackCompression::Xbox => return Err("ERROR - Xbox compression requires Archive2".to_string()),
+    };
+
+    // Multithreaded packing writes files in whatever order the threads finish
+    if request.reproducible {
+        options.multithreaded = false;
+    }
+
+    let root = request.root.to_string_lossy();
+    let archive_path = request.archive_path.to_string_lossy();
+    let mut bsarch_args = vec!["pack", &root, &archive_path, "-fo4"];
+    bsarch_args.extend(options.flags());
+
+    let progress = ArchiveProgress::packing(&request.archive_path, &request.root, &["."]);
+    run_bsarch(bsarch_path, "packing archive", &bsarch_args, Some(progress))
+}
+
+/// Starts of BSArch output lines (matched case-insensitively) that mean the
+/// action failed even when the exit code is 0. Only line starts are matched,
+/// as BSArch also echoes the packed file names, which may contain any word.
+const BSARCH_ERROR_PREFIXES: &[&str] = &["error", "exception", "access violation", "fatal"];
+
+/// Starts of BSArch output lines (matched case-insensitively) that mean some
+/// files were left out
+const BSARCH_WARNING_PREFIXES: &[&str] = &["skipping", "skipped", "skip", "ignoring", "ignored", "warning"];
+
+/// Whether a BSArch output line starts with one of the words, followed by
+/// the end of the line or something other than a letter (`Error: ...` or
+/// `Skipping ...`, but not a file named `errorcheck.nif` or `Errors: 0`)
+fn bsarch_line_starts_with(lowercase_line: &str, words: &[&str]) -> bool {
+    words.iter().any(|word| {
+        lowercase_line
+            .strip_prefix(word)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+    })
+}
+
+/// Whether a line is a Delphi exception BSArch reports, e.g. `EFCreateError: Cannot create file`
+fn is_bsarch_exception(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(class, _)| {
+        class.starts_with('E')
+            && class.len() > 1
+            && class.chars().all(|c| c.is_ascii_alphanumeric())
+            && (class.ends_with("Error") || class.ends_with("Exception"))
+    })
+}
+
+/// Scans BSArch output for errors and skipped files.
 ///
 /// # Returns
-/// A `&'static str` representing the additional qualifiers for archiving:
-/// - If the build mode is `BuildMode::Xbox`, returns `"-compression=XBox"`.
-/// - For all other build modes, returns an empty string (`""`).
-pub fn get_archive_qualifiers(build_mode: &BuildMode) -> &'static str {
-    match build_mode {
-        BuildMode::Xbox => "-compression=XBox",
-        _ => "",
-    }
-}
\ No newline at end of file
+/// * `Ok(())` if no error lines were found (skipped files are logged as warnings)
+/// * `Err(String)` listing the error lines otherwise
+pub fn check_bsarch_output<'a>(action: &str, lines: impl Iterator<Item = &'a str>) -> Result<(), String> {
+    let mut errors = Vec::new();
+
+    for line in lines.map(str::trim).filter(|line| !line.is_empty()) {
+        let lowercase = line.to_lowercase();
+        if bsarch_line_starts_with(&lowercase, BSARCH_ERROR_PREFIXES) || is_bsarch_exception(line) {
+            errors.push(line.to_string());
+        } else if bsarch_line_starts_with(&lowercase, BSARCH_WARNING_PREFIXES) {
+            warn!("BSArch: {}", line);
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+
+    let error_msg = format!(
+        "BSArch action '{}' reported errors despite a successful exit code:\n{}",
+        action,
+        errors.join("\n")
+    );
+    error!("{}", error_msg);
+    Err(error_msg)
+}
+
+/// Returns the file name used for the Xbox copy of an archive
+pub fn xbox_archive_name(archive_name: &str) -> String {
+    let stem = archive_name
+        .strip_suffix(".ba2")
+        .unwrap_or(archive_name);
+    format!("{} (Xbox).ba2", stem)
+}
+
+/// Returns the file name of part `index` (1-based) of a plugin's main archive.
+/// The first part is the regular `- Main.ba2`, later parts are `- Main2.ba2`, `- Main3.ba2`...
+pub fn part_archive_name(plugin_name: &str, index: usize) -> String {
+    if index <= 1 {
+        format!("{} - Main.ba2", plugin_name)
+    } else {
+        format!("{} - Main{}.ba2", plugin_name, index)
+    }
+}
+
+/// Returns the names of the plugin's main archive parts that exist in the Data directory
+pub fn existing_part_archives(data_dir: &Path, plugin_name: &str) -> Vec<String> {
+    let mut archives = Vec::new();
+    let main_archive = part_archive_name(plugin_name, 1);
+    if data_dir.join(&main_archive).exists() {
+        archives.push(main_archive);
+    }
+
+    let mut index = 2;
+    loop {
+        let archive_name = part_archive_name(plugin_name, index);
+        if !data_dir.join(&archive_name).exists() {
+            break;
+        }
+        archives.push(archive_name);
+        index += 1;
+    }
+
+    archives
+}
+
+/// Splits the content of a staging folder into parts no larger than `max_bytes`.
+///
+/// Files are assigned in sorted order so related files (e.g. one worldspace's
+/// precombines) stay together. The first part stays in `staging_dir`; files for
+/// later parts are moved into sibling folders (`<staging_dir>2`, `<staging_dir>3`...).
+///
+/// # Returns
+/// The root folder of every part, starting with `staging_dir`
+pub fn split_staged_content(staging_dir: &Path, max_bytes: u64) -> Result<Vec<PathBuf>, String> {
+    let mut names = list_entry_names(staging_dir);
+    sort_entry_names(&mut names);
+
+    let mut part_dirs = vec![staging_dir.to_path_buf()];
+    let mut part_size = 0u64;
+
+    for name in names {
+        let path = entry_path(staging_dir, &name);
+        let size = fs::metadata(&path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?
+            .len();
+
+        if part_size > 0 && part_size.saturating_add(size) > max_bytes {
+            let mut part_name = staging_dir.as_os_str().to_os_string();
+            part_name.push((part_dirs.len() + 1).to_string());
+            let part_dir = PathBuf::from(part_name);
+            if part_dir.exists() {
+                remove_dir_all_with_retry(&part_dir)
+                    .map_err(|e| format!("Error removing directory {}: {}", part_dir.display(), e))?;
+            }
+            part_dirs.push(part_dir);
+            part_size = 0;
+        }
+        part_size += size;
+
+        let part_dir = part_dirs.last().unwrap();
+        if part_dir != staging_dir {
+            let target = entry_path(part_dir, &name);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Error creating directory {}: {}", parent.display(), e))?;
+            }
+            fs::rename(&path, &target)
+                .map_err(|e| format!("Error moving {} to {}: {}", path.display(), target.display(), e))?;
+        }
+    }
+
+    if part_dirs.len() > 1 {
+        info!("Split archive content into {} parts", part_dirs.len());
+    }
+
+    Ok(part_dirs)
+}
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+use crate::cli::{Args, ArchiverKind};
+use crate::tools::archive::{
+    extract_archive, extract_bsarch, run_archive, run_bsarch_pack, BsarchOptions, PackRequest,
+};
+use crate::tools::ba2::{extract_ba2, pack_ba2, read_ba2_entries};
+use crate::tools::archive::PackCompression;
+use crate::tools::simulate::is_simulating;
+
+/// A tool that creates and extracts BA2 archives
+pub trait Archiver {
+    /// Name of the archiver shown in messages
+    fn name(&self) -> &'static str;
+
+    /// Creates the archive described by the request
+    fn pack(&self, request: &PackRequest) -> Result<(), String>;
+
+    /// Extracts every file of an archive into `output_dir`
+    fn extract(&self, archive_path: &Path, output_dir: &Path) -> Result<(), String>;
+
+    /// Lists the entry names stored in an archive
+    fn list(&self, archive_path: &Path) -> Result<Vec<String>, String> {
+        read_ba2_entries(archive_path)
+    }
+
+    /// Whether packed loose files are moved back into Data afterwards instead
+    /// of being deleted (BSArch builds keep them, as the original script did)
+    fn keeps_loose_files(&self) -> bool {
+        false
+    }
+}
+
+/// Archive2.exe from the Creation Kit
+pub struct Archive2Archiver {
+    path: PathBuf,
+}
+
+impl Archiver for Archive2Archiver {
+    fn name(&self) -> &'static str {
+        "Archive2"
+    }
+
+    fn pack(&self, request: &PackRequest) -> Result<(), String> {
+        run_archive(&self.path, request)
+    }
+
+    fn extract(&self, archive_path: &Path, output_dir: &Path) -> Result<(), String> {
+        let data_dir = archive_path.parent().unwrap_or(Path::new("."));
+        let archive_name = archive_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        extract_archive(&self.path, data_dir, &archive_name, output_dir)
+    }
+}
+
+/// BSArch.exe from xEdit
+pub struct BsarchArchiver {
+    path: PathBuf,
+    options: BsarchOptions,
+}
+
+impl Archiver for BsarchArchiver {
+    fn name(&self) -> &'static str {
+        "BSArch"
+    }
+
+    fn pack(&self, request: &PackRequest) -> Result<(), String> {
+        run_bsarch_pack(&self.path, request, &self.options)
+    }
+
+    fn extract(&self, archive_path: &Path, output_dir: &Path) -> Result<(), String> {
+        extract_bsarch(&self.path, archive_path, output_dir)
+    }
+
+    fn keeps_loose_files(&self) -> bool {
+        true
+    }
+}
+
+/// The built-in BA2 reader and writer
+pub struct NativeArchiver {
+    version: u32,
+}
+
+impl Archiver for NativeArchiver {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn pack(&self, request: &PackRequest) -> Result<(), String> {
+        pack_ba2(request, self.version)
+    }
+
+    fn extract(&self, archive_path: &Path, output_dir: &Path) -> Result<(), String> {
+        extract_ba2(archive_path, output_dir)
+    }
+}
+
+/// Stands in for the selected archiver with --simulate, writing the
+/// archives with the built-in writer instead of launching a tool
+pub struct SimulatedArchiver {
+    name: &'static str,
+    keeps_loose_files: bool,
+    version: u32,
+}
+
+impl Archiver for SimulatedArchiver {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn pack(&self, request: &PackRequest) -> Result<(), String> {
+        let mut request = request.clone();
+        if request.compression == PackCompression::Xbox {
+            request.compression = PackCompression::Default;
+        }
+        pack_ba2(&request, self.version)
+    }
+
+    fn extract(&self, archive_path: &Path, output_dir: &Path) -> Result<(), String> {
+        extract_ba2(archive_path, output_dir)
+    }
+
+    fn keeps_loose_files(&self) -> bool {
+        self.keeps_loose_files
+    }
+}
+
+/// Creates the archiver selected on the command line.
+///
+/// # Arguments
+/// * `kind` - The selected archiver
+/// * `archive2_path` - Path to Archive2.exe
+/// * `bsarch_path` - Path to BSArch, if one was found
+/// * `args` - Command line arguments (BSArch packing options)
+/// * `ba2_version` - BA2 version the native writer should write (v1 when not set)
+///
+/// # Returns
+/// * `Ok(Box<dyn Archiver>)` with the selected archiver, or a `SimulatedArchiver` with --simulate
+/// * `Err(String)` if BSArch was selected but couldn't be found
+pub fn create_archiver(
+    kind: ArchiverKind,
+    archive2_path: &Path,
+    bsarch_path: Option<&Path>,
+    args: &Args,
+    ba2_version: Option<u32>,
+) -> Result<Box<dyn Archiver>, String> {
+    if is_simulating() {
+        return Ok(Box::new(SimulatedArchiver {
+            name: match kind {
+                ArchiverKind::Archive2 => "Archive2",
+                ArchiverKind::Bsarch => "BSArch",
+                ArchiverKind::Native => "native",
+            },
+            keeps_loose_files: kind == ArchiverKind::Bsarch,
+            version: ba2_version.unwrap_or(1),
+        }));
+    }
+    match kind {
+        ArchiverKind::Archive2 => Ok(Box::new(Archive2Archiver {
+            path: archive2_path.to_path_buf(),
+        })),
+        ArchiverKind::Bsarch => {
+            let path = bsarch_path.ok_or_else(|| {
+                "ERROR - BSArch enabled but not found next to xEdit or in common locations. \
+                 Please specify it with --bsarch-path"
+                    .to_string()
+            })?;
+            Ok(Box::new(BsarchArchiver {
+                path: path.to_path_buf(),
+                options: BsarchOptions::from_args(args),
+            }))
+        }
+        ArchiverKind::Native => Ok(Box::new(NativeArchiver {
+            version: ba2_version.unwrap_or(1),
+        })),
+    }
+}
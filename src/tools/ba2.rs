@@ -0,0 +1,501 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use log::info;
+use crate::tools::archive::{entry_path, PackCompression, PackRequest};
+
+/// Magic bytes at the start of every BA2 archive
+const BA2_MAGIC: &[u8; 4] = b"BTDX";
+
+/// Fallout 4 BA2 versions that share the same header and record layout
+const FALLOUT4_VERSIONS: [u32; 3] = [1, 7, 8];
+
+/// Size of the BA2 header in bytes
+const HEADER_SIZE: u64 = 24;
+
+/// Size of a GNRL file record in bytes
+const GNRL_RECORD_SIZE: u64 = 36;
+
+/// Flags value Archive2 writes for every GNRL file record
+const GNRL_RECORD_FLAGS: u32 = 0x0010_0100;
+
+/// Sentinel written at the end of every GNRL file record
+const GNRL_RECORD_END: u32 = 0xBAAD_F00D;
+
+/// Header information read from a BA2 archive
+#[derive(Debug, Clone)]
+pub struct Ba2Header {
+    pub version: u32,
+    pub archive_type: String,
+    pub file_count: u32,
+    pub name_table_offset: u64,
+}
+
+/// Reads the header of a BA2 archive.
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+///
+/// # Returns
+/// * `Ok(Ba2Header)` if the file is a valid BA2 archive
+/// * `Err(String)` if the file can't be read or isn't a BA2 archive
+pub fn read_ba2_header(archive_path: &Path) -> Result<Ba2Header, String> {
+    let file = File::open(archive_path)
+        .map_err(|e| format!("Error opening archive {}: {}", archive_path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    read_header(&mut reader, archive_path)
+}
+
+/// Reads the names of all files stored in a BA2 archive.
+///
+/// Names are returned exactly as stored in the archive's name table, which
+/// uses backslash separators (e.g. `meshes\precombined\0000abcd_1234.nif`).
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+///
+/// # Returns
+/// * `Ok(Vec<String>)` containing every file name in the archive
+/// * `Err(String)` if the archive can't be read or is malformed
+pub fn read_ba2_entries(archive_path: &Path) -> Result<Vec<String>, String> {
+    let file = File::open(archive_path)
+        .map_err(|e| format!("Error opening archive {}: {}", archive_path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader, archive_path)?;
+
+    if header.name_table_offset == 0 {
+        return Err(format!(
+            "Archive {} has no name table",
+            archive_path.display()
+        ));
+    }
+
+    reader
+        .seek(SeekFrom::Start(header.name_table_offset))
+        .map_err(|e| format!("Error seeking in archive {}: {}", archive_path.display(), e))?;
+
+    // Every name takes at least its two byte length prefix
+    let names_size = archive_len(reader.get_ref(), archive_path)?.saturating_sub(header.name_table_offset);
+    if u64::from(header.file_count) * 2 > names_size {
+        return Err(truncated_archive(archive_path, header.file_count));
+    }
+
+    let mut entries = Vec::with_capacity(header.file_count as usize);
+    for _ in 0..header.file_count {
+        let length = read_u16(&mut reader, archive_path)? as usize;
+        let mut name = vec![0u8; length];
+        reader
+            .read_exact(&mut name)
+            .map_err(|e| format!("Error reading name table of {}: {}", archive_path.display(), e))?;
+        entries.push(String::from_utf8_lossy(&name).into_owned());
+    }
+
+    Ok(entries)
+}
+
+/// Rewrites the version field of a Fallout 4 BA2 archive.
+///
+/// Versions 1, 7 and 8 share the same layout; old-gen games only accept
+/// version 1 while next-gen archive tools write 7 or 8, so switching between
+/// them only requires changing the header.
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+/// * `version` - The version to write (1, 7 or 8)
+///
+/// # Returns
+/// * `Ok(())` if the version was written or already matched
+/// * `Err(String)` if the archive isn't a Fallout 4 BA2 or can't be written
+pub fn set_ba2_version(archive_path: &Path, version: u32) -> Result<(), String> {
+    if !FALLOUT4_VERSIONS.contains(&version) {
+        return Err(format!("Unsupported BA2 version {}", version));
+    }
+
+    let header = read_ba2_header(archive_path)?;
+    if header.version == version {
+        return Ok(());
+    }
+    if !FALLOUT4_VERSIONS.contains(&header.version) {
+        return Err(format!(
+            "Archive {} has version {} which can't be converted to version {}",
+            archive_path.display(),
+            header.version,
+            version
+        ));
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(archive_path)
+        .map_err(|e| format!("Error opening archive {}: {}", archive_path.display(), e))?;
+    file.seek(SeekFrom::Start(4))
+        .and_then(|_| file.write_all(&version.to_le_bytes()))
+        .map_err(|e| format!("Error writing archive {}: {}", archive_path.display(), e))?;
+
+    Ok(())
+}
+
+/// A file stored in a GNRL archive
+#[derive(Debug, Clone)]
+pub struct Ba2FileRecord {
+    pub name: String,
+    pub offset: u64,
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+}
+
+/// Reads the file records of a general (GNRL) BA2 archive.
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+///
+/// # Returns
+/// * `Ok(Vec<Ba2FileRecord>)` with the name, location and sizes of every file
+/// * `Err(String)` if the archive can't be read or isn't a GNRL archive
+pub fn read_ba2_records(archive_path: &Path) -> Result<Vec<Ba2FileRecord>, String> {
+    let file = File::open(archive_path)
+        .map_err(|e| format!("Error opening archive {}: {}", archive_path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader, archive_path)?;
+
+    if header.archive_type != "GNRL" {
+        return Err(format!(
+            "Archive {} is a {} archive, only GNRL archives are supported",
+            archive_path.display(),
+            header.archive_type
+        ));
+    }
+
+    let records_size = archive_len(reader.get_ref(), archive_path)?.saturating_sub(HEADER_SIZE);
+    if u64::from(header.file_count) * GNRL_RECORD_SIZE > records_size {
+        return Err(truncated_archive(archive_path, header.file_count));
+    }
+
+    let mut records = Vec::with_capacity(header.file_count as usize);
+    for _ in 0..header.file_count {
+        let mut record = [0u8; GNRL_RECORD_SIZE as usize];
+        reader
+            .read_exact(&mut record)
+            .map_err(|e| format!("Error reading file records of {}: {}", archive_path.display(), e))?;
+
+        records.push(Ba2FileRecord {
+            name: String::new(),
+            offset: u64::from_le_bytes(record[16..24].try_into().unwrap()),
+            packed_size: u32::from_le_bytes(record[24..28].try_into().unwrap()),
+            unpacked_size: u32::from_le_bytes(record[28..32].try_into().unwrap()),
+        });
+    }
+
+    let names = read_ba2_entries(archive_path)?;
+    for (record, name) in records.iter_mut().zip(names) {
+        record.name = name;
+    }
+
+    Ok(records)
+}
+
+/// Extracts every file of a general (GNRL) BA2 archive into a directory.
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+/// * `output_dir` - Directory the archive contents are extracted into
+///
+/// # Returns
+/// * `Ok(())` if every file was extracted
+/// * `Err(String)` if the archive can't be read or a file can't be written
+pub fn extract_ba2(archive_path: &Path, output_dir: &Path) -> Result<(), String> {
+    for_each_ba2_file(archive_path, |record, data| {
+        let target = entry_path(output_dir, &record.name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Error creating directory {}: {}", parent.display(), e))?;
+        }
+        fs::write(&target, data)
+            .map_err(|e| format!("Error writing {}: {}", target.display(), e))
+    })
+}
+
+/// Reads every file of a general (GNRL) BA2 archive in turn, decompressed,
+/// without holding more than one in memory.
+///
+/// # Arguments
+/// * `archive_path` - Path to the BA2 archive
+/// * `visit` - Called with the record and contents of each file; an error stops the reading
+///
+/// # Returns
+/// * `Ok(())` if every file was read and visited
+/// * `Err(String)` if the archive can't be read or `visit` failed
+pub fn for_each_ba2_file(
+    archive_path: &Path,
+    mut visit: impl FnMut(&Ba2FileRecord, Vec<u8>) -> Result<(), String>,
+) -> Result<(), String> {
+    let records = read_ba2_records(archive_path)?;
+    let mut file = File::open(archive_path)
+        .map_err(|e| format!("Error opening archive {}: {}", archive_path.display(), e))?;
+    let len = archive_len(&file, archive_path)?;
+
+    for record in &records {
+        let stored_size = if record.packed_size > 0 { record.packed_size } else { record.unpacked_size };
+        if record.offset.saturating_add(u64::from(stored_size)) > len {
+            return Err(format!(
+                "Archive {} is truncated, {} extends past the end of the file",
+                archive_path.display(),
+                record.name
+            ));
+        }
+        let mut stored = vec![0u8; stored_size as usize];
+        file.seek(SeekFrom::Start(record.offset))
+            .and_then(|_| file.read_exact(&mut stored))
+            .map_err(|e| format!("Error reading {} from {}: {}", record.name, archive_path.display(), e))?;
+
+        let data = if record.packed_size > 0 {
+            let mut data = Vec::new();
+            ZlibDecoder::new(&stored[..])
+                .read_to_end(&mut data)
+                .map_err(|e| format!("Error decompressing {}: {}", record.name, e))?;
+            data
+        } else {
+            stored
+        };
+
+        visit(record, data)?;
+    }
+
+    Ok(())
+}
+
+/// Packs an archive with the native BA2 writer.
+///
+/// # Arguments
+/// * `request` - The archive to create and what to pack into it
+/// * `version` - BA2 version written to the header
+///
+/// # Returns
+/// * `Ok(())` if the archive was written
+/// * `Err(String)` if Xbox compression was requested or the archive can't be written
+pub fn pack_ba2(request: &PackRequest, version: u32) -> Result<(), String> {
+    let compress = match request.compression {
+        PackCompression::Default => true,
+        PackCompression::None => false,
+        PackCompression::Xbox => {
+            return Err("ERROR - Xbox compression isn't supported by the native archiver, use Archive2".to_string())
+        }
+    };
+
+    info!("Creating archive: {} with the native archiver", request.archive_path.display());
+    let entries = request.entry_names()?;
+    write_ba2(&request.archive_path, &request.root, &entries, version, compress)
+}
+
+/// Writes a general (GNRL) BA2 archive from a list of files.
+///
+/// # Arguments
+/// * `archive_path` - Path of the archive to create
+/// * `source_dir` - Directory the entry names are relative to
+/// * `entries` - Entry names (backslash separated, relative to `source_dir`) in the order they are stored
+/// * `version` - BA2 version written to the header
+/// * `compress` - Whether to zlib-compress file data
+///
+/// # Returns
+/// * `Ok(())` if the archive was written
+/// * `Err(String)` if a file can't be read or the archive can't be written
+pub fn write_ba2(
+    archive_path: &Path,
+    source_dir: &Path,
+    entries: &[String],
+    version: u32,
+    compress: bool,
+) -> Result<(), String> {
+    let write_error = |e: std::io::Error| format!("Error writing archive {}: {}", archive_path.display(), e);
+
+    let file = File::create(archive_path).map_err(write_error)?;
+    let mut writer = BufWriter::new(file);
+
+    // Header, with the name table offset filled in once the data is written
+    writer.write_all(BA2_MAGIC).map_err(write_error)?;
+    writer.write_all(&version.to_le_bytes()).map_err(write_error)?;
+    writer.write_all(b"GNRL").map_err(write_error)?;
+    let file_count = u32::try_from(entries.len())
+        .map_err(|_| format!("Error writing archive {}: too many files ({})", archive_path.display(), entries.len()))?;
+    writer.write_all(&file_count.to_le_bytes()).map_err(write_error)?;
+    writer.write_all(&0u64.to_le_bytes()).map_err(write_error)?;
+
+    // Reserve space for the file records
+    let records_size = GNRL_RECORD_SIZE * entries.len() as u64;
+    writer.write_all(&vec![0u8; records_size as usize]).map_err(write_error)?;
+
+    let mut records = Vec::with_capacity(entries.len());
+    let mut offset = HEADER_SIZE + records_size;
+
+    for entry in entries {
+        let source = entry_path(source_dir, entry);
+        let data = fs::read(&source)
+            .map_err(|e| format!("Error reading {}: {}", source.display(), e))?;
+
+        let packed = if compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder
+                .write_all(&data)
+                .map_err(|e| format!("Error compressing {}: {}", source.display(), e))?;
+            let packed = encoder
+                .finish()
+                .map_err(|e| format!("Error compressing {}: {}", source.display(), e))?;
+            // Files that don't shrink are stored uncompressed
+            if packed.len() < data.len() { Some(packed) } else { None }
+        } else {
+            None
+        };
+
+        let stored = packed.as_deref().unwrap_or(&data);
+        writer.write_all(stored).map_err(write_error)?;
+
+        let too_large = |_| format!("Error writing archive {}: {} is larger than 4 GB", archive_path.display(), entry);
+        let packed_size = match &packed {
+            Some(p) => u32::try_from(p.len()).map_err(too_large)?,
+            None => 0,
+        };
+        let unpacked_size = u32::try_from(data.len()).map_err(too_large)?;
+
+        records.push(Ba2FileRecord {
+            name: entry.clone(),
+            offset,
+            packed_size,
+            unpacked_size,
+        });
+        offset += stored.len() as u64;
+    }
+
+    // Name table
+    let name_table_offset = offset;
+    for record in &records {
+        let name = record.name.as_bytes();
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| format!("Error writing archive {}: file name {} is too long", archive_path.display(), record.name))?;
+        writer.write_all(&name_len.to_le_bytes()).map_err(write_error)?;
+        writer.write_all(name).map_err(write_error)?;
+    }
+
+    // Go back and fill in the name table offset and file records
+    writer.seek(SeekFrom::Start(16)).map_err(write_error)?;
+    writer.write_all(&name_table_offset.to_le_bytes()).map_err(write_error)?;
+
+    for record in &records {
+        let (directory, file_name) = match record.name.rfind(['\\', '/']) {
+            Some(index) => (&record.name[..index], &record.name[index + 1..]),
+            None => ("", record.name.as_str()),
+        };
+        let (stem, extension) = match file_name.rfind('.') {
+            Some(index) => (&file_name[..index], &file_name[index + 1..]),
+            None => (file_name, ""),
+        };
+
+        let mut extension_bytes = [0u8; 4];
+        for (byte, c) in extension_bytes.iter_mut().zip(extension.to_lowercase().bytes()) {
+            *byte = c;
+        }
+
+        writer.write_all(&name_hash(stem).to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&extension_bytes).map_err(write_error)?;
+        writer.write_all(&name_hash(directory).to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&GNRL_RECORD_FLAGS.to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&record.offset.to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&record.packed_size.to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&record.unpacked_size.to_le_bytes()).map_err(write_error)?;
+        writer.write_all(&GNRL_RECORD_END.to_le_bytes()).map_err(write_error)?;
+    }
+
+    writer.flush().map_err(write_error)
+}
+
+/// Hashes a file stem or directory path the way the game looks them up:
+/// a CRC-32 over the lowercased, backslash-separated name
+fn name_hash(name: &str) -> u32 {
+    let mut hash = 0u32;
+    for byte in name.bytes() {
+        let byte = match byte {
+            b'/' => b'\\',
+            b => b.to_ascii_lowercase(),
+        };
+        hash = (hash >> 8) ^ crc32_table_entry((hash ^ byte as u32) & 0xFF);
+    }
+    hash
+}
+
+/// Computes one entry of the standard CRC-32 (0xEDB88320) lookup table
+fn crc32_table_entry(index: u32) -> u32 {
+    let mut value = index;
+    for _ in 0..8 {
+        value = if value & 1 != 0 { (value >> 1) ^ 0xEDB8_8320 } else { value >> 1 };
+    }
+    value
+}
+
+/// Returns the size of an open archive file in bytes.
+fn archive_len(file: &File, archive_path: &Path) -> Result<u64, String> {
+    file.metadata()
+        .map(|metadata| metadata.len())
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))
+}
+
+/// Error for an archive whose header claims more files than it can hold.
+fn truncated_archive(archive_path: &Path, file_count: u32) -> String {
+    format!(
+        "Archive {} is truncated or corrupt, its header lists {} files",
+        archive_path.display(),
+        file_count
+    )
+}
+
+fn read_header<R: Read>(reader: &mut R, archive_path: &Path) -> Result<Ba2Header, String> {
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))?;
+    if &magic != BA2_MAGIC {
+        return Err(format!("{} is not a BA2 archive", archive_path.display()));
+    }
+
+    let version = read_u32(reader, archive_path)?;
+
+    let mut archive_type = [0u8; 4];
+    reader
+        .read_exact(&mut archive_type)
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))?;
+
+    let file_count = read_u32(reader, archive_path)?;
+    let name_table_offset = read_u64(reader, archive_path)?;
+
+    Ok(Ba2Header {
+        version,
+        archive_type: String::from_utf8_lossy(&archive_type).into_owned(),
+        file_count,
+        name_table_offset,
+    })
+}
+
+fn read_u16<R: Read>(reader: &mut R, archive_path: &Path) -> Result<u16, String> {
+    let mut buf = [0u8; 2];
+    reader
+        .read_exact(&mut buf)
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R, archive_path: &Path) -> Result<u32, String> {
+    let mut buf = [0u8; 4];
+    reader
+        .read_exact(&mut buf)
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R, archive_path: &Path) -> Result<u64, String> {
+    let mut buf = [0u8; 8];
+    reader
+        .read_exact(&mut buf)
+        .map_err(|e| format!("Error reading archive {}: {}", archive_path.display(), e))?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use crate::diagnostics::{ERROR_CONTEXT_LINES, ERROR_MARKERS, FAILURE_EXCERPT_LINES};
+
+/// Line the CK logs when CKPE's reference handles run out
+pub const OUT_OF_HANDLES_MARKER: &str = "DEFAULT: OUT OF HANDLE ARRAY ENTRIES";
+
+/// Line the CK logs when GeneratePreVisData gives up
+pub const VISIBILITY_INCOMPLETE_MARKER: &str = "ERROR: visibility task did not complete.";
+
+/// Lowercase phrases of a CK log line reporting that a file couldn't be
+/// opened or written because access was denied
+const ACCESS_DENIED_MARKERS: [&str; 3] = ["access is denied", "access denied", "permission denied"];
+
+/// Lines copied into the build log from the start of a CK log
+const HEAD_LINES: usize = 200;
+
+/// Lines copied into the build log from the end of a CK log
+const TAIL_LINES: usize = 500;
+
+/// Error lines whose context is copied into the build log; later ones are
+/// only counted
+const MAX_ERROR_CONTEXTS: usize = 50;
+
+/// The last CK log read, so the stage checks, the build log and the run
+/// folder copy don't each read it again
+static LAST_LOG: Mutex<Option<ReadLog>> = Mutex::new(None);
+
+/// A log read earlier, with what identified its contents at the time
+struct ReadLog {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    summary: Arc<CkLogSummary>,
+}
+
+/// What a build needs from a Creation Kit log, gathered in one pass without
+/// holding the whole log in memory
+#[derive(Debug, Default)]
+pub struct CkLogSummary {
+    /// Number of lines in the log
+    pub lines: usize,
+    /// Lines mentioning a warning
+    pub warnings: usize,
+    /// Lines reporting an error
+    pub errors: usize,
+    /// Whether the reference handles ran out
+    pub out_of_handles: bool,
+    /// Whether the visibility task didn't complete
+    pub visibility_incomplete: bool,
+    /// The first line reporting that access to a file was denied
+    pub access_denied: Option<String>,
+    /// Lines kept for the build log (start, error contexts, end), by line number
+    kept: BTreeMap<usize, String>,
+    /// The last error line with the lines around it, or the end of the log
+    excerpt: Vec<String>,
+}
+
+impl CkLogSummary {
+    /// The kept lines in order, with a note where lines were left out
+    pub fn capped_lines(&self) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.kept.len() + 8);
+        let mut next = 0;
+        for (number, line) in &self.kept {
+            if *number > next {
+                lines.push(format!("[... {} lines omitted ...]", number - next));
+            }
+            lines.push(line.clone());
+            next = number + 1;
+        }
+        if self.lines > next {
+            lines.push(format!("[... {} lines omitted ...]", self.lines - next));
+        }
+        lines
+    }
+
+    /// The part of the log showing why a stage failed: the last error line
+    /// with the lines around it, or else the end of the log
+    pub fn failure_excerpt(&self) -> &[String] {
+        &self.excerpt
+    }
+}
+
+/// Reads a Creation Kit log line by line, keeping only its start, its end and
+/// the lines around errors. Reading the same unchanged log again returns the
+/// earlier result.
+///
+/// # Arguments
+/// * `path` - Path to the CK log
+///
+/// # Returns
+/// * `Ok(Arc<CkLogSummary>)` with what was found
+/// * `Err(String)` if the log can't be read
+pub fn read_ck_log(path: &Path) -> Result<Arc<CkLogSummary>, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Error reading CK log file {}: {}", path.display(), e))?;
+    let modified = metadata.modified().ok();
+    let len = metadata.len();
+
+    let mut last = LAST_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(read) = last.as_ref().filter(|read| read.path == path && read.modified == modified && read.len == len) {
+        return Ok(Arc::clone(&read.summary));
+    }
+
+    let file = File::open(path).map_err(|e| format!("Error reading CK log file {}: {}", path.display(), e))?;
+    let summary = Arc::new(
+        scan(BufReader::new(file)).map_err(|e| format!("Error reading CK log file {}: {}", path.display(), e))?,
+    );
+    *last = Some(ReadLog { path: path.to_path_buf(), modified, len, summary: Arc::clone(&summary) });
+    Ok(summary)
+}
+
+/// Forgets the last log read, for when the CK is about to write a new one
+pub fn forget_ck_log() {
+    *LAST_LOG.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Writes a gzip-compressed copy of a log, reading it in chunks
+///
+/// # Arguments
+/// * `source` - The log to copy
+/// * `target` - The `.gz` file to write
+pub fn compress_log(source: &Path, target: &Path) -> Result<(), String> {
+    let copy = || -> io::Result<()> {
+        let mut reader = File::open(source)?;
+        let mut encoder = GzEncoder::new(File::create(target)?, Compression::default());
+        io::copy(&mut reader, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    };
+    copy().map_err(|e| format!("Error compressing {} to {}: {}", source.display(), target.display(), e))
+}
+
+fn scan(mut reader: impl BufRead) -> io::Result<CkLogSummary> {
+    let mut summary = CkLogSummary::default();
+    let mut before: VecDeque<(usize, String)> = VecDeque::with_capacity(ERROR_CONTEXT_LINES);
+    let mut tail: VecDeque<(usize, String)> = VecDeque::with_capacity(TAIL_LINES);
+    let mut kept_after = 0;
+    let mut excerpt_after = 0;
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buffer).trim_end_matches(['\r', '\n']).to_string();
+        let number = summary.lines;
+        summary.lines += 1;
+
+        summary.out_of_handles |= line.contains(OUT_OF_HANDLES_MARKER);
+        summary.visibility_incomplete |= line.contains(VISIBILITY_INCOMPLETE_MARKER);
+        let lower = line.to_lowercase();
+        if lower.contains("warning") {
+            summary.warnings += 1;
+        }
+        if summary.access_denied.is_none() && ACCESS_DENIED_MARKERS.iter().any(|marker| lower.contains(marker)) {
+            summary.access_denied = Some(line.clone());
+        }
+        let is_error = ERROR_MARKERS.iter().any(|marker| lower.contains(marker));
+
+        if is_error {
+            summary.errors += 1;
+            if summary.errors <= MAX_ERROR_CONTEXTS {
+                for (number, line) in &before {
+                    summary.kept.insert(*number, line.clone());
+                }
+                kept_after = ERROR_CONTEXT_LINES + 1;
+            }
+            summary.excerpt = before.iter().map(|(_, line)| line.clone()).collect();
+            excerpt_after = ERROR_CONTEXT_LINES + 1;
+        }
+        if number < HEAD_LINES || kept_after > 0 {
+            summary.kept.insert(number, line.clone());
+            kept_after = kept_after.saturating_sub(1);
+        }
+        if excerpt_after > 0 {
+            summary.excerpt.push(line.clone());
+            excerpt_after -= 1;
+        }
+
+        if before.len() == ERROR_CONTEXT_LINES {
+            before.pop_front();
+        }
+        before.push_back((number, line.clone()));
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back((number, line));
+    }
+
+    if summary.errors == 0 {
+        summary.excerpt = tail
+            .iter()
+            .skip(tail.len().saturating_sub(FAILURE_EXCERPT_LINES))
+            .map(|(_, line)| line.clone())
+            .collect();
+    }
+    summary.kept.extend(tail);
+    Ok(summary)
+}
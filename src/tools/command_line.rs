@@ -0,0 +1,127 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use crate::console;
+use crate::events;
+
+/// Set by --show-commands
+static SHOW_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+/// Environment variables set for the tools of the running stage (--tool-env)
+static TOOL_ENVIRONMENT: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Prints every external command before it runs (--show-commands)
+pub fn enable_show_commands() {
+    SHOW_COMMANDS.store(true, Ordering::Relaxed);
+}
+
+/// Sets the environment variables the external tools started from now on
+/// get, replacing the previous stage's (--tool-env)
+pub fn set_tool_environment(variables: Vec<(String, String)>) {
+    *TOOL_ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner()) = variables;
+}
+
+/// Gets `command` ready to run: adds the running stage's environment
+/// variables and shows it with --show-commands
+pub fn prepare_command(command: &mut Command) {
+    command.envs(TOOL_ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned());
+    show_command(command);
+}
+
+/// Prints `command` exactly as it will run, if --show-commands is on:
+/// its working folder, environment changes and command line, in a form
+/// that can be pasted into a Windows command prompt. Also reported as a
+/// `tool` event when events are enabled.
+pub fn show_command(command: &Command) {
+    events::emit_tool(command);
+    if !SHOW_COMMANDS.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let cwd = command
+        .get_current_dir()
+        .map(Path::to_path_buf)
+        .or_else(|| env::current_dir().ok());
+    if let Some(cwd) = cwd {
+        console!("> cd /d {}", quote_arg(&cwd.display().to_string()));
+    }
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => console!("> set {}={}", key.to_string_lossy(), value.to_string_lossy()),
+            None => console!("> set {}=", key.to_string_lossy()),
+        }
+    }
+
+    let line = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| quote_arg(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    console!("> {}", line);
+}
+
+/// Quotes an argument the way the Windows C runtime splits command lines,
+/// leaving plain arguments as they are
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes before a quote are escaped along with the quote
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // Backslashes before the closing quote are doubled
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Splits a command line into its program and arguments at spaces, keeping
+/// double-quoted parts (e.g. paths with spaces) together. Backslashes are
+/// kept as they are, as they separate folders in Windows paths.
+pub fn split_command_line(line: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_part = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_part = true;
+            }
+            ' ' | '\t' if !in_quotes => {
+                if has_part {
+                    parts.push(std::mem::take(&mut current));
+                    has_part = false;
+                }
+            }
+            _ => {
+                current.push(c);
+                has_part = true;
+            }
+        }
+    }
+    if has_part {
+        parts.push(current);
+    }
+    parts
+}
@@ -1,12 +1,71 @@
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
 use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use crate::locks::{describe_io_error, explain_access_denied};
+use crate::logging;
+use crate::tools::ck_log::{forget_ck_log, read_ck_log};
+use crate::tools::command_line::{prepare_command, split_command_line};
+use crate::tools::progress::output_with_elapsed;
+use crate::tools::simulate::{is_simulating, simulate_creation_kit};
+
+/// ENB/ReShade DLLs that crash the Creation Kit and are disabled while it runs
+pub const CK_CONFLICTING_DLLS: [&str; 6] = [
+    "d3d11.dll",
+    "d3d10.dll",
+    "d3d9.dll",
+    "dxgi.dll",
+    "enbimgui.dll",
+    "d3dcompiler_46e.dll",
+];
+
+/// Suffix appended to a DLL's file name while it is disabled
+pub const DISABLED_DLL_SUFFIX: &str = "-PJMdisabled";
+
+/// Program and arguments the Creation Kit is started through (--ck-launcher)
+static CK_LAUNCHER: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// Starts the Creation Kit through a loader command instead of directly
+/// (--ck-launcher). The command receives CreationKit.exe and its arguments
+/// after its own, and must wait for the Creation Kit to exit.
+///
+/// # Arguments
+/// * `launcher` - The command line, with double quotes around parts containing spaces
+///
+/// # Returns
+/// * `Ok(())` once set
+/// * `Err(String)` if the command is empty or names a program that doesn't exist
+pub fn set_ck_launcher(launcher: &str) -> Result<(), String> {
+    let parts = split_command_line(launcher);
+    let Some(program) = parts.first() else {
+        return Err("ERROR - The CK launcher command is empty".to_string());
+    };
+    // Programs given by name alone are looked up on PATH when started
+    let program_path = Path::new(program);
+    if program_path.components().count() > 1 && !program_path.exists() {
+        return Err(format!("ERROR - CK launcher {} not found", program));
+    }
+    *CK_LAUNCHER.lock().unwrap_or_else(|e| e.into_inner()) = Some(parts);
+    Ok(())
+}
 
-#[derive(Clone)]
+/// The command starting the Creation Kit, through the launcher if one is set
+fn creation_kit_command(creation_kit_path: &Path) -> Command {
+    match CK_LAUNCHER.lock().unwrap_or_else(|e| e.into_inner()).as_deref() {
+        Some([program, launcher_args @ ..]) => {
+            let mut command = Command::new(program);
+            command.args(launcher_args).arg(creation_kit_path);
+            command
+        }
+        _ => Command::new(creation_kit_path),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CkpeSettings {
     pub ini_file: String,
     pub handle_setting: String,
@@ -24,101 +83,94 @@ pub struct CkpeSettings {
 /// * `output_file` - The expected output file that should be created by the action
 /// * `args` - Additional command-line arguments for the Creation Kit
 /// * `ckpe_settings` - CKPE configuration settings
-/// * `logfile` - Path to the main log file
 ///
 /// # Returns
 /// * `Ok(())` if the Creation Kit runs successfully and produces the expected output
 /// * `Err(String)` if the command fails or doesn't produce the expected output
 pub fn run_creation_kit(
-    creation_kit_path: &PathBuf,
+    creation_kit_path: &Path,
     fallout4_path: &PathBuf,
     plugin_name_ext: &str,
     action: &str,
     output_file: &str,
     args: &str,
     ckpe_settings: &CkpeSettings,
-    logfile: &PathBuf,
 ) -> Result<(), String> {
     info!("Running CK option {}", action);
 
     // Disable ENB/ReShade DLLs
-    let dlls_to_disable = [
-        "d3d11.dll",
-        "d3d10.dll",
-        "d3d9.dll",
-        "dxgi.dll",
-        "enbimgui.dll",
-        "d3dcompiler_46e.dll",
-    ];
-
-    for dll in dlls_to_disable.iter() {
+    for dll in CK_CONFLICTING_DLLS.iter() {
         let dll_path = fallout4_path.join(dll);
         if dll_path.exists() {
-            let disabled_path = fallout4_path.join(format!("{}-PJMdisabled", dll));
+            let disabled_path = fallout4_path.join(format!("{}{}", dll, DISABLED_DLL_SUFFIX));
             fs::rename(&dll_path, &disabled_path)
                 .map_err(|e| format!("Error disabling {}: {}", dll, e))?;
         }
     }
 
     // Delete previous log if it exists
+    forget_ck_log();
     if let Some(log_file) = &ckpe_settings.log_file {
         if log_file.exists() {
-            fs::remove_file(log_file).map_err(|e| {
-                format!("Error removing log file {}: {}", log_file.display(), e)
-            })?;
+            fs::remove_file(log_file).map_err(|e| describe_io_error("removing log file", log_file, &e))?;
         }
     }
 
-    // Log to our logfile
-    let mut log_file = File::options()
-        .append(true)
-        .create(true)
-        .open(logfile)
-        .map_err(|e| format!("Error opening log file {}: {}", logfile.display(), e))?;
-
-    writeln!(log_file, "Running CK option {}:", action)
-        .map_err(|e| format!("Error writing to log file: {}", e))?;
-    writeln!(log_file, "====================================")
-        .map_err(|e| format!("Error writing to log file: {}", e))?;
+    logging::append("====================================");
 
     // Build command line
     let cmd_args = format!("-{}:\"{}\" {}", action, plugin_name_ext, args);
 
     // Run CreationKit
-    let output = Command::new(creation_kit_path)
-        .current_dir(fallout4_path)
-        .args(cmd_args.split_whitespace())
-        .output()
-        .map_err(|e| format!("Error executing Creation Kit: {}", e))?;
-
-    let exit_code = output.status.code().unwrap_or(-1);
-
-    // Wait for MO2 to process files
-    sleep(Duration::from_secs(5));
-
-    // Append CK log to our log if it exists
+    let mut command = creation_kit_command(creation_kit_path);
+    command.current_dir(fallout4_path).args(cmd_args.split_whitespace());
+    prepare_command(&mut command);
+    let exit_code = if is_simulating() {
+        simulate_creation_kit(
+            creation_kit_path,
+            fallout4_path,
+            plugin_name_ext,
+            action,
+            output_file,
+            args,
+            ckpe_settings.log_file.as_deref(),
+        )?;
+        0
+    } else {
+        let output = output_with_elapsed(&mut command, action, ckpe_settings.log_file.clone())
+            .map_err(|e| format!("Error executing Creation Kit: {}", e))?;
+
+        // Wait for MO2 to process files
+        sleep(Duration::from_secs(5));
+        output.status.code().unwrap_or(-1)
+    };
+
+    // Append the start, end and error contexts of the CK log to our log if it exists
+    let mut access_denied = None;
     if let Some(log_file_path) = &ckpe_settings.log_file {
         if log_file_path.exists() {
-            let ck_log = fs::read_to_string(log_file_path).map_err(|e| {
-                format!(
-                    "Error reading CK log file {}: {}",
-                    log_file_path.display(),
-                    e
-                )
-            })?;
-
-            writeln!(log_file, "{}", ck_log)
-                .map_err(|e| format!("Error writing to log file: {}", e))?;
+            let ck_log = read_ck_log(log_file_path)?;
+            logging::append(&ck_log.capped_lines().join("\n"));
+            access_denied = ck_log.access_denied.clone();
         }
     }
 
     // Check if output file was created
     let output_path = fallout4_path.join("Data").join(output_file);
     if !output_path.exists() {
-        return Err(format!(
+        let mut message = format!(
             "ERROR - {} failed to create file {} with exit status {}",
             action, output_file, exit_code
-        ));
+        );
+        // The CK can't write to Data when antivirus blocks it
+        if let Some(line) = access_denied {
+            message.push_str(&format!(
+                "\nThe CK log reports: {}\n{}",
+                line.trim(),
+                explain_access_denied(&output_path, &[creation_kit_path])
+            ));
+        }
+        return Err(message);
     }
 
     if exit_code != 0 {
@@ -129,8 +181,8 @@ pub fn run_creation_kit(
     }
 
     // Re-enable ENB/ReShade DLLs
-    for dll in dlls_to_disable.iter() {
-        let disabled_path = fallout4_path.join(format!("{}-PJMdisabled", dll));
+    for dll in CK_CONFLICTING_DLLS.iter() {
+        let disabled_path = fallout4_path.join(format!("{}{}", dll, DISABLED_DLL_SUFFIX));
         if disabled_path.exists() {
             let dll_path = fallout4_path.join(dll);
             fs::rename(&disabled_path, &dll_path)
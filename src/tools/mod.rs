@@ -1,7 +1,19 @@
 pub mod archive;
+pub mod archiver;
+pub mod ba2;
+pub mod ck_log;
+pub mod progress;
+pub mod command_line;
 pub mod creation_kit;
+pub mod simulate;
 pub mod xedit;
 
-pub use archive::{run_archive, extract_archive, add_to_archive, run_bsarch, get_archive_qualifiers};
-pub use creation_kit::{run_creation_kit, CkpeSettings};
-pub use xedit::run_xedit_script;
\ No newline at end of file
+pub use archive::{run_archive, check_bsarch_output, run_bsarch_pack, extract_archive, extract_bsarch, run_bsarch, ArchiveFormat, PackCompression, PackRequest, xbox_archive_name, part_archive_name, existing_part_archives, split_staged_content, is_previs_entry, is_texture_archive, verify_extraction, entry_path, audit_archive, list_entry_names, sort_entry_names, BsarchOptions, ARCHIVE_STAGING_DIR, LOOSE_BACKUP_DIR};
+pub use archiver::{create_archiver, Archive2Archiver, Archiver, BsarchArchiver, NativeArchiver, SimulatedArchiver};
+pub use ba2::{read_ba2_entries, read_ba2_header, read_ba2_records, extract_ba2, for_each_ba2_file, pack_ba2, write_ba2, set_ba2_version, Ba2Header, Ba2FileRecord};
+pub use ck_log::{read_ck_log, forget_ck_log, compress_log, CkLogSummary, OUT_OF_HANDLES_MARKER, VISIBILITY_INCOMPLETE_MARKER};
+pub use command_line::{enable_show_commands, prepare_command, set_tool_environment, show_command, split_command_line};
+pub use progress::{output_with_elapsed, output_with_progress, ArchiveProgress};
+pub use creation_kit::{run_creation_kit, set_ck_launcher, CkpeSettings, CK_CONFLICTING_DLLS, DISABLED_DLL_SUFFIX};
+pub use simulate::{enable_simulation, is_simulating, simulate_failure, SimulatedFailure};
+pub use xedit::run_xedit_script;
@@ -0,0 +1,224 @@
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::headless;
+use crate::style;
+use crate::tools::archive::{entry_path, list_entry_names};
+use crate::utils::format_duration;
+
+/// How often the progress line is refreshed while an archiver runs
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a status line is printed with --plain
+const PLAIN_STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How much of the end of the CK log is read to find the current cell
+const LOG_TAIL_BYTES: u64 = 4096;
+
+/// Longest cell name shown on the status line
+const MAX_CELL_NAME: usize = 48;
+
+/// What to poll to report progress while an external archiver runs
+///
+/// Neither Archive2 nor BSArch report progress in a form that can be parsed
+/// reliably, so progress is measured from the files they write.
+#[derive(Debug, Clone)]
+pub enum ArchiveProgress {
+    /// Packing loose files into the archive at `archive_path`
+    Packing {
+        archive_path: PathBuf,
+        total_files: usize,
+        total_bytes: u64,
+    },
+    /// Extracting `total_files` archive entries into `output_dir`
+    Extracting {
+        output_dir: PathBuf,
+        total_files: usize,
+    },
+}
+
+impl ArchiveProgress {
+    /// Progress for packing `folders` (relative to `source_dir`) into `archive_path`
+    pub fn packing(archive_path: &Path, source_dir: &Path, folders: &[&str]) -> Self {
+        let mut total_files = 0;
+        let mut total_bytes = 0;
+        for folder in folders {
+            let folder_dir = source_dir.join(folder);
+            for entry in list_entry_names(&folder_dir) {
+                total_files += 1;
+                total_bytes += fs::metadata(entry_path(&folder_dir, &entry))
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+            }
+        }
+
+        ArchiveProgress::Packing {
+            archive_path: archive_path.to_path_buf(),
+            total_files,
+            total_bytes,
+        }
+    }
+
+    /// Progress for extracting `total_files` entries into `output_dir`
+    pub fn extracting(output_dir: &Path, total_files: usize) -> Self {
+        ArchiveProgress::Extracting {
+            output_dir: output_dir.to_path_buf(),
+            total_files,
+        }
+    }
+
+    /// Returns the current progress line, `baseline` being the number of
+    /// files already in the output folder before the archiver started
+    fn status_line(&self, baseline: usize) -> String {
+        match self {
+            ArchiveProgress::Packing { archive_path, total_files, total_bytes } => {
+                let written = fs::metadata(archive_path).map(|metadata| metadata.len()).unwrap_or(0);
+                format!(
+                    "Packing {} files: {} MB written ({} MB loose)",
+                    total_files,
+                    written / (1024 * 1024),
+                    total_bytes / (1024 * 1024)
+                )
+            }
+            ArchiveProgress::Extracting { output_dir, total_files } => {
+                let extracted = list_entry_names(output_dir).len().saturating_sub(baseline);
+                let percent = (extracted * 100).checked_div(*total_files).unwrap_or(100).min(100);
+                format!(
+                    "Extracting: {}/{} files ({}%)",
+                    extracted.min(*total_files),
+                    total_files,
+                    percent
+                )
+            }
+        }
+    }
+
+    fn baseline(&self) -> usize {
+        match self {
+            ArchiveProgress::Packing { .. } => 0,
+            ArchiveProgress::Extracting { output_dir, .. } => list_entry_names(output_dir).len(),
+        }
+    }
+}
+
+/// Runs the command to completion, capturing its output, while a progress
+/// line is redrawn on the console.
+///
+/// Progress is only shown when stdout is a terminal and not in headless mode,
+/// so redirected logs don't fill up with progress updates. With --plain it is
+/// printed as a line of its own every `PLAIN_STATUS_INTERVAL` instead, also
+/// when stdout is redirected.
+///
+/// # Arguments
+/// * `command` - The archiver command to run
+/// * `progress` - What to poll for progress
+///
+/// # Returns
+/// * The process output, as returned by `Command::output`
+pub fn output_with_progress(command: &mut Command, progress: ArchiveProgress) -> io::Result<Output> {
+    if !shows_status() {
+        return command.output();
+    }
+
+    let baseline = progress.baseline();
+    output_with_status(command, move || progress.status_line(baseline))
+}
+
+/// Runs a Creation Kit stage to completion, capturing its output, while the
+/// elapsed time and the cell the CK is working on (when its log names one)
+/// are redrawn on the console, so a slow stage can be told from a hung one.
+///
+/// Shown under the same conditions as by `output_with_progress`.
+///
+/// # Arguments
+/// * `command` - The Creation Kit command to run
+/// * `action` - The CK action, shown on the status line
+/// * `log_file` - The CK log, read for the current cell
+pub fn output_with_elapsed(command: &mut Command, action: &str, log_file: Option<PathBuf>) -> io::Result<Output> {
+    if !shows_status() {
+        return command.output();
+    }
+
+    let action = action.to_string();
+    let started = Instant::now();
+    output_with_status(command, move || {
+        let elapsed = format!("{}: running for {}", action, format_duration(started.elapsed()));
+        match log_file.as_deref().and_then(current_cell) {
+            Some(cell) => format!("{} (cell {})", elapsed, cell),
+            None => elapsed,
+        }
+    })
+}
+
+/// Whether status lines are shown while external tools run
+fn shows_status() -> bool {
+    !headless::is_enabled() && (io::stdout().is_terminal() || style::is_plain())
+}
+
+/// Runs the command while `status_line` is redrawn every `PROGRESS_INTERVAL`,
+/// or printed on its own line every `PLAIN_STATUS_INTERVAL` with --plain
+fn output_with_status(command: &mut Command, mut status_line: impl FnMut() -> String + Send + 'static) -> io::Result<Output> {
+    let done = Arc::new(AtomicBool::new(false));
+    let reporter = if style::is_plain() {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let mut waited = Duration::ZERO;
+            while !done.load(Ordering::Relaxed) {
+                thread::sleep(PROGRESS_INTERVAL);
+                waited += PROGRESS_INTERVAL;
+                if waited >= PLAIN_STATUS_INTERVAL {
+                    println!("{}", status_line());
+                    waited = Duration::ZERO;
+                }
+            }
+        })
+    } else {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let mut last_len = 0;
+            loop {
+                let finished = done.load(Ordering::Relaxed);
+                let line = status_line();
+                print!("\r{:<width$}", line, width = last_len);
+                let _ = io::stdout().flush();
+                last_len = line.len();
+
+                if finished {
+                    println!();
+                    break;
+                }
+                thread::sleep(PROGRESS_INTERVAL);
+            }
+        })
+    };
+
+    let result = command.output();
+    done.store(true, Ordering::Relaxed);
+    let _ = reporter.join();
+    result
+}
+
+/// Returns the cell named on the last line of the CK log that mentions one
+fn current_cell(log_file: &Path) -> Option<String> {
+    let mut file = File::open(log_file).ok()?;
+    let length = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(LOG_TAIL_BYTES))).ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+
+    String::from_utf8_lossy(&tail).lines().rev().find_map(|line| {
+        let start = line.to_ascii_lowercase().find("cell")? + "cell".len();
+        let cell: String = line[start..]
+            .trim_start_matches(|c: char| c.is_whitespace() || c == ':' || c == '\'' || c == '"')
+            .chars()
+            .take(MAX_CELL_NAME)
+            .collect();
+        let cell = cell.trim_end_matches(|c: char| c.is_whitespace() || c == '\'' || c == '"' || c == '.');
+        (!cell.is_empty()).then(|| cell.to_string())
+    })
+}
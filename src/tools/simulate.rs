@@ -0,0 +1,173 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use log::info;
+use crate::utils::ensure_directory_exists;
+
+/// Set by --simulate
+static SIMULATE: AtomicBool = AtomicBool::new(false);
+
+/// Tool runs set up to fail, by tool path and action or script
+static FAILURES: Mutex<Vec<(PathBuf, String, SimulatedFailure)>> = Mutex::new(Vec::new());
+
+/// How a simulated tool run goes wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulatedFailure {
+    /// The tool exits without writing any output or log
+    NoOutput,
+    /// The tool writes its outputs but leaves this log instead of a clean one
+    Log(String),
+}
+
+/// Placeholder written in place of every binary file the tools would create
+const PLACEHOLDER: &[u8] = b"GeneratePrevisbines simulated output\n";
+
+/// Makes the tool runners write placeholder outputs instead of launching
+/// the Creation Kit, xEdit, Archive2 or BSArch (--simulate)
+pub fn enable_simulation() {
+    SIMULATE.store(true, Ordering::Relaxed);
+}
+
+/// Whether --simulate is on
+pub fn is_simulating() -> bool {
+    SIMULATE.load(Ordering::Relaxed)
+}
+
+/// Makes every later simulated run of `action` (a CK action or xEdit script)
+/// by the tool at `tool_path` fail as described, to exercise error handling.
+///
+/// # Arguments
+/// * `tool_path` - Path of the CreationKit.exe or FO4Edit.exe being simulated
+/// * `action` - The CK action (e.g. `GeneratePrecombined`) or xEdit script name
+/// * `failure` - How the run goes wrong
+pub fn simulate_failure(tool_path: &Path, action: &str, failure: SimulatedFailure) {
+    let mut failures = FAILURES.lock().unwrap_or_else(|e| e.into_inner());
+    failures.retain(|(path, name, _)| !(path == tool_path && name == action));
+    failures.push((tool_path.to_path_buf(), action.to_string(), failure));
+}
+
+/// The failure set up for a run of `action` by the tool at `tool_path`, if any
+fn failure_for(tool_path: &Path, action: &str) -> Option<SimulatedFailure> {
+    let failures = FAILURES.lock().unwrap_or_else(|e| e.into_inner());
+    failures
+        .iter()
+        .find(|(path, name, _)| path == tool_path && name == action)
+        .map(|(_, _, failure)| failure.clone())
+}
+
+/// Writes what a Creation Kit action would leave in Data, plus a CK log.
+///
+/// # Arguments
+/// * `creation_kit_path` - Path to the Creation Kit executable being simulated
+/// * `fallout4_path` - Path to the Fallout 4 installation directory
+/// * `plugin_name_ext` - The plugin file name with extension
+/// * `action` - The Creation Kit action being simulated
+/// * `output_file` - The file in Data the action is expected to create
+/// * `args` - The action's additional arguments (`clean` also writes the PSG)
+/// * `log_file` - Where the CK would write its log, if it's redirected
+///
+/// # Returns
+/// * `Ok(())` if the placeholders were written
+/// * `Err(String)` if a file can't be written
+pub fn simulate_creation_kit(
+    creation_kit_path: &Path,
+    fallout4_path: &Path,
+    plugin_name_ext: &str,
+    action: &str,
+    output_file: &str,
+    args: &str,
+    log_file: Option<&Path>,
+) -> Result<(), String> {
+    info!("Simulating CK option {}", action);
+
+    let failure = failure_for(creation_kit_path, action);
+    if failure == Some(SimulatedFailure::NoOutput) {
+        return Ok(());
+    }
+
+    let data_dir = fallout4_path.join("Data");
+    let plugin_name = Path::new(plugin_name_ext)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match action {
+        "GeneratePrecombined" => {
+            copy_plugin(&data_dir, plugin_name_ext, output_file)?;
+            write_placeholder(&data_dir.join("meshes").join("precombined").join("0000F4A3_5C1D2B7E_OC.nif"))?;
+            if args.split_whitespace().any(|arg| arg == "clean") {
+                write_placeholder(&data_dir.join(format!("{} - Geometry.psg", plugin_name)))?;
+            }
+        }
+        "GeneratePreVisData" => {
+            copy_plugin(&data_dir, plugin_name_ext, output_file)?;
+            write_placeholder(&data_dir.join("vis").join("0000003C.uvd"))?;
+        }
+        _ => write_placeholder(&data_dir.join(output_file))?,
+    }
+
+    if let Some(log_file) = log_file {
+        let log = match failure {
+            Some(SimulatedFailure::Log(log)) => log,
+            _ => format!(
+                "Simulated {} of {}\nDEFAULT: Wrote {}\nDEFAULT: Done\n",
+                action, plugin_name_ext, output_file
+            ),
+        };
+        fs::write(log_file, log)
+            .map_err(|e| format!("Error writing CK log file {}: {}", log_file.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the log an xEdit script leaves, a clean one unless a failure was set up.
+///
+/// # Arguments
+/// * `fo4edit_path` - Path to the FO4Edit executable being simulated
+/// * `script` - The name of the xEdit script being simulated
+/// * `plugin1` - The plugin the script would update
+/// * `plugin2` - The plugin it would merge from
+/// * `unattended_logfile` - Path to the unattended script log file
+///
+/// # Returns
+/// * `Ok(())` if the log was written
+/// * `Err(String)` if it can't be written
+pub fn simulate_xedit_script(
+    fo4edit_path: &Path,
+    script: &str,
+    plugin1: &str,
+    plugin2: &str,
+    unattended_logfile: &Path,
+) -> Result<(), String> {
+    info!("Simulating xEdit script {} against {}", script, plugin1);
+
+    let log = match failure_for(fo4edit_path, script) {
+        Some(SimulatedFailure::NoOutput) => return Ok(()),
+        Some(SimulatedFailure::Log(log)) => log,
+        None => format!(
+            "Simulated {} merging {} into {}\nCompleted: No Errors.\n",
+            script, plugin2, plugin1
+        ),
+    };
+    if let Some(parent) = unattended_logfile.parent() {
+        ensure_directory_exists(parent)?;
+    }
+    fs::write(unattended_logfile, log)
+        .map_err(|e| format!("Error writing unattended log file: {}", e))
+}
+
+/// Copies the plugin to `output_file`, standing in for the plugin the CK saves
+fn copy_plugin(data_dir: &Path, plugin_name_ext: &str, output_file: &str) -> Result<(), String> {
+    fs::copy(data_dir.join(plugin_name_ext), data_dir.join(output_file))
+        .map(|_| ())
+        .map_err(|e| format!("Error creating simulated {}: {}", output_file, e))
+}
+
+fn write_placeholder(path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        ensure_directory_exists(parent)?;
+    }
+    fs::write(path, PLACEHOLDER)
+        .map_err(|e| format!("Error writing simulated {}: {}", path.display(), e))
+}
@@ -5,6 +5,10 @@ use std::process::Command;
 use std::thread::sleep;
 use std::time::Duration;
 use log::info;
+use crate::logging;
+use crate::tools::command_line::prepare_command;
+use crate::tools::simulate::{is_simulating, simulate_xedit_script};
+use crate::utils::work_dir;
 
 /// Runs an xEdit script against two specified plugin files and logs the results.
 ///
@@ -18,7 +22,7 @@ use log::info;
 ///   in the `Edit Scripts` directory under the xEdit path.
 /// - `plugin1`: The name of the primary plugin file for the script to process.
 /// - `plugin2`: The name of the secondary plugin file, if applicable, for the script.
-/// - `logfile`: Path to the main log file
+/// - `masters`: Plugins loaded ahead of the two above (e.g. PRP)
 /// - `unattended_logfile`: Path to the unattended script log file
 ///
 /// # Returns
@@ -30,31 +34,22 @@ pub fn run_xedit_script(
     script: &str,
     plugin1: &str,
     plugin2: &str,
-    logfile: &PathBuf,
+    masters: &[String],
     unattended_logfile: &PathBuf,
 ) -> Result<(), String> {
     info!("Running xEdit script {} against {}", script, plugin1);
 
-    let mut log_file = File::options()
-        .append(true)
-        .create(true)
-        .open(logfile)
-        .map_err(|e| format!("Error opening log file {}: {}", logfile.display(), e))?;
-
-    writeln!(
-        log_file,
-        "Running xEdit script {} against {}",
-        script, plugin1
-    )
-    .map_err(|e| format!("Error writing to log file: {}", e))?;
-    writeln!(log_file, "====================================")
-        .map_err(|e| format!("Error writing to log file: {}", e))?;
+    logging::append("====================================");
 
     // Create plugins list
-    let plugins_file = std::env::temp_dir().join("Plugins.txt");
+    let plugins_file = work_dir().join("Plugins.txt");
     let mut file = File::create(&plugins_file)
         .map_err(|e| format!("Error creating plugins file: {}", e))?;
 
+    for master in masters {
+        writeln!(file, "*{}", master)
+            .map_err(|e| format!("Error writing to plugins file: {}", e))?;
+    }
     writeln!(file, "*{}", plugin1)
         .map_err(|e| format!("Error writing to plugins file: {}", e))?;
     writeln!(file, "*{}", plugin2)
@@ -73,15 +68,21 @@ pub fn run_xedit_script(
         script
     );
 
-    let mut xedit_process = Command::new(fo4edit_path)
-        .args(&[
-            "-fo4",
-            "-autoexit",
-            format!("-P:{}", plugins_file.display()).as_str(),
-            format!("-Script:{}", script).as_str(),
-            format!("-Mod:{}", plugin1).as_str(),
-            format!("-log:{}", unattended_logfile.display()).as_str(),
-        ])
+    let mut command = Command::new(fo4edit_path);
+    command.args([
+        "-fo4",
+        "-autoexit",
+        format!("-P:{}", plugins_file.display()).as_str(),
+        format!("-Script:{}", script).as_str(),
+        format!("-Mod:{}", plugin1).as_str(),
+        format!("-log:{}", unattended_logfile.display()).as_str(),
+    ]);
+    prepare_command(&mut command);
+    if is_simulating() {
+        simulate_xedit_script(fo4edit_path, script, plugin1, plugin2, unattended_logfile)?;
+        return check_xedit_log(script, unattended_logfile);
+    }
+    let mut xedit_process = command
         .spawn()
         .map_err(|e| format!("Error starting xEdit: {}", e))?;
 
@@ -107,13 +108,16 @@ pub fn run_xedit_script(
     // Wait for MO2 to process files
     sleep(Duration::from_secs(5));
 
-    // Append xEdit log to our log
+    check_xedit_log(script, unattended_logfile)
+}
+
+/// Appends the script's log to ours and checks it completed
+fn check_xedit_log(script: &str, unattended_logfile: &PathBuf) -> Result<(), String> {
     if unattended_logfile.exists() {
         let xedit_log = fs::read_to_string(unattended_logfile)
             .map_err(|e| format!("Error reading xEdit log file: {}", e))?;
 
-        writeln!(log_file, "{}", xedit_log)
-            .map_err(|e| format!("Error writing to log file: {}", e))?;
+        logging::append(&xedit_log);
 
         // Check for completion message
         if !xedit_log.contains("Completed: ") {
@@ -1,8 +1,104 @@
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use log::info;
+use crate::answers::{answer_for, assume_yes, prompt_timeout, timeout_answer, Prompt};
 use crate::cli::{BuildMode, BuildStage};
+use crate::esp::resolve_plugin_name;
+use crate::frontend;
+use crate::headless;
+use crate::notify::notify;
+use crate::style;
+use crate::stages::{available_in, check_requirements, unavailable_message, StageContext, ALL_STAGES};
+use crate::utils::format_duration;
+use crate::validation::ModeRecommendation;
+
+/// Lines typed by the user, read on a background thread so prompts can time out
+static INPUT: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+
+/// Returns the receiver of the lines typed by the user, starting the reader
+/// thread on first use
+fn input() -> &'static Mutex<Receiver<String>> {
+    INPUT.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    })
+}
+
+/// Reads the user's answer to a prompt.
+///
+/// Fails when running non-interactively. In frontend mode the prompt is announced as an event first, and the
+/// answer is taken from the frontend's reply.
+///
+/// # Arguments
+/// * `prompt` - Which question this is, if it has an identifier
+/// * `message` - The question, for frontends
+fn read_answer(prompt: Option<Prompt>, message: &str) -> Result<String, String> {
+    if headless::is_non_interactive() {
+        return Err(headless::input_required(prompt, message));
+    }
+    if !frontend::is_enabled() {
+        return read_input(prompt);
+    }
+    frontend::emit_prompt(prompt, message);
+    read_input(prompt).map(|line| frontend::parse_answer(&line))
+}
+
+/// Reads a line typed by the user.
+///
+/// With --prompt-timeout, prompts with an identifier stop waiting after the
+/// timeout and take their default answer.
+///
+/// # Returns
+/// * `Ok(String)` with the line entered (empty once input is closed) or the default answer
+/// * `Err(String)` if the prompt timed out without a default answer, or input
+///   was closed while waiting for a prompt without an identifier
+fn read_input(prompt: Option<Prompt>) -> Result<String, String> {
+    let receiver = input().lock().map_err(|_| "Error reading input: reader failed".to_string())?;
+    let Some(prompt) = prompt else {
+        return receiver.recv().map_err(|_| "Error reading input: input closed".to_string());
+    };
+    let Some(timeout) = prompt_timeout() else {
+        return Ok(receiver.recv().unwrap_or_default());
+    };
+
+    match receiver.recv_timeout(timeout) {
+        Ok(line) => Ok(line),
+        Err(RecvTimeoutError::Disconnected) => Ok(String::new()),
+        Err(RecvTimeoutError::Timeout) => match timeout_answer(prompt) {
+            Some(answer) => {
+                println!("\nNo answer within {}, using '{}'", format_duration(timeout), answer);
+                info!("Prompt {} timed out after {}, answered '{}'", prompt.id(), format_duration(timeout), answer);
+                Ok(answer.to_string())
+            }
+            None => Err(format!(
+                "ERROR - No answer to {} within {}. Set one with --timeout-answer {}=...",
+                prompt.id(),
+                format_duration(timeout),
+                prompt.id()
+            )),
+        },
+    }
+}
 
 /// Prompts the user to input a plugin name if none is specified.
 ///
+/// # Arguments
+/// * `data_dir` - The Data folder, to find the extension of a name entered without one
+/// * `forced` - Extension the plugin must have (--plugin-extension)
+///
 /// # Returns
 /// A tuple containing:
 /// - The plugin name without extension
@@ -13,45 +109,80 @@ use crate::cli::{BuildMode, BuildStage};
 /// Returns an error if:
 /// - There's an error reading input
 /// - No plugin name is entered
-pub fn prompt_for_plugin_name() -> Result<(String, String, String), String> {
-    println!("No plugin specified. Please enter a plugin name:");
-    print!("Enter plugin name: ");
-    io::stdout().flush().unwrap();
-
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .map_err(|e| format!("Error reading input: {}", e))?;
+pub fn prompt_for_plugin_name(data_dir: &Path, forced: Option<&str>) -> Result<(String, String, String), String> {
+    let plugin_name = match answer_for(Prompt::PluginName) {
+        Some(answer) => answer.trim().to_string(),
+        None => {
+            println!("No plugin specified. Please enter a plugin name:");
+            print!("Enter plugin name: ");
+            io::stdout().flush().unwrap();
+            notify("Input needed", "The previsbine builder is waiting for an answer");
 
-    let plugin_name = input.trim().to_string();
+            read_answer(Some(Prompt::PluginName), "Enter plugin name")?.trim().to_string()
+        }
+    };
     if plugin_name.is_empty() {
         return Err("No plugin name entered".to_string());
     }
 
     // Extract plugin name and extension
-    let (plugin_name_no_ext, plugin_name_ext) = if plugin_name.to_lowercase().ends_with(".esp")
-        || plugin_name.to_lowercase().ends_with(".esm")
-        || plugin_name.to_lowercase().ends_with(".esl")
-    {
-        let name_without_ext = plugin_name
-            .rfind('.')
-            .map(|i| &plugin_name[0..i])
-            .unwrap_or(&plugin_name)
-            .to_string();
-        (name_without_ext, plugin_name)
-    } else {
-        (plugin_name.clone(), format!("{}.esp", plugin_name))
-    };
+    let (plugin_name_no_ext, plugin_name_ext) = resolve_plugin_name(&plugin_name, data_dir, forced)?;
 
     let plugin_archive = format!("{} - Main.ba2", &plugin_name_no_ext);
 
     Ok((plugin_name_no_ext, plugin_name_ext, plugin_archive))
 }
 
+/// Prompts the user for a line of input.
+///
+/// # Returns
+/// The input without surrounding whitespace (empty if nothing was entered)
+///
+/// # Errors
+/// Returns an error if there's an error reading input
+pub fn prompt_for_line(prompt: &str) -> Result<String, String> {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    notify("Input needed", "The previsbine builder is waiting for an answer");
+
+    Ok(read_answer(None, prompt)?.trim().to_string())
+}
+
+/// Prompts the user for the masters of a new plugin.
+///
+/// # Returns
+/// The plugin names entered, in the order given (an empty list if none were entered)
+///
+/// # Errors
+/// Returns an error if there's an error reading input
+pub fn prompt_for_masters() -> Result<Vec<String>, String> {
+    let input = match answer_for(Prompt::Masters) {
+        Some(answer) => answer.to_string(),
+        None => {
+            println!("Enter the masters of the new plugin in load order, separated by commas");
+            print!("Masters (Fallout4.esm is always included): ");
+            io::stdout().flush().unwrap();
+            notify("Input needed", "The previsbine builder is waiting for an answer");
+
+            read_answer(Some(Prompt::Masters), "Masters of the new plugin in load order, separated by commas")?
+        }
+    };
+
+    Ok(input
+        .split(',')
+        .map(str::trim)
+        .filter(|master| !master.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
 /// Prompts the user to choose a build stage to start from.
 ///
+/// Only the stages available in the build mode are listed, each marked with
+/// whether what it needs from earlier stages is already in Data.
+///
 /// # Parameters
-/// - `build_mode`: The current build mode, which determines which stages are available
+/// - `context`: The plugin and build mode, which determine which stages are available
 ///
 /// # Returns
 /// The selected build stage
@@ -59,64 +190,270 @@ pub fn prompt_for_plugin_name() -> Result<(String, String, String), String> {
 /// # Errors
 /// Returns an error if:
 /// - There's an error reading input
-/// - The user enters an invalid stage number
-pub fn prompt_for_stage(build_mode: &BuildMode) -> Result<BuildStage, String> {
-    println!("Plugin already exists. Choose a stage to start from:");
-
-    // Print all stages except VerifyEnvironment (0)
-    for stage in 1..=8 {
-        if let Some(build_stage) = BuildStage::from_i32(stage) {
-            // Skip stages that aren't applicable for the current build mode
-            if (build_mode != &BuildMode::Clean)
-                && (build_stage == BuildStage::CompressPsg
-                    || build_stage == BuildStage::BuildCdx)
-            {
-                continue;
+/// - The user enters a stage number that doesn't exist or isn't available in the build mode
+pub fn prompt_for_stage(context: &StageContext) -> Result<BuildStage, String> {
+    let stages: Vec<BuildStage> = ALL_STAGES
+        .iter()
+        .copied()
+        .filter(|stage| available_in(*stage, context.build_mode))
+        .collect();
+
+    let input = match answer_for(Prompt::StartStage) {
+        Some(answer) => answer.to_string(),
+        None => {
+            println!("Plugin already exists. Choose a stage to start from:");
+            for stage in &stages {
+                let status = match check_requirements(*stage, context) {
+                    Ok(()) => style::check_mark(true).to_string(),
+                    Err(e) => format!("{} {}", style::check_mark(false), e.trim_start_matches("ERROR - ")),
+                };
+                println!("[{}] {:<22} {}", *stage as i32, stage.description(), status);
             }
-            println!("[{}] {}", stage, build_stage.description());
-        }
-    }
 
-    print!("Enter stage number (1-8): ");
-    io::stdout().flush().unwrap();
+            let numbers: Vec<String> = stages.iter().map(|stage| (*stage as i32).to_string()).collect();
+            print!("Enter stage number ({}): ", numbers.join(", "));
+            io::stdout().flush().unwrap();
+            notify("Input needed", "The previsbine builder is waiting for an answer");
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .map_err(|e| format!("Error reading input: {}", e))?;
+            read_answer(Some(Prompt::StartStage), "Stage to start from")?
+        }
+    };
 
     let stage_num = input
         .trim()
         .parse::<i32>()
-        .map_err(|_| "Invalid stage number".to_string())?;
+        .map_err(|_| format!("Invalid stage number: {}", input.trim()))?;
+    let stage = BuildStage::from_i32(stage_num)
+        .ok_or_else(|| format!("Invalid stage number: {}", stage_num))?;
+    if !available_in(stage, context.build_mode) {
+        return Err(unavailable_message(stage, context.build_mode));
+    }
+    Ok(stage)
+}
 
-    BuildStage::from_i32(stage_num)
-        .ok_or_else(|| format!("Invalid stage number: {}", stage_num))
+/// What to do after a stage failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureAction {
+    Retry,
+    OpenLogs,
+    Skip,
+    Abort,
+}
+
+/// Asks what to do after a stage failed.
+///
+/// Only skip and abort can be answered in the answers file, as the same
+/// answer to retry or open the logs would be given again every time.
+///
+/// # Arguments
+/// * `stage` - The stage that failed
+/// * `can_skip` - Whether no later stage needs the stage's results
+///
+/// # Returns
+/// * `Ok(FailureAction)` with the action chosen
+/// * `Err(String)` if there was an error reading input or the answer isn't an action
+pub fn prompt_after_failure(stage: BuildStage, can_skip: bool) -> Result<FailureAction, String> {
+    let input = match answer_for(Prompt::StageFailed) {
+        Some(answer) => {
+            let answer = answer.trim().to_lowercase();
+            if answer != "skip" && answer != "abort" {
+                return Err(format!("ERROR - {} can only be answered with skip or abort in the answers file", Prompt::StageFailed.id()));
+            }
+            answer
+        }
+        None => {
+            println!("What now?");
+            println!("[R] Retry {}", stage.description());
+            println!("[L] Open the logs");
+            if can_skip {
+                println!("[S] Skip {} (nothing later in the build needs it)", stage.description());
+            }
+            println!("[A] Abort and clean up the working files");
+            print!("Choice: ");
+            io::stdout().flush().unwrap();
+            notify("Build needs attention", &format!("{} failed", stage.description()));
+
+            read_answer(Some(Prompt::StageFailed), &format!("{} failed. Retry, open logs, skip or abort?", stage.description()))?
+                .trim()
+                .to_lowercase()
+        }
+    };
+
+    match input.as_str() {
+        "r" | "retry" => Ok(FailureAction::Retry),
+        "l" | "logs" | "open-logs" => Ok(FailureAction::OpenLogs),
+        "s" | "skip" if can_skip => Ok(FailureAction::Skip),
+        "s" | "skip" => Err(format!("ERROR - {} can't be skipped, later stages need its results", stage.description())),
+        "a" | "abort" | "" => Ok(FailureAction::Abort),
+        other => Err(format!("Invalid choice: {}", other)),
+    }
+}
+
+/// Asks which seed plugin a missing plugin should be made from, by number
+/// or name.
+///
+/// # Arguments
+/// * `plugin_name_ext` - The plugin to create
+/// * `seeds` - Seed plugins found in Data
+///
+/// # Returns
+/// * `Ok(Some(String))` with the seed chosen
+/// * `Ok(None)` if the user chose to abort
+/// * `Err(String)` if there was an error reading input or the answer isn't one of the seeds
+pub fn prompt_for_seed(plugin_name_ext: &str, seeds: &[String]) -> Result<Option<String>, String> {
+    let input = match answer_for(Prompt::SeedPlugin) {
+        Some(answer) => answer.trim().to_string(),
+        None => {
+            println!("{} does not exist. Which seed plugin should it be made from?", plugin_name_ext);
+            for (index, seed) in seeds.iter().enumerate() {
+                println!("[{}] {}", index + 1, seed);
+            }
+            println!("[A] Abort");
+            print!("Choice: ");
+            io::stdout().flush().unwrap();
+            notify("Input needed", "The previsbine builder is waiting for an answer");
+
+            read_answer(Some(Prompt::SeedPlugin), &format!("{} does not exist. Seed plugin to make it from?", plugin_name_ext))?
+                .trim()
+                .to_string()
+        }
+    };
+
+    if input.is_empty() || input.eq_ignore_ascii_case("a") || input.eq_ignore_ascii_case("abort") {
+        return Ok(None);
+    }
+    let chosen = match input.parse::<usize>() {
+        Ok(number) => number.checked_sub(1).and_then(|index| seeds.get(index)),
+        Err(_) => seeds.iter().find(|seed| seed.eq_ignore_ascii_case(&input)),
+    };
+    chosen
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| format!("Invalid choice: {}", input))
+}
+
+/// Asks whether to build a light plugin that adds new cells, showing how
+/// to convert it when asked. Unattended builds go ahead, as the warning is
+/// already in the log.
+///
+/// # Arguments
+/// * `guidance` - How to convert the plugin
+///
+/// # Returns
+/// * `Ok(true)` to build anyway
+/// * `Ok(false)` to abort
+/// * `Err(String)` if there was an error reading input or the answer isn't proceed or abort
+pub fn prompt_light_plugin_cells(guidance: &str) -> Result<bool, String> {
+    if let Some(answer) = answer_for(Prompt::LightPluginCells) {
+        return match answer.trim().to_lowercase().as_str() {
+            "proceed" => Ok(true),
+            "abort" => Ok(false),
+            _ => Err(format!("ERROR - {} can only be answered with proceed or abort", Prompt::LightPluginCells.id())),
+        };
+    }
+    if assume_yes() || headless::is_non_interactive() {
+        info!("Building the light plugin anyway, as nobody is there to answer");
+        return Ok(true);
+    }
+
+    loop {
+        println!("[P] Proceed anyway");
+        println!("[G] Show how to convert the plugin");
+        println!("[A] Abort");
+        print!("Choice: ");
+        io::stdout().flush().unwrap();
+        notify("Input needed", "The previsbine builder is waiting for an answer");
+
+        let input = read_answer(Some(Prompt::LightPluginCells), "Light plugin adds new cells. Proceed, show guidance or abort?")?;
+        match input.trim().to_lowercase().as_str() {
+            "p" | "proceed" => return Ok(true),
+            "g" | "guidance" => println!("\n{}\n", guidance),
+            "a" | "abort" | "" => return Ok(false),
+            other => println!("Invalid choice: {}", other),
+        }
+    }
+}
+
+/// Prints what each build mode is for
+pub fn print_build_modes() {
+    println!("[1] Clean    - Full precombines and previs, plus compressed PSG and CDX files.");
+    println!("               For plugins adding new content, and for masters others build on.");
+    println!("[2] Filtered - Precombines and previs only for the cells the plugin changes.");
+    println!("               For patches fixing previs broken by other mods.");
+    println!("[3] Xbox     - Like clean, with Xbox-compressed archives.");
+    println!("[4] Previs-only - Only previs, reusing the precombines of an earlier build.");
+    println!("               For when occlusion changed but the geometry didn't.");
+}
+
+/// Asks which build mode to use, explaining the modes and why another than
+/// the default suits the plugin.
+///
+/// # Arguments
+/// * `plugin_name_ext` - The plugin file name with extension
+/// * `recommendation` - The mode suited to the plugin, taken when nothing is entered
+///
+/// # Returns
+/// * `Ok(BuildMode)` with the mode chosen
+/// * `Err(String)` if there was an error reading input or the answer isn't a mode
+pub fn prompt_for_build_mode(plugin_name_ext: &str, recommendation: &ModeRecommendation) -> Result<BuildMode, String> {
+    let input = match answer_for(Prompt::BuildMode) {
+        Some(answer) => answer.to_string(),
+        None => {
+            println!(
+                "No --mode given, but {} mode looks better suited to {}: {}",
+                recommendation.mode,
+                plugin_name_ext,
+                recommendation.reasons.join(", ")
+            );
+            print_build_modes();
+            print!("Mode (Enter for {}): ", recommendation.mode);
+            io::stdout().flush().unwrap();
+            notify("Input needed", "The previsbine builder is waiting for an answer");
+
+            read_answer(Some(Prompt::BuildMode), "Build mode")?
+        }
+    };
+
+    match input.trim().to_lowercase().as_str() {
+        "" => Ok(recommendation.mode),
+        "1" | "clean" => Ok(BuildMode::Clean),
+        "2" | "filtered" => Ok(BuildMode::Filtered),
+        "3" | "xbox" => Ok(BuildMode::Xbox),
+        "4" | "previs-only" => Ok(BuildMode::PrevisOnly),
+        other => Err(format!("Invalid build mode: {} (enter clean, filtered, xbox or previs-only)", other)),
+    }
 }
 
 /// Prompts the user with a yes/no question and returns their response.
 ///
 /// # Arguments
+/// * `prompt` - Which question this is, to look it up in the answers file
 /// * `message` - The question or message to display to the user
-/// * `no_prompt` - If true, automatically returns Ok(true) without prompting
 ///
 /// # Returns
-/// * `Ok(true)` if the user input starts with 'y' or if `no_prompt` is true
-/// * `Ok(false)` if the user input doesn't start with 'y'
-/// * `Err(String)` if there was an error reading input
-pub fn prompt_yes_no(message: &str, no_prompt: bool) -> Result<bool, String> {
-    if no_prompt {
+/// * `Ok(true)` if the answer (from the answers file, or else the user) starts
+///   with 'y' or is `true`, or with --assume-yes if there is no answer on file
+/// * `Ok(false)` if the answer doesn't start with 'y'
+/// * `Err(String)` if there was an error reading input, or input is needed
+///   when running non-interactively
+pub fn prompt_yes_no(prompt: Prompt, message: &str) -> Result<bool, String> {
+    if let Some(answer) = answer_for(prompt) {
+        let answer = answer.trim().to_lowercase();
+        let yes = answer.starts_with('y') || answer == "true";
+        println!("{} {}", message, if yes { "Y" } else { "N" });
+        return Ok(yes);
+    }
+    if assume_yes() {
+        println!("{} Y", message);
         return Ok(true);
     }
 
     println!("{}", message);
     print!("[Y/N]? ");
     io::stdout().flush().unwrap();
+    notify("Input needed", "The previsbine builder is waiting for an answer");
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .map_err(|e| format!("Error reading input: {}", e))?;
+    let input = read_answer(Some(prompt), message)?;
 
     Ok(input.trim().to_lowercase().starts_with('y'))
 }
@@ -0,0 +1,284 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{debug, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::answers::Prompt;
+use crate::cli::SelfUpdateArgs;
+use crate::config::{Config, CONFIG_FILE};
+use crate::ui::prompt_yes_no;
+use crate::validation::parse_version;
+
+/// Latest release of the project on GitHub
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/evildarkarchon/GeneratePrevisibines-rs/releases/latest";
+
+/// Checksum file listing every asset of a release
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+/// Largest download accepted, well above the size of the executable
+const MAX_DOWNLOAD_BYTES: u64 = 100 * 1024 * 1024;
+
+/// How long the startup check waits for GitHub before giving up
+const STARTUP_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the downloads of self-update may take
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A published release
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    /// Git tag of the release, e.g. `v2.7.0`
+    pub tag_name: String,
+    /// Release notes (markdown)
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// A file attached to a release
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// Version of the release without the leading `v`
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches(['v', 'V'])
+    }
+
+    /// Whether the release is newer than the running version
+    pub fn is_newer(&self) -> bool {
+        parse_version(self.version()) > parse_version(env!("CARGO_PKG_VERSION"))
+    }
+
+    fn asset(&self, name: &str) -> Option<&ReleaseAsset> {
+        self.assets.iter().find(|asset| asset.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Asks GitHub for the latest release.
+///
+/// # Arguments
+/// * `timeout` - How long to wait for GitHub
+///
+/// # Returns
+/// * `Ok(Release)` with the latest release
+/// * `Err(String)` if GitHub can't be reached or the answer can't be read
+pub fn fetch_latest_release(timeout: Duration) -> Result<Release, String> {
+    let json = get(LATEST_RELEASE_URL, timeout)?
+        .into_string()
+        .map_err(|e| format!("Error reading the latest release: {}", e))?;
+    serde_json::from_str(&json).map_err(|e| format!("Error reading the latest release: {}", e))
+}
+
+/// Runs the `self-update` command: downloads the executable of the latest
+/// release for this system, verifies it against the release's checksums and
+/// replaces the running executable with it.
+///
+/// The replaced executable is kept next to the new one until the next start.
+pub fn run_self_update(update_args: &SelfUpdateArgs) -> Result<(), String> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = fetch_latest_release(DOWNLOAD_TIMEOUT)?;
+    if !release.is_newer() && !update_args.force {
+        println!("Already up to date ({})", current);
+        return Ok(());
+    }
+    if update_args.check {
+        println!("Version {} is available (running {}). Run `generate_previsbines self-update` to install it.", release.version(), current);
+        return Ok(());
+    }
+
+    let asset_name = executable_asset_name();
+    let asset = release.asset(&asset_name).ok_or_else(|| {
+        format!("ERROR - Release {} has no executable for this system ({})", release.tag_name, asset_name)
+    })?;
+    let expected = expected_checksum(&release, &asset_name)?;
+
+    if !prompt_yes_no(Prompt::SelfUpdate, &format!("Update from {} to {}?", current, release.version()))? {
+        return Err("Update cancelled by user".to_string());
+    }
+
+    println!("Downloading {}...", asset.name);
+    let executable = download(&asset.browser_download_url)?;
+    let actual = format!("{:x}", Sha256::digest(&executable));
+    if actual != expected {
+        return Err(format!(
+            "ERROR - Checksum of the downloaded {} doesn't match the release (expected {}, got {}). Nothing was changed",
+            asset.name, expected, actual
+        ));
+    }
+
+    let exe = env::current_exe().map_err(|e| format!("Error finding the running executable: {}", e))?;
+    replace_executable(&exe, &executable)?;
+    println!("Updated to {}. Run the tool again to use it.", release.version());
+    Ok(())
+}
+
+/// Prints a notice when a newer release exists (--check-updates or
+/// `check-updates = true`), followed by the breaking changes in its notes
+/// that concern the settings file. Says nothing if GitHub can't be reached.
+///
+/// # Arguments
+/// * `config` - The loaded settings, whose keys pick the relevant changes
+pub fn print_update_notice(config: Option<&Config>) {
+    let release = match fetch_latest_release(STARTUP_CHECK_TIMEOUT) {
+        Ok(release) => release,
+        Err(e) => {
+            debug!("Update check failed: {}", e);
+            return;
+        }
+    };
+    if !release.is_newer() {
+        return;
+    }
+
+    println!(
+        "Version {} is available (running {}), run `generate_previsbines self-update` to install it",
+        release.version(),
+        env!("CARGO_PKG_VERSION")
+    );
+    let keys = config.map(Config::set_keys).unwrap_or_default();
+    for change in breaking_changes(release.body.as_deref().unwrap_or_default())
+        .into_iter()
+        .filter(|change| concerns_config(change, &keys))
+    {
+        println!("  Breaking: {}", change);
+    }
+}
+
+/// Lines of release notes describing breaking changes: the items under a
+/// heading mentioning "breaking", and any line marked BREAKING
+fn breaking_changes(notes: &str) -> Vec<String> {
+    let mut changes = Vec::new();
+    let mut in_section = false;
+    for line in notes.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('#') {
+            in_section = trimmed.to_lowercase().contains("breaking");
+            continue;
+        }
+        let item = trimmed.trim_start_matches(['-', '*', '+']).trim();
+        if !item.is_empty() && (in_section || item.contains("BREAKING")) {
+            changes.push(item.to_string());
+        }
+    }
+    changes
+}
+
+/// Whether a change mentions the settings file or one of the settings it sets
+fn concerns_config(change: &str, keys: &[&str]) -> bool {
+    let change = change.to_lowercase();
+    change.contains(CONFIG_FILE)
+        || change.contains("settings file")
+        || change.contains("config")
+        || keys.iter().any(|key| change.contains(key))
+}
+
+/// Removes the executable a previous self-update replaced, which Windows
+/// doesn't allow while it is still running
+pub fn remove_replaced_executable() {
+    if let Ok(exe) = env::current_exe() {
+        let replaced = replaced_path(&exe);
+        if replaced.exists() {
+            if let Err(e) = fs::remove_file(&replaced) {
+                warn!("WARNING - Couldn't remove {} left by the last update: {}", replaced.display(), e);
+            }
+        }
+    }
+}
+
+/// Name of the release asset holding the executable for this system,
+/// e.g. `generate_previsbines-windows-x86_64.exe`
+fn executable_asset_name() -> String {
+    format!(
+        "generate_previsbines-{}-{}{}",
+        env::consts::OS,
+        env::consts::ARCH,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+/// Reads the SHA-256 of `asset_name` from the release's `<asset>.sha256`
+/// file or its SHA256SUMS list
+fn expected_checksum(release: &Release, asset_name: &str) -> Result<String, String> {
+    if let Some(checksum) = release.asset(&format!("{}.sha256", asset_name)) {
+        let text = download_text(&checksum.browser_download_url)?;
+        if let Some(hash) = text.split_whitespace().next() {
+            return Ok(hash.to_lowercase());
+        }
+    }
+
+    if let Some(checksums) = release.asset(CHECKSUMS_ASSET) {
+        let text = download_text(&checksums.browser_download_url)?;
+        let hash = text.lines().find_map(|line| {
+            let mut parts = line.split_whitespace();
+            let hash = parts.next()?;
+            let name = parts.next()?.trim_start_matches('*');
+            name.eq_ignore_ascii_case(asset_name).then(|| hash.to_lowercase())
+        });
+        if let Some(hash) = hash {
+            return Ok(hash);
+        }
+    }
+
+    Err(format!(
+        "ERROR - Release {} has no checksum for {}, refusing to install it",
+        release.tag_name, asset_name
+    ))
+}
+
+/// Swaps the running executable for `contents`, keeping the old one as
+/// `<name>.old` (a running executable can be renamed but not overwritten)
+fn replace_executable(exe: &Path, contents: &[u8]) -> Result<(), String> {
+    let replaced = replaced_path(exe);
+    if replaced.exists() {
+        fs::remove_file(&replaced).map_err(|e| format!("Error removing {}: {}", replaced.display(), e))?;
+    }
+    let permissions = fs::metadata(exe)
+        .map_err(|e| format!("Error reading {}: {}", exe.display(), e))?
+        .permissions();
+
+    fs::rename(exe, &replaced).map_err(|e| format!("Error moving {} aside: {}", exe.display(), e))?;
+    if let Err(e) = fs::write(exe, contents).and_then(|_| fs::set_permissions(exe, permissions)) {
+        let _ = fs::remove_file(exe);
+        let _ = fs::rename(&replaced, exe);
+        return Err(format!("Error writing {}: {}. The previous version was restored", exe.display(), e));
+    }
+    Ok(())
+}
+
+fn replaced_path(exe: &Path) -> PathBuf {
+    let name = exe.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    exe.with_file_name(format!("{}.old", name))
+}
+
+fn get(url: &str, timeout: Duration) -> Result<ureq::Response, String> {
+    ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .build()
+        .get(url)
+        .set("User-Agent", concat!("generate_previsbines/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .map_err(|e| format!("Error contacting GitHub: {}", e))
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let mut contents = Vec::new();
+    get(url, DOWNLOAD_TIMEOUT)?
+        .into_reader()
+        .take(MAX_DOWNLOAD_BYTES)
+        .read_to_end(&mut contents)
+        .map_err(|e| format!("Error downloading {}: {}", url, e))?;
+    Ok(contents)
+}
+
+fn download_text(url: &str) -> Result<String, String> {
+    String::from_utf8(download(url)?).map_err(|e| format!("Error reading {}: {}", url, e))
+}
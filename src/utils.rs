@@ -1,21 +1,248 @@
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::debug;
+use crate::locks::describe_io_error;
 
-/// Appends a message to a log file
-pub fn append_to_log<P: AsRef<Path>>(log_path: P, message: &str) -> Result<(), String> {
-    let mut log_file = File::options()
-        .append(true)
-        .create(true)
-        .open(log_path.as_ref())
-        .map_err(|e| format!("Error opening log file {}: {}", log_path.as_ref().display(), e))?;
+/// Folder for transient files set with --work-dir
+static WORK_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
 
-    writeln!(log_file, "{}", message)
-        .map_err(|e| format!("Error writing to log file: {}", e))?;
+/// How many times a deletion is attempted before giving up
+const DELETE_ATTEMPTS: u32 = 5;
 
+/// Delay before the first deletion retry, doubled after every failed attempt
+const DELETE_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Returns the current UTC time as `YYYYMMDD-HHMMSS`, used to name
+/// per-run folders so they sort chronologically
+pub fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Puts transient files in `dir` instead of the system temp folder (--work-dir)
+///
+/// # Returns
+/// * `Ok(())` if the folder exists or was created
+/// * `Err(String)` if it can't be created
+pub fn set_work_dir(dir: &Path) -> Result<(), String> {
+    ensure_directory_exists(dir)?;
+    *WORK_DIR.lock().unwrap_or_else(|e| e.into_inner()) = Some(dir.to_path_buf());
     Ok(())
 }
 
+/// Folder for transient files: --work-dir, or the system temp folder
+pub fn work_dir() -> PathBuf {
+    WORK_DIR
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Folder holding the executable, or the current folder if its location is unknown
+pub fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+}
+
+/// Reads a `YYYYMMDD-HHMMSS` timestamp written by `timestamp`
+///
+/// # Returns
+/// * `Some(seconds)` since the Unix epoch
+/// * `None` if the text isn't a valid timestamp
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    if text.len() != 15 || text.as_bytes()[8] != b'-' || !text.is_char_boundary(8) {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hours, minutes, seconds) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+
+    // Days since 1970-01-01 from the civil date (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    u64::try_from(days * 86_400 + hours * 3600 + minutes * 60 + seconds).ok()
+}
+
+/// Formats a duration as `1h 02m 03s`, `2m 03s` or `3s`
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let (hours, minutes, seconds) = (total / 3600, (total % 3600) / 60, total % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Formats a size as `512 B`, `12.3 MB` or `1.20 GB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if size < 10.0 {
+        format!("{:.2} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Walks everything under a folder, depth first, visiting each subfolder
+/// before its contents.
+///
+/// # Arguments
+/// * `root` - The folder to walk, which isn't visited itself
+/// * `visit` - Called with the path of every file and subfolder, and whether it's a folder
+///
+/// # Returns
+/// Whether every folder could be read
+pub fn walk_dir(root: &Path, visit: &mut dyn FnMut(&Path, bool)) -> bool {
+    let Ok(entries) = fs::read_dir(root) else {
+        return false;
+    };
+    let mut complete = true;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        visit(&path, is_dir);
+        if is_dir {
+            complete &= walk_dir(&path, visit);
+        }
+    }
+    complete
+}
+
+/// Total size of a file, or of every file under a folder; 0 if it doesn't exist
+pub fn path_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    let mut size = 0;
+    walk_dir(path, &mut |file, is_dir| {
+        if !is_dir {
+            size += fs::metadata(file).map_or(0, |metadata| metadata.len());
+        }
+    });
+    size
+}
+
+/// Space available to this user on the drive holding `path`
+///
+/// # Returns
+/// * `Some(bytes)` free
+/// * `None` if it can't be determined
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: the path is NUL-terminated and stat is a plain struct that
+    // statvfs fills in
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        // The field types differ between platforms
+        #[allow(clippy::unnecessary_cast)]
+        let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+        Some(free)
+    }
+}
+
+/// Space available to this user on the drive holding `path`
+///
+/// # Returns
+/// * `Some(bytes)` free
+/// * `None` if it can't be determined
+#[cfg(windows)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: the path is NUL-terminated and the unused outputs may be null
+    let succeeded = unsafe {
+        GetDiskFreeSpaceExW(wide_path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut())
+    };
+    (succeeded != 0).then_some(available)
+}
+
+/// Space available to this user on the drive holding `path` (unknown on this system)
+#[cfg(not(any(unix, windows)))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Opens a folder in the system's file manager, without waiting for it
+pub fn open_in_file_manager(path: &Path) -> Result<(), String> {
+    let program = if cfg!(windows) {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(program)
+        .arg(path)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Error opening {}: {}", path.display(), e))
+}
+
 /// Creates a directory if it doesn't exist
 pub fn ensure_directory_exists<P: AsRef<Path>>(path: P) -> Result<(), String> {
     if !path.as_ref().exists() {
@@ -28,8 +255,7 @@ pub fn ensure_directory_exists<P: AsRef<Path>>(path: P) -> Result<(), String> {
 /// Removes a file if it exists
 pub fn remove_file_if_exists<P: AsRef<Path>>(path: P) -> Result<(), String> {
     if path.as_ref().exists() {
-        fs::remove_file(path.as_ref())
-            .map_err(|e| format!("Error removing file {}: {}", path.as_ref().display(), e))?;
+        remove_file_with_retry(path.as_ref()).map_err(|e| describe_io_error("removing file", path.as_ref(), &e))?;
     }
     Ok(())
 }
@@ -37,8 +263,151 @@ pub fn remove_file_if_exists<P: AsRef<Path>>(path: P) -> Result<(), String> {
 /// Removes a directory and all its contents if it exists
 pub fn remove_dir_all_if_exists<P: AsRef<Path>>(path: P) -> Result<(), String> {
     if path.as_ref().exists() {
-        fs::remove_dir_all(path.as_ref())
-            .map_err(|e| format!("Error removing directory {}: {}", path.as_ref().display(), e))?;
+        remove_dir_all_with_retry(path.as_ref()).map_err(|e| describe_io_error("removing directory", path.as_ref(), &e))?;
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Deletes a file, clearing its read-only attribute and retrying with backoff
+/// while it is briefly locked (antivirus scans, MO2's virtual file system).
+pub fn remove_file_with_retry(path: &Path) -> io::Result<()> {
+    retry_with_backoff(path, || {
+        clear_readonly(path);
+        fs::remove_file(path)
+    })
+}
+
+/// Deletes a directory tree, clearing read-only attributes (files extracted
+/// from archives can carry them) and retrying with backoff while files are
+/// briefly locked.
+pub fn remove_dir_all_with_retry(path: &Path) -> io::Result<()> {
+    retry_with_backoff(path, || {
+        match fs::remove_dir_all(path) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                clear_readonly_recursive(path);
+                fs::remove_dir_all(path)
+            }
+            result => result,
+        }
+    })
+}
+
+/// Runs a deletion until it succeeds, the target is gone, or the attempts run out
+fn retry_with_backoff<F: FnMut() -> io::Result<()>>(path: &Path, mut delete: F) -> io::Result<()> {
+    let mut delay = DELETE_RETRY_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        match delete() {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) if attempt >= DELETE_ATTEMPTS => return Err(e),
+            Err(e) => {
+                debug!(
+                    "Deleting {} failed (attempt {}/{}): {}, retrying in {} ms",
+                    path.display(),
+                    attempt,
+                    DELETE_ATTEMPTS,
+                    e,
+                    delay.as_millis()
+                );
+                sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Clears the read-only attribute of a file, ignoring failures
+fn clear_readonly(path: &Path) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+
+    let mut permissions = metadata.permissions();
+    if !permissions.readonly() {
+        return;
+    }
+
+    #[cfg(windows)]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+
+    let _ = fs::set_permissions(path, permissions);
+}
+
+/// Clears the read-only attribute of a directory and everything below it
+fn clear_readonly_recursive(path: &Path) {
+    clear_readonly(path);
+    walk_dir(path, &mut |entry_path, _| clear_readonly(entry_path));
+}
+
+/// Copies the contents of one directory into another, merging with and
+/// overwriting anything already present at the destination
+pub fn copy_dir_merge<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), String> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    ensure_directory_exists(dst)?;
+
+    let entries = fs::read_dir(src)
+        .map_err(|e| format!("Error reading directory {}: {}", src.display(), e))?;
+    for entry in entries.flatten() {
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        if src_path.is_dir() {
+            copy_dir_merge(&src_path, &dst_path)?;
+        } else {
+            fs::copy(&src_path, &dst_path).map_err(|e| {
+                format!("Error copying {} to {}: {}", src_path.display(), dst_path.display(), e)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the contents of one directory into another, merging with and
+/// overwriting anything already present at the destination. The source
+/// directory is removed once empty.
+pub fn move_dir_merge<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), String> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    if !src.exists() {
+        return Ok(());
+    }
+
+    ensure_directory_exists(dst)?;
+
+    let entries = fs::read_dir(src)
+        .map_err(|e| format!("Error reading directory {}: {}", src.display(), e))?;
+
+    for entry in entries.flatten() {
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+
+        if src_path.is_dir() {
+            move_dir_merge(&src_path, &dst_path)?;
+        } else {
+            remove_file_if_exists(&dst_path)?;
+            fs::rename(&src_path, &dst_path).map_err(|e| {
+                format!(
+                    "Error moving {} to {}: {}",
+                    src_path.display(),
+                    dst_path.display(),
+                    e
+                )
+            })?;
+        }
+    }
+
+    fs::remove_dir(src)
+        .map_err(|e| format!("Error removing directory {}: {}", src.display(), e))?;
+
+    Ok(())
+}
@@ -1,20 +1,143 @@
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::{BufRead, BufReader};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 use regex::Regex;
 use log::{info, warn};
 use serde::Deserialize;
-use crate::cli::{BuildMode, BuildStage};
+use crate::backup::RunBackup;
+use crate::cli::{ArchiverKind, BuildMode, BuildStage};
+use crate::esp::{extension_flags, read_plugin_header, set_plugin_flags, summarize_cells, FLAG_LIGHT, FLAG_MASTER};
+use crate::game::{
+    is_fallout_london, is_next_gen_creation_kit, read_exe_version, DOWNGRADED_F4SE_DLL, DOWNGRADED_GAME_VERSION,
+    F4SE_LOADER, FALLOUT_LONDON_MARKER,
+};
 use crate::paths::Paths;
+use crate::stages::{check_requirements, StageContext};
+use crate::tools::ba2::read_ba2_header;
 use crate::tools::creation_kit::CkpeSettings;
+use crate::tools::simulate::is_simulating;
+use crate::utils::walk_dir;
+use crate::verify_cache::{fingerprint_files, VerifiedEnvironment, VerifyCache};
+
+/// How long a folder must have gone unchanged before its file counts are cached
+const FILE_COUNT_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Folder scans of `count_files`, reused while their folders are unchanged
+static FILE_COUNTS: Mutex<Vec<FolderScan>> = Mutex::new(Vec::new());
+
+/// A cached `count_files` result
+struct FolderScan {
+    root: PathBuf,
+    extensions: Vec<String>,
+    /// Every folder scanned, with its modified time at the time
+    folders: Vec<(PathBuf, SystemTime)>,
+    counts: Vec<usize>,
+}
 
 /// Validates the environment for required files, directories, and settings
 pub fn verify_environment(
     paths: &Paths,
     ckpe_settings: &mut CkpeSettings,
     _plugin_name: &str,
-    use_bsarch: bool,
+    archiver: ArchiverKind,
+    downgraded: bool,
+) -> Result<(), String> {
+    for warning in check_environment(paths, ckpe_settings, archiver, downgraded)? {
+        warn!("{}", warning);
+    }
+    info!("Environment verified successfully");
+    Ok(())
+}
+
+/// Validates the environment like `verify_environment`, skipping the checks
+/// when the profile's last check passed and none of the files it read have
+/// changed since.
+///
+/// # Arguments
+/// * `paths` - Paths to the game and tools
+/// * `ckpe_settings` - Filled in with the CKPE configuration found
+/// * `archiver` - Archiver the build uses
+/// * `downgraded` - Check the install is downgraded to 1.10.163 (--fallout-london)
+/// * `profile` - Settings profile the result is cached for
+/// * `reverify` - Run every check even if the cache says they passed (--reverify)
+///
+/// # Returns
+/// * `Ok(())` if the environment is usable
+/// * `Err(String)` describing the first problem found
+pub fn verify_environment_cached(
+    paths: &Paths,
+    ckpe_settings: &mut CkpeSettings,
+    archiver: ArchiverKind,
+    downgraded: bool,
+    profile: &str,
+    reverify: bool,
 ) -> Result<(), String> {
+    let settings = format!("{} {:?} downgraded={}", env!("CARGO_PKG_VERSION"), archiver, downgraded);
+    let files = fingerprint_files(&environment_files(paths));
+    let mut cache = VerifyCache::load(&paths.fallout4);
+    if !reverify {
+        if let Some(verified) = cache.find(profile, &settings, &files) {
+            *ckpe_settings = verified.ckpe_settings.clone();
+            for warning in &verified.warnings {
+                warn!("{}", warning);
+            }
+            info!("Environment unchanged since it was last verified, use --reverify to check it again");
+            return Ok(());
+        }
+    }
+
+    let warnings = check_environment(paths, ckpe_settings, archiver, downgraded)?;
+    for warning in &warnings {
+        warn!("{}", warning);
+    }
+    info!("Environment verified successfully");
+
+    cache.record(profile, VerifiedEnvironment { settings, files, ckpe_settings: ckpe_settings.clone(), warnings });
+    if let Err(e) = cache.save(&paths.fallout4) {
+        warn!("{}", e);
+    }
+    Ok(())
+}
+
+/// Every file `check_environment` looks at, whether or not it exists
+fn environment_files(paths: &Paths) -> Vec<PathBuf> {
+    let mut files = vec![
+        paths.fo4edit.clone(),
+        paths.fallout4.join("Fallout4.exe"),
+        paths.creation_kit.clone(),
+        paths.fallout4.join("winhttp.dll"),
+        paths.archive2.clone(),
+        paths.fallout4.join("CreationKitPlatformExtended.ini"),
+        paths.fallout4.join("CreationKitPlatformExtended.toml"),
+        paths.fallout4.join("fallout4_test.ini"),
+        paths.fallout4.join(F4SE_LOADER),
+        paths.fallout4.join(DOWNGRADED_F4SE_DLL),
+        paths.fallout4.join("Data").join(FALLOUT_LONDON_MARKER),
+    ];
+    if let Some(xedit_dir) = paths.fo4edit.parent() {
+        files.extend(XEDIT_SCRIPTS.iter().map(|script| xedit_dir.join("Edit Scripts").join(script)));
+    }
+    files.extend(paths.bsarch.clone());
+    files
+}
+
+/// The xEdit scripts the merge stages run
+const XEDIT_SCRIPTS: [&str; 2] = [
+    "Batch_FO4MergeCombinedObjectsAndCheck.pas",
+    "Batch_FO4MergePreVisAndAutoUpdateRefr.pas",
+];
+
+/// Runs the environment checks, returning the warnings found
+fn check_environment(
+    paths: &Paths,
+    ckpe_settings: &mut CkpeSettings,
+    archiver: ArchiverKind,
+    downgraded: bool,
+) -> Result<Vec<String>, String> {
+    let mut warnings = Vec::new();
     // Check FO4Edit
     if !paths.fo4edit.exists() {
         return Err(format!(
@@ -49,23 +172,34 @@ pub fn verify_environment(
     }
 
     // Check Archive2.exe
-    if !paths.archive2.exists() {
+    if archiver == ArchiverKind::Archive2 && !paths.archive2.exists() {
         return Err(
             "ERROR - Archive2.exe not found. Creation Kit not properly installed".to_string(),
         );
     }
 
+    // Flag install locations known to break CK builds
+    warnings.extend(install_location_warnings(&paths.fallout4));
+
+    // Fallout London only runs on a game downgraded to 1.10.163
+    let downgraded = downgraded || {
+        let london = is_fallout_london(&paths.fallout4);
+        if london {
+            info!("Fallout London found, checking the install is downgraded to {}", DOWNGRADED_GAME_VERSION);
+        }
+        london
+    };
+    if downgraded {
+        warnings.extend(check_downgraded_install(paths)?);
+    }
+
     // Check CKPE configuration files
     detect_ckpe_configuration(paths, ckpe_settings)?;
 
     // Check xEdit scripts exist
-    let scripts = [
-        "Batch_FO4MergeCombinedObjectsAndCheck.pas",
-        "Batch_FO4MergePreVisAndAutoUpdateRefr.pas",
-    ];
-
     let xedit_scripts_dir = paths.fo4edit.parent().unwrap().join("Edit Scripts");
-    for script in scripts.iter() {
+    let version_regex = Regex::new(r"BatchVersion\s*=\s*(\d+)").unwrap();
+    for script in XEDIT_SCRIPTS.iter() {
         let script_path = xedit_scripts_dir.join(script);
         if !script_path.exists() {
             return Err(format!(
@@ -79,21 +213,17 @@ pub fn verify_environment(
             let reader = BufReader::new(file);
             let mut version_found = false;
 
-            let version_regex = Regex::new(r"BatchVersion\s*=\s*(\d+)").unwrap();
-
-            for line in reader.lines() {
-                if let Ok(line_content) = line {
-                    if let Some(captures) = version_regex.captures(&line_content) {
-                        if let Ok(version) = captures[1].parse::<i32>() {
-                            if version < 10 {
-                                return Err(format!(
-                                    "ERROR - FO4Edit Script {} is outdated (version {}). Please update.",
-                                    script, version
-                                ));
-                            }
-                            version_found = true;
-                            break;
+            for line_content in reader.lines().map_while(Result::ok) {
+                if let Some(captures) = version_regex.captures(&line_content) {
+                    if let Ok(version) = captures[1].parse::<i32>() {
+                        if version < 10 {
+                            return Err(format!(
+                                "ERROR - FO4Edit Script {} is outdated (version {}). Please update.",
+                                script, version
+                            ));
                         }
+                        version_found = true;
+                        break;
                     }
                 }
             }
@@ -136,22 +266,28 @@ pub fn verify_environment(
         Some(true) => {
             // Handle patch is enabled, all good
         }
+        _ if downgraded => {
+            return Err(format!(
+                "ERROR - {} must be enabled in {} for a downgraded install. Total conversions such as Fallout London run out of Reference Handles without it.",
+                ckpe_settings.handle_setting, ckpe_settings.ini_file
+            ));
+        }
         Some(false) => {
-            warn!(
+            warnings.push(format!(
                 "WARNING - {} is disabled. You may run out of Reference Handles.",
                 ckpe_settings.handle_setting
-            );
+            ));
         }
         None => {
-            warn!(
+            warnings.push(format!(
                 "WARNING - {} not found. You may run out of Reference Handles.",
                 ckpe_settings.handle_setting
-            );
+            ));
         }
     }
 
     // Check BSArch if enabled
-    if use_bsarch {
+    if archiver == ArchiverKind::Bsarch {
         if let Some(bsarch_path) = &paths.bsarch {
             if !bsarch_path.exists() {
                 return Err(format!(
@@ -160,213 +296,633 @@ pub fn verify_environment(
                 ));
             }
         } else {
-            return Err("ERROR - BSArch enabled but path not specified".to_string());
+            return Err("ERROR - BSArch enabled but not found next to xEdit or in common locations. Please specify it with --bsarch-path".to_string());
         }
     }
 
-    info!("Environment verified successfully");
-    Ok(())
+    Ok(warnings)
+}
+
+/// Checks a game downgraded to 1.10.163 (as Fallout London needs) has the
+/// matching Fallout4.exe, F4SE and Creation Kit.
+///
+/// # Arguments
+/// * `paths` - Paths to the game and tools
+///
+/// # Returns
+/// * `Ok(Vec<String>)` with warnings for what couldn't be checked
+/// * `Err(String)` if a file belongs to another game version
+pub fn check_downgraded_install(paths: &Paths) -> Result<Vec<String>, String> {
+    let mut warnings = Vec::new();
+
+    let fallout4_exe = paths.fallout4.join("Fallout4.exe");
+    match read_exe_version(&fallout4_exe) {
+        Some(version)
+            if (version.major, version.minor, version.build)
+                != (DOWNGRADED_GAME_VERSION.major, DOWNGRADED_GAME_VERSION.minor, DOWNGRADED_GAME_VERSION.build) =>
+        {
+            return Err(format!(
+                "ERROR - Fallout4.exe is version {} but a downgraded install needs {}. Downgrade the game before building.",
+                version, DOWNGRADED_GAME_VERSION
+            ));
+        }
+        Some(_) => {}
+        None => warnings.push(format!(
+            "WARNING - Could not read the version of {}, make sure it is {}",
+            fallout4_exe.display(),
+            DOWNGRADED_GAME_VERSION
+        )),
+    }
+
+    if paths.fallout4.join(F4SE_LOADER).exists() {
+        if !paths.fallout4.join(DOWNGRADED_F4SE_DLL).exists() {
+            return Err(format!(
+                "ERROR - F4SE is installed but {} is missing. Install the F4SE build for {} (0.6.23).",
+                DOWNGRADED_F4SE_DLL, DOWNGRADED_GAME_VERSION
+            ));
+        }
+    } else {
+        warnings.push(format!(
+            "WARNING - F4SE not found in {}. The game needs F4SE 0.6.23 to run a downgraded install.",
+            paths.fallout4.display()
+        ));
+    }
+
+    match is_next_gen_creation_kit(&paths.creation_kit) {
+        Some(true) => {
+            return Err(format!(
+                "ERROR - {} is the next-gen Creation Kit, which can't build for a downgraded game. Downgrade the Creation Kit to 1.10.162 along with the game, and use the CKPE build for it.",
+                paths.creation_kit.display()
+            ));
+        }
+        Some(false) => {}
+        None => warnings.push(format!(
+            "WARNING - Could not read the version of {}, make sure it is the 1.10.162 Creation Kit",
+            paths.creation_kit.display()
+        )),
+    }
+
+    Ok(warnings)
 }
 
-/// Checks if the plugin and archive are valid for processing
+/// Folder names of file sync clients, lowercase
+const SYNCED_FOLDER_NAMES: [&str; 4] = ["onedrive", "dropbox", "google drive", "iclouddrive"];
+
+/// Environment variables holding the OneDrive folders
+const ONEDRIVE_VARIABLES: [&str; 3] = ["OneDrive", "OneDriveConsumer", "OneDriveCommercial"];
+
+/// Checks where the game is installed for locations that make CK builds
+/// fail in confusing ways: Program Files, folders kept in sync by OneDrive
+/// and similar clients, and network drives.
+///
+/// # Arguments
+/// * `fallout4` - The Fallout 4 folder
+///
+/// # Returns
+/// A warning explaining each problem found
+pub fn install_location_warnings(fallout4: &Path) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let folder = std::path::absolute(fallout4).unwrap_or_else(|_| fallout4.to_path_buf());
+    let components: Vec<String> = folder
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+
+    let program_files = components
+        .iter()
+        .any(|component| component == "program files" || component == "program files (x86)");
+    if program_files {
+        warnings.push(format!(
+            "WARNING - Fallout 4 is installed under Program Files ({}). Without administrator rights Windows redirects \
+             the files the Creation Kit and xEdit write there to the VirtualStore folder, so stages report missing \
+             output. Move the game to a folder such as C:\\Games, or run the build as administrator.",
+            folder.display()
+        ));
+    }
+
+    let lowercase_folder = folder.to_string_lossy().to_lowercase();
+    let in_onedrive = ONEDRIVE_VARIABLES.iter().any(|name| {
+        env::var(name).is_ok_and(|dir| !dir.is_empty() && lowercase_folder.starts_with(&dir.to_lowercase()))
+    });
+    let sync_folder = components.iter().find(|component| {
+        SYNCED_FOLDER_NAMES
+            .iter()
+            .any(|name| component.as_str() == *name || component.starts_with(&format!("{} - ", name)))
+    });
+    if in_onedrive || sync_folder.is_some() {
+        warnings.push(format!(
+            "WARNING - Fallout 4 is in a folder kept in sync by {} ({}). The sync client locks files while uploading them, \
+             which makes the Creation Kit and the archiver fail with access denied errors, and can upload or restore \
+             half-written meshes and archives. Pause syncing during the build or move the game out of the synced folder.",
+            if in_onedrive { "OneDrive" } else { "a cloud storage client" },
+            folder.display()
+        ));
+    }
+
+    if is_network_path(&folder) {
+        warnings.push(format!(
+            "WARNING - Fallout 4 is on a network drive ({}). Builds write tens of thousands of files, which is very slow \
+             over the network, and dropped connections or file locks held by other machines fail stages part way. \
+             Build on a local drive.",
+            folder.display()
+        ));
+    }
+    warnings
+}
+
+/// Whether a path is a UNC share or on a mapped network drive
+#[cfg(windows)]
+fn is_network_path(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Component, Prefix};
+    use windows_sys::Win32::Storage::FileSystem::GetDriveTypeW;
+
+    /// GetDriveTypeW's value for remote drives
+    const DRIVE_REMOTE: u32 = 4;
+
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::UNC(..) | Prefix::VerbatimUNC(..) => true,
+            Prefix::Disk(_) | Prefix::VerbatimDisk(_) => {
+                let root: Vec<u16> = Path::new(prefix.as_os_str()).join("\\").as_os_str().encode_wide().chain(Some(0)).collect();
+                // SAFETY: the root path is NUL-terminated
+                unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_REMOTE }
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether a path is a UNC share (`//server/share`)
+#[cfg(not(windows))]
+fn is_network_path(path: &Path) -> bool {
+    let text = path.to_string_lossy();
+    text.starts_with("//") || text.starts_with("\\\\")
+}
+
+/// Checks that the external tools the stages need can run on this system.
+///
+/// The Creation Kit, xEdit, Archive2 and BSArch are Windows programs. Elsewhere
+/// only --simulate builds and stages packed by the native archiver can run.
+///
+/// # Returns
+/// * `Ok(())` on Windows, when simulating, or when no stage needs a Windows tool
+/// * `Err(String)` naming the tools that can't run here
+pub fn check_platform(stages: &[BuildStage], archiver: ArchiverKind) -> Result<(), String> {
+    if cfg!(windows) || is_simulating() {
+        return Ok(());
+    }
+
+    let mut tools = Vec::new();
+    let uses = |candidates: &[BuildStage]| stages.iter().any(|stage| candidates.contains(stage));
+    if uses(&[BuildStage::GeneratePrecombines, BuildStage::CompressPsg, BuildStage::BuildCdx, BuildStage::GeneratePrevis]) {
+        tools.push("Creation Kit");
+    }
+    if uses(&[BuildStage::MergePrecombines, BuildStage::MergePrevis]) {
+        tools.push("xEdit");
+    }
+    if archiver != ArchiverKind::Native && uses(&[BuildStage::ArchivePrecombines, BuildStage::ArchiveVis]) {
+        tools.push(if archiver == ArchiverKind::Bsarch { "BSArch" } else { "Archive2" });
+    }
+    if tools.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "ERROR - {} only run on Windows. On this system use --simulate to try the build, \
+         --archiver native for the archive stages, or the repack, clean-previsbines and history commands",
+        tools.join(", ")
+    ))
+}
+
+/// Plugin renamed to the plugin being built when it doesn't exist yet
+pub const SEED_PLUGIN: &str = "xPrevisPatch.esp";
+
+/// Lists the seed plugins in Data a missing plugin can be made from:
+/// xPrevisPatch.esp, then the configured templates (--seed-plugin)
+///
+/// # Arguments
+/// * `data_dir` - The Data folder
+/// * `templates` - Template plugin names, in order
+pub fn find_seed_plugins(data_dir: &Path, templates: &[String]) -> Vec<String> {
+    let mut seeds: Vec<String> = Vec::new();
+    for seed in std::iter::once(SEED_PLUGIN).chain(templates.iter().map(String::as_str)) {
+        if data_dir.join(seed).is_file() && !seeds.iter().any(|found| found.eq_ignore_ascii_case(seed)) {
+            seeds.push(seed.to_string());
+        }
+    }
+    seeds
+}
+
+/// Checks if the plugin and archive are valid for processing. A missing
+/// plugin is made from a seed, flagged as its extension calls for:
+/// xPrevisPatch.esp is renamed, a template plugin is copied so it can be
+/// used again.
+///
+/// # Arguments
+/// * `templates` - Template plugins offered when xPrevisPatch.esp is missing (--seed-plugin)
+/// * `prompt_fn` - Asks whether to use the only seed found
+/// * `choose_fn` - Picks one of several seeds found, or none to abort
 pub fn check_plugin(
     paths: &Paths,
     plugin_name_ext: &str,
     plugin_archive: &str,
-    no_prompt: bool,
+    templates: &[String],
+    backup: &RunBackup,
     prompt_fn: impl Fn(&str) -> Result<bool, String>,
+    choose_fn: impl Fn(&[String]) -> Result<Option<String>, String>,
 ) -> Result<(), String> {
     info!("Checking plugin: {}", plugin_name_ext);
 
     let plugin_path = paths.fallout4.join("Data").join(plugin_name_ext);
     let archive_path = paths.fallout4.join("Data").join(plugin_archive);
 
-    if archive_path.exists() {
-        return Err(format!(
-            "ERROR - This Plugin already has an Archive: {}",
-            plugin_archive
-        ));
-    }
-
     if !plugin_path.exists() {
-        // Plugin doesn't exist, try to use xPrevisPatch.esp as seed
-        let seed_path = paths.fallout4.join("Data").join("xPrevisPatch.esp");
+        if archive_path.exists() {
+            return Err(format!(
+                "ERROR - This Plugin already has an Archive: {}",
+                plugin_archive
+            ));
+        }
+
+        // Plugin doesn't exist, try to use xPrevisPatch.esp or a template as seed
+        let data_dir = paths.fallout4.join("Data");
+        let seeds = find_seed_plugins(&data_dir, templates);
+        let seed = match seeds.as_slice() {
+            [] if templates.is_empty() => {
+                return Err(format!("ERROR - Neither {} nor {} exists in Data", plugin_name_ext, SEED_PLUGIN));
+            }
+            [] => {
+                return Err(format!(
+                    "ERROR - Neither {} nor a seed plugin ({}, {}) exists in Data",
+                    plugin_name_ext,
+                    SEED_PLUGIN,
+                    templates.join(", ")
+                ));
+            }
+            [seed] => {
+                let verb = if seed.eq_ignore_ascii_case(SEED_PLUGIN) { "Rename" } else { "Copy" };
+                if !prompt_fn(&format!("{} does not exist, {} {} to {}? [Y/N]", plugin_name_ext, verb, seed, plugin_name_ext))? {
+                    return Err("Aborted by user".to_string());
+                }
+                seed.clone()
+            }
+            _ => choose_fn(&seeds)?.ok_or_else(|| "Aborted by user".to_string())?,
+        };
+        let seed_path = data_dir.join(&seed);
 
-        if !seed_path.exists() {
-            return Err("ERROR - Specified Plugin or xPrevisPatch does not exist".to_string());
+        if seed.eq_ignore_ascii_case(SEED_PLUGIN) {
+            // Rename xPrevisPatch.esp to the plugin name
+            backup.save(&seed_path)?;
+            fs::rename(&seed_path, &plugin_path)
+                .map_err(|e| format!("Error renaming {} to {}: {}", seed, plugin_name_ext, e))?;
+            info!("Renamed {} to {}", seed, plugin_name_ext);
+        } else {
+            fs::copy(&seed_path, &plugin_path)
+                .map_err(|e| format!("Error copying {} to {}: {}", seed, plugin_name_ext, e))?;
+            info!("Copied {} to {}", seed, plugin_name_ext);
         }
 
-        if no_prompt {
+        // An .esm or .esl name only works with the matching header flags
+        let flags = extension_flags(plugin_name_ext);
+        if flags != 0 {
+            set_plugin_flags(&plugin_path, flags)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the Previs Repair Pack plugin a patch is built against (--prp).
+///
+/// # Arguments
+/// * `paths` - Paths to the game and tools
+/// * `prp` - PRP plugin file name in Data (e.g. `PRP.esp`)
+/// * `plugin_name_ext` - The plugin being built
+/// * `min_version` - Oldest PRP version accepted, if any
+///
+/// # Returns
+/// * `Ok(Option<String>)` with the PRP version read from its description, if found
+/// * `Err(String)` if PRP is missing, unreadable or older than `min_version`
+pub fn check_prp(
+    paths: &Paths,
+    prp: &str,
+    plugin_name_ext: &str,
+    min_version: Option<&str>,
+) -> Result<Option<String>, String> {
+    let data_dir = paths.fallout4.join("Data");
+    let prp_path = data_dir.join(prp);
+    if !prp_path.exists() {
+        return Err(format!("ERROR - PRP plugin {} not found in Data", prp));
+    }
+
+    let header = read_plugin_header(&prp_path)?;
+    let version_pattern = Regex::new(r"\d+(?:\.\d+)+").unwrap();
+    let version = header
+        .description
+        .as_deref()
+        .and_then(|description| version_pattern.find(description))
+        .map(|version| version.as_str().to_string());
+
+    match (&version, min_version) {
+        (Some(version), Some(min_version)) if parse_version(version) < parse_version(min_version) => {
             return Err(format!(
-                "ERROR - Plugin {} does not exist",
-                plugin_name_ext
+                "ERROR - {} is version {}, at least {} is required",
+                prp, version, min_version
             ));
         }
-
-        if !prompt_fn("Plugin does not exist, Rename xPrevisPatch.esp to this? [Y/N]")? {
-            return Err("Aborted by user".to_string());
+        (None, Some(min_version)) => {
+            warn!("WARNING - Couldn't read the version of {}, make sure it is at least {}", prp, min_version);
         }
+        _ => {}
+    }
+    match &version {
+        Some(version) => info!("Building against {} version {}", prp, version),
+        None => info!("Building against {}", prp),
+    }
 
-        // Rename xPrevisPatch.esp to the plugin name
-        fs::rename(&seed_path, &plugin_path)
-            .map_err(|e| format!("Error renaming xPrevisPatch.esp: {}", e))?;
+    let plugin_path = data_dir.join(plugin_name_ext);
+    if plugin_path.exists() && !read_plugin_header(&plugin_path)?.has_master(prp) {
+        warn!(
+            "WARNING - {} doesn't have {} as a master, its previs won't match when PRP is loaded",
+            plugin_name_ext, prp
+        );
+    }
+
+    Ok(version)
+}
+
+/// Last object ID a light plugin can use
+const LIGHT_PLUGIN_LAST_OBJECT_ID: u32 = 0xFFF;
 
-        info!("Renamed xPrevisPatch.esp to {}", plugin_name_ext);
+/// How to get a light plugin's new cells built safely
+pub const LIGHT_PLUGIN_GUIDANCE: &str = "\
+To build previsbines for new cells safely:
+  1. In xEdit, remove the ESL flag from the plugin's File Header (or rename an .esl to .esp)
+  2. Build previsbines for the full plugin
+  3. Only ESL-flag it again if xEdit's \"Compact FormIDs for ESL\" and \"Check for ESL\" still
+     report it fits, and rebuild previsbines after compacting, as FormIDs change
+Alternatively, move the new cells into a full plugin and keep the light plugin for the rest.";
+
+/// Checks whether a light (ESL-flagged or .esl) plugin adds new cells.
+/// Light plugins only have object IDs 0x800-0xFFF, and the references the
+/// build adds to new cells can run past them or end up with previs data
+/// that doesn't match once the plugin is compacted.
+///
+/// # Returns
+/// * `Ok(Some(String))` with a warning for a light plugin adding new cells
+/// * `Ok(None)` for any other plugin
+/// * `Err(String)` if the plugin can't be read
+pub fn light_plugin_warning(plugin_path: &Path) -> Result<Option<String>, String> {
+    let header = read_plugin_header(plugin_path)?;
+    let file_name = plugin_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if (header.flags | extension_flags(&file_name)) & FLAG_LIGHT == 0 {
+        return Ok(None);
+    }
+    let cells = summarize_cells(plugin_path)?;
+    if cells.new_cells() == 0 {
+        return Ok(None);
     }
 
-    Ok(())
+    let mut warning = format!(
+        "WARNING - {} is a light (ESL) plugin adding {} new cell(s). Light plugins only have FormIDs 0x800-0x{:X}, \
+         and precombines and previs built for new cells in them break easily: the build's new references can run out of FormIDs, \
+         and compacting the plugin later leaves the previs pointing at the wrong references.",
+        file_name,
+        cells.new_cells(),
+        LIGHT_PLUGIN_LAST_OBJECT_ID
+    );
+    if header.next_object_id > LIGHT_PLUGIN_LAST_OBJECT_ID {
+        warning.push_str(&format!(
+            " Its next FormID (0x{:X}) is already past that range.",
+            header.next_object_id
+        ));
+    }
+    Ok(Some(warning))
 }
 
-/// Checks if the specified directory contains any files with the given file extension
-pub fn directory_has_files(dir_path: &PathBuf, extension: &str) -> bool {
-    if !dir_path.exists() {
-        return false;
+/// Build mode suited to a plugin, with the reasons for it
+#[derive(Debug, Clone)]
+pub struct ModeRecommendation {
+    pub mode: BuildMode,
+    pub reasons: Vec<String>,
+}
+
+/// Recommends clean or filtered mode from what the plugin contains.
+///
+/// Clean mode suits plugins adding content others build on (masters, new
+/// worldspaces, mostly new cells). Filtered mode suits patches that only
+/// change cells from their masters.
+///
+/// # Returns
+/// * `Ok(Some(ModeRecommendation))` with the recommended mode and why
+/// * `Ok(None)` if the plugin has no cells to judge by
+/// * `Err(String)` if the plugin can't be read
+pub fn recommend_build_mode(plugin_path: &Path) -> Result<Option<ModeRecommendation>, String> {
+    let header = read_plugin_header(plugin_path)?;
+    let cells = summarize_cells(plugin_path)?;
+    if cells.new_cells() + cells.overridden_cells() == 0 {
+        return Ok(None);
     }
 
-    if let Ok(entries) = fs::read_dir(dir_path) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Some(file_name) = entry.file_name().to_str() {
-                    if file_name.ends_with(extension) {
-                        return true;
-                    }
-                }
-            }
-        }
+    let mut reasons = Vec::new();
+    if header.flags & FLAG_MASTER != 0 {
+        reasons.push("it is flagged as a master, so other plugins build on its precombines".to_string());
+    }
+    if cells.new_worldspaces > 0 {
+        reasons.push(format!("it adds {} worldspace(s)", cells.new_worldspaces));
+    }
+    if cells.new_cells() >= cells.overridden_cells() {
+        reasons.push(format!(
+            "most of its cells are new ({} new, {} overridden)",
+            cells.new_cells(),
+            cells.overridden_cells()
+        ));
+    }
+    if !reasons.is_empty() {
+        return Ok(Some(ModeRecommendation { mode: BuildMode::Clean, reasons }));
     }
 
-    false
+    Ok(Some(ModeRecommendation {
+        mode: BuildMode::Filtered,
+        reasons: vec![format!(
+            "it mostly changes existing cells ({} overridden, {} new), like a patch",
+            cells.overridden_cells(),
+            cells.new_cells()
+        )],
+    }))
 }
 
-/// Validates the prerequisites for various build stages
-pub fn check_stage_prerequisites(
-    stage: BuildStage,
-    paths: &Paths,
-    plugin_name_ext: &str,
-    plugin_name: &str,
-    build_mode: &BuildMode,
-    has_files_fn: impl Fn(&PathBuf, &str) -> bool,
-) -> Result<(), String> {
-    match stage {
-        BuildStage::VerifyEnvironment => Ok(()),
+/// Splits a dotted version into its numbers for comparison
+pub(crate) fn parse_version(version: &str) -> Vec<u32> {
+    version.split('.').map(|part| part.trim().parse().unwrap_or(0)).collect()
+}
 
-        BuildStage::GeneratePrecombines => {
-            // Check if plugin exists
-            let plugin_path = paths.fallout4.join("Data").join(plugin_name_ext);
-            if !plugin_path.exists() {
-                return Err(format!(
-                    "ERROR - Plugin {} does not exist",
-                    plugin_name_ext
-                ));
-            }
-            Ok(())
-        }
+/// Checks if the specified directory or any folder under it contains files
+/// with the given file extension (ignoring case)
+pub fn directory_has_files(dir_path: &Path, extension: &str) -> bool {
+    count_files(dir_path, &[extension])[0] > 0
+}
 
-        BuildStage::MergePrecombines => {
-            // Check if precombined meshes exist
-            let precombined_dir = paths
-                .fallout4
-                .join("Data")
-                .join("meshes")
-                .join("precombined");
-            if !has_files_fn(&precombined_dir, ".nif") {
-                return Err(
-                    "ERROR - No precombined meshes found. Run GeneratePrecombines first."
-                        .to_string(),
-                );
-            }
-            Ok(())
+/// Counts the files under a directory, its subfolders included, with each of
+/// the given extensions (ignoring case), in one pass.
+///
+/// Results are kept until `clear_file_counts` and reused while no folder of
+/// the scan has changed, as the precombined and vis folders can hold tens of
+/// thousands of files and are checked several times per stage.
+///
+/// # Arguments
+/// * `dir_path` - The directory to scan
+/// * `extensions` - The extensions to count, e.g. `.nif`
+///
+/// # Returns
+/// The number of files per extension, in the order given; zeros if the
+/// directory doesn't exist
+pub fn count_files(dir_path: &Path, extensions: &[&str]) -> Vec<usize> {
+    let extensions: Vec<String> = extensions.iter().map(|extension| extension.to_lowercase()).collect();
+    let mut cache = FILE_COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(scan) = cache.iter().find(|scan| scan.root == dir_path && scan.extensions == extensions) {
+        let unchanged = scan
+            .folders
+            .iter()
+            .all(|(folder, modified)| fs::metadata(folder).and_then(|metadata| metadata.modified()).ok() == Some(*modified));
+        if unchanged {
+            return scan.counts.clone();
         }
+    }
+    cache.retain(|scan| !(scan.root == dir_path && scan.extensions == extensions));
 
-        BuildStage::ArchivePrecombines => {
-            // Check if precombined meshes exist
-            let precombined_dir = paths
-                .fallout4
-                .join("Data")
-                .join("meshes")
-                .join("precombined");
-            if !has_files_fn(&precombined_dir, ".nif") {
-                return Err(
-                    "ERROR - No precombined meshes found. Run GeneratePrecombines first."
-                        .to_string(),
-                );
-            }
-            Ok(())
-        }
+    let mut counts = vec![0; extensions.len()];
+    let mut folders = Vec::new();
+    let settled = scan_folder(dir_path, &extensions, &mut counts, &mut folders);
+    // Files added within the timestamp resolution of the file system may not
+    // change a folder's modified time, so recent changes aren't cached
+    if settled && !folders.is_empty() {
+        cache.push(FolderScan { root: dir_path.to_path_buf(), extensions, folders, counts: counts.clone() });
+    }
+    counts
+}
 
-        BuildStage::CompressPsg => {
-            if build_mode != &BuildMode::Clean {
-                return Err("ERROR - CompressPSG is only available in Clean mode".to_string());
-            }
+/// Forgets the file counts kept by `count_files`, e.g. at the start of a stage
+pub fn clear_file_counts() {
+    FILE_COUNTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
 
-            // Check if PSG file exists
-            let psg_path = paths
-                .fallout4
-                .join("Data")
-                .join(format!("{} - Geometry.psg", plugin_name));
-            if !psg_path.exists() {
-                return Err(
-                    "ERROR - No Geometry.psg file found. Run GeneratePrecombines first."
-                        .to_string(),
-                );
-            }
-            Ok(())
+/// Counts the matching files of a folder and its subfolders, noting the
+/// modified time of every folder.
+///
+/// # Returns
+/// Whether every folder was last changed long enough ago to cache the result
+fn scan_folder(
+    dir_path: &Path,
+    extensions: &[String],
+    counts: &mut [usize],
+    folders: &mut Vec<(PathBuf, SystemTime)>,
+) -> bool {
+    let mut settled = note_folder(dir_path, folders);
+    let complete = walk_dir(dir_path, &mut |path, is_dir| {
+        if is_dir {
+            settled &= note_folder(path, folders);
+            return;
         }
-
-        BuildStage::BuildCdx => {
-            if build_mode != &BuildMode::Clean {
-                return Err("ERROR - BuildCDX is only available in Clean mode".to_string());
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        for (count, extension) in counts.iter_mut().zip(extensions) {
+            if name.ends_with(extension.as_str()) {
+                *count += 1;
             }
-            Ok(())
         }
+    });
+    settled && complete
+}
 
-        BuildStage::GeneratePrevis => {
-            // Check if plugin exists
-            let plugin_path = paths.fallout4.join("Data").join(plugin_name_ext);
-            if !plugin_path.exists() {
-                return Err(format!(
-                    "ERROR - Plugin {} does not exist",
-                    plugin_name_ext
-                ));
-            }
-            Ok(())
+/// Notes the modified time of a folder scanned by `scan_folder`.
+///
+/// # Returns
+/// Whether the folder was last changed long enough ago to cache its counts
+fn note_folder(folder: &Path, folders: &mut Vec<(PathBuf, SystemTime)>) -> bool {
+    let Some(modified) = fs::metadata(folder).and_then(|metadata| metadata.modified()).ok() else {
+        return false;
+    };
+    folders.push((folder.to_path_buf(), modified));
+    SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age >= FILE_COUNT_SETTLE_TIME)
+}
+
+/// Recursively collects all files under a directory with the given file extension
+pub fn find_files_recursive(dir_path: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let extension = extension.to_lowercase();
+    walk_dir(dir_path, &mut |path, is_dir| {
+        if !is_dir && path.file_name().unwrap_or_default().to_string_lossy().to_lowercase().ends_with(&extension) {
+            files.push(path.to_path_buf());
         }
+    });
+    files
+}
 
-        BuildStage::MergePrevis => {
-            // Check if vis files exist
-            let vis_dir = paths.fallout4.join("Data").join("vis");
-            if !has_files_fn(&vis_dir, ".uvd") {
-                return Err(
-                    "ERROR - No visibility files found. Run GeneratePreVisData first."
-                        .to_string(),
-                );
-            }
+/// Checks the plugin's texture archive, if present, for problems.
+///
+/// The texture archive is never modified by this tool, but a `<plugin> - Textures.ba2`
+/// that isn't in the DX10 format won't load its textures in game, which is
+/// worth flagging while the user is rebuilding the plugin's archives anyway.
+pub fn check_texture_archive(paths: &Paths, plugin_name: &str) -> Result<(), String> {
+    let texture_archive = format!("{} - Textures.ba2", plugin_name);
+    let texture_archive_path = paths.fallout4.join("Data").join(&texture_archive);
 
-            // Check if Previs.esp exists
-            let previs_path = paths.fallout4.join("Data").join("Previs.esp");
-            if !previs_path.exists() {
-                return Err(
-                    "ERROR - Previs.esp not found. Run GeneratePreVisData first.".to_string(),
-                );
-            }
+    if !texture_archive_path.exists() {
+        return Ok(());
+    }
 
-            Ok(())
-        }
+    info!("Found texture archive {}, it will not be modified", texture_archive);
 
-        BuildStage::ArchiveVis => {
-            // Check if vis files exist
-            let vis_dir = paths.fallout4.join("Data").join("vis");
-            if !has_files_fn(&vis_dir, ".uvd") {
-                return Err(
-                    "ERROR - No visibility files found. Run GeneratePreVisData first."
-                        .to_string(),
-                );
-            }
-            Ok(())
-        }
+    let header = read_ba2_header(&texture_archive_path)?;
+    if header.archive_type != "DX10" {
+        warn!(
+            "WARNING - {} is a {} archive, texture archives must use the DX10 format",
+            texture_archive, header.archive_type
+        );
+    }
+
+    Ok(())
+}
+
+/// Warns if content about to be packed into the main archive contains textures,
+/// which belong in the plugin's texture archive instead
+pub fn warn_on_texture_files(content_dir: &Path, plugin_name: &str) {
+    let textures = find_files_recursive(content_dir, ".dds");
+    if !textures.is_empty() {
+        warn!(
+            "WARNING - {} texture file(s) found in content for the main archive (e.g. {}). \
+             Textures belong in {} - Textures.ba2 and may be refused or mis-packed by Archive2.",
+            textures.len(),
+            textures[0].display(),
+            plugin_name
+        );
     }
 }
 
+/// Checks that the files a stage needs are in Data before starting a build at it.
+/// The requirements themselves are described by the stage graph in `stages`.
+pub fn check_stage_prerequisites(
+    stage: BuildStage,
+    paths: &Paths,
+    plugin_name_ext: &str,
+    plugin_name: &str,
+    build_mode: &BuildMode,
+    has_files_fn: impl Fn(&Path, &str) -> bool,
+) -> Result<(), String> {
+    let context = StageContext {
+        data_dir: paths.fallout4.join("Data"),
+        plugin_name_ext,
+        plugin_name,
+        build_mode: *build_mode,
+        has_files: &has_files_fn,
+    };
+    check_requirements(stage, &context)
+}
+
 #[derive(Deserialize)]
 struct TomlCkpeConfig {
     #[serde(rename = "CreationKit")]
@@ -442,27 +998,25 @@ fn read_ini_config(file_path: &PathBuf, ckpe_settings: &CkpeSettings) -> Result<
         regex::escape(&ckpe_settings.log_setting)
     )).unwrap();
 
-    for line in reader.lines() {
-        if let Ok(line_content) = line {
-            // Check handle setting
-            if let Some(captures) = handle_regex.captures(&line_content) {
-                if ckpe_settings.handle_setting == "bBSPointerHandleExtremly" {
-                    // New format: boolean
-                    handle_enabled = Some(captures[1].trim() == "true");
-                } else {
-                    // Old format: numeric (1 = enabled, 0 = disabled)
-                    if let Ok(value) = captures[1].parse::<i32>() {
-                        handle_enabled = Some(value != 0);
-                    }
+    for line_content in reader.lines().map_while(Result::ok) {
+        // Check handle setting
+        if let Some(captures) = handle_regex.captures(&line_content) {
+            if ckpe_settings.handle_setting == "bBSPointerHandleExtremly" {
+                // New format: boolean
+                handle_enabled = Some(captures[1].trim() == "true");
+            } else {
+                // Old format: numeric (1 = enabled, 0 = disabled)
+                if let Ok(value) = captures[1].parse::<i32>() {
+                    handle_enabled = Some(value != 0);
                 }
             }
+        }
 
-            // Check log setting
-            if let Some(captures) = log_regex.captures(&line_content) {
-                let log_file_str = captures[1].trim();
-                if !log_file_str.is_empty() && log_file_str != "none" {
-                    log_file = Some(log_file_str.to_string());
-                }
+        // Check log setting
+        if let Some(captures) = log_regex.captures(&line_content) {
+            let log_file_str = captures[1].trim();
+            if !log_file_str.is_empty() && log_file_str != "none" {
+                log_file = Some(log_file_str.to_string());
             }
         }
     }
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::cache::{file_fingerprint, CACHE_DIR};
+use crate::tools::creation_kit::CkpeSettings;
+use crate::utils::ensure_directory_exists;
+
+/// Name of the environment check cache in the cache folder
+const VERIFY_CACHE_FILE: &str = "environment.json";
+
+/// Profile name used when no --profile is selected
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// An environment check that passed, with the state of the files it read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiedEnvironment {
+    /// Tool version and settings the check was made with
+    pub settings: String,
+    /// Size and modified time of every file the check read, by path; missing
+    /// files are left out
+    pub files: BTreeMap<String, String>,
+    /// CKPE configuration found by the check
+    pub ckpe_settings: CkpeSettings,
+    /// Warnings the check gave, repeated when it is skipped
+    pub warnings: Vec<String>,
+}
+
+/// Environment checks that passed, per settings profile
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyCache {
+    pub profiles: BTreeMap<String, VerifiedEnvironment>,
+}
+
+impl VerifyCache {
+    /// Path of the cache file
+    pub fn path(fallout4: &Path) -> PathBuf {
+        fallout4.join(CACHE_DIR).join(VERIFY_CACHE_FILE)
+    }
+
+    /// Loads the cache, starting empty if there is none or it can't be read
+    pub fn load(fallout4: &Path) -> Self {
+        fs::read_to_string(Self::path(fallout4))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the cache file
+    pub fn save(&self, fallout4: &Path) -> Result<(), String> {
+        let path = Self::path(fallout4);
+        if let Some(parent) = path.parent() {
+            ensure_directory_exists(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Error serializing environment cache: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Error writing environment cache {}: {}", path.display(), e))
+    }
+
+    /// Finds the last check of a profile, if it was made with the same
+    /// settings and none of the files it read changed since
+    ///
+    /// # Arguments
+    /// * `profile` - Settings profile the check is for
+    /// * `settings` - Settings the check depends on
+    /// * `files` - Current state of the files the check reads, from `fingerprint_files`
+    pub fn find(&self, profile: &str, settings: &str, files: &BTreeMap<String, String>) -> Option<&VerifiedEnvironment> {
+        self.profiles
+            .get(profile)
+            .filter(|verified| verified.settings == settings && &verified.files == files)
+    }
+
+    /// Records a check that passed, replacing the profile's earlier one
+    pub fn record(&mut self, profile: &str, verified: VerifiedEnvironment) {
+        self.profiles.insert(profile.to_string(), verified);
+    }
+}
+
+/// Size and modified time of each existing file, keyed by its path
+pub fn fingerprint_files(paths: &[PathBuf]) -> BTreeMap<String, String> {
+    paths
+        .iter()
+        .filter_map(|path| file_fingerprint(path).map(|fingerprint| (path.display().to_string(), fingerprint)))
+        .collect()
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::answers::Prompt;
+use crate::cli::{ArchiverKind, Args, BuildMode};
+use crate::esp::split_plugin_name;
+use crate::style;
+use crate::paths::{archive2_path, find_fallout4, Paths};
+use crate::tools::creation_kit::CK_CONFLICTING_DLLS;
+use crate::ui::{print_build_modes, prompt_for_line, prompt_yes_no};
+use crate::validation::recommend_build_mode;
+
+/// Plugins of the base game and its DLC, which are never built
+const BASE_GAME_PLUGINS: [&str; 8] = [
+    "fallout4.esm",
+    "dlcrobot.esm",
+    "dlcworkshop01.esm",
+    "dlccoast.esm",
+    "dlcworkshop02.esm",
+    "dlcworkshop03.esm",
+    "dlcnukaworld.esm",
+    "dlcultrahighresolution.esm",
+];
+
+/// Working plugins created by the build itself
+const WORKING_PLUGINS: [&str; 3] = ["combinedobjects.esp", "previs.esp", "xprevispatch.esp"];
+
+/// Walks the user through setting up a build (--wizard).
+///
+/// Locates the game and xEdit (asking for their paths if they can't be
+/// found), points out environment problems, lets the user pick the plugin
+/// from the Data folder and the build mode, and asks for confirmation
+/// before anything runs.
+///
+/// # Arguments
+/// * `args` - Command line arguments; options already given are kept
+///
+/// # Returns
+/// * `Ok(Some(Args))` with the arguments of the build to run
+/// * `Ok(None)` if the user cancelled
+/// * `Err(String)` if input couldn't be read or the Data folder can't be listed
+pub fn run_wizard(mut args: Args) -> Result<Option<Args>, String> {
+    if let Some(rule) = style::rule() {
+        println!("{}", rule);
+    }
+    println!("Previsbine build wizard");
+    println!("Answer the questions below to set up the build. Nothing is changed until you confirm.");
+    println!();
+
+    // Step 1: game and tools
+    let fallout4 = match args.fallout4_path.as_ref().map(PathBuf::from) {
+        Some(path) => path,
+        None => match find_fallout4() {
+            Ok(path) => path,
+            Err(_) => prompt_for_existing_path("Fallout 4 folder (the one holding Fallout4.exe)", |path| {
+                path.join("Data").is_dir()
+            })?,
+        },
+    };
+    println!("Fallout 4: {}", fallout4.display());
+    args.fallout4_path = Some(fallout4.display().to_string());
+
+    if Paths::new(args.fo4edit_path.clone(), args.fallout4_path.clone(), false, None).is_err() {
+        let xedit = prompt_for_existing_path("Path of FO4Edit64.exe or xEdit64.exe", |path| path.is_file())?;
+        args.fo4edit_path = Some(xedit.display().to_string());
+    }
+
+    // Step 2: environment
+    if !check_environment(&fallout4, &mut args)? {
+        return Ok(None);
+    }
+
+    // Step 3: plugin
+    if args.plugin.is_none() {
+        args.plugin = Some(choose_plugin(&fallout4.join("Data"))?);
+    }
+    let plugin = args.plugin.clone().unwrap_or_default();
+
+    // Step 4: build mode
+    args.mode = choose_mode(&fallout4.join("Data").join(&plugin))?;
+    args.auto_mode = false;
+    args.mode_given = true;
+
+    // Step 5: confirmation
+    println!();
+    println!("Ready to build:");
+    println!("  Plugin:   {}", plugin);
+    println!("  Mode:     {}", args.mode);
+    println!("  Archiver: {}", args.archiver_kind());
+    println!("  Output:   {}", if args.no_archive { "loose files" } else { "BA2 archive" });
+    println!();
+    if !prompt_yes_no(Prompt::StartBuild, "Start the build?")? {
+        println!("Build cancelled");
+        return Ok(None);
+    }
+    Ok(Some(args))
+}
+
+/// Lists problems with the game folder and offers fixes where there are any
+///
+/// # Returns
+/// * `Ok(true)` to continue
+/// * `Ok(false)` if the user chose to stop
+fn check_environment(fallout4: &Path, args: &mut Args) -> Result<bool, String> {
+    let mut problems = 0;
+
+    if !fallout4.join("CreationKit.exe").exists() {
+        problems += 1;
+        println!("- The Creation Kit is not installed. Install it from Steam (Tools > Creation Kit: Fallout 4).");
+    }
+    if !fallout4.join("winhttp.dll").exists() {
+        problems += 1;
+        println!("- Creation Kit Platform Extended (CKPE) is not installed. It is required to generate previsbines.");
+    }
+    if args.archiver_kind() == ArchiverKind::Archive2 && !archive2_path(fallout4).exists() {
+        problems += 1;
+        println!("- Archive2.exe was not found (it comes with the Creation Kit).");
+        if prompt_yes_no(Prompt::UseNativeArchiver, "  Use the built-in archiver instead?")? {
+            args.archiver = ArchiverKind::Native;
+            args.use_bsarch = false;
+        }
+    }
+
+    let dlls: Vec<&str> = CK_CONFLICTING_DLLS
+        .iter()
+        .copied()
+        .filter(|dll| fallout4.join(dll).exists())
+        .collect();
+    if !dlls.is_empty() {
+        println!(
+            "- Found {}, which crash the Creation Kit. They are disabled while it runs and restored afterwards.",
+            dlls.join(", ")
+        );
+    }
+
+    if problems == 0 {
+        println!("Environment looks good");
+        return Ok(true);
+    }
+    prompt_yes_no(Prompt::IgnoreEnvironmentProblems, "Continue anyway? The build checks the environment again before it starts.")
+}
+
+/// Creation Club plugins are named like `ccBGSFO4001-PipBoy(Black).esl`
+fn is_creation_club(lowercase_name: &str) -> bool {
+    lowercase_name.starts_with("cc") && lowercase_name.get(5..8) == Some("fo4")
+}
+
+/// Lists the plugins in the Data folder previsbines can be built for,
+/// leaving out the base game, DLC, Creation Club and the build's working plugins
+///
+/// # Returns
+/// * `Ok(Vec<String>)` with the plugin file names, sorted case-insensitively
+/// * `Err(String)` if the Data folder can't be read
+pub fn list_buildable_plugins(data_dir: &Path) -> Result<Vec<String>, String> {
+    let mut plugins: Vec<String> = fs::read_dir(data_dir)
+        .map_err(|e| format!("Error reading {}: {}", data_dir.display(), e))?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| {
+            let lowercase = name.to_lowercase();
+            split_plugin_name(name).is_some()
+                && !BASE_GAME_PLUGINS.contains(&lowercase.as_str())
+                && !WORKING_PLUGINS.contains(&lowercase.as_str())
+                && !is_creation_club(&lowercase)
+        })
+        .collect();
+    plugins.sort_by_key(|name| name.to_lowercase());
+    Ok(plugins)
+}
+
+/// Lets the user pick a plugin from the Data folder, by number or name
+fn choose_plugin(data_dir: &Path) -> Result<String, String> {
+    let plugins = list_buildable_plugins(data_dir)?;
+
+    println!();
+    println!("Which plugin should previsbines be generated for?");
+    for (index, plugin) in plugins.iter().enumerate() {
+        println!("[{}] {}", index + 1, plugin);
+    }
+    println!("Or type the name of a new plugin to create it.");
+
+    loop {
+        let input = prompt_for_line("Plugin: ")?;
+        if input.is_empty() {
+            continue;
+        }
+        match input.parse::<usize>() {
+            Ok(number) if (1..=plugins.len()).contains(&number) => return Ok(plugins[number - 1].clone()),
+            Ok(_) => println!("Enter a number between 1 and {}", plugins.len()),
+            Err(_) => return Ok(input),
+        }
+    }
+}
+
+/// Explains the build modes and lets the user pick one, suggesting the
+/// mode that suits the plugin
+fn choose_mode(plugin_path: &Path) -> Result<BuildMode, String> {
+    let recommendation = recommend_build_mode(plugin_path).ok().flatten();
+
+    println!();
+    println!("Which build mode?");
+    print_build_modes();
+    let default = match &recommendation {
+        Some(recommendation) => {
+            println!(
+                "{} mode is recommended for this plugin: {}",
+                recommendation.mode,
+                recommendation.reasons.join(", ")
+            );
+            recommendation.mode
+        }
+        None => BuildMode::Clean,
+    };
+
+    loop {
+        let input = prompt_for_line(&format!("Mode (Enter for {}): ", default))?;
+        match input.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "1" | "clean" => return Ok(BuildMode::Clean),
+            "2" | "filtered" => return Ok(BuildMode::Filtered),
+            "3" | "xbox" => return Ok(BuildMode::Xbox),
+            "4" | "previs-only" => return Ok(BuildMode::PrevisOnly),
+            _ => println!("Enter 1, 2, 3 or 4"),
+        }
+    }
+}
+
+/// Asks for a path until one passing `is_valid` is entered
+pub(crate) fn prompt_for_existing_path(what: &str, is_valid: impl Fn(&Path) -> bool) -> Result<PathBuf, String> {
+    loop {
+        let input = prompt_for_line(&format!("{}: ", what))?;
+        let path = PathBuf::from(input.trim_matches('"'));
+        if is_valid(&path) {
+            return Ok(path);
+        }
+        println!("{} is not valid, try again", path.display());
+    }
+}
@@ -0,0 +1,993 @@
+//! Builds against a fake installation with the external tools simulated,
+//! covering each stage's success and failure paths.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use generate_previsbines::backup::{latest_backup, read_manifest, RunBackup};
+use generate_previsbines::check::check_previsbines;
+use generate_previsbines::clean::run_clean_previsbines;
+use generate_previsbines::cli::{Ba2Version, BuildMode, CkpeFormat, Command};
+use generate_previsbines::config::{lint_config, Config};
+use generate_previsbines::esp::{read_cell_previsbines, read_plugin_header, resolve_plugin_name, write_plugin_header, FLAG_LIGHT, FLAG_MASTER};
+use generate_previsbines::fixture::{create_fixture, Fixture, FixtureOptions};
+use generate_previsbines::game::read_exe_version;
+use generate_previsbines::headless::set_non_interactive;
+use generate_previsbines::leftovers::{find_disabled_dlls, restore_disabled_dlls};
+use generate_previsbines::logging::{apply_retention, expired_runs, Retention};
+use generate_previsbines::manifest::BuildManifest;
+use generate_previsbines::paths::Paths;
+use generate_previsbines::tools::{
+    check_bsarch_output, enable_simulation, read_ba2_entries, read_ba2_records, set_ck_launcher, simulate_failure, split_command_line,
+    write_ba2, BsarchOptions, SimulatedFailure,
+};
+use generate_previsbines::utils::timestamp;
+use generate_previsbines::validation::{check_plugin, install_location_warnings, light_plugin_warning, recommend_build_mode};
+use generate_previsbines::verify_cache::{VerifyCache, DEFAULT_PROFILE};
+use generate_previsbines::{Args, BuildStage, PrevisbineBuilder};
+
+const MERGE_PRECOMBINES_SCRIPT: &str = "Batch_FO4MergeCombinedObjectsAndCheck.pas";
+const MERGE_PREVIS_SCRIPT: &str = "Batch_FO4MergePreVisAndAutoUpdateRefr.pas";
+
+/// Builds share the log file and warning list, so they run one at a time
+static BUILD_LOCK: Mutex<()> = Mutex::new(());
+
+/// A fake installation in its own temporary folder, removed when dropped
+struct TestInstall {
+    root: PathBuf,
+    fixture: Fixture,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestInstall {
+    fn new(name: &str) -> Self {
+        Self::with_options(name, FixtureOptions::default())
+    }
+
+    fn with_options(name: &str, options: FixtureOptions) -> Self {
+        let guard = BUILD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        enable_simulation();
+        set_non_interactive();
+
+        let root = env::temp_dir()
+            .join("generate_previsbines-tests")
+            .join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let fixture = create_fixture(&root, &options).expect("fixture should be created");
+        Self { root, fixture, _guard: guard }
+    }
+
+    fn data(&self, name: &str) -> PathBuf {
+        self.fixture.fallout4.join("Data").join(name)
+    }
+
+    fn creation_kit(&self) -> PathBuf {
+        self.fixture.fallout4.join("CreationKit.exe")
+    }
+
+    /// Builds the fixture plugin with the given extra arguments
+    fn build(&self, extra: &[&str]) -> Result<(), String> {
+        let fallout4 = self.fixture.fallout4.display().to_string();
+        let fo4edit = self.fixture.fo4edit.display().to_string();
+        let runs_dir = self.root.join("runs").display().to_string();
+        let mut argv = vec![
+            "generate_previsbines",
+            "--non-interactive",
+            "--fallout4-path",
+            &fallout4,
+            "--fo4edit-path",
+            &fo4edit,
+            "--runs-dir",
+            &runs_dir,
+        ];
+        argv.extend_from_slice(extra);
+        argv.push("Fixture.esp");
+
+        let args = Args::try_parse_from(argv).map_err(|e| e.to_string())?;
+        PrevisbineBuilder::new(args)?.run()
+    }
+}
+
+impl Drop for TestInstall {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn assert_error_contains(result: Result<(), String>, expected: &str) {
+    match result {
+        Ok(()) => panic!("build should fail with \"{}\"", expected),
+        Err(e) => assert!(e.contains(expected), "expected \"{}\" in \"{}\"", expected, e),
+    }
+}
+
+fn archive_entries(path: &Path) -> Vec<String> {
+    read_ba2_entries(path).expect("archive should be readable")
+}
+
+#[test]
+fn clean_build_archives_precombines_and_previs() {
+    let install = TestInstall::new("clean-build");
+    install.build(&["--start-stage", "1"]).expect("build should succeed");
+
+    let entries = archive_entries(&install.data("Fixture - Main.ba2"));
+    assert!(entries.iter().any(|entry| entry.to_lowercase().starts_with("meshes\\precombined\\")));
+    assert!(entries.iter().any(|entry| entry.to_lowercase().starts_with("vis\\")));
+    assert!(install.data("Fixture - Geometry.csg").exists());
+    assert!(install.data("Fixture.cdx").exists());
+    assert!(!install.data("Fixture - Geometry.psg").exists());
+    assert!(!install.data("CombinedObjects.esp").exists());
+    assert!(!install.data("Previs.esp").exists());
+}
+
+#[test]
+fn filtered_build_skips_psg_and_cdx() {
+    let install = TestInstall::new("filtered-build");
+    install.build(&["--mode", "filtered", "--start-stage", "1"]).expect("build should succeed");
+
+    assert!(install.data("Fixture - Main.ba2").exists());
+    assert!(!install.data("Fixture - Geometry.csg").exists());
+    assert!(!install.data("Fixture.cdx").exists());
+}
+
+#[test]
+fn builds_with_every_ckpe_configuration() {
+    for ckpe in [CkpeFormat::Toml, CkpeFormat::Legacy] {
+        let install = TestInstall::with_options("ckpe-format", FixtureOptions { ckpe, ..FixtureOptions::default() });
+        install.build(&["--start-stage", "1"]).unwrap_or_else(|e| panic!("{:?} build failed: {}", ckpe, e));
+    }
+}
+
+#[test]
+fn generate_precombines_fails_without_output() {
+    let install = TestInstall::new("precombines-no-output");
+    simulate_failure(&install.creation_kit(), "GeneratePrecombined", SimulatedFailure::NoOutput);
+    assert_error_contains(install.build(&["--start-stage", "1"]), "failed to create file CombinedObjects.esp");
+}
+
+#[test]
+fn generate_precombines_fails_when_out_of_handles() {
+    let install = TestInstall::new("precombines-handles");
+    simulate_failure(
+        &install.creation_kit(),
+        "GeneratePrecombined",
+        SimulatedFailure::Log("DEFAULT: OUT OF HANDLE ARRAY ENTRIES\n".to_string()),
+    );
+    assert_error_contains(install.build(&["--start-stage", "1"]), "ran out of Reference Handles");
+}
+
+#[test]
+fn merge_precombines_fails_without_script_log() {
+    let install = TestInstall::new("merge-precombines-no-log");
+    simulate_failure(&install.fixture.fo4edit, MERGE_PRECOMBINES_SCRIPT, SimulatedFailure::NoOutput);
+    assert_error_contains(install.build(&["--start-stage", "1"]), "did not produce a log file");
+}
+
+#[test]
+fn merge_precombines_fails_when_script_does_not_complete() {
+    let install = TestInstall::new("merge-precombines-incomplete");
+    simulate_failure(
+        &install.fixture.fo4edit,
+        MERGE_PRECOMBINES_SCRIPT,
+        SimulatedFailure::Log("Error: could not load CombinedObjects.esp\n".to_string()),
+    );
+    assert_error_contains(install.build(&["--start-stage", "1"]), "script Batch_FO4MergeCombinedObjectsAndCheck.pas failed");
+}
+
+#[test]
+fn compress_psg_fails_without_csg() {
+    let install = TestInstall::new("compress-psg-no-output");
+    simulate_failure(&install.creation_kit(), "CompressPSG", SimulatedFailure::NoOutput);
+    assert_error_contains(install.build(&["--start-stage", "1"]), "failed to create file Fixture - Geometry.csg");
+}
+
+#[test]
+fn build_cdx_fails_without_cdx() {
+    let install = TestInstall::new("build-cdx-no-output");
+    simulate_failure(&install.creation_kit(), "BuildCDX", SimulatedFailure::NoOutput);
+    assert_error_contains(install.build(&["--start-stage", "1"]), "failed to create file Fixture.cdx");
+}
+
+#[test]
+fn generate_previs_fails_without_output() {
+    let install = TestInstall::new("previs-no-output");
+    simulate_failure(&install.creation_kit(), "GeneratePreVisData", SimulatedFailure::NoOutput);
+    assert_error_contains(install.build(&["--start-stage", "1"]), "failed to create file Previs.esp");
+}
+
+#[test]
+fn generate_previs_fails_when_visibility_task_incomplete() {
+    let install = TestInstall::new("previs-incomplete");
+    simulate_failure(
+        &install.creation_kit(),
+        "GeneratePreVisData",
+        SimulatedFailure::Log("ERROR: visibility task did not complete.\n".to_string()),
+    );
+    assert_error_contains(install.build(&["--start-stage", "1"]), "visibility task did not complete");
+}
+
+#[test]
+fn merge_previs_fails_when_script_reports_errors() {
+    let install = TestInstall::new("merge-previs-errors");
+    simulate_failure(
+        &install.fixture.fo4edit,
+        MERGE_PREVIS_SCRIPT,
+        SimulatedFailure::Log("Completed: 3 Errors.\n".to_string()),
+    );
+    assert_error_contains(install.build(&["--start-stage", "1"]), "Merge Previs script did not complete successfully");
+}
+
+#[test]
+fn stages_require_earlier_results() {
+    let install = TestInstall::new("prerequisites");
+    assert_error_contains(install.build(&["--only", "2"]), "No precombined meshes found");
+    assert_error_contains(install.build(&["--only", "4"]), "No Geometry.psg file found");
+    assert_error_contains(install.build(&["--only", "7"]), "No visibility files found");
+}
+
+#[test]
+fn clean_only_stages_are_refused_in_filtered_mode() {
+    let install = TestInstall::new("filtered-only-stage");
+    assert_error_contains(install.build(&["--mode", "filtered", "--only", "4"]), "only available in Clean mode");
+}
+
+#[test]
+fn previs_only_build_reuses_existing_precombines() {
+    let install = TestInstall::new("previs-only");
+    assert_error_contains(install.build(&["--mode", "previs-only"]), "No precombined meshes found loose or in Fixture - Main.ba2");
+
+    install.build(&["--start-stage", "1"]).expect("clean build should succeed");
+    install.build(&["--mode", "previs-only"]).expect("previs-only build should succeed");
+
+    let entries = archive_entries(&install.data("Fixture - Main.ba2"));
+    assert!(entries.iter().any(|entry| entry.to_lowercase().starts_with("meshes\\precombined\\")));
+    assert!(entries.iter().any(|entry| entry.to_lowercase().starts_with("vis\\")));
+    assert!(!install.data("CombinedObjects.esp").exists());
+    assert!(!install.data("Previs.esp").exists());
+    assert_error_contains(install.build(&["--mode", "previs-only", "--only", "1"]), "isn't run in previs-only mode");
+}
+
+#[test]
+fn disabled_dlls_are_restored_unless_reinstalled() {
+    let install = TestInstall::new("disabled-dlls");
+    let fallout4 = &install.fixture.fallout4;
+    fs::write(fallout4.join("d3d11.dll-PJMdisabled"), "enb").unwrap();
+    fs::write(fallout4.join("dxgi.dll-PJMdisabled"), "old reshade").unwrap();
+    fs::write(fallout4.join("dxgi.dll"), "new reshade").unwrap();
+
+    let dlls = find_disabled_dlls(fallout4);
+    assert_eq!(dlls.len(), 2);
+    let restored = restore_disabled_dlls(&dlls).expect("DLLs should be restored");
+
+    assert_eq!(restored, vec![fallout4.join("d3d11.dll")]);
+    assert_eq!(fs::read_to_string(fallout4.join("d3d11.dll")).unwrap(), "enb");
+    assert!(!fallout4.join("d3d11.dll-PJMdisabled").exists());
+    assert_eq!(fs::read_to_string(fallout4.join("dxgi.dll")).unwrap(), "new reshade");
+    assert!(fallout4.join("dxgi.dll-PJMdisabled").exists());
+}
+
+#[test]
+fn queue_continues_past_failed_plugins_and_reports_each() {
+    let install = TestInstall::new("queue");
+    let fallout4 = install.fixture.fallout4.display().to_string();
+    let fo4edit = install.fixture.fo4edit.display().to_string();
+    let runs_dir = install.root.join("runs");
+    let runs = runs_dir.display().to_string();
+    let args = Args::try_parse_from([
+        "generate_previsbines",
+        "--non-interactive",
+        "--fallout4-path",
+        &fallout4,
+        "--fo4edit-path",
+        &fo4edit,
+        "--runs-dir",
+        &runs,
+        "--start-stage",
+        "1",
+        "Missing.esp",
+        "--queue",
+        "Fixture.esp",
+    ])
+    .expect("arguments should parse");
+
+    assert_error_contains(PrevisbineBuilder::run_queue(args), "1 of 2 plugins failed: Missing.esp");
+    assert!(install.data("Fixture - Main.ba2").exists());
+
+    let report_path = fs::read_dir(&runs_dir)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.file_name().unwrap().to_string_lossy().starts_with("queue-"))
+        .expect("queue report should be written");
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(report_path).unwrap()).unwrap();
+    assert_eq!(report["succeeded"], 1);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["builds"][0]["plugin"], "Missing.esp");
+    assert_eq!(report["builds"][1]["success"], true);
+}
+
+#[test]
+fn config_lint_reports_unknown_conflicting_and_missing_settings() {
+    let install = TestInstall::new("config-lint");
+    let config_path = install.root.join("generate_previsbines.toml");
+    let missing = install.root.join("missing");
+    fs::write(
+        &config_path,
+        format!(
+            "fallout4-path = '{}'\nfo4edit-path = '{}'\nbsarch_path = 'BSArch.exe'\nbsarch-path = '{}'\nuse-bsarch = true\n[profiles.test]\nmode = 'fast'\n",
+            install.fixture.fallout4.display(),
+            install.fixture.fo4edit.display(),
+            missing.display()
+        ),
+    )
+    .unwrap();
+
+    let problems = lint_config(&config_path).expect("config should be read");
+    let expected = [
+        "WARNING - Unknown setting bsarch_path is ignored, did you mean bsarch-path?",
+        "WARNING - use-bsarch is deprecated",
+        "ERROR - Unknown build mode 'fast'",
+        &format!("ERROR - bsarch-path: {} doesn't exist", missing.display()),
+        "WARNING - bsarch-path is set but only used with archiver = \"bsarch\"",
+    ];
+    for expected in expected {
+        assert!(problems.iter().any(|problem| problem.starts_with(expected)), "expected \"{}\" in {:?}", expected, problems);
+    }
+    assert_eq!(problems.len(), expected.len(), "unexpected problems in {:?}", problems);
+}
+
+#[test]
+fn ck_launcher_command_is_split_and_checked() {
+    assert_eq!(
+        split_command_line(r#""C:\Program Files\Loader\loader.exe" --wait  --profile "Previs Build""#),
+        vec!["C:\\Program Files\\Loader\\loader.exe", "--wait", "--profile", "Previs Build"]
+    );
+    assert_eq!(split_command_line("loader.exe"), vec!["loader.exe"]);
+
+    let install = TestInstall::new("ck-launcher");
+    let missing = install.root.join("tools").join("loader.exe");
+    assert_error_contains(set_ck_launcher(&format!("\"{}\" --wait", missing.display())), "CK launcher");
+    assert_error_contains(set_ck_launcher("  "), "empty");
+}
+
+#[test]
+fn tool_env_applies_to_every_stage_or_one() {
+    let args = Args::try_parse_from([
+        "generate_previsbines",
+        "--tool-env",
+        "SHIM=all",
+        "--tool-env",
+        "generate-previs:SHIM=previs",
+        "--tool-env",
+        "6:OPTIONS=a=b",
+        "--tool-env",
+        "merge_previs:OTHER=1",
+        "Fixture.esp",
+    ])
+    .expect("arguments should parse");
+
+    let pair = |name: &str, value: &str| (name.to_string(), value.to_string());
+    assert_eq!(args.tool_env_for(BuildStage::GeneratePrevis), vec![pair("OPTIONS", "a=b"), pair("SHIM", "previs")]);
+    assert_eq!(args.tool_env_for(BuildStage::ArchiveVis), vec![pair("SHIM", "all")]);
+    assert!(Args::try_parse_from(["generate_previsbines", "--tool-env", "bogus:SHIM=1", "Fixture.esp"]).is_err());
+}
+
+#[test]
+fn exe_version_split_across_read_chunks_is_found() {
+    let install = TestInstall::new("exe-version");
+    // The signature ends just past the first 64 KB chunk, the version in the next one
+    let mut exe = vec![0u8; 64 * 1024 - 2];
+    exe.extend_from_slice(&[0xBD, 0x04, 0xEF, 0xFE, 0, 0, 1, 0]);
+    exe.extend_from_slice(&((1u32 << 16) | 10).to_le_bytes());
+    exe.extend_from_slice(&(163u32 << 16).to_le_bytes());
+    exe.extend_from_slice(&[0u8; 4096]);
+    let exe_path = install.root.join("Large.exe");
+    fs::write(&exe_path, exe).unwrap();
+
+    assert_eq!(read_exe_version(&exe_path).map(|version| version.to_string()), Some("1.10.163.0".to_string()));
+}
+
+#[test]
+fn default_runs_dir_is_next_to_the_executable() {
+    let args = Args::try_parse_from(["generate_previsbines", "Fixture.esp"]).expect("arguments should parse");
+    let exe_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    assert_eq!(PathBuf::from(&args.runs_dir), exe_dir.join("previsbine-runs"));
+}
+
+#[test]
+fn max_archive_size_is_bounded() {
+    let parse = |size: &str| Args::try_parse_from(["generate_previsbines", "--max-archive-size", size, "Fixture.esp"]);
+    for size in ["0", "4097", "18446744073709551615"] {
+        assert!(parse(size).is_err(), "--max-archive-size {} should be rejected", size);
+    }
+    let args = parse("4096").expect("arguments should parse");
+    assert_eq!(args.max_archive_bytes(), Ok(4096 * 1024 * 1024));
+}
+
+#[test]
+fn retries_from_config_are_overridden_by_retry() {
+    let config: Config = toml::from_str("[retries]\ngenerate_previs = 2\nmerge-previs = 5\n").unwrap();
+    let matches = Args::command()
+        .try_get_matches_from(["generate_previsbines", "--retry", "merge-previs=1", "Fixture.esp"])
+        .expect("arguments should parse");
+    let mut args = Args::from_arg_matches(&matches).unwrap();
+    config.apply(&mut args, &matches).expect("config should apply");
+
+    assert_eq!(args.retries_for(BuildStage::GeneratePrevis), 2);
+    assert_eq!(args.retries_for(BuildStage::MergePrevis), 1);
+    assert_eq!(args.retries_for(BuildStage::ArchiveVis), 0);
+
+    let install = TestInstall::new("config-retries");
+    let config_path = install.root.join("generate_previsbines.toml");
+    fs::write(&config_path, "[retries]\ngenerate_previs = 2\n[profiles.test.retries]\nprevis = 1\n").unwrap();
+    let problems = lint_config(&config_path).expect("config should be read");
+    assert_eq!(problems.len(), 1, "unexpected problems in {:?}", problems);
+    assert!(problems[0].starts_with("ERROR - Unknown stage 'previs' in retries"), "{:?}", problems);
+}
+
+#[test]
+fn bsarch_options_from_config_yield_to_the_command_line() {
+    let config: Config = toml::from_str("bsarch-multithreaded = false\nbsarch-compress = true\nbsarch-share = true\n").unwrap();
+    let options = |argv: &[&str]| {
+        let matches = Args::command().try_get_matches_from(argv).expect("arguments should parse");
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches).expect("config should apply");
+        BsarchOptions::from_args(&args).flags()
+    };
+    assert_eq!(options(&["generate_previsbines", "Fixture.esp"]), vec!["-z", "-share"]);
+    assert_eq!(options(&["generate_previsbines", "--bsarch-no-compress", "Fixture.esp"]), vec!["-share"]);
+    assert!(config.set_keys().starts_with(&["bsarch-multithreaded", "bsarch-compress", "bsarch-share"]));
+}
+
+#[test]
+fn fallout_london_keeps_an_explicit_ba2_version() {
+    let parse = |argv: &[&str]| {
+        let matches = Args::command().try_get_matches_from(argv).expect("arguments should parse");
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.apply_presets(&matches);
+        args.ba2_version
+    };
+    assert_eq!(parse(&["generate_previsbines", "--fallout-london", "Fixture.esp"]), Ba2Version::V1);
+    assert_eq!(
+        parse(&["generate_previsbines", "--fallout-london", "--ba2-version", "v8", "Fixture.esp"]),
+        Ba2Version::V8
+    );
+}
+
+#[test]
+fn bsarch_output_naming_error_files_is_not_a_failure() {
+    let packed = [
+        "Packing: meshes\\precombined\\errorcheck.nif",
+        "meshes\\props\\FailedReactor\\cannot_open.nif",
+        "Errors: 0",
+        "Done.",
+    ];
+    check_bsarch_output("pack", packed.into_iter()).expect("file names mentioning errors are fine");
+
+    for failure in ["Error: Access denied", "EFCreateError: Cannot create file \"Fixture - Main.ba2\"", "Access violation at address 0040A1B2"] {
+        assert_error_contains(check_bsarch_output("pack", ["Packing: vis\\0000F00D.uvd", failure].into_iter()), failure);
+    }
+}
+
+#[test]
+fn corrupt_ba2_file_count_is_rejected() {
+    let install = TestInstall::new("corrupt-ba2");
+    let mut header = b"BTDX".to_vec();
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(b"GNRL");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(&24u64.to_le_bytes());
+    let archive = install.root.join("Corrupt - Main.ba2");
+    fs::write(&archive, header).unwrap();
+
+    assert_error_contains(read_ba2_records(&archive).map(|_| ()), "is truncated or corrupt");
+    assert_error_contains(read_ba2_entries(&archive).map(|_| ()), "is truncated or corrupt");
+}
+
+#[test]
+fn outdated_xedit_scripts_are_refused() {
+    let install = TestInstall::with_options(
+        "outdated-scripts",
+        FixtureOptions { script_version: 9, ..FixtureOptions::default() },
+    );
+    assert_error_contains(install.build(&["--start-stage", "1"]), "is outdated");
+}
+
+#[test]
+fn changed_environment_is_verified_again() {
+    let install = TestInstall::new("verify-cache");
+    install.build(&["--start-stage", "1"]).expect("build should succeed");
+    let cache = VerifyCache::load(&install.fixture.fallout4);
+    assert!(cache.profiles.contains_key(DEFAULT_PROFILE));
+
+    let script = install.fixture.fo4edit.parent().unwrap().join("Edit Scripts").join(MERGE_PREVIS_SCRIPT);
+    fs::write(&script, "unit Outdated;\nconst BatchVersion = 9;\nend.\n").unwrap();
+    assert_error_contains(install.build(&["--start-stage", "1"]), "is outdated");
+}
+
+#[test]
+fn missing_ckpe_configuration_is_refused() {
+    let install = TestInstall::new("missing-ckpe");
+    fs::remove_file(install.fixture.fallout4.join("CreationKitPlatformExtended.ini")).unwrap();
+    assert_error_contains(install.build(&["--start-stage", "1"]), "CKPE not installed properly");
+}
+
+#[test]
+fn leftover_precombines_need_auto_clean_when_non_interactive() {
+    let install = TestInstall::new("leftovers");
+    let precombined = install.data("meshes").join("precombined");
+    fs::create_dir_all(&precombined).unwrap();
+    fs::write(precombined.join("00000000_00000000_OC.nif"), b"left over").unwrap();
+
+    assert!(install.build(&["--start-stage", "1"]).is_err());
+    install.build(&["--start-stage", "1", "--auto-clean"]).expect("build should clean up and succeed");
+}
+
+#[test]
+fn large_ck_logs_are_capped_and_compressed() {
+    let install = TestInstall::new("large-ck-log");
+    let log: String = (0..20_000).map(|line| format!("DEFAULT: Processing reference {}\n", line)).collect();
+    simulate_failure(&install.creation_kit(), "GeneratePrecombined", SimulatedFailure::Log(log));
+    install.build(&["--start-stage", "1"]).expect("build should succeed");
+
+    let run_dir = fs::read_dir(install.root.join("runs"))
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.is_dir())
+        .expect("run folder should exist");
+    assert!(run_dir.join("1-GeneratePrecombines-CreationKit.log.gz").exists());
+    let build_log = fs::read_to_string(run_dir.join("Fixture.log")).unwrap();
+    assert!(build_log.contains("DEFAULT: Processing reference 0\n"));
+    assert!(build_log.contains("DEFAULT: Processing reference 19999\n"));
+    assert!(!build_log.contains("DEFAULT: Processing reference 10000\n"));
+    assert!(build_log.contains("lines omitted"));
+}
+
+#[test]
+fn outputs_in_nested_folders_are_found_and_archived() {
+    let install = TestInstall::new("nested-outputs");
+    let precombined = install.data("meshes").join("precombined").join("Commonwealth");
+    fs::create_dir_all(&precombined).unwrap();
+    fs::write(precombined.join("0000F4A3_5C1D2B7E_OC.NIF"), b"nested").unwrap();
+    install.build(&["--only", "3"]).expect("precombines in a subfolder should be archived");
+
+    let vis = install.data("vis").join("Commonwealth");
+    fs::create_dir_all(&vis).unwrap();
+    fs::write(vis.join("0000003C.UVD"), b"nested").unwrap();
+    install.build(&["--only", "8"]).expect("vis files in a subfolder should be archived");
+
+    let entries: Vec<String> = archive_entries(&install.data("Fixture - Main.ba2"))
+        .iter()
+        .map(|entry| entry.to_lowercase())
+        .collect();
+    assert!(entries.contains(&"meshes\\precombined\\commonwealth\\0000f4a3_5c1d2b7e_oc.nif".to_string()));
+    assert!(entries.contains(&"vis\\commonwealth\\0000003c.uvd".to_string()));
+}
+
+#[test]
+fn successful_build_writes_manifest_of_outputs() {
+    let install = TestInstall::new("manifest");
+    install.build(&["--start-stage", "1"]).expect("build should succeed");
+
+    let manifest_path = fs::read_dir(install.root.join("runs"))
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path().join("Fixture-manifest.json"))
+        .find(|path| path.exists())
+        .expect("manifest should be written");
+    let manifest = BuildManifest::read(&manifest_path).unwrap();
+    let paths: Vec<String> = manifest.files.iter().map(|file| file.key()).collect();
+    for expected in [
+        "fixture.esp",
+        "fixture - geometry.csg",
+        "fixture.cdx",
+        "fixture - main.ba2",
+        "fixture - main.ba2\\meshes\\precombined\\0000f4a3_5c1d2b7e_oc.nif",
+        "fixture - main.ba2\\vis\\0000003c.uvd",
+    ] {
+        assert!(paths.contains(&expected.to_string()), "{} missing from {:?}", expected, paths);
+    }
+    assert!(manifest.files.iter().all(|file| file.sha256.len() == 64));
+}
+
+#[test]
+fn run_folder_is_exported_to_artifacts_dir() {
+    let install = TestInstall::new("artifacts");
+    let artifacts = install.root.join("artifacts");
+    let artifacts_arg = artifacts.display().to_string();
+    install
+        .build(&["--start-stage", "1", "--collect-outputs", "--artifacts-dir", &artifacts_arg])
+        .expect("build should succeed");
+    assert!(artifacts.join("Fixture-report.json").exists());
+    assert!(artifacts.join("Fixture-manifest.json").exists());
+    assert!(artifacts.join("output").join("Fixture - Main.ba2").exists());
+
+    simulate_failure(&install.creation_kit(), "GeneratePrecombined", SimulatedFailure::NoOutput);
+    assert!(install.build(&["--start-stage", "1", "--artifacts-dir", &artifacts_arg]).is_err());
+    assert!(artifacts.join("Fixture-diagnostics.zip").exists());
+}
+
+#[test]
+fn packaged_build_writes_release_notes() {
+    let install = TestInstall::new("release-notes");
+    install
+        .build(&["--start-stage", "1", "--package", "--package-version", "1.2", "--release-notes", "bbcode"])
+        .expect("build should succeed");
+
+    let notes_path = fs::read_dir(install.root.join("runs"))
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path().join("Fixture-1.2-notes.bbcode.txt"))
+        .find(|path| path.exists())
+        .expect("release notes should be written");
+    let notes = fs::read_to_string(notes_path).unwrap();
+    assert!(notes.starts_with("[size=5][b]Fixture.esp 1.2[/b][/size]"));
+    assert!(notes.contains("[*]Fixture - Main.ba2"));
+    assert!(notes.contains("[*]Build mode: clean"));
+    assert!(notes.contains("[*]Cells: "));
+}
+
+#[test]
+fn retention_removes_old_and_surplus_run_folders() {
+    let runs = env::temp_dir()
+        .join("generate_previsbines-tests")
+        .join(format!("retention-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&runs);
+    let today = timestamp();
+    for name in [
+        "Fixture-20200101-120000".to_string(),
+        "Fixture-20200102-120000".to_string(),
+        format!("Fixture-{}", today),
+        "Other-20200101-120000".to_string(),
+        "notarun".to_string(),
+    ] {
+        fs::create_dir_all(runs.join(name)).unwrap();
+    }
+    let names = |retention: &Retention| -> Vec<String> {
+        expired_runs(&runs, retention)
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+
+    let keep_two = Retention { keep_runs: Some(2), ..Retention::default() };
+    assert_eq!(names(&keep_two), ["Fixture-20200101-120000"]);
+    let keep_week = Retention { keep_days: Some(7), ..Retention::default() };
+    assert_eq!(names(&keep_week), ["Fixture-20200101-120000", "Other-20200101-120000", "Fixture-20200102-120000"]);
+
+    let removed = apply_retention(&runs, &Retention { plugin: Some("other".to_string()), ..keep_week }).unwrap();
+    assert_eq!(removed.len(), 1);
+    assert!(!runs.join("Other-20200101-120000").exists());
+    assert!(runs.join(format!("Fixture-{}", today)).exists());
+    let _ = fs::remove_dir_all(&runs);
+}
+
+#[test]
+fn risky_install_locations_are_flagged() {
+    let warnings = |path: &str| install_location_warnings(Path::new(path));
+    assert!(warnings("/games/Program Files (x86)/Steam/steamapps/common/Fallout 4")[0].contains("Program Files"));
+    assert!(warnings("/home/modder/OneDrive - Contoso/Games/Fallout 4")[0].contains("sync"));
+    assert!(warnings("//nas/games/Fallout 4")[0].contains("network drive"));
+    assert!(warnings("/games/Fallout 4").is_empty());
+}
+
+/// A stub executable with a version resource of the given version
+fn versioned_exe(major: u16, minor: u16, build: u16, revision: u16) -> Vec<u8> {
+    let mut exe = b"MZ stub".to_vec();
+    exe.extend([0xBD, 0x04, 0xEF, 0xFE, 0x00, 0x00, 0x01, 0x00]);
+    exe.extend((u32::from(major) << 16 | u32::from(minor)).to_le_bytes());
+    exe.extend((u32::from(build) << 16 | u32::from(revision)).to_le_bytes());
+    exe
+}
+
+#[test]
+fn fallout_london_install_needs_old_gen_creation_kit() {
+    let install = TestInstall::new("fallout-london");
+    let fallout4 = &install.fixture.fallout4;
+    fs::write(install.data("LondonWorldSpace.esm"), b"").unwrap();
+    fs::write(fallout4.join("Fallout4.exe"), versioned_exe(1, 10, 163, 0)).unwrap();
+    fs::write(fallout4.join("f4se_loader.exe"), b"").unwrap();
+    fs::write(fallout4.join("f4se_1_10_163.dll"), b"").unwrap();
+
+    fs::write(install.creation_kit(), versioned_exe(1, 10, 943, 1)).unwrap();
+    assert_error_contains(install.build(&["--start-stage", "1"]), "next-gen Creation Kit");
+
+    fs::write(install.creation_kit(), versioned_exe(1, 10, 162, 0)).unwrap();
+    install.build(&["--start-stage", "1"]).expect("build with the old-gen Creation Kit should succeed");
+}
+
+#[test]
+fn seed_renamed_to_forced_extension_is_flagged() {
+    let install = TestInstall::new("plugin-extension");
+    let data_dir = install.fixture.fallout4.join("Data");
+    fs::rename(install.data("Fixture.esp"), install.data("xPrevisPatch.esp")).unwrap();
+
+    let (name, file_name) = resolve_plugin_name("Fixture", &data_dir, Some(".esm")).unwrap();
+    assert_eq!((name.as_str(), file_name.as_str()), ("Fixture", "Fixture.esm"));
+    assert!(resolve_plugin_name("Fixture.esp", &data_dir, Some(".esl")).unwrap_err().contains("--plugin-extension esl"));
+
+    let paths = Paths::new(
+        Some(install.fixture.fo4edit.display().to_string()),
+        Some(install.fixture.fallout4.display().to_string()),
+        false,
+        None,
+    )
+    .unwrap();
+    let backup = RunBackup::new(&install.fixture.fallout4, &file_name, false);
+    check_plugin(&paths, &file_name, "Fixture - Main.ba2", &[], &backup, |_| Ok(true), |_| Ok(None))
+        .expect("seed should be renamed");
+
+    let header = read_plugin_header(&install.data("Fixture.esm")).expect("plugin should be renamed");
+    assert_eq!(header.flags & FLAG_MASTER, FLAG_MASTER);
+    assert_eq!(resolve_plugin_name("Fixture", &data_dir, None).unwrap().1, "Fixture.esm");
+}
+
+#[test]
+fn light_plugin_with_new_cells_is_flagged() {
+    let install = TestInstall::new("light-plugin");
+    let plugin = install.data("Fixture.esp");
+    assert!(light_plugin_warning(&plugin).unwrap().is_none());
+
+    let mut header = read_plugin_header(&plugin).unwrap();
+    header.flags |= FLAG_LIGHT;
+    write_plugin_header(&plugin, &header).unwrap();
+    assert!(light_plugin_warning(&plugin).unwrap().is_none(), "a light plugin without cells is fine");
+
+    // An empty CELL record with a FormID of the plugin itself
+    let mut cell = b"CELL".to_vec();
+    cell.extend([0u8; 8]);
+    cell.extend(0x0100_0800u32.to_le_bytes());
+    cell.extend([0u8; 8]);
+    let mut contents = fs::read(&plugin).unwrap();
+    contents.extend(cell);
+    fs::write(&plugin, contents).unwrap();
+    let warning = light_plugin_warning(&plugin).unwrap().expect("new cells in a light plugin should be flagged");
+    assert!(warning.contains("1 new cell"), "{}", warning);
+}
+
+#[test]
+fn seed_chosen_from_several_templates_is_copied() {
+    let install = TestInstall::new("seed-templates");
+    fs::rename(install.data("Fixture.esp"), install.data("TemplateA.esp")).unwrap();
+    fs::copy(install.data("TemplateA.esp"), install.data("TemplateB.esp")).unwrap();
+    let templates = vec!["TemplateA.esp".to_string(), "Missing.esp".to_string(), "TemplateB.esp".to_string()];
+
+    let paths = Paths::new(
+        Some(install.fixture.fo4edit.display().to_string()),
+        Some(install.fixture.fallout4.display().to_string()),
+        false,
+        None,
+    )
+    .unwrap();
+    let backup = RunBackup::new(&install.fixture.fallout4, "Fixture.esp", false);
+    check_plugin(&paths, "Fixture.esp", "Fixture - Main.ba2", &templates, &backup, |_| Ok(false), |seeds| {
+        assert_eq!(seeds, ["TemplateA.esp", "TemplateB.esp"]);
+        Ok(Some(seeds[1].clone()))
+    })
+    .expect("the chosen template should be copied");
+
+    assert!(install.data("Fixture.esp").exists());
+    assert!(install.data("TemplateB.esp").exists(), "templates are kept for later builds");
+}
+
+/// Flag of a record whose fields are zlib compressed
+const FLAG_COMPRESSED: u32 = 0x40000;
+
+/// The fields of a record, each with its type and 16-bit size
+fn record_fields(fields: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (kind, value) in fields {
+        data.extend_from_slice(*kind);
+        data.extend((value.len() as u16).to_le_bytes());
+        data.extend(value);
+    }
+    data
+}
+
+/// A record of the given type, flags and form ID holding `body`
+fn record(kind: &[u8; 4], flags: u32, form_id: u32, body: &[u8]) -> Vec<u8> {
+    let mut record = kind.to_vec();
+    record.extend((body.len() as u32).to_le_bytes());
+    record.extend(flags.to_le_bytes());
+    record.extend(form_id.to_le_bytes());
+    record.extend([0u8; 8]);
+    record.extend(body);
+    record
+}
+
+/// A CELL record with the given fields
+fn cell_record(form_id: u32, fields: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    record(b"CELL", 0, form_id, &record_fields(fields))
+}
+
+/// A CELL record with the given fields compressed, as the Creation Kit saves large cells
+fn compressed_cell_record(form_id: u32, fields: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let data = record_fields(fields);
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&data).unwrap();
+    let mut body = (data.len() as u32).to_le_bytes().to_vec();
+    body.extend(encoder.finish().unwrap());
+    record(b"CELL", FLAG_COMPRESSED, form_id, &body)
+}
+
+/// A group of the given type holding `contents`, its size including its header
+fn group(label: [u8; 4], group_type: i32, contents: &[u8]) -> Vec<u8> {
+    let mut group = b"GRUP".to_vec();
+    group.extend(((contents.len() + 24) as u32).to_le_bytes());
+    group.extend(label);
+    group.extend(group_type.to_le_bytes());
+    group.extend([0u8; 8]);
+    group.extend(contents);
+    group
+}
+
+/// Interior cells as the Creation Kit groups them: top group, block, sub-block
+fn interior_cells(cells: &[Vec<u8>]) -> Vec<u8> {
+    group(*b"CELL", 0, &group([0; 4], 2, &group([0; 4], 3, &cells.concat())))
+}
+
+/// A worldspace record and the group of its exterior cells
+fn worldspace(form_id: u32, cells: &[Vec<u8>]) -> Vec<u8> {
+    let mut world = record(b"WRLD", 0, form_id, &record_fields(&[(b"EDID", b"FixtureWorld\0".to_vec())]));
+    let block = group([0; 4], 4, &group([0; 4], 5, &cells.concat()));
+    world.extend(group(form_id.to_le_bytes(), 1, &block));
+    world
+}
+
+/// Appends groups and records to the fixture plugin
+fn append_to_plugin(install: &TestInstall, contents: &[u8]) {
+    let mut plugin = fs::read(install.data("Fixture.esp")).unwrap();
+    plugin.extend_from_slice(contents);
+    fs::write(install.data("Fixture.esp"), plugin).unwrap();
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+#[test]
+fn check_finds_missing_precombined_meshes_and_previs() {
+    let install = TestInstall::new("check");
+    let mut plugin = fs::read(install.data("Fixture.esp")).unwrap();
+    plugin.extend(cell_record(0x0000_F4A3, &[(b"XCRI", words(&[2, 0, 0x5C1D_2B7E, 0x1234_ABCD])), (b"VISI", words(&[1]))]));
+    plugin.extend(cell_record(0x0000_0ABC, &[(b"XCRI", words(&[1, 0, 0x0BAD_F00D])), (b"RVIS", words(&[0x3C]))]));
+    fs::write(install.data("Fixture.esp"), plugin).unwrap();
+
+    let precombined = install.data("meshes").join("precombined");
+    fs::create_dir_all(&precombined).unwrap();
+    fs::create_dir_all(install.data("vis")).unwrap();
+    fs::write(precombined.join("0000F4A3_5C1D2B7E_OC.nif"), b"").unwrap();
+    fs::write(precombined.join("00000ABC_0BADF00D_OC.nif"), b"").unwrap();
+    fs::write(install.data("vis").join("0000F4A3.uvd"), b"").unwrap();
+
+    let report = check_previsbines(&install.fixture.fallout4.join("Data"), "Fixture.esp", None).unwrap();
+    assert_eq!((report.cells, report.precombined_cells, report.referenced_meshes), (2, 2, 3));
+    assert_eq!(report.missing_meshes, ["meshes\\precombined\\0000F4A3_1234ABCD_OC.nif (cell 0000F4A3)"]);
+    assert_eq!(report.missing_previs.len(), 1);
+    assert!(report.missing_previs[0].contains("00000ABC"), "{:?}", report.missing_previs);
+}
+
+#[test]
+fn clean_previsbines_strips_cells_and_archives_of_a_built_plugin() {
+    let install = TestInstall::new("clean-previsbines");
+    install.build(&["--start-stage", "1", "--no-backup"]).expect("build should succeed");
+
+    // The cells as the merge stages leave them, fields to keep around the generated ones
+    let kept_a = [(b"EDID", b"FixtureCellA\0".to_vec()), (b"DATA", vec![1, 0]), (b"XCLL", vec![7; 16])];
+    let kept_b = [(b"EDID", b"FixtureCellB\0".to_vec()), (b"FULL", b"Kept\0".to_vec())];
+    let built_a = [
+        kept_a[0].clone(),
+        (b"XCRI", words(&[1, 2, 0x5C1D_2B7E, 0x0000_1234, 0x0000_5678])),
+        kept_a[1].clone(),
+        (b"XPRI", words(&[0x0000_1234])),
+        (b"VISI", words(&[1])),
+        (b"RVIS", words(&[0x0100_0800])),
+        kept_a[2].clone(),
+    ];
+    let built_b = [kept_b[0].clone(), (b"PCMB", words(&[1])), kept_b[1].clone(), (b"VISI", words(&[1]))];
+    let header = fs::read(install.data("Fixture.esp")).unwrap();
+    append_to_plugin(
+        &install,
+        &interior_cells(&[cell_record(0x0100_0800, &built_a), compressed_cell_record(0x0100_0801, &built_b)]),
+    );
+
+    // A second archive part mixing generated files with the mod's own
+    let source = install.root.join("part2");
+    let part_entries = [
+        "meshes\\precombined\\01000800_5c1d2b7e_oc.nif",
+        "vis\\01000800.uvd",
+        "meshes\\props\\keep.nif",
+        "sound\\fx\\keep.wav",
+    ];
+    for entry in part_entries {
+        let path = entry.split('\\').fold(source.clone(), |path, part| path.join(part));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, entry).unwrap();
+    }
+    let entries: Vec<String> = part_entries.iter().map(|entry| entry.to_string()).collect();
+    write_ba2(&install.data("Fixture - Main2.ba2"), &source, &entries, 1, true).unwrap();
+
+    let fallout4 = install.fixture.fallout4.display().to_string();
+    let args = Args::try_parse_from(["generate_previsbines", "--fallout4-path", &fallout4, "clean-previsbines", "Fixture.esp"])
+        .expect("arguments should parse");
+    let Some(Command::CleanPrevisbines(clean)) = &args.command else {
+        panic!("clean-previsbines should be parsed as its command");
+    };
+    run_clean_previsbines(&args, clean).expect("clean-previsbines should succeed");
+
+    // Only the generated fields are gone; the recompressed cell and the group sizes match a plugin built without them
+    let mut expected = header;
+    expected.extend(interior_cells(&[cell_record(0x0100_0800, &kept_a), compressed_cell_record(0x0100_0801, &kept_b)]));
+    assert_eq!(fs::read(install.data("Fixture.esp")).unwrap(), expected);
+    read_plugin_header(&install.data("Fixture.esp")).expect("plugin should still parse");
+    let cells = read_cell_previsbines(&install.data("Fixture.esp")).expect("cells should still parse");
+    assert_eq!(cells.len(), 2);
+    assert!(cells.iter().all(|cell| cell.combined_meshes.is_empty() && !cell.has_previs && cell.previs_cell.is_none()));
+
+    assert!(!install.data("Fixture - Main.ba2").exists(), "an archive of only generated files is removed");
+    let mut remaining = archive_entries(&install.data("Fixture - Main2.ba2"));
+    remaining.sort();
+    assert_eq!(remaining, ["meshes\\props\\keep.nif", "sound\\fx\\keep.wav"]);
+    assert!(!install.data("Fixture - Geometry.csg").exists());
+    assert!(!install.data("Fixture.cdx").exists());
+
+    let backup = latest_backup(&install.fixture.fallout4, Some("Fixture.esp")).expect("backup should be written");
+    let mut saved: Vec<String> = read_manifest(&backup)
+        .unwrap()
+        .entries
+        .iter()
+        .map(|entry| entry.original.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    saved.sort();
+    assert_eq!(saved, ["Fixture - Geometry.csg", "Fixture - Main.ba2", "Fixture - Main2.ba2", "Fixture.cdx", "Fixture.esp"]);
+}
+
+#[test]
+fn build_mode_is_recommended_from_the_plugin_cells() {
+    let install = TestInstall::new("recommend-mode");
+    let plugin = install.data("Fixture.esp");
+    assert!(recommend_build_mode(&plugin).unwrap().is_none(), "a plugin without cells gives no recommendation");
+
+    // A patch of Fallout4.esm: overridden interior and exterior cells, one new cell
+    append_to_plugin(&install, &interior_cells(&[cell_record(0x0000_1000, &[]), cell_record(0x0100_0800, &[])]));
+    append_to_plugin(&install, &worldspace(0x0000_003C, &[cell_record(0x0000_2000, &[]), cell_record(0x0000_2001, &[])]));
+    let patch = recommend_build_mode(&plugin).unwrap().expect("a plugin with cells gets a recommendation");
+    assert_eq!(patch.mode, BuildMode::Filtered);
+    assert_eq!(patch.reasons, ["it mostly changes existing cells (3 overridden, 1 new), like a patch"]);
+
+    install.build(&["--auto-mode", "--start-stage", "1"]).expect("build should succeed");
+    assert!(install.data("Fixture - Main.ba2").exists());
+    assert!(!install.data("Fixture - Geometry.csg").exists(), "--auto-mode should build the patch in filtered mode");
+    assert!(!install.data("Fixture.cdx").exists());
+
+    // A master adding a worldspace and mostly new cells
+    let mut header = read_plugin_header(&plugin).unwrap();
+    header.flags |= FLAG_MASTER;
+    write_plugin_header(&plugin, &header).unwrap();
+    append_to_plugin(&install, &interior_cells(&[cell_record(0x0100_0800, &[]), cell_record(0x0000_1000, &[])]));
+    append_to_plugin(&install, &worldspace(0x0100_0900, &[cell_record(0x0100_0901, &[]), cell_record(0x0100_0902, &[])]));
+    let master = recommend_build_mode(&plugin).unwrap().expect("a plugin with cells gets a recommendation");
+    assert_eq!(master.mode, BuildMode::Clean);
+    assert_eq!(
+        master.reasons,
+        [
+            "it is flagged as a master, so other plugins build on its precombines",
+            "it adds 1 worldspace(s)",
+            "most of its cells are new (3 new, 1 overridden)",
+        ]
+    );
+
+    // New content without the master flag is still built clean
+    header.flags &= !FLAG_MASTER;
+    write_plugin_header(&plugin, &header).unwrap();
+    append_to_plugin(&install, &interior_cells(&[cell_record(0x0100_0800, &[])]));
+    let new_cells = recommend_build_mode(&plugin).unwrap().expect("a plugin with cells gets a recommendation");
+    assert_eq!(new_cells.mode, BuildMode::Clean);
+    assert_eq!(new_cells.reasons, ["most of its cells are new (1 new, 0 overridden)"]);
+}